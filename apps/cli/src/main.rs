@@ -152,11 +152,26 @@ window {}:
 struct Export {
     project_path: PathBuf,
     output_path: Option<PathBuf>,
+    /// JSON merge patch applied over the project config, inline or as a path to a JSON file.
+    /// Can be repeated; later patches win
+    #[arg(long = "override")]
+    overrides: Vec<String>,
 }
 
 impl Export {
     async fn run(self) -> Result<(), String> {
-        let exporter_base = ExporterBase::builder(self.project_path)
+        let mut builder = ExporterBase::builder(self.project_path);
+        for patch in &self.overrides {
+            let patch = match std::fs::read_to_string(patch) {
+                Ok(contents) => contents,
+                Err(_) => patch.clone(),
+            };
+            builder = builder.with_config_override(
+                serde_json::from_str(&patch).map_err(|e| format!("Invalid override: {e}"))?,
+            );
+        }
+
+        let exporter_base = builder
             .build()
             .await
             .map_err(|v| format!("Exporter build error: {v}"))?;
@@ -169,6 +184,16 @@ impl Export {
             compression: cap_export::mp4::ExportCompression::Maximum,
             custom_bpp: None,
             force_ffmpeg_decoder: false,
+            crf: None,
+            target_size_mb: None,
+            metadata: None,
+            storyboard: None,
+            loudness: None,
+            audio_stems: false,
+            video_codec: cap_export::mp4::Mp4VideoCodec::Auto,
+            hdr: None,
+            resumable: false,
+            time_range: None,
         }
         .export(exporter_base, move |_f| {
             // print!("\rrendered frame {f}");
@@ -1218,6 +1218,11 @@ pub fn parse_captions_json(json: &str) -> Result<cap_project::CaptionsData, Stri
                         .and_then(|v| v.as_bool())
                         .unwrap_or(false);
 
+                    let language = settings_obj
+                        .get("language")
+                        .and_then(|v| v.as_str())
+                        .map(str::to_string);
+
                     cap_project::CaptionSettings {
                         enabled,
                         font,
@@ -1236,6 +1241,7 @@ pub fn parse_captions_json(json: &str) -> Result<cap_project::CaptionsData, Stri
                         linger_duration,
                         word_transition_duration,
                         active_word_highlight,
+                        language,
                     }
                 } else {
                     cap_project::CaptionSettings::default()
@@ -27,11 +27,18 @@ impl Drop for ExportActiveGuard<'_> {
     }
 }
 
-#[derive(Deserialize, Clone, Copy, Debug, Type)]
+#[derive(Deserialize, Clone, Debug, Type)]
 #[serde(tag = "format")]
 pub enum ExportSettings {
     Mp4(cap_export::mp4::Mp4ExportSettings),
     Gif(cap_export::gif::GifExportSettings),
+    Webm(cap_export::webm::WebmExportSettings),
+    Audio(cap_export::audio::AudioExportSettings),
+    ImageSequence(cap_export::image_sequence::ImageSequenceExportSettings),
+    Streaming(cap_export::streaming::StreamingExportSettings),
+    Alpha(cap_export::alpha::AlphaExportSettings),
+    Camera(cap_export::camera::CameraExportSettings),
+    AnimatedImage(cap_export::animated::AnimatedImageExportSettings),
 }
 
 impl ExportSettings {
@@ -39,6 +46,27 @@ impl ExportSettings {
         match self {
             ExportSettings::Mp4(settings) => settings.fps,
             ExportSettings::Gif(settings) => settings.fps,
+            ExportSettings::Webm(settings) => settings.fps,
+            ExportSettings::Audio(_) => cap_export::audio::AudioExportSettings::BLOCK_RATE,
+            ExportSettings::ImageSequence(settings) => settings.fps,
+            ExportSettings::Streaming(settings) => settings.fps,
+            ExportSettings::Alpha(settings) => settings.fps,
+            ExportSettings::Camera(settings) => settings.fps,
+            ExportSettings::AnimatedImage(settings) => settings.fps,
+        }
+    }
+
+    fn time_range(&self) -> Option<cap_export::ExportTimeRange> {
+        match self {
+            ExportSettings::Mp4(settings) => settings.time_range,
+            ExportSettings::Gif(settings) => settings.time_range,
+            ExportSettings::Webm(settings) => settings.time_range,
+            ExportSettings::Audio(settings) => settings.time_range,
+            ExportSettings::ImageSequence(settings) => settings.time_range,
+            ExportSettings::Streaming(settings) => settings.time_range,
+            ExportSettings::Alpha(settings) => settings.time_range,
+            ExportSettings::Camera(settings) => settings.time_range,
+            ExportSettings::AnimatedImage(settings) => settings.time_range,
         }
     }
 }
@@ -50,47 +78,132 @@ async fn do_export_with_path(
     progress: &tauri::ipc::Channel<FramesRendered>,
     force_ffmpeg: bool,
 ) -> Result<PathBuf, String> {
-    let exporter_base = ExporterBase::builder(project_path.to_path_buf())
+    match settings {
+        ExportSettings::Audio(audio_settings) => {
+            let mut exporter_base = ExporterBase::builder(project_path.to_path_buf())
+                .with_output_path(output_path.to_path_buf())
+                .build_audio()
+                .map_err(|e| e.to_string())?;
+
+            exporter_base.set_time_range(settings.time_range());
+
+            let total_frames = exporter_base.total_frames(settings.fps());
+            report_started(progress, total_frames);
+
+            audio_settings
+                .export(exporter_base, frame_progress(progress, total_frames))
+                .await
+        }
+        ExportSettings::Mp4(mp4_settings) => {
+            let (exporter_base, total_frames) =
+                video_exporter_base(project_path, output_path, settings, progress, force_ffmpeg)
+                    .await?;
+            mp4_settings
+                .clone()
+                .export(exporter_base, frame_progress(progress, total_frames))
+                .await
+        }
+        ExportSettings::Gif(gif_settings) => {
+            let (exporter_base, total_frames) =
+                video_exporter_base(project_path, output_path, settings, progress, force_ffmpeg)
+                    .await?;
+            gif_settings
+                .export(exporter_base, frame_progress(progress, total_frames))
+                .await
+        }
+        ExportSettings::Webm(webm_settings) => {
+            let (exporter_base, total_frames) =
+                video_exporter_base(project_path, output_path, settings, progress, force_ffmpeg)
+                    .await?;
+            webm_settings
+                .export(exporter_base, frame_progress(progress, total_frames))
+                .await
+        }
+        ExportSettings::ImageSequence(sequence_settings) => {
+            let (exporter_base, total_frames) =
+                video_exporter_base(project_path, output_path, settings, progress, force_ffmpeg)
+                    .await?;
+            sequence_settings
+                .export(exporter_base, frame_progress(progress, total_frames))
+                .await
+        }
+        ExportSettings::Streaming(streaming_settings) => {
+            let (exporter_base, total_frames) =
+                video_exporter_base(project_path, output_path, settings, progress, force_ffmpeg)
+                    .await?;
+            streaming_settings
+                .export(exporter_base, frame_progress(progress, total_frames))
+                .await
+        }
+        ExportSettings::Alpha(alpha_settings) => {
+            let (exporter_base, total_frames) =
+                video_exporter_base(project_path, output_path, settings, progress, force_ffmpeg)
+                    .await?;
+            alpha_settings
+                .export(exporter_base, frame_progress(progress, total_frames))
+                .await
+        }
+        ExportSettings::Camera(camera_settings) => {
+            let (exporter_base, total_frames) =
+                video_exporter_base(project_path, output_path, settings, progress, force_ffmpeg)
+                    .await?;
+            camera_settings
+                .export(exporter_base, frame_progress(progress, total_frames))
+                .await
+        }
+        ExportSettings::AnimatedImage(animated_settings) => {
+            let (exporter_base, total_frames) =
+                video_exporter_base(project_path, output_path, settings, progress, force_ffmpeg)
+                    .await?;
+            animated_settings
+                .export(exporter_base, frame_progress(progress, total_frames))
+                .await
+        }
+    }
+}
+
+/// Loads the project with its renderer for a video export and reports zero progress.
+async fn video_exporter_base(
+    project_path: &Path,
+    output_path: &Path,
+    settings: &ExportSettings,
+    progress: &tauri::ipc::Channel<FramesRendered>,
+    force_ffmpeg: bool,
+) -> Result<(ExporterBase, u32), String> {
+    let mut exporter_base = ExporterBase::builder(project_path.to_path_buf())
         .with_force_ffmpeg_decoder(force_ffmpeg)
         .with_output_path(output_path.to_path_buf())
         .build()
         .await
         .map_err(|e| e.to_string())?;
 
+    exporter_base.set_time_range(settings.time_range());
+
     let total_frames = exporter_base.total_frames(settings.fps());
+    report_started(progress, total_frames);
 
+    Ok((exporter_base, total_frames))
+}
+
+fn report_started(progress: &tauri::ipc::Channel<FramesRendered>, total_frames: u32) {
     let _ = progress.send(FramesRendered {
         rendered_count: 0,
         total_frames,
     });
+}
 
-    match settings {
-        ExportSettings::Mp4(mp4_settings) => {
-            let progress = progress.clone();
-            mp4_settings
-                .export(exporter_base, move |frame_index| {
-                    progress
-                        .send(FramesRendered {
-                            rendered_count: (frame_index + 1).min(total_frames),
-                            total_frames,
-                        })
-                        .is_ok()
-                })
-                .await
-        }
-        ExportSettings::Gif(gif_settings) => {
-            let progress = progress.clone();
-            gif_settings
-                .export(exporter_base, move |frame_index| {
-                    progress
-                        .send(FramesRendered {
-                            rendered_count: (frame_index + 1).min(total_frames),
-                            total_frames,
-                        })
-                        .is_ok()
-                })
-                .await
-        }
+fn frame_progress(
+    progress: &tauri::ipc::Channel<FramesRendered>,
+    total_frames: u32,
+) -> impl FnMut(u32) -> bool + Send + 'static {
+    let progress = progress.clone();
+    move |frame_index| {
+        progress
+            .send(FramesRendered {
+                rendered_count: (frame_index + 1).min(total_frames),
+                total_frames,
+            })
+            .is_ok()
     }
 }
 
@@ -130,12 +243,25 @@ pub async fn export_video(
         .and_then(|s| s.to_str())
         .unwrap_or("recording");
 
-    let extension = match settings {
-        ExportSettings::Mp4(_) => "mp4",
-        ExportSettings::Gif(_) => "gif",
+    // Streaming exports are a directory of segments and playlists, named after the project.
+    // Exports that sit alongside another format's file get a suffix so neither replaces
+    // the other.
+    let (suffix, extension) = match &settings {
+        ExportSettings::Mp4(_) => ("", Some("mp4")),
+        ExportSettings::Gif(_) => ("", Some("gif")),
+        ExportSettings::Webm(_) => ("", Some("webm")),
+        ExportSettings::Audio(s) => ("", Some(s.audio_format.extension())),
+        ExportSettings::ImageSequence(s) => ("", Some(s.image_format.extension())),
+        ExportSettings::Streaming(_) => ("-stream", None),
+        ExportSettings::Alpha(s) => (s.codec.file_suffix(), Some(s.codec.extension())),
+        ExportSettings::Camera(_) => ("-camera", Some("mp4")),
+        ExportSettings::AnimatedImage(s) => ("", Some(s.image_format.extension())),
     };
 
-    let output_path = output_dir.join(format!("{}.{}", file_name, extension));
+    let output_path = match extension {
+        Some(extension) => output_dir.join(format!("{}{}.{}", file_name, suffix, extension)),
+        None => output_dir.join(format!("{}{}", file_name, suffix)),
+    };
 
     let result = do_export_with_path(
         &project_path,
@@ -204,10 +330,25 @@ pub async fn get_export_estimates(
     } else {
         metadata.duration
     };
+    let duration_seconds = match settings.time_range() {
+        Some(range) => (range.end.min(duration_seconds) - range.start.max(0.0)).max(0.0),
+        None => duration_seconds,
+    };
 
     let (resolution, fps) = match &settings {
         ExportSettings::Mp4(s) => (s.resolution_base, s.fps),
         ExportSettings::Gif(s) => (s.resolution_base, s.fps),
+        ExportSettings::Webm(s) => (s.resolution_base, s.fps),
+        ExportSettings::ImageSequence(s) => (s.resolution_base, s.fps),
+        ExportSettings::Streaming(s) => (s.resolution_base, s.fps),
+        ExportSettings::Alpha(s) => (s.resolution_base, s.fps),
+        // Exported at the camera's own size, which webcams rarely take above 1080p.
+        ExportSettings::Camera(s) => (XY::new(1920, 1080), s.fps),
+        ExportSettings::AnimatedImage(s) => (s.resolution_base, s.fps),
+        ExportSettings::Audio(_) => (
+            XY::new(0, 0),
+            cap_export::audio::AudioExportSettings::BLOCK_RATE,
+        ),
     };
 
     let (width, height) = (resolution.x, resolution.y);
@@ -216,6 +357,18 @@ pub async fn get_export_estimates(
     let total_frames = (duration_seconds * fps_f64).ceil();
 
     let (estimated_size_mb, estimated_time_seconds) = match &settings {
+        ExportSettings::Mp4(mp4_settings) if mp4_settings.target_size_mb.is_some() => {
+            let size_mb =
+                mp4_settings.target_size_bytes().unwrap_or_default() as f64 / (1024.0 * 1024.0);
+
+            let effective_render_fps = match (width, height) {
+                (w, _) if w >= 3840 => 175.0,
+                _ => 290.0,
+            };
+            let time_estimate = total_frames / effective_render_fps;
+
+            (size_mb, time_estimate)
+        }
         ExportSettings::Mp4(mp4_settings) => {
             let bits_per_pixel = mp4_settings.compression.bits_per_pixel() as f64;
             let effective_fps = ((fps_f64 - 30.0).max(0.0) * 0.6) + fps_f64.min(30.0);
@@ -234,6 +387,124 @@ pub async fn get_export_estimates(
 
             (size_mb, time_estimate)
         }
+        ExportSettings::Webm(webm_settings) => {
+            let bits_per_pixel = webm_settings.effective_bpp() as f64;
+            let effective_fps = ((fps_f64 - 30.0).max(0.0) * 0.6) + fps_f64.min(30.0);
+            let video_bitrate = total_pixels * bits_per_pixel * effective_fps;
+            let audio_bitrate = 128_000.0;
+            let total_bitrate = video_bitrate + audio_bitrate;
+            let encoder_efficiency = 0.5;
+            let size_mb =
+                (total_bitrate * encoder_efficiency * duration_seconds) / (8.0 * 1024.0 * 1024.0);
+
+            let effective_render_fps = match (width, height) {
+                (w, _) if w >= 3840 => 30.0,
+                _ => 60.0,
+            };
+            let time_estimate = total_frames / effective_render_fps;
+
+            (size_mb, time_estimate)
+        }
+        ExportSettings::Audio(audio_settings) => {
+            use cap_export::audio::AudioExportFormat;
+
+            let bitrate = match audio_settings.audio_format {
+                AudioExportFormat::Wav => 48_000.0 * 2.0 * 16.0,
+                AudioExportFormat::Flac => 48_000.0 * 2.0 * 16.0 * 0.6,
+                AudioExportFormat::M4a | AudioExportFormat::Opus => 128_000.0,
+            };
+            let size_mb = (bitrate * duration_seconds) / (8.0 * 1024.0 * 1024.0);
+            let time_estimate = duration_seconds / 200.0;
+
+            (size_mb, time_estimate)
+        }
+        ExportSettings::Streaming(streaming_settings) => {
+            use cap_export::streaming::StreamLadder;
+
+            let bits_per_pixel = streaming_settings.effective_bpp() as f64;
+            let effective_fps = ((fps_f64 - 30.0).max(0.0) * 0.6) + fps_f64.min(30.0);
+            let ladder_pixels = match streaming_settings.ladder {
+                StreamLadder::Single => 1.0,
+                StreamLadder::Standard => 1.5,
+            };
+            let video_bitrate = total_pixels * ladder_pixels * bits_per_pixel * effective_fps;
+            let audio_bitrate = 192_000.0;
+            let total_bitrate = video_bitrate + audio_bitrate;
+            let encoder_efficiency = 0.5;
+            let size_mb =
+                (total_bitrate * encoder_efficiency * duration_seconds) / (8.0 * 1024.0 * 1024.0);
+
+            let effective_render_fps = match streaming_settings.ladder {
+                StreamLadder::Single => 120.0,
+                StreamLadder::Standard => 60.0,
+            };
+            let time_estimate = total_frames / effective_render_fps;
+
+            (size_mb, time_estimate)
+        }
+        ExportSettings::Alpha(alpha_settings) => {
+            use cap_export::{alpha::AlphaCodec, webm::WebmVideoCodec};
+
+            let effective_fps = ((fps_f64 - 30.0).max(0.0) * 0.6) + fps_f64.min(30.0);
+            let (video_bitrate, audio_bitrate) = match alpha_settings.codec {
+                // ProRes 4444 runs at roughly 5 bits per pixel regardless of content.
+                AlphaCodec::ProRes4444 => (total_pixels * 5.0 * fps_f64, 48_000.0 * 2.0 * 16.0),
+                // The alpha plane adds about a third on top of the colour planes.
+                AlphaCodec::Vp9 => (
+                    total_pixels
+                        * alpha_settings.compression.bits_per_pixel() as f64
+                        * WebmVideoCodec::Vp9.bpp_scale() as f64
+                        * effective_fps
+                        * 1.3
+                        * 0.5,
+                    128_000.0,
+                ),
+            };
+            let size_mb =
+                ((video_bitrate + audio_bitrate) * duration_seconds) / (8.0 * 1024.0 * 1024.0);
+
+            let effective_render_fps = match alpha_settings.codec {
+                AlphaCodec::ProRes4444 => 30.0,
+                AlphaCodec::Vp9 => 20.0,
+            };
+            let time_estimate = total_frames / effective_render_fps;
+
+            (size_mb, time_estimate)
+        }
+        ExportSettings::Camera(camera_settings) => {
+            let bits_per_pixel = camera_settings.compression.bits_per_pixel() as f64;
+            let effective_fps = ((fps_f64 - 30.0).max(0.0) * 0.6) + fps_f64.min(30.0);
+            let video_bitrate = total_pixels * bits_per_pixel * effective_fps;
+            let audio_bitrate = 192_000.0;
+            let total_bitrate = video_bitrate + audio_bitrate;
+            let encoder_efficiency = 0.5;
+            let size_mb =
+                (total_bitrate * encoder_efficiency * duration_seconds) / (8.0 * 1024.0 * 1024.0);
+
+            // No compositing, so it's bound by decoding the camera.
+            let time_estimate = total_frames / 240.0;
+
+            (size_mb, time_estimate)
+        }
+        ExportSettings::ImageSequence(sequence_settings) => {
+            use cap_export::image_sequence::ImageSequenceFormat;
+
+            let bytes_per_pixel = match (sequence_settings.image_format, sequence_settings.alpha) {
+                (ImageSequenceFormat::Png, _) => 1.5,
+                (ImageSequenceFormat::Exr, true) => 16.0,
+                (ImageSequenceFormat::Exr, false) => 12.0,
+            };
+            let size_mb = (total_pixels * bytes_per_pixel * total_frames) / (1024.0 * 1024.0);
+
+            let frames_per_sec = match (width, height) {
+                (w, h) if w <= 1280 && h <= 720 => 30.0,
+                (w, h) if w <= 1920 && h <= 1080 => 15.0,
+                _ => 5.0,
+            };
+            let time_estimate = total_frames / frames_per_sec;
+
+            (size_mb, time_estimate)
+        }
         ExportSettings::Gif(_) => {
             let bytes_per_frame = total_pixels * 0.5;
             let gif_efficiency = 0.07;
@@ -246,6 +517,26 @@ pub async fn get_export_estimates(
             };
             let time_estimate = total_frames / frames_per_sec;
 
+            (size_mb, time_estimate)
+        }
+        ExportSettings::AnimatedImage(animated_settings) => {
+            use cap_export::animated::AnimatedImageFormat;
+
+            let bytes_per_frame = total_pixels * 0.5;
+            let efficiency = match animated_settings.image_format {
+                AnimatedImageFormat::WebP if animated_settings.lossless => 0.1,
+                AnimatedImageFormat::WebP => 0.03,
+                AnimatedImageFormat::Apng => 0.2,
+            };
+            let size_mb = (bytes_per_frame * efficiency * total_frames) / (1024.0 * 1024.0);
+
+            let frames_per_sec = match (width, height) {
+                (w, h) if w <= 1280 && h <= 720 => 15.0,
+                (w, h) if w <= 1920 && h <= 1080 => 8.0,
+                _ => 3.0,
+            };
+            let time_estimate = total_frames / frames_per_sec;
+
             (size_mb, time_estimate)
         }
     };
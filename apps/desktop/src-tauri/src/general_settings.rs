@@ -76,6 +76,12 @@ impl RecordingQuality {
                 Self::Standard => 0.20,
                 Self::Low => 0.10,
             },
+            RecordingCodec::Av1 => match self {
+                Self::Ultra => 0.55,
+                Self::High => 0.28,
+                Self::Standard => 0.15,
+                Self::Low => 0.08,
+            },
         }
     }
 }
@@ -86,6 +92,7 @@ pub enum RecordingCodec {
     #[default]
     H264,
     H265,
+    Av1,
 }
 
 impl MainWindowRecordingStartBehaviour {
@@ -892,6 +892,9 @@ pub async fn start_recording(
                                 crate::general_settings::RecordingCodec::H265 => {
                                     cap_recording::VideoCodec::H265
                                 }
+                                crate::general_settings::RecordingCodec::Av1 => {
+                                    cap_recording::VideoCodec::Av1
+                                }
                             };
 
                             let mut builder = instant_recording::Actor::builder(
@@ -2249,6 +2252,7 @@ fn project_config_from_recording(
         text_segments: Vec::new(),
         caption_segments: Vec::new(),
         keyboard_segments: Vec::new(),
+        markers: Vec::new(),
     });
 
     config
@@ -514,6 +514,7 @@ function Inner(props: { initialStore: GeneralSettingsStore | null }) {
 						options={[
 							{ text: "H.264", value: "h264" as RecordingCodec },
 							{ text: "H.265 (HEVC)", value: "h265" as RecordingCodec },
+							{ text: "AV1", value: "av1" as RecordingCodec },
 						]}
 					/>
 					<SelectSettingItem
@@ -410,6 +410,7 @@ export function CaptionsTab() {
 			if (result && result.segments.length > 0) {
 				setProject("captions", "segments", result.segments);
 				updateCaptionSetting("enabled", true);
+				updateCaptionSetting("language", lang === "auto" ? null : lang);
 				toast.success("Captions generated successfully!");
 			} else {
 				toast.error(
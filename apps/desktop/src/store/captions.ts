@@ -149,6 +149,7 @@ function createCaptionsStore() {
 						lingerDuration: state.settings.lingerDuration,
 						wordTransitionDuration: state.settings.wordTransitionDuration,
 						activeWordHighlight: state.settings.activeWordHighlight,
+						language: state.settings.language,
 					},
 				};
 
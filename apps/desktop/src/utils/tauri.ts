@@ -402,19 +402,42 @@ videoImportProgress: "video-import-progress"
 
 /** user-defined types **/
 
+export type AlphaCodec = "ProRes4444" | "Vp9"
+export type AlphaExportSettings = { fps: number; resolution_base: XY<number>; codec?: AlphaCodec; 
+/**
+ * Only used for VP9, ProRes picks its own bitrate.
+ */
+compression: ExportCompression; time_range?: ExportTimeRange | null }
 export type AllGpusInfo = { gpus: GpuInfoDiag[]; primaryGpuIndex: number | null; isMultiGpuSystem: boolean; hasDiscreteGpu: boolean }
+export type AnimatedImageExportSettings = { fps: number; resolution_base: XY<number>; image_format?: AnimatedImageFormat; 
+/**
+ * WebP quality from 0-100 (default: 80). APNG is always lossless.
+ */
+quality?: number | null; 
+/**
+ * Encode WebP losslessly, ignoring `quality`.
+ */
+lossless?: boolean; 
+/**
+ * How many times the animation plays (default: loops forever)
+ */
+loop_count?: number | null; time_range?: ExportTimeRange | null }
+export type AnimatedImageFormat = "WebP" | "Apng"
 export type Annotation = { id: string; type: AnnotationType; x: number; y: number; width: number; height: number; strokeColor: string; strokeWidth: number; fillColor: string; opacity: number; rotation: number; text: string | null; maskType?: MaskType | null; maskLevel?: number | null }
 export type AnnotationType = "arrow" | "circle" | "rectangle" | "text" | "mask"
 export type AppTheme = "system" | "light" | "dark"
 export type AspectRatio = "wide" | "vertical" | "square" | "classic" | "tall"
 export type Audio = { duration: number; sample_rate: number; channels: number; start_time: number }
 export type AudioConfiguration = { mute: boolean; improve: boolean; micVolumeDb: number; micStereoMode: StereoMode; systemVolumeDb: number }
+export type AudioExportFormat = "Wav" | "Flac" | "M4a" | "Opus"
+export type AudioExportSettings = { audio_format: AudioExportFormat; time_range?: ExportTimeRange | null }
 export type AudioInputLevelChange = number
 export type AudioMeta = { path: string; start_time?: number | null; device_id?: string | null }
 export type BackgroundConfiguration = { source: BackgroundSource; blur: number; padding: number; rounding: number; roundingType: CornerStyle; inset: number; crop: Crop | null; shadow: number; advancedShadow: ShadowConfiguration | null; border: BorderConfiguration | null }
 export type BackgroundSource = { type: "wallpaper"; path: string | null } | { type: "image"; path: string | null } | { type: "color"; value: [number, number, number]; alpha?: number } | { type: "gradient"; from: [number, number, number]; to: [number, number, number]; angle?: number; noise_intensity?: number | null; noise_scale?: number | null; animated?: boolean | null; animation_speed?: number | null }
 export type BorderConfiguration = { enabled: boolean; width: number; color: [number, number, number]; opacity: number }
 export type Camera = { hide: boolean; mirror: boolean; position: CameraPosition; size: number; zoomSize: number | null; rounding: number; shadow: number; advancedShadow: ShadowConfiguration | null; shape: CameraShape; roundingType: CornerStyle; scaleDuringZoom?: number }
+export type CameraExportSettings = { fps: number; compression: ExportCompression; time_range?: ExportTimeRange | null }
 export type CameraFormatInfo = { width: number; height: number; frameRate: number }
 export type CameraInfo = { device_id: string; model_id: ModelIDType | null; display_name: string }
 export type CameraPosition = { x: CameraXPosition; y: CameraYPosition }
@@ -426,7 +449,11 @@ export type CameraXPosition = "left" | "center" | "right"
 export type CameraYPosition = "top" | "bottom"
 export type CaptionData = { segments: CaptionSegment[]; settings: CaptionSettings | null }
 export type CaptionSegment = { id: string; start: number; end: number; text: string; words?: CaptionWord[] }
-export type CaptionSettings = { enabled: boolean; font: string; size: number; color: string; backgroundColor: string; backgroundOpacity: number; position: string; italic: boolean; fontWeight: number; outline: boolean; outlineColor: string; exportWithSubtitles: boolean; highlightColor: string; fadeDuration: number; lingerDuration: number; wordTransitionDuration: number; activeWordHighlight: boolean }
+export type CaptionSettings = { enabled: boolean; font: string; size: number; color: string; backgroundColor: string; backgroundOpacity: number; position: string; italic: boolean; fontWeight: number; outline: boolean; outlineColor: string; exportWithSubtitles: boolean; highlightColor: string; fadeDuration: number; lingerDuration: number; wordTransitionDuration: number; activeWordHighlight: boolean; 
+/**
+ * Language of the caption text (e.g. `en`), used to tag exported subtitle tracks.
+ */
+language?: string | null }
 export type CaptionTrackSegment = { id: string; start: number; end: number; text: string; words?: CaptionWord[]; fadeDurationOverride?: number | null; lingerDurationOverride?: number | null; positionOverride?: string | null; colorOverride?: string | null; backgroundColorOverride?: string | null; fontSizeOverride?: number | null }
 export type CaptionWord = { text: string; start: number; end: number }
 export type CaptionsData = { segments: CaptionSegment[]; settings: CaptionSettings }
@@ -457,14 +484,24 @@ export type EditorPreviewQuality = "quarter" | "half" | "full"
 export type EditorStateChanged = { playhead_position: number }
 export type ExportCompression = "Maximum" | "Social" | "Web" | "Potato"
 export type ExportEstimates = { duration_seconds: number; estimated_time_seconds: number; estimated_size_mb: number }
+export type ExportMetadata = { 
+/**
+ * Defaults to the recording's name.
+ */
+title?: string | null; author?: string | null; comment?: string | null; 
+/**
+ * Defaults to `Cap`.
+ */
+encoder?: string | null; tags?: { [key in string]: string } }
 export type ExportPreviewResult = { jpeg_base64: string; estimated_size_mb: number; actual_width: number; actual_height: number; frame_render_time_ms: number; total_frames: number }
 export type ExportPreviewSettings = { fps: number; resolution_base: XY<number>; compression_bpp: number }
-export type ExportSettings = ({ format: "Mp4" } & Mp4ExportSettings) | ({ format: "Gif" } & GifExportSettings)
+export type ExportSettings = ({ format: "Mp4" } & Mp4ExportSettings) | ({ format: "Gif" } & GifExportSettings) | ({ format: "Webm" } & WebmExportSettings) | ({ format: "Audio" } & AudioExportSettings) | ({ format: "ImageSequence" } & ImageSequenceExportSettings) | ({ format: "Streaming" } & StreamingExportSettings) | ({ format: "Alpha" } & AlphaExportSettings) | ({ format: "Camera" } & CameraExportSettings) | ({ format: "AnimatedImage" } & AnimatedImageExportSettings)
+export type ExportTimeRange = { start: number; end: number }
 export type FileType = "recording" | "screenshot"
 export type Flags = { captions: boolean }
 export type FramesRendered = { renderedCount: number; totalFrames: number; type: "FramesRendered" }
 export type GeneralSettingsStore = { instanceId?: string; hideDockIcon?: boolean; enableNotifications?: boolean; hasCompletedStartup?: boolean; theme?: AppTheme; lastVersion?: string | null; windowTransparency?: boolean; postStudioRecordingBehaviour?: PostStudioRecordingBehaviour; mainWindowRecordingStartBehaviour?: MainWindowRecordingStartBehaviour; custom_cursor_capture2?: boolean; recordingCountdown?: number | null; enableNativeCameraPreview: boolean; autoZoomOnClicks?: boolean; captureKeyboardEvents?: boolean; postDeletionBehaviour?: PostDeletionBehaviour; excludedWindows?: WindowExclusion[]; instantModeMaxResolution?: number; defaultProjectNameTemplate?: string | null; crashRecoveryRecording?: boolean; maxFps?: number; transcriptionHints?: string[]; editorPreviewQuality?: EditorPreviewQuality; mainWindowPosition?: WindowPosition | null; cameraWindowPosition?: WindowPosition | null; recordingsSavePath?: string | null; language?: string | null; recordingQuality?: RecordingQuality; recordingCodec?: RecordingCodec; disableContentProtection?: boolean; autoCompressInstant?: boolean; autoCompressDeleteOriginal?: boolean; openLibraryAfterRecording?: boolean }
-export type GifExportSettings = { fps: number; resolution_base: XY<number>; quality: GifQuality | null }
+export type GifExportSettings = { fps: number; resolution_base: XY<number>; quality: GifQuality | null; time_range?: ExportTimeRange | null }
 export type GifQuality = { 
 /**
  * Encoding quality from 1-100 (default: 90)
@@ -473,15 +510,46 @@ quality: number | null;
 /**
  * Whether to prioritize speed over quality (default: false)
  */
-fast: boolean | null }
+fast: boolean | null; 
+/**
+ * How many times the animation plays (default: loops forever)
+ */
+loop_count?: number | null; 
+/**
+ * Most colours in each frame's palette, 2-256 (default: 256)
+ */
+max_colors?: number | null; 
+/**
+ * Reuse the first frame's palette for every frame (default: false)
+ */
+shared_palette?: boolean | null; 
+/**
+ * Dithering strength from 0.0 to 1.0 (default: 1.0)
+ */
+dithering?: number | null; 
+/**
+ * Lossy compression quality from 1-100, lower is smaller (default: lossless)
+ */
+lossy_quality?: number | null; 
+/**
+ * Hold the previous frame instead of repeating identical ones (default: false)
+ */
+merge_duplicate_frames?: boolean | null }
 export type GlideDirection = "none" | "left" | "right" | "up" | "down"
 export type GpuInfoDiag = { vendor: string; description: string; dedicatedVideoMemoryMb: number; adapterIndex: number; isSoftwareAdapter: boolean; isBasicRenderDriver: boolean; supportsHardwareEncoding: boolean }
 export type HapticPattern = "alignment" | "levelChange" | "generic"
 export type HapticPerformanceTime = "default" | "now" | "drawCompleted"
+export type HdrFormat = "Hdr10" | "Hlg"
 export type Hotkey = { code: string; meta: boolean; ctrl: boolean; alt: boolean; shift: boolean }
 export type HotkeyAction = "startStudioRecording" | "startInstantRecording" | "stopRecording" | "restartRecording" | "togglePauseRecording" | "cycleRecordingMode" | "openRecordingPicker" | "openRecordingPickerDisplay" | "openRecordingPickerWindow" | "openRecordingPickerArea" | "screenshotDisplay" | "screenshotWindow" | "screenshotArea" | "other"
 export type HotkeysConfiguration = { show: boolean }
 export type HotkeysStore = { hotkeys: { [key in HotkeyAction]: Hotkey } }
+export type ImageSequenceExportSettings = { fps: number; resolution_base: XY<number>; image_format?: ImageSequenceFormat; 
+/**
+ * Keep the alpha channel, for projects with a transparent background.
+ */
+alpha?: boolean; time_range?: ExportTimeRange | null }
+export type ImageSequenceFormat = "Png" | "Exr"
 export type ImportStage = "Probing" | "Converting" | "Finalizing" | "Complete" | "Failed"
 export type IncompleteRecordingInfo = { projectPath: string; prettyName: string; segmentCount: number; estimatedDurationSecs: number }
 export type InstantRecordingMeta = { recording: boolean } | { error: string } | { fps: number; sample_rate: number | null }
@@ -496,6 +564,15 @@ export type LibraryItemType = "video" | "screenshot"
 export type LogicalBounds = { position: LogicalPosition; size: LogicalSize }
 export type LogicalPosition = { x: number; y: number }
 export type LogicalSize = { width: number; height: number }
+export type LoudnessNormalization = { 
+/**
+ * Integrated loudness to aim for, in LUFS.
+ */
+target_lufs?: number; 
+/**
+ * Highest true peak allowed after normalization, in dBTP.
+ */
+max_true_peak_db?: number }
 export type MainWindowRecordingStartBehaviour = "close" | "minimise"
 export type MaskKeyframes = { position?: MaskVectorKeyframe[]; size?: MaskVectorKeyframe[]; intensity?: MaskScalarKeyframe[] }
 export type MaskKind = "sensitive" | "highlight"
@@ -505,7 +582,42 @@ export type MaskType = "blur" | "pixelate"
 export type MaskVectorKeyframe = { time: number; x: number; y: number }
 export type MicrophoneInfo = { name: string; sampleRate: number; channels: number }
 export type ModelIDType = string
-export type Mp4ExportSettings = { fps: number; resolution_base: XY<number>; compression: ExportCompression; custom_bpp: number | null; force_ffmpeg_decoder?: boolean; crf?: number | null }
+export type Mp4ExportSettings = { fps: number; resolution_base: XY<number>; compression: ExportCompression; custom_bpp: number | null; force_ffmpeg_decoder?: boolean; crf?: number | null; 
+/**
+ * Fit the export under this many megabytes (10^6 bytes) by encoding at the bitrate the
+ * budget allows instead of using `compression`. Takes precedence over `crf`.
+ */
+target_size_mb?: number | null; 
+/**
+ * Title, creation time and other tags to write into the file. `None` writes none.
+ */
+metadata?: ExportMetadata | null; 
+/**
+ * Poster frame, thumbnail sprite sheet and WebVTT storyboard to write next to the file.
+ */
+storyboard?: StoryboardSettings | null; 
+/**
+ * Measure the audio first and normalize it to a loudness target. `None` keeps the
+ * project's volumes as they are.
+ */
+loudness?: LoudnessNormalization | null; 
+/**
+ * Also write the microphone and system audio as their own labelled tracks after the
+ * mix, for editing them separately.
+ */
+audio_stems?: boolean; video_codec?: Mp4VideoCodec; 
+/**
+ * Encode 10-bit HEVC signalled as HDR10 or HLG, with the SDR render placed at HDR
+ * reference white. Ignored for AV1.
+ */
+hdr?: HdrFormat | null; 
+/**
+ * Write the export in fragments next to a checkpoint, so an interrupted export picks
+ * up where it stopped. Costs a final pass to join the fragments, and each fragment's
+ * audio starts with its own encoder delay.
+ */
+resumable?: boolean; time_range?: ExportTimeRange | null }
+export type Mp4VideoCodec = "Auto" | "Av1"
 export type MultipleSegment = { display: VideoMeta; camera?: VideoMeta | null; mic?: AudioMeta | null; system_audio?: AudioMeta | null; cursor?: string | null; keyboard?: string | null }
 export type MultipleSegments = { segments: MultipleSegment[]; cursors: Cursors; status?: StudioRecordingStatus | null }
 export type NewNotification = { title: string; body: string; is_error: boolean }
@@ -521,10 +633,16 @@ export type PostDeletionBehaviour = "doNothing" | "reopenRecordingWindow"
 export type PostStudioRecordingBehaviour = "openEditor" | "showOverlay"
 export type Preset = { name: string; config: ProjectConfiguration }
 export type PresetsStore = { presets: Preset[]; default: number | null }
-export type ProjectConfiguration = { aspectRatio: AspectRatio | null; background: BackgroundConfiguration; camera: Camera; audio: AudioConfiguration; cursor: CursorConfiguration; hotkeys: HotkeysConfiguration; timeline: TimelineConfiguration | null; captions: CaptionsData | null; keyboard: KeyboardData | null; clips: ClipConfiguration[]; annotations: Annotation[]; screenMotionBlur?: number; screenMovementSpring?: ScreenMovementSpring }
+export type ProjectConfiguration = { aspectRatio: AspectRatio | null; background: BackgroundConfiguration; camera: Camera; audio: AudioConfiguration; cursor: CursorConfiguration; hotkeys: HotkeysConfiguration; timeline: TimelineConfiguration | null; captions: CaptionsData | null; keyboard: KeyboardData | null; clips: ClipConfiguration[]; annotations: Annotation[]; screenMotionBlur?: number; screenMovementSpring?: ScreenMovementSpring; 
+/**
+ * Instead of fitting the whole crop into a narrower aspect ratio, show a full-height
+ * window of it that pans to follow the cursor. Turns landscape recordings into
+ * vertical clips without keyframing.
+ */
+autoReframe?: boolean }
 export type ProjectRecordingsMeta = { segments: SegmentRecordings[] }
 export type RecordingAction = "Started"
-export type RecordingCodec = "h264" | "h265"
+export type RecordingCodec = "h264" | "h265" | "av1"
 export type RecordingDeleted = { path: string }
 export type RecordingEvent = { variant: "Countdown"; value: number } | { variant: "Started" } | { variant: "Stopped" } | { variant: "Paused" } | { variant: "Resumed" } | { variant: "Failed"; error: string } | { variant: "InputLost"; input: RecordingInputKind } | { variant: "InputRestored"; input: RecordingInputKind } | { variant: "Degraded"; reason: string } | { variant: "Recovered" }
 export type RecordingInputKind = "microphone" | "camera"
@@ -559,18 +677,43 @@ export type ShowCapWindow = "Setup" | { Main: { init_target_mode: RecordingTarge
 export type SingleSegment = { display: VideoMeta; camera?: VideoMeta | null; audio?: AudioMeta | null; cursor?: string | null }
 export type StartRecordingInputs = { capture_target: ScreenCaptureTarget; capture_system_audio?: boolean; mode: RecordingMode; quality?: RecordingQuality | null }
 export type StereoMode = "stereo" | "monoL" | "monoR"
+export type StoryboardSettings = { 
+/**
+ * Seconds into the export to take the poster frame from. No poster is written when
+ * unset.
+ */
+poster_time_secs?: number | null; 
+/**
+ * Seconds between sprite sheet thumbnails. No sprite sheet or WebVTT track is written
+ * when unset.
+ */
+thumbnail_interval_secs?: number | null; thumbnail_width?: number | null; 
+/**
+ * Thumbnails per row of the sprite sheet.
+ */
+columns?: number | null }
+export type StreamLadder = "Single" | "Standard"
+export type StreamingExportSettings = { fps: number; resolution_base: XY<number>; compression: ExportCompression; custom_bpp?: number | null; ladder?: StreamLadder; 
+/**
+ * Segment length in seconds. Kept to a multiple of the encoder's 3 second keyframe
+ * interval so every segment starts on a keyframe.
+ */
+segment_duration_secs?: number | null; time_range?: ExportTimeRange | null }
 export type StudioRecordingMeta = { segment: SingleSegment } | { inner: MultipleSegments }
 export type StudioRecordingStatus = { status: "InProgress" } | { status: "NeedsRemux" } | { status: "Failed"; error: string } | { status: "Complete" }
 export type SystemDiagnostics = { windowsVersion: WindowsVersionInfo | null; gpuInfo: GpuInfoDiag | null; allGpus: AllGpusInfo | null; renderingStatus: RenderingStatus; availableEncoders: string[]; graphicsCaptureSupported: boolean; d3D11VideoProcessorAvailable: boolean }
 export type TargetUnderCursor = { display_id: DisplayId | null; window: WindowUnderCursor | null }
 export type TextSegment = { start: number; end: number; track?: number; enabled?: boolean; content?: string; center?: XY<number>; size?: XY<number>; fontFamily?: string; fontSize?: number; fontWeight?: number; italic?: boolean; color?: string; fadeDuration?: number }
-export type TimelineConfiguration = { segments: TimelineSegment[]; zoomSegments: ZoomSegment[]; sceneSegments?: SceneSegment[]; maskSegments?: MaskSegment[]; textSegments?: TextSegment[]; captionSegments?: CaptionTrackSegment[]; keyboardSegments?: KeyboardTrackSegment[] }
+export type TimelineConfiguration = { segments: TimelineSegment[]; zoomSegments: ZoomSegment[]; sceneSegments?: SceneSegment[]; maskSegments?: MaskSegment[]; textSegments?: TextSegment[]; captionSegments?: CaptionTrackSegment[]; keyboardSegments?: KeyboardTrackSegment[]; markers?: TimelineMarker[] }
+export type TimelineMarker = { id: string; time: number; title: string }
 export type TimelineSegment = { recordingSegment?: number; timescale: number; start: number; end: number }
 export type TypedJsonValue<T> = [T]
 export type Video = { duration: number; width: number; height: number; fps: number; start_time: number }
 export type VideoImportProgress = { project_path: string; stage: ImportStage; progress: number; message: string }
 export type VideoMeta = { path: string; fps?: number; start_time?: number | null; device_id?: string | null }
 export type VideoRecordingMetadata = { duration: number; size: number }
+export type WebmExportSettings = { fps: number; resolution_base: XY<number>; compression: ExportCompression; custom_bpp: number | null; codec?: WebmVideoCodec; crf?: number | null; audio_stems?: boolean; time_range?: ExportTimeRange | null }
+export type WebmVideoCodec = "Vp9" | "Av1"
 export type WindowExclusion = { bundleIdentifier?: string | null; ownerName?: string | null; windowTitle?: string | null }
 export type WindowId = string
 export type WindowPosition = { x: number; y: number }
@@ -203,6 +203,7 @@ impl EditorInstance {
                     text_segments: Vec::new(),
                     caption_segments: Vec::new(),
                     keyboard_segments: Vec::new(),
+                    markers: Vec::new(),
                 });
 
                 if let Err(e) = project.write(&recording_meta.project_path) {
@@ -4,7 +4,7 @@ mod editor_instance;
 mod playback;
 mod segments;
 
-pub use audio::AudioRenderer;
+pub use audio::{AudioRenderer, AudioSegment};
 pub use editor::EditorFrameOutput;
 pub use editor_instance::{EditorInstance, EditorState, SegmentMedia, create_segments};
-pub use segments::get_audio_segments;
+pub use segments::{AudioStem, get_audio_segments, get_audio_stem_segments, load_audio_segments};
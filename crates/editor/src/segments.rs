@@ -1,3 +1,8 @@
+use std::sync::Arc;
+
+use cap_audio::AudioData;
+use cap_project::{RecordingMeta, StudioRecordingMeta};
+
 use crate::{
     SegmentMedia,
     audio::{AudioSegment, AudioSegmentTrack},
@@ -6,32 +11,108 @@ use crate::{
 pub fn get_audio_segments(segments: &[SegmentMedia]) -> Vec<AudioSegment> {
     segments
         .iter()
-        .map(|s| AudioSegment {
-            tracks: [
-                s.audio.clone().map(|a| {
-                    AudioSegmentTrack::new(
-                        a,
-                        |c| c.mic_volume_db,
-                        |c| match c.mic_stereo_mode {
-                            cap_project::StereoMode::Stereo => cap_audio::StereoMode::Stereo,
-                            cap_project::StereoMode::MonoL => cap_audio::StereoMode::MonoL,
-                            cap_project::StereoMode::MonoR => cap_audio::StereoMode::MonoR,
-                        },
-                        |o| o.mic,
-                    )
-                }),
-                s.system_audio.clone().map(|a| -> AudioSegmentTrack {
-                    AudioSegmentTrack::new(
-                        a,
-                        |c| c.system_volume_db,
-                        |_| cap_audio::StereoMode::Stereo,
-                        |o| o.system_audio,
-                    )
-                }),
-            ]
-            .into_iter()
-            .flatten()
-            .collect::<Vec<_>>(),
+        .map(|s| audio_segment(s.audio.clone(), s.system_audio.clone()))
+        .collect::<Vec<_>>()
+}
+
+/// One of the sources that [`get_audio_segments`] mixes together.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AudioStem {
+    Mic,
+    SystemAudio,
+}
+
+/// Like [`get_audio_segments`] but with only one source in each segment, so it can be
+/// rendered on its own with the same cuts, offsets and volume as in the mix.
+pub fn get_audio_stem_segments(segments: &[SegmentMedia], stem: AudioStem) -> Vec<AudioSegment> {
+    segments
+        .iter()
+        .map(|s| match stem {
+            AudioStem::Mic => audio_segment(s.audio.clone(), None),
+            AudioStem::SystemAudio => audio_segment(None, s.system_audio.clone()),
         })
         .collect::<Vec<_>>()
 }
+
+/// Loads only the audio of each recording segment, without opening any video decoders.
+pub fn load_audio_segments(
+    recording_meta: &RecordingMeta,
+    meta: &StudioRecordingMeta,
+) -> Result<Vec<AudioSegment>, String> {
+    match meta {
+        StudioRecordingMeta::SingleSegment { segment: s } => {
+            let audio = s
+                .audio
+                .as_ref()
+                .map(|audio_meta| {
+                    AudioData::from_file(recording_meta.path(&audio_meta.path))
+                        .map_err(|e| format!("SingleSegment Audio / {e}"))
+                })
+                .transpose()?
+                .map(Arc::new);
+
+            Ok(vec![audio_segment(audio, None)])
+        }
+        StudioRecordingMeta::MultipleSegments { inner, .. } => inner
+            .segments
+            .iter()
+            .enumerate()
+            .map(|(i, s)| {
+                let audio = s
+                    .mic
+                    .as_ref()
+                    .map(|audio| {
+                        AudioData::from_file(recording_meta.path(&audio.path))
+                            .map_err(|e| format!("MultipleSegments {i} Audio / {e}"))
+                    })
+                    .transpose()?
+                    .map(Arc::new);
+
+                let system_audio = s
+                    .system_audio
+                    .as_ref()
+                    .map(|audio| {
+                        AudioData::from_file(recording_meta.path(&audio.path))
+                            .map_err(|e| format!("MultipleSegments {i} System Audio / {e}"))
+                    })
+                    .transpose()?
+                    .map(Arc::new);
+
+                Ok(audio_segment(audio, system_audio))
+            })
+            .collect(),
+    }
+}
+
+fn audio_segment(
+    audio: Option<Arc<AudioData>>,
+    system_audio: Option<Arc<AudioData>>,
+) -> AudioSegment {
+    AudioSegment {
+        tracks: [
+            audio.map(|a| {
+                AudioSegmentTrack::new(
+                    a,
+                    |c| c.mic_volume_db,
+                    |c| match c.mic_stereo_mode {
+                        cap_project::StereoMode::Stereo => cap_audio::StereoMode::Stereo,
+                        cap_project::StereoMode::MonoL => cap_audio::StereoMode::MonoL,
+                        cap_project::StereoMode::MonoR => cap_audio::StereoMode::MonoR,
+                    },
+                    |o| o.mic,
+                )
+            }),
+            system_audio.map(|a| -> AudioSegmentTrack {
+                AudioSegmentTrack::new(
+                    a,
+                    |c| c.system_volume_db,
+                    |_| cap_audio::StereoMode::Stereo,
+                    |o| o.system_audio,
+                )
+            }),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>(),
+    }
+}
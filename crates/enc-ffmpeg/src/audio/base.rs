@@ -10,6 +10,9 @@ pub struct AudioEncoderBase {
 }
 
 impl AudioEncoderBase {
+    /// Chunk size used for encoders that accept any frame size (PCM), which report 0.
+    const VARIABLE_FRAME_SIZE: usize = 1024;
+
     pub fn new(encoder: encoder::Audio, resampler: BufferedResampler, stream_index: usize) -> Self {
         Self {
             inner: EncoderBase::new(stream_index),
@@ -29,7 +32,8 @@ impl AudioEncoderBase {
 
         self.resampler.add_frame(frame);
 
-        while let Some(frame) = self.resampler.get_frame(self.encoder.frame_size() as usize) {
+        let frame_size = self.frame_size();
+        while let Some(frame) = self.resampler.get_frame(frame_size) {
             self.inner.send_frame(&frame, output, &mut self.encoder)?;
         }
 
@@ -37,10 +41,18 @@ impl AudioEncoderBase {
     }
 
     pub fn flush(&mut self, output: &mut format::context::Output) -> Result<(), ffmpeg::Error> {
-        while let Some(frame) = self.resampler.flush(self.encoder.frame_size() as usize) {
+        let frame_size = self.frame_size();
+        while let Some(frame) = self.resampler.flush(frame_size) {
             self.inner.send_frame(&frame, output, &mut self.encoder)?;
         }
 
         self.inner.process_eof(output, &mut self.encoder)
     }
+
+    fn frame_size(&self) -> usize {
+        match self.encoder.frame_size() {
+            0 => Self::VARIABLE_FRAME_SIZE,
+            size => size as usize,
+        }
+    }
 }
@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+use cap_media_info::{AudioInfo, FFRational};
+use ffmpeg::{
+    codec::{context, encoder},
+    format::{self, Sample, sample::Type},
+    frame,
+};
+
+use crate::audio::{
+    audio_encoder::AudioEncoder, base::AudioEncoderBase, buffered_resampler::BufferedResampler,
+};
+
+/// Lossless 16-bit FLAC.
+pub struct FlacEncoder {
+    base: AudioEncoderBase,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum FlacEncoderError {
+    #[error("{0:?}")]
+    FFmpeg(#[from] ffmpeg::Error),
+    #[error("FLAC codec not found")]
+    CodecNotFound,
+    #[error("Resampler: {0}")]
+    Resampler(ffmpeg::Error),
+}
+
+impl FlacEncoder {
+    const SAMPLE_FORMAT: Sample = Sample::I16(Type::Packed);
+
+    pub fn factory(
+        input_config: AudioInfo,
+    ) -> impl FnOnce(&mut format::context::Output) -> Result<Self, FlacEncoderError> {
+        move |o| Self::init(input_config, o)
+    }
+
+    pub fn init(
+        input_config: AudioInfo,
+        output: &mut format::context::Output,
+    ) -> Result<Self, FlacEncoderError> {
+        let codec = encoder::find_by_name("flac").ok_or(FlacEncoderError::CodecNotFound)?;
+        let encoder_ctx = context::Context::new_with_codec(codec);
+        let mut encoder = encoder_ctx.encoder().audio()?;
+
+        let mut output_config = input_config;
+        output_config.sample_format = Self::SAMPLE_FORMAT;
+
+        let resampler = BufferedResampler::new(input_config, output_config)
+            .map_err(FlacEncoderError::Resampler)?;
+
+        encoder.set_rate(output_config.rate());
+        encoder.set_format(output_config.sample_format);
+        encoder.set_channel_layout(output_config.channel_layout());
+        encoder.set_time_base(FFRational(1, output_config.rate()));
+
+        let encoder = encoder.open()?;
+
+        let mut output_stream = output.add_stream(codec)?;
+        let stream_index = output_stream.index();
+        output_stream.set_time_base(FFRational(1, output_config.rate()));
+        output_stream.set_parameters(&encoder);
+
+        Ok(Self {
+            base: AudioEncoderBase::new(encoder, resampler, stream_index),
+        })
+    }
+
+    pub fn queue_frame(
+        &mut self,
+        frame: frame::Audio,
+        timestamp: Duration,
+        output: &mut format::context::Output,
+    ) -> Result<(), ffmpeg::Error> {
+        self.base.send_frame(frame, timestamp, output)
+    }
+
+    pub fn flush(&mut self, output: &mut format::context::Output) -> Result<(), ffmpeg::Error> {
+        self.base.flush(output)
+    }
+}
+
+impl AudioEncoder for FlacEncoder {
+    fn send_frame(&mut self, frame: frame::Audio, output: &mut format::context::Output) {
+        let _ = self.queue_frame(frame, Duration::MAX, output);
+    }
+
+    fn flush(&mut self, output: &mut format::context::Output) -> Result<(), ffmpeg::Error> {
+        self.flush(output)
+    }
+}
@@ -6,4 +6,6 @@ mod base;
 pub mod buffered_resampler;
 
 pub mod aac;
+pub mod flac;
 pub mod opus;
+pub mod pcm;
@@ -1,16 +1,18 @@
-use std::time::Duration;
+use std::{collections::VecDeque, time::Duration};
 
 use ffmpeg::{
     Packet,
     codec::encoder,
     format::{self},
-    frame,
+    frame, picture,
 };
 
 pub struct EncoderBase {
     packet: ffmpeg::Packet,
     stream_index: usize,
     first_pts: Option<i64>,
+    forced_keyframes: VecDeque<i64>,
+    marked_keyframe: bool,
 }
 
 impl EncoderBase {
@@ -19,9 +21,45 @@ impl EncoderBase {
             packet: Packet::empty(),
             first_pts: None,
             stream_index,
+            forced_keyframes: VecDeque::new(),
+            marked_keyframe: false,
         }
     }
 
+    /// Queues keyframes at these pts, in the encoder time base and relative to the first
+    /// frame, for [`Self::mark_forced_keyframe`] to apply.
+    pub(crate) fn force_keyframes(&mut self, pts: impl IntoIterator<Item = i64>) {
+        self.forced_keyframes.extend(pts);
+        self.forced_keyframes.make_contiguous().sort_unstable();
+        self.forced_keyframes.dedup();
+    }
+
+    /// Flags `frame` as a keyframe if it's the first at or after the next forced keyframe.
+    /// Call after [`Self::update_pts`] on the frame actually sent. Frames are often reused,
+    /// so the flag is cleared again on the next one.
+    pub(crate) fn mark_forced_keyframe(&mut self, frame: &mut frame::Video) {
+        let Some(pts) = frame.pts() else {
+            return;
+        };
+
+        let mut forced = false;
+        while self
+            .forced_keyframes
+            .front()
+            .is_some_and(|&next| next <= pts)
+        {
+            self.forced_keyframes.pop_front();
+            forced = true;
+        }
+
+        if forced {
+            frame.set_kind(picture::Type::I);
+        } else if self.marked_keyframe {
+            frame.set_kind(picture::Type::None);
+        }
+        self.marked_keyframe = forced;
+    }
+
     pub fn update_pts(
         &mut self,
         frame: &mut frame::Frame,
@@ -0,0 +1,110 @@
+use ffmpeg::{format, frame};
+use std::{path::PathBuf, time::Duration};
+use tracing::*;
+
+use crate::{
+    mux::segmented_stream::set_muxer_options,
+    video::animated::{self, AnimatedImageCodec, AnimatedImageEncoder, AnimatedImageEncoderError},
+};
+
+/// Animated WebP or APNG file. Neither format carries audio.
+pub struct AnimatedImageFile {
+    output: format::context::Output,
+    video: AnimatedImageEncoder,
+    is_finished: bool,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum InitError {
+    #[error("{0:?}")]
+    Ffmpeg(ffmpeg::Error),
+    #[error("Video/{0}")]
+    VideoInit(AnimatedImageEncoderError),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum FinishError {
+    #[error("Already finished")]
+    AlreadyFinished,
+    #[error("{0}")]
+    WriteTrailerFailed(ffmpeg::Error),
+}
+
+pub struct FinishResult {
+    pub video_finish: Result<(), ffmpeg::Error>,
+}
+
+impl AnimatedImageFile {
+    /// `loop_count` is how many times the animation plays, `None` loops forever.
+    pub fn init(
+        mut output: PathBuf,
+        codec: AnimatedImageCodec,
+        loop_count: Option<u16>,
+        video: impl FnOnce(
+            &mut format::context::Output,
+        ) -> Result<AnimatedImageEncoder, AnimatedImageEncoderError>,
+    ) -> Result<Self, InitError> {
+        output.set_extension(codec.extension());
+
+        if let Some(parent) = output.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+
+        let mut output = format::output_as(&output, codec.muxer()).map_err(InitError::Ffmpeg)?;
+
+        trace!("Preparing encoder for {codec:?} file");
+
+        let video = video(&mut output).map_err(InitError::VideoInit)?;
+
+        let plays = loop_count.unwrap_or(0).to_string();
+        set_muxer_options(&mut output, &[(codec.loop_option(), &plays)]);
+
+        info!("Prepared encoder for {codec:?} file");
+
+        output.write_header().map_err(InitError::Ffmpeg)?;
+
+        Ok(Self {
+            output,
+            video,
+            is_finished: false,
+        })
+    }
+
+    pub fn queue_video_frame_reusable(
+        &mut self,
+        frame: &mut frame::Video,
+        converted_frame: &mut Option<frame::Video>,
+        timestamp: Duration,
+    ) -> Result<(), animated::QueueFrameError> {
+        if self.is_finished {
+            return Ok(());
+        }
+
+        self.video
+            .queue_frame_reusable(frame, converted_frame, timestamp, &mut self.output)
+    }
+
+    pub fn finish(&mut self) -> Result<FinishResult, FinishError> {
+        if self.is_finished {
+            return Err(FinishError::AlreadyFinished);
+        }
+
+        self.is_finished = true;
+
+        let video_finish = self.video.flush(&mut self.output).inspect_err(|e| {
+            error!("Failed to finish video encoder: {e:#}");
+        });
+
+        self.output
+            .write_trailer()
+            .map_err(FinishError::WriteTrailerFailed)?;
+
+        Ok(FinishResult { video_finish })
+    }
+}
+
+impl Drop for AnimatedImageFile {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
@@ -0,0 +1,88 @@
+use ffmpeg::{format, frame};
+use std::path::PathBuf;
+use tracing::*;
+
+use crate::audio::AudioEncoder;
+
+/// A single audio stream in whichever container the output path's extension selects
+/// (`.wav`, `.flac`, `.m4a`, `.opus`, ...).
+pub struct AudioFile {
+    output: format::context::Output,
+    encoder: Box<dyn AudioEncoder + Send>,
+    is_finished: bool,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum InitError {
+    #[error("{0:?}")]
+    Ffmpeg(ffmpeg::Error),
+    #[error("Audio/{0}")]
+    AudioInit(Box<dyn std::error::Error>),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum FinishError {
+    #[error("Already finished")]
+    AlreadyFinished,
+    #[error("{0}")]
+    WriteTrailerFailed(ffmpeg::Error),
+}
+
+impl AudioFile {
+    pub fn init(
+        output: PathBuf,
+        encoder: impl FnOnce(
+            &mut format::context::Output,
+        )
+            -> Result<Box<dyn AudioEncoder + Send>, Box<dyn std::error::Error>>,
+    ) -> Result<Self, InitError> {
+        if let Some(parent) = output.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+
+        let mut output = format::output(&output).map_err(InitError::Ffmpeg)?;
+
+        let encoder = encoder(&mut output).map_err(InitError::AudioInit)?;
+
+        // make sure this happens after adding all encoders!
+        output.write_header().map_err(InitError::Ffmpeg)?;
+
+        Ok(Self {
+            output,
+            encoder,
+            is_finished: false,
+        })
+    }
+
+    pub fn queue_frame(&mut self, frame: frame::Audio) {
+        if self.is_finished {
+            return;
+        }
+
+        self.encoder.send_frame(frame, &mut self.output);
+    }
+
+    pub fn finish(&mut self) -> Result<Result<(), ffmpeg::Error>, FinishError> {
+        if self.is_finished {
+            return Err(FinishError::AlreadyFinished);
+        }
+
+        self.is_finished = true;
+
+        let flush_result = self.encoder.flush(&mut self.output).inspect_err(|e| {
+            error!("Failed to finish audio encoder: {e:#}");
+        });
+
+        self.output
+            .write_trailer()
+            .map_err(FinishError::WriteTrailerFailed)?;
+
+        Ok(flush_result)
+    }
+}
+
+impl Drop for AudioFile {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
@@ -1,5 +1,10 @@
+pub mod animated_image;
+pub mod audio_file;
 pub mod fragmented_audio;
+pub mod mov;
 pub mod mp4;
 pub mod ogg;
+pub mod packaged_stream;
 pub mod segmented_audio;
 pub mod segmented_stream;
+pub mod webm;
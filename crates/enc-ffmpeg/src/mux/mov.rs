@@ -0,0 +1,141 @@
+use ffmpeg::{format, frame};
+use std::{path::PathBuf, time::Duration};
+use tracing::*;
+
+use crate::{
+    audio::AudioEncoder,
+    video::prores::{self, ProResEncoder, ProResEncoderError},
+};
+
+/// QuickTime file holding a ProRes video track, for footage that's going to be edited
+/// further rather than played back.
+pub struct MovFile {
+    output: format::context::Output,
+    video: ProResEncoder,
+    audio: Option<Box<dyn AudioEncoder + Send>>,
+    is_finished: bool,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum InitError {
+    #[error("{0:?}")]
+    Ffmpeg(ffmpeg::Error),
+    #[error("Video/{0}")]
+    VideoInit(ProResEncoderError),
+    #[error("Audio/{0}")]
+    AudioInit(Box<dyn std::error::Error>),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum FinishError {
+    #[error("Already finished")]
+    AlreadyFinished,
+    #[error("{0}")]
+    WriteTrailerFailed(ffmpeg::Error),
+}
+
+pub struct FinishResult {
+    pub video_finish: Result<(), ffmpeg::Error>,
+    pub audio_finish: Result<(), ffmpeg::Error>,
+}
+
+impl MovFile {
+    pub fn init(
+        mut output: PathBuf,
+        video: impl FnOnce(&mut format::context::Output) -> Result<ProResEncoder, ProResEncoderError>,
+        audio: impl FnOnce(
+            &mut format::context::Output,
+        )
+            -> Option<Result<Box<dyn AudioEncoder + Send>, Box<dyn std::error::Error>>>,
+    ) -> Result<Self, InitError> {
+        output.set_extension("mov");
+
+        if let Some(parent) = output.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+
+        let mut output = format::output(&output).map_err(InitError::Ffmpeg)?;
+
+        trace!("Preparing encoders for mov file");
+
+        let video = video(&mut output).map_err(InitError::VideoInit)?;
+        let audio = audio(&mut output)
+            .transpose()
+            .map_err(InitError::AudioInit)?;
+
+        info!("Prepared encoders for mov file");
+
+        // make sure this happens after adding all encoders!
+        output.write_header().map_err(InitError::Ffmpeg)?;
+
+        Ok(Self {
+            output,
+            video,
+            audio,
+            is_finished: false,
+        })
+    }
+
+    pub fn queue_video_frame_reusable(
+        &mut self,
+        frame: &mut frame::Video,
+        converted_frame: &mut Option<frame::Video>,
+        timestamp: Duration,
+    ) -> Result<(), prores::QueueFrameError> {
+        if self.is_finished {
+            return Ok(());
+        }
+
+        self.video
+            .queue_frame_reusable(frame, converted_frame, timestamp, &mut self.output)
+    }
+
+    pub fn queue_audio_frame(&mut self, frame: frame::Audio) {
+        if self.is_finished {
+            return;
+        }
+
+        let Some(audio) = &mut self.audio else {
+            return;
+        };
+
+        audio.send_frame(frame, &mut self.output);
+    }
+
+    pub fn finish(&mut self) -> Result<FinishResult, FinishError> {
+        if self.is_finished {
+            return Err(FinishError::AlreadyFinished);
+        }
+
+        self.is_finished = true;
+
+        let video_finish = self.video.flush(&mut self.output).inspect_err(|e| {
+            error!("Failed to finish video encoder: {e:#}");
+        });
+
+        let audio_finish = self
+            .audio
+            .as_mut()
+            .map(|enc| {
+                enc.flush(&mut self.output).inspect_err(|e| {
+                    error!("Failed to finish audio encoder: {e:#}");
+                })
+            })
+            .unwrap_or(Ok(()));
+
+        self.output
+            .write_trailer()
+            .map_err(FinishError::WriteTrailerFailed)?;
+
+        Ok(FinishResult {
+            video_finish,
+            audio_finish,
+        })
+    }
+}
+
+impl Drop for MovFile {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
@@ -1,34 +1,223 @@
 use cap_media_info::RawVideoFormat;
-use ffmpeg::{format, frame};
-use std::{path::PathBuf, time::Duration};
+use ffmpeg::{Dictionary, Packet, Rational, codec, format, frame, packet};
+use std::{ffi::CString, path::PathBuf, time::Duration};
 use tracing::*;
 
 use crate::{
     audio::AudioEncoder,
-    h264, hevc,
+    av1, h264, hevc,
+    mux::segmented_stream::set_muxer_options,
+    video::av1::{Av1Encoder, Av1EncoderError},
     video::h264::{H264Encoder, H264EncoderError},
     video::hevc::{HevcEncoder, HevcEncoderError},
 };
 
-pub struct MP4File {
+/// A video encoder [`MP4File`] can mux.
+pub trait MP4VideoEncoder: Sized {
+    type InitError: std::error::Error;
+    type QueueFrameError;
+
+    /// Used in log messages.
+    const NAME: &'static str;
+
+    /// The frame format to feed the encoder.
+    fn video_format() -> RawVideoFormat;
+
+    fn queue_frame(
+        &mut self,
+        frame: frame::Video,
+        timestamp: Duration,
+        output: &mut format::context::Output,
+    ) -> Result<(), Self::QueueFrameError>;
+
+    fn queue_frame_reusable(
+        &mut self,
+        frame: &mut frame::Video,
+        converted_frame: &mut Option<frame::Video>,
+        timestamp: Duration,
+        output: &mut format::context::Output,
+    ) -> Result<(), Self::QueueFrameError>;
+
+    fn flush(&mut self, output: &mut format::context::Output) -> Result<(), ffmpeg::Error>;
+}
+
+macro_rules! impl_mp4_video_encoder {
+    ($encoder:ty, $init_error:ty, $queue_error:ty, $name:literal, $format:expr) => {
+        impl MP4VideoEncoder for $encoder {
+            type InitError = $init_error;
+            type QueueFrameError = $queue_error;
+
+            const NAME: &'static str = $name;
+
+            fn video_format() -> RawVideoFormat {
+                $format
+            }
+
+            fn queue_frame(
+                &mut self,
+                frame: frame::Video,
+                timestamp: Duration,
+                output: &mut format::context::Output,
+            ) -> Result<(), Self::QueueFrameError> {
+                <$encoder>::queue_frame(self, frame, timestamp, output)
+            }
+
+            fn queue_frame_reusable(
+                &mut self,
+                frame: &mut frame::Video,
+                converted_frame: &mut Option<frame::Video>,
+                timestamp: Duration,
+                output: &mut format::context::Output,
+            ) -> Result<(), Self::QueueFrameError> {
+                <$encoder>::queue_frame_reusable(self, frame, converted_frame, timestamp, output)
+            }
+
+            fn flush(&mut self, output: &mut format::context::Output) -> Result<(), ffmpeg::Error> {
+                <$encoder>::flush(self, output)
+            }
+        }
+    };
+}
+
+impl_mp4_video_encoder!(
+    H264Encoder,
+    H264EncoderError,
+    h264::QueueFrameError,
+    "H.264",
+    RawVideoFormat::Yuv420p
+);
+impl_mp4_video_encoder!(
+    HevcEncoder,
+    HevcEncoderError,
+    hevc::QueueFrameError,
+    "HEVC",
+    RawVideoFormat::Nv12
+);
+impl_mp4_video_encoder!(
+    Av1Encoder,
+    Av1EncoderError,
+    av1::QueueFrameError,
+    "AV1",
+    RawVideoFormat::Yuv420p
+);
+
+pub struct MP4File<V: MP4VideoEncoder = H264Encoder> {
     #[allow(unused)]
     tag: &'static str,
     output: format::context::Output,
-    video: H264Encoder,
+    video: V,
     audio: Option<Box<dyn AudioEncoder + Send>>,
+    audio_stems: Vec<Box<dyn AudioEncoder + Send>>,
+    subtitles: Vec<MovTextStream>,
     is_finished: bool,
 }
 
+pub type HevcMP4File = MP4File<HevcEncoder>;
+
+pub type Av1MP4File = MP4File<Av1Encoder>;
+
+#[derive(Clone, Debug)]
+pub struct SubtitleCue {
+    pub start: Duration,
+    pub end: Duration,
+    pub text: String,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SubtitleTrack {
+    /// ISO 639-2 language code, e.g. `eng`. Tagged as `und` when unset.
+    pub language: Option<String>,
+    pub title: Option<String>,
+    pub cues: Vec<SubtitleCue>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Chapter {
+    pub start: Duration,
+    pub end: Duration,
+    pub title: String,
+}
+
+/// Container-level tags, written to the `udta` box so files can be indexed without a
+/// sidecar.
+#[derive(Clone, Debug, Default)]
+pub struct ContainerMetadata {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub comment: Option<String>,
+    /// ISO 8601 in UTC, e.g. `2024-11-15T16:35:36Z`. Also sets the movie header's creation
+    /// time.
+    pub creation_time: Option<String>,
+    pub encoder: Option<String>,
+    /// Arbitrary keys. When there are any, every tag is written as a QuickTime `mdta` key
+    /// instead of an iTunes-style atom, since those only exist for a fixed set of keys.
+    pub tags: Vec<(String, String)>,
+}
+
+impl ContainerMetadata {
+    /// Carries an existing file's tags over to a new one. The brand keys are dropped since
+    /// the muxer derives those itself.
+    pub(crate) fn from_tags<'a>(tags: impl IntoIterator<Item = (&'a str, &'a str)>) -> Self {
+        let mut metadata = Self::default();
+
+        for (key, value) in tags {
+            let slot = match key {
+                "title" => &mut metadata.title,
+                "artist" => &mut metadata.artist,
+                "comment" => &mut metadata.comment,
+                "creation_time" => &mut metadata.creation_time,
+                "encoder" => &mut metadata.encoder,
+                "major_brand" | "minor_version" | "compatible_brands" => continue,
+                key => {
+                    metadata.tags.push((key.to_string(), value.to_string()));
+                    continue;
+                }
+            };
+            *slot = Some(value.to_string());
+        }
+
+        metadata
+    }
+
+    fn entries(&self) -> impl Iterator<Item = (&str, &str)> {
+        [
+            ("title", &self.title),
+            ("artist", &self.artist),
+            ("comment", &self.comment),
+            ("creation_time", &self.creation_time),
+            ("encoder", &self.encoder),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| value.as_deref().map(|value| (key, value)))
+        .chain(self.tags.iter().map(|(k, v)| (k.as_str(), v.as_str())))
+    }
+}
+
+/// Extra streams and tags muxed alongside the audio and video.
+#[derive(Clone, Debug, Default)]
+pub struct Mp4FileOptions {
+    pub subtitles: Vec<SubtitleTrack>,
+    pub chapters: Vec<Chapter>,
+    pub metadata: ContainerMetadata,
+    /// Titles for the audio streams, in the order they're added. Only the first audio
+    /// stream is played by default.
+    pub audio_titles: Vec<String>,
+}
+
 #[derive(thiserror::Error, Debug)]
-pub enum InitError {
+pub enum InitError<E = H264EncoderError> {
     #[error("{0:?}")]
     Ffmpeg(ffmpeg::Error),
     #[error("Video/{0}")]
-    VideoInit(H264EncoderError),
+    VideoInit(E),
     #[error("Audio/{0}")]
     AudioInit(Box<dyn std::error::Error>),
 }
 
+pub type HevcInitError = InitError<HevcEncoderError>;
+
+pub type Av1InitError = InitError<Av1EncoderError>;
+
 #[derive(thiserror::Error, Debug)]
 pub enum FinishError {
     #[error("Already finished")]
@@ -42,16 +231,50 @@ pub struct FinishResult {
     pub audio_finish: Result<(), ffmpeg::Error>,
 }
 
-impl MP4File {
+impl<V: MP4VideoEncoder> MP4File<V> {
     pub fn init(
+        tag: &'static str,
+        output: PathBuf,
+        video: impl FnOnce(&mut format::context::Output) -> Result<V, V::InitError>,
+        audio: impl FnOnce(
+            &mut format::context::Output,
+        )
+            -> Option<Result<Box<dyn AudioEncoder + Send>, Box<dyn std::error::Error>>>,
+    ) -> Result<Self, InitError<V::InitError>> {
+        Self::init_with_options(tag, output, Mp4FileOptions::default(), video, audio)
+    }
+
+    pub fn init_with_options(
+        tag: &'static str,
+        output: PathBuf,
+        options: Mp4FileOptions,
+        video: impl FnOnce(&mut format::context::Output) -> Result<V, V::InitError>,
+        audio: impl FnOnce(
+            &mut format::context::Output,
+        )
+            -> Option<Result<Box<dyn AudioEncoder + Send>, Box<dyn std::error::Error>>>,
+    ) -> Result<Self, InitError<V::InitError>> {
+        Self::init_with_audio_stems(tag, output, options, video, audio, |_| Ok(vec![]))
+    }
+
+    /// Like [`Self::init_with_options`], with `audio_stems` muxed as extra audio streams
+    /// after the main one. Frames for them go through [`Self::queue_audio_stem_frame`].
+    pub fn init_with_audio_stems(
         tag: &'static str,
         mut output: PathBuf,
-        video: impl FnOnce(&mut format::context::Output) -> Result<H264Encoder, H264EncoderError>,
+        options: Mp4FileOptions,
+        video: impl FnOnce(&mut format::context::Output) -> Result<V, V::InitError>,
         audio: impl FnOnce(
             &mut format::context::Output,
         )
             -> Option<Result<Box<dyn AudioEncoder + Send>, Box<dyn std::error::Error>>>,
-    ) -> Result<Self, InitError> {
+        audio_stems: impl FnOnce(
+            &mut format::context::Output,
+        ) -> Result<
+            Vec<Box<dyn AudioEncoder + Send>>,
+            Box<dyn std::error::Error>,
+        >,
+    ) -> Result<Self, InitError<V::InitError>> {
         output.set_extension("mp4");
 
         if let Some(parent) = output.parent() {
@@ -60,36 +283,50 @@ impl MP4File {
 
         let mut output = format::output(&output).map_err(InitError::Ffmpeg)?;
 
-        trace!("Preparing encoders for mp4 file");
+        trace!("Preparing {} encoders for mp4 file", V::NAME);
 
         let video = video(&mut output).map_err(InitError::VideoInit)?;
         let audio = audio(&mut output)
             .transpose()
             .map_err(InitError::AudioInit)?;
-
-        info!("Prepared encoders for mp4 file");
+        let audio_stems = audio_stems(&mut output).map_err(InitError::AudioInit)?;
+        label_audio_streams(&mut output, &options.audio_titles);
+        let subtitles = options
+            .subtitles
+            .into_iter()
+            .map(|track| MovTextStream::add(&mut output, track))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(InitError::Ffmpeg)?;
+        add_chapters(&mut output, &options.chapters).map_err(InitError::Ffmpeg)?;
+        set_container_metadata(&mut output, &options.metadata);
+        signal_hdr_video(&mut output);
+
+        info!("Prepared {} encoders for mp4 file", V::NAME);
 
         // make sure this happens after adding all encoders!
         output.write_header().map_err(InitError::Ffmpeg)?;
+        set_encoder_tag(&mut output, &options.metadata);
 
         Ok(Self {
             tag,
             output,
             video,
             audio,
+            audio_stems,
+            subtitles,
             is_finished: false,
         })
     }
 
     pub fn video_format() -> RawVideoFormat {
-        RawVideoFormat::Yuv420p
+        V::video_format()
     }
 
     pub fn queue_video_frame(
         &mut self,
         frame: frame::Video,
         timestamp: Duration,
-    ) -> Result<(), h264::QueueFrameError> {
+    ) -> Result<(), V::QueueFrameError> {
         if self.is_finished {
             return Ok(());
         }
@@ -102,7 +339,7 @@ impl MP4File {
         frame: &mut frame::Video,
         converted_frame: &mut Option<frame::Video>,
         timestamp: Duration,
-    ) -> Result<(), h264::QueueFrameError> {
+    ) -> Result<(), V::QueueFrameError> {
         if self.is_finished {
             return Ok(());
         }
@@ -123,6 +360,18 @@ impl MP4File {
         audio.send_frame(frame, &mut self.output);
     }
 
+    pub fn queue_audio_stem_frame(&mut self, index: usize, frame: frame::Audio) {
+        if self.is_finished {
+            return;
+        }
+
+        let Some(audio) = self.audio_stems.get_mut(index) else {
+            return;
+        };
+
+        audio.send_frame(frame, &mut self.output);
+    }
+
     pub fn finish(&mut self) -> Result<FinishResult, FinishError> {
         if self.is_finished {
             return Err(FinishError::AlreadyFinished);
@@ -130,10 +379,10 @@ impl MP4File {
 
         self.is_finished = true;
 
-        tracing::info!("MP4Encoder: Finishing encoding");
+        tracing::info!("MP4Encoder: Finishing {} encoding", V::NAME);
 
         let video_finish = self.video.flush(&mut self.output).inspect_err(|e| {
-            error!("Failed to finish video encoder: {e:#}");
+            error!("Failed to finish {} video encoder: {e:#}", V::NAME);
         });
 
         let audio_finish = self
@@ -145,7 +394,14 @@ impl MP4File {
                     error!("Failed to finish audio encoder: {e:#}");
                 })
             })
-            .unwrap_or(Ok(()));
+            .unwrap_or(Ok(()))
+            .and(flush_audio_stems(&mut self.audio_stems, &mut self.output));
+
+        for subtitles in &self.subtitles {
+            if let Err(e) = subtitles.write(&mut self.output) {
+                error!("Failed to write subtitle track: {e:#}");
+            }
+        }
 
         tracing::info!("MP4Encoder: Writing trailer");
         self.output
@@ -158,16 +414,23 @@ impl MP4File {
         })
     }
 
-    pub fn video(&self) -> &H264Encoder {
+    pub fn video(&self) -> &V {
         &self.video
     }
 
-    pub fn video_mut(&mut self) -> &mut H264Encoder {
+    pub fn video_mut(&mut self) -> &mut V {
         &mut self.video
     }
 }
 
-impl Drop for MP4File {
+impl HevcMP4File {
+    /// The frame format to feed an encoder built `with_hdr`.
+    pub fn hdr_video_format() -> RawVideoFormat {
+        RawVideoFormat::P010
+    }
+}
+
+impl<V: MP4VideoEncoder> Drop for MP4File<V> {
     fn drop(&mut self) {
         let _ = self.finish();
     }
@@ -180,145 +443,271 @@ pub struct MP4Input {
 
 unsafe impl Send for H264Encoder {}
 
-pub struct HevcMP4File {
-    #[allow(unused)]
-    tag: &'static str,
-    output: format::context::Output,
-    video: HevcEncoder,
-    audio: Option<Box<dyn AudioEncoder + Send>>,
-    is_finished: bool,
-}
-
-#[derive(thiserror::Error, Debug)]
-pub enum HevcInitError {
-    #[error("{0:?}")]
-    Ffmpeg(ffmpeg::Error),
-    #[error("Video/{0}")]
-    VideoInit(HevcEncoderError),
-    #[error("Audio/{0}")]
-    AudioInit(Box<dyn std::error::Error>),
-}
+unsafe impl Send for HevcEncoder {}
 
-impl HevcMP4File {
-    pub fn init(
-        tag: &'static str,
-        mut output: PathBuf,
-        video: impl FnOnce(&mut format::context::Output) -> Result<HevcEncoder, HevcEncoderError>,
-        audio: impl FnOnce(
-            &mut format::context::Output,
-        )
-            -> Option<Result<Box<dyn AudioEncoder + Send>, Box<dyn std::error::Error>>>,
-    ) -> Result<Self, HevcInitError> {
-        output.set_extension("mp4");
+pub(crate) fn flush_audio_stems(
+    audio_stems: &mut [Box<dyn AudioEncoder + Send>],
+    output: &mut format::context::Output,
+) -> Result<(), ffmpeg::Error> {
+    let mut result = Ok(());
 
-        if let Some(parent) = output.parent() {
-            let _ = std::fs::create_dir_all(parent);
+    for (index, enc) in audio_stems.iter_mut().enumerate() {
+        if let Err(e) = enc.flush(output) {
+            error!("Failed to finish audio stem {index} encoder: {e:#}");
+            result = result.and(Err(e));
         }
+    }
 
-        let mut output = format::output(&output).map_err(HevcInitError::Ffmpeg)?;
-
-        trace!("Preparing HEVC encoders for mp4 file");
+    result
+}
 
-        let video = video(&mut output).map_err(HevcInitError::VideoInit)?;
-        let audio = audio(&mut output)
-            .transpose()
-            .map_err(HevcInitError::AudioInit)?;
+/// Titles the audio streams in order and marks only the first as default, so players
+/// don't mix the extra ones in. Works for the Matroska-family muxers as well as MP4. Must
+/// be called before the header is written.
+pub(crate) fn label_audio_streams(output: &mut format::context::Output, titles: &[String]) {
+    if titles.is_empty() {
+        return;
+    }
 
-        info!("Prepared HEVC encoders for mp4 file");
+    let audio_streams = output
+        .streams()
+        .filter(|s| s.parameters().medium() == ffmpeg::media::Type::Audio)
+        .map(|s| s.index())
+        .collect::<Vec<_>>();
 
-        output.write_header().map_err(HevcInitError::Ffmpeg)?;
+    for (position, index) in audio_streams.into_iter().enumerate() {
+        let Some(mut stream) = output.stream_mut(index) else {
+            continue;
+        };
 
-        Ok(Self {
-            tag,
-            output,
-            video,
-            audio,
-            is_finished: false,
-        })
+        let mut metadata = stream.metadata().to_owned();
+        if let Some(title) = titles.get(position) {
+            metadata.set("title", title);
+            metadata.set("handler_name", title);
+        }
+        stream.set_metadata(metadata);
+
+        unsafe {
+            (*stream.as_mut_ptr()).disposition = if position == 0 {
+                ffmpeg::ffi::AV_DISPOSITION_DEFAULT as i32
+            } else {
+                0
+            };
+        }
     }
+}
 
-    pub fn video_format() -> RawVideoFormat {
-        RawVideoFormat::Nv12
+/// Chapters must be registered before the header is written; the mov muxer then emits
+/// both a QuickTime chapter track and a Nero `chpl` atom.
+pub(crate) fn add_chapters(
+    output: &mut format::context::Output,
+    chapters: &[Chapter],
+) -> Result<(), ffmpeg::Error> {
+    for (id, chapter) in chapters.iter().enumerate() {
+        output.add_chapter(
+            id as i64,
+            (1, 1000),
+            chapter.start.as_millis() as i64,
+            chapter.end.as_millis() as i64,
+            &chapter.title,
+        )?;
     }
 
-    pub fn queue_video_frame(
-        &mut self,
-        frame: frame::Video,
-        timestamp: Duration,
-    ) -> Result<(), hevc::QueueFrameError> {
-        if self.is_finished {
-            return Ok(());
-        }
+    Ok(())
+}
 
-        self.video.queue_frame(frame, timestamp, &mut self.output)
+/// Must be called before the header is written, which is when the mov muxer reads
+/// `creation_time` and its flags.
+pub(crate) fn set_container_metadata(
+    output: &mut format::context::Output,
+    metadata: &ContainerMetadata,
+) {
+    for (key, value) in metadata.entries() {
+        set_metadata_entry(output, key, value);
     }
 
-    pub fn queue_video_frame_reusable(
-        &mut self,
-        frame: &mut frame::Video,
-        converted_frame: &mut Option<frame::Video>,
-        timestamp: Duration,
-    ) -> Result<(), hevc::QueueFrameError> {
-        if self.is_finished {
-            return Ok(());
-        }
+    if !metadata.tags.is_empty() {
+        set_muxer_options(output, &[("movflags", "+use_metadata_tags")]);
+    }
+}
 
-        self.video
-            .queue_frame_reusable(frame, converted_frame, timestamp, &mut self.output)
+/// Keeps HDR colour signalling intact in the file: the mov muxer only writes the `colr`
+/// box carrying primaries and transfer when asked, and Apple players only treat HEVC as
+/// HDR under the `hvc1` sample entry. Must be called before the header is written.
+pub(crate) fn signal_hdr_video(output: &mut format::context::Output) {
+    let hdr_streams = output
+        .streams()
+        .filter(|s| {
+            let color_trc = unsafe { (*s.parameters().as_ptr()).color_trc };
+            matches!(
+                color_trc,
+                ffmpeg::ffi::AVColorTransferCharacteristic::AVCOL_TRC_SMPTE2084
+                    | ffmpeg::ffi::AVColorTransferCharacteristic::AVCOL_TRC_ARIB_STD_B67
+            )
+        })
+        .map(|s| s.index())
+        .collect::<Vec<_>>();
+
+    if hdr_streams.is_empty() {
+        return;
     }
 
-    pub fn queue_audio_frame(&mut self, frame: frame::Audio) {
-        if self.is_finished {
-            return;
+    for index in hdr_streams {
+        let Some(mut stream) = output.stream_mut(index) else {
+            continue;
+        };
+
+        if stream.parameters().id() == codec::Id::HEVC {
+            unsafe {
+                (*(*stream.as_mut_ptr()).codecpar).codec_tag = u32::from_le_bytes(*b"hvc1");
+            }
         }
+    }
 
-        let Some(audio) = &mut self.audio else {
-            return;
-        };
+    set_muxer_options(output, &[("movflags", "+write_colr")]);
+}
 
-        audio.send_frame(frame, &mut self.output);
+/// Writing the header replaces `encoder` with libavformat's own ident. The tags are only
+/// serialized with the `moov` box in the trailer, so setting it again afterwards sticks.
+pub(crate) fn set_encoder_tag(output: &mut format::context::Output, metadata: &ContainerMetadata) {
+    if let Some(encoder) = &metadata.encoder {
+        set_metadata_entry(output, "encoder", encoder);
     }
+}
 
-    pub fn finish(&mut self) -> Result<FinishResult, FinishError> {
-        if self.is_finished {
-            return Err(FinishError::AlreadyFinished);
+fn set_metadata_entry(output: &mut format::context::Output, key: &str, value: &str) {
+    let (Ok(key), Ok(value)) = (CString::new(key), CString::new(value)) else {
+        warn!("Skipping metadata tag {key:?} containing a nul byte");
+        return;
+    };
+
+    unsafe {
+        ffmpeg::ffi::av_dict_set(
+            &mut (*output.as_mut_ptr()).metadata,
+            key.as_ptr(),
+            value.as_ptr(),
+            0,
+        );
+    }
+}
+
+pub(crate) struct MovTextStream {
+    stream_index: usize,
+    cues: Vec<SubtitleCue>,
+}
+
+impl MovTextStream {
+    const TIME_BASE: i32 = 1000;
+
+    // tx3g sample description: centered, bottom-aligned white 18pt text, matching what
+    // FFmpeg's own mov_text encoder writes.
+    const SAMPLE_DESCRIPTION: [u8; 48] = [
+        0x00, 0x00, 0x00, 0x00, // display flags
+        0x01, // horizontal justification (center)
+        0xFF, // vertical justification (bottom)
+        0x00, 0x00, 0x00, 0x00, // background color
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // box record
+        0x00, 0x00, 0x00, 0x00, // style record start/end char
+        0x00, 0x01, // font id
+        0x00, // font style flags
+        0x12, // font size
+        0xFF, 0xFF, 0xFF, 0xFF, // text color
+        0x00, 0x00, 0x00, 0x12, b'f', b't', b'a', b'b', // font table box
+        0x00, 0x01, // entry count
+        0x00, 0x01, // font id
+        0x05, b'S', b'e', b'r', b'i', b'f',
+    ];
+
+    pub(crate) fn add(
+        output: &mut format::context::Output,
+        track: SubtitleTrack,
+    ) -> Result<Self, ffmpeg::Error> {
+        let mut stream = output.add_stream(codec::Id::MOV_TEXT)?;
+        let stream_index = stream.index();
+        stream.set_time_base((1, Self::TIME_BASE));
+
+        unsafe {
+            let codecpar = (*stream.as_mut_ptr()).codecpar;
+            (*codecpar).codec_type = ffmpeg::ffi::AVMediaType::AVMEDIA_TYPE_SUBTITLE;
+            (*codecpar).codec_id = ffmpeg::ffi::AVCodecID::AV_CODEC_ID_MOV_TEXT;
+
+            let size = Self::SAMPLE_DESCRIPTION.len();
+            let alloc_size = size + ffmpeg::ffi::AV_INPUT_BUFFER_PADDING_SIZE as usize;
+            let ptr = ffmpeg::ffi::av_mallocz(alloc_size) as *mut u8;
+            if ptr.is_null() {
+                return Err(ffmpeg::Error::Unknown);
+            }
+            std::ptr::copy_nonoverlapping(Self::SAMPLE_DESCRIPTION.as_ptr(), ptr, size);
+            (*codecpar).extradata = ptr;
+            (*codecpar).extradata_size = size as i32;
         }
 
-        self.is_finished = true;
+        let mut metadata = Dictionary::new();
+        metadata.set("language", track.language.as_deref().unwrap_or("und"));
+        if let Some(title) = &track.title {
+            metadata.set("title", title);
+            metadata.set("handler_name", title);
+        }
+        stream.set_metadata(metadata);
 
-        tracing::info!("HevcMP4Encoder: Finishing encoding");
+        let mut cues = track.cues;
+        cues.sort_by_key(|cue| cue.start);
 
-        let video_finish = self.video.flush(&mut self.output).inspect_err(|e| {
-            error!("Failed to finish HEVC video encoder: {e:#}");
-        });
+        Ok(Self { stream_index, cues })
+    }
 
-        let audio_finish = self
-            .audio
-            .as_mut()
-            .map(|enc| {
-                tracing::info!("HevcMP4Encoder: Flushing audio encoder");
-                enc.flush(&mut self.output).inspect_err(|e| {
-                    error!("Failed to finish audio encoder: {e:#}");
-                })
-            })
-            .unwrap_or(Ok(()));
+    /// Writes every cue, filling gaps with empty samples so players clear the previous
+    /// caption instead of holding it until the next one.
+    pub(crate) fn write(&self, output: &mut format::context::Output) -> Result<(), ffmpeg::Error> {
+        let Some(time_base) = output.stream(self.stream_index).map(|s| s.time_base()) else {
+            return Ok(());
+        };
 
-        tracing::info!("HevcMP4Encoder: Writing trailer");
-        self.output
-            .write_trailer()
-            .map_err(FinishError::WriteTrailerFailed)?;
+        let mut cursor = Duration::ZERO;
 
-        Ok(FinishResult {
-            video_finish,
-            audio_finish,
-        })
+        for cue in &self.cues {
+            let start = cue.start.max(cursor);
+            if cue.end <= start {
+                continue;
+            }
+
+            if start > cursor {
+                self.write_sample(output, time_base, cursor, start, "")?;
+            }
+
+            self.write_sample(output, time_base, start, cue.end, &cue.text)?;
+            cursor = cue.end;
+        }
+
+        Ok(())
     }
-}
 
-impl Drop for HevcMP4File {
-    fn drop(&mut self) {
-        let _ = self.finish();
+    fn write_sample(
+        &self,
+        output: &mut format::context::Output,
+        time_base: Rational,
+        start: Duration,
+        end: Duration,
+        text: &str,
+    ) -> Result<(), ffmpeg::Error> {
+        let mut len = text.len().min(u16::MAX as usize);
+        while !text.is_char_boundary(len) {
+            len -= 1;
+        }
+
+        let mut data = Vec::with_capacity(2 + len);
+        data.extend_from_slice(&(len as u16).to_be_bytes());
+        data.extend_from_slice(&text.as_bytes()[..len]);
+
+        let pts = start.as_millis() as i64;
+        let mut packet = Packet::copy(&data);
+        packet.set_stream(self.stream_index);
+        packet.set_pts(Some(pts));
+        packet.set_dts(Some(pts));
+        packet.set_duration((end - start).as_millis() as i64);
+        packet.set_flags(packet::Flags::KEY);
+        packet.rescale_ts(Rational(1, Self::TIME_BASE), time_base);
+
+        packet.write_interleaved(output)
     }
 }
-
-unsafe impl Send for HevcEncoder {}
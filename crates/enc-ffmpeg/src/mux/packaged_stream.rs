@@ -0,0 +1,242 @@
+use cap_media_info::VideoInfo;
+use ffmpeg::{format, frame};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tracing::*;
+
+use crate::{
+    audio::AudioEncoder,
+    mux::segmented_stream::{dash_output, set_muxer_options},
+    video::h264::{self, H264Encoder, H264EncoderError},
+};
+
+/// One rung of the ABR ladder.
+#[derive(Clone, Copy, Debug)]
+pub struct PackagedStreamRendition {
+    pub width: u32,
+    pub height: u32,
+    pub bpp: f32,
+}
+
+pub struct PackagedStreamConfig {
+    /// Target segment length. Segments are cut on keyframes, so this should be a
+    /// multiple of the encoder's keyframe interval to keep renditions aligned.
+    pub segment_duration: Duration,
+    pub renditions: Vec<PackagedStreamRendition>,
+}
+
+/// CMAF fMP4 segments for one or more H.264 renditions plus AAC audio, described by both
+/// a DASH manifest and HLS playlists so the directory can be served as-is.
+pub struct PackagedStream {
+    base_path: PathBuf,
+    output: format::context::Output,
+    videos: Vec<(H264Encoder, Option<frame::Video>)>,
+    audio: Option<Box<dyn AudioEncoder + Send>>,
+    is_finished: bool,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum InitError {
+    #[error("{0:?}")]
+    Ffmpeg(ffmpeg::Error),
+    #[error("IO: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("No renditions to package")]
+    NoRenditions,
+    #[error("Video/{0}")]
+    VideoInit(H264EncoderError),
+    #[error("Audio/{0}")]
+    AudioInit(Box<dyn std::error::Error>),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum FinishError {
+    #[error("Already finished")]
+    AlreadyFinished,
+    #[error("{0}")]
+    WriteTrailerFailed(ffmpeg::Error),
+}
+
+pub struct FinishResult {
+    pub video_finish: Result<(), ffmpeg::Error>,
+    pub audio_finish: Result<(), ffmpeg::Error>,
+}
+
+impl PackagedStream {
+    pub const DASH_MANIFEST_NAME: &str = "manifest.mpd";
+    pub const HLS_MASTER_NAME: &str = "master.m3u8";
+
+    pub fn init(
+        base_path: PathBuf,
+        video_config: VideoInfo,
+        config: PackagedStreamConfig,
+        audio: impl FnOnce(
+            &mut format::context::Output,
+        )
+            -> Option<Result<Box<dyn AudioEncoder + Send>, Box<dyn std::error::Error>>>,
+    ) -> Result<Self, InitError> {
+        if config.renditions.is_empty() {
+            return Err(InitError::NoRenditions);
+        }
+
+        std::fs::create_dir_all(&base_path)?;
+
+        let mut output =
+            dash_output(&base_path.join(Self::DASH_MANIFEST_NAME)).map_err(InitError::Ffmpeg)?;
+
+        let videos = config
+            .renditions
+            .iter()
+            .map(|rendition| {
+                H264Encoder::builder(video_config)
+                    .with_bpp(rendition.bpp)
+                    .with_output_size(rendition.width, rendition.height)
+                    .and_then(|builder| builder.build(&mut output))
+                    .map(|encoder| (encoder, None))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(InitError::VideoInit)?;
+
+        let audio = audio(&mut output)
+            .transpose()
+            .map_err(InitError::AudioInit)?;
+
+        let adaptation_sets = if audio.is_some() {
+            "id=0,streams=v id=1,streams=a"
+        } else {
+            "id=0,streams=v"
+        };
+
+        set_muxer_options(
+            &mut output,
+            &[
+                ("adaptation_sets", adaptation_sets),
+                ("init_seg_name", "init-$RepresentationID$.m4s"),
+                (
+                    "media_seg_name",
+                    "chunk-$RepresentationID$-$Number%05d$.m4s",
+                ),
+                (
+                    "seg_duration",
+                    &config.segment_duration.as_secs_f64().to_string(),
+                ),
+                ("use_timeline", "1"),
+                ("use_template", "1"),
+                ("single_file", "0"),
+                ("dash_segment_type", "mp4"),
+                ("format_options", "movflags=+cmaf"),
+                ("hls_playlist", "1"),
+                ("hls_master_name", Self::HLS_MASTER_NAME),
+            ],
+        );
+
+        // make sure this happens after adding all encoders!
+        output.write_header().map_err(InitError::Ffmpeg)?;
+
+        info!(
+            path = %base_path.display(),
+            renditions = config.renditions.len(),
+            has_audio = audio.is_some(),
+            "Initialized packaged HLS/DASH stream"
+        );
+
+        Ok(Self {
+            base_path,
+            output,
+            videos,
+            audio,
+            is_finished: false,
+        })
+    }
+
+    /// Queues the frame to every rendition, each scaling it to its own size.
+    pub fn queue_video_frame(
+        &mut self,
+        frame: &mut frame::Video,
+    ) -> Result<(), h264::QueueFrameError> {
+        if self.is_finished {
+            return Ok(());
+        }
+
+        let pts = frame.pts();
+        for (encoder, converted_frame) in &mut self.videos {
+            frame.set_pts(pts);
+            encoder.queue_frame_reusable(
+                frame,
+                converted_frame,
+                Duration::MAX,
+                &mut self.output,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    pub fn queue_audio_frame(&mut self, frame: frame::Audio) {
+        if self.is_finished {
+            return;
+        }
+
+        let Some(audio) = &mut self.audio else {
+            return;
+        };
+
+        audio.send_frame(frame, &mut self.output);
+    }
+
+    pub fn finish(&mut self) -> Result<FinishResult, FinishError> {
+        if self.is_finished {
+            return Err(FinishError::AlreadyFinished);
+        }
+
+        self.is_finished = true;
+
+        let video_finish = self
+            .videos
+            .iter_mut()
+            .map(|(encoder, _)| encoder.flush(&mut self.output))
+            .fold(Ok(()), Result::and)
+            .inspect_err(|e| {
+                error!("Failed to finish video encoder: {e:#}");
+            });
+
+        let audio_finish = self
+            .audio
+            .as_mut()
+            .map(|enc| {
+                enc.flush(&mut self.output).inspect_err(|e| {
+                    error!("Failed to finish audio encoder: {e:#}");
+                })
+            })
+            .unwrap_or(Ok(()));
+
+        self.output
+            .write_trailer()
+            .map_err(FinishError::WriteTrailerFailed)?;
+
+        Ok(FinishResult {
+            video_finish,
+            audio_finish,
+        })
+    }
+
+    pub fn base_path(&self) -> &Path {
+        &self.base_path
+    }
+
+    pub fn dash_manifest_path(&self) -> PathBuf {
+        self.base_path.join(Self::DASH_MANIFEST_NAME)
+    }
+
+    pub fn hls_master_path(&self) -> PathBuf {
+        self.base_path.join(Self::HLS_MASTER_NAME)
+    }
+}
+
+impl Drop for PackagedStream {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
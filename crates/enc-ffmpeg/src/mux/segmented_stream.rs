@@ -50,6 +50,29 @@ fn atomic_write_json<T: Serialize>(path: &Path, data: &T) -> std::io::Result<()>
     Ok(())
 }
 
+/// Sets private options on the muxer, such as the `dash` muxer's segment naming.
+pub(crate) fn set_muxer_options(output: &mut format::context::Output, options: &[(&str, &str)]) {
+    unsafe {
+        let opts = output.as_mut_ptr();
+
+        for (key, value) in options {
+            let k = CString::new(*key).unwrap();
+            let v = CString::new(*value).unwrap();
+            ffmpeg::ffi::av_opt_set((*opts).priv_data, k.as_ptr(), v.as_ptr(), 0);
+        }
+    }
+}
+
+/// Opens the `dash` muxer writing its manifest to `manifest_path`, with segments alongside.
+pub(crate) fn dash_output(manifest_path: &Path) -> Result<format::context::Output, ffmpeg::Error> {
+    #[cfg(target_os = "windows")]
+    let manifest_path_str = manifest_path.to_string_lossy().replace('\\', "/");
+    #[cfg(not(target_os = "windows"))]
+    let manifest_path_str = manifest_path.to_string_lossy().to_string();
+
+    format::output_as(&manifest_path_str, "dash")
+}
+
 fn sync_file(path: &Path) {
     if let Ok(file) = std::fs::File::open(path)
         && let Err(e) = file.sync_all()
@@ -178,37 +201,26 @@ impl SegmentedVideoEncoder {
         std::fs::create_dir_all(&base_path)?;
 
         let manifest_path = base_path.join("dash_manifest.mpd");
-
-        #[cfg(target_os = "windows")]
-        let manifest_path_str = manifest_path.to_string_lossy().replace('\\', "/");
-        #[cfg(not(target_os = "windows"))]
-        let manifest_path_str = manifest_path.to_string_lossy().to_string();
-
-        let mut output = format::output_as(&manifest_path_str, "dash")?;
+        let mut output = dash_output(&manifest_path)?;
 
         let init_seg_str = INIT_SEGMENT_NAME;
         let media_seg_str = "segment_$Number%03d$.m4s";
 
-        unsafe {
-            let opts = output.as_mut_ptr();
-
-            let set_opt = |key: &str, value: &str| {
-                let k = CString::new(key).unwrap();
-                let v = CString::new(value).unwrap();
-                ffmpeg::ffi::av_opt_set((*opts).priv_data, k.as_ptr(), v.as_ptr(), 0);
-            };
-
-            set_opt("init_seg_name", init_seg_str);
-            set_opt("media_seg_name", media_seg_str);
-            set_opt(
-                "seg_duration",
-                &config.segment_duration.as_secs_f64().to_string(),
-            );
-            set_opt("use_timeline", "0");
-            set_opt("use_template", "1");
-            set_opt("single_file", "0");
-            set_opt("hls_playlist", "1");
-        }
+        set_muxer_options(
+            &mut output,
+            &[
+                ("init_seg_name", init_seg_str),
+                ("media_seg_name", media_seg_str),
+                (
+                    "seg_duration",
+                    &config.segment_duration.as_secs_f64().to_string(),
+                ),
+                ("use_timeline", "0"),
+                ("use_template", "1"),
+                ("single_file", "0"),
+                ("hls_playlist", "1"),
+            ],
+        );
 
         let mut builder = H264EncoderBuilder::new(video_config)
             .with_preset(config.preset)
@@ -0,0 +1,258 @@
+use ffmpeg::{format, frame};
+use std::{path::PathBuf, time::Duration};
+use tracing::*;
+
+use crate::{
+    audio::AudioEncoder,
+    mp4::{flush_audio_stems, label_audio_streams},
+    video::av1::{self, Av1Encoder, Av1EncoderError},
+    video::vp9::{self, Vp9Encoder, Vp9EncoderError},
+};
+
+pub enum WebMVideoEncoder {
+    Vp9(Vp9Encoder),
+    Av1(Av1Encoder),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum WebMVideoEncoderError {
+    #[error("VP9/{0}")]
+    Vp9(#[from] Vp9EncoderError),
+    #[error("AV1/{0}")]
+    Av1(#[from] Av1EncoderError),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum QueueFrameError {
+    #[error("VP9/{0}")]
+    Vp9(#[from] vp9::QueueFrameError),
+    #[error("AV1/{0}")]
+    Av1(#[from] av1::QueueFrameError),
+}
+
+impl WebMVideoEncoder {
+    fn queue_frame_reusable(
+        &mut self,
+        frame: &mut frame::Video,
+        converted_frame: &mut Option<frame::Video>,
+        timestamp: Duration,
+        output: &mut format::context::Output,
+    ) -> Result<(), QueueFrameError> {
+        match self {
+            Self::Vp9(encoder) => encoder
+                .queue_frame_reusable(frame, converted_frame, timestamp, output)
+                .map_err(Into::into),
+            Self::Av1(encoder) => encoder
+                .queue_frame_reusable(frame, converted_frame, timestamp, output)
+                .map_err(Into::into),
+        }
+    }
+
+    fn flush(&mut self, output: &mut format::context::Output) -> Result<(), ffmpeg::Error> {
+        match self {
+            Self::Vp9(encoder) => encoder.flush(output),
+            Self::Av1(encoder) => encoder.flush(output),
+        }
+    }
+}
+
+impl From<Vp9Encoder> for WebMVideoEncoder {
+    fn from(value: Vp9Encoder) -> Self {
+        Self::Vp9(value)
+    }
+}
+
+impl From<Av1Encoder> for WebMVideoEncoder {
+    fn from(value: Av1Encoder) -> Self {
+        Self::Av1(value)
+    }
+}
+
+pub struct WebMFile {
+    #[allow(unused)]
+    tag: &'static str,
+    output: format::context::Output,
+    video: WebMVideoEncoder,
+    audio: Option<Box<dyn AudioEncoder + Send>>,
+    audio_stems: Vec<Box<dyn AudioEncoder + Send>>,
+    is_finished: bool,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum InitError {
+    #[error("{0:?}")]
+    Ffmpeg(ffmpeg::Error),
+    #[error("Video/{0}")]
+    VideoInit(WebMVideoEncoderError),
+    #[error("Audio/{0}")]
+    AudioInit(Box<dyn std::error::Error>),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum FinishError {
+    #[error("Already finished")]
+    AlreadyFinished,
+    #[error("{0}")]
+    WriteTrailerFailed(ffmpeg::Error),
+}
+
+pub struct FinishResult {
+    pub video_finish: Result<(), ffmpeg::Error>,
+    pub audio_finish: Result<(), ffmpeg::Error>,
+}
+
+impl WebMFile {
+    pub fn init(
+        tag: &'static str,
+        output: PathBuf,
+        video: impl FnOnce(
+            &mut format::context::Output,
+        ) -> Result<WebMVideoEncoder, WebMVideoEncoderError>,
+        audio: impl FnOnce(
+            &mut format::context::Output,
+        )
+            -> Option<Result<Box<dyn AudioEncoder + Send>, Box<dyn std::error::Error>>>,
+    ) -> Result<Self, InitError> {
+        Self::init_with_audio_stems(tag, output, &[], video, audio, |_| Ok(vec![]))
+    }
+
+    /// Like [`Self::init`], with `audio_stems` muxed as extra audio streams after the main
+    /// one and every audio stream titled from `audio_titles`. Frames for the stems go
+    /// through [`Self::queue_audio_stem_frame`].
+    pub fn init_with_audio_stems(
+        tag: &'static str,
+        mut output: PathBuf,
+        audio_titles: &[String],
+        video: impl FnOnce(
+            &mut format::context::Output,
+        ) -> Result<WebMVideoEncoder, WebMVideoEncoderError>,
+        audio: impl FnOnce(
+            &mut format::context::Output,
+        )
+            -> Option<Result<Box<dyn AudioEncoder + Send>, Box<dyn std::error::Error>>>,
+        audio_stems: impl FnOnce(
+            &mut format::context::Output,
+        ) -> Result<
+            Vec<Box<dyn AudioEncoder + Send>>,
+            Box<dyn std::error::Error>,
+        >,
+    ) -> Result<Self, InitError> {
+        output.set_extension("webm");
+
+        if let Some(parent) = output.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+
+        let mut output = format::output(&output).map_err(InitError::Ffmpeg)?;
+
+        trace!("Preparing encoders for webm file");
+
+        let video = video(&mut output).map_err(InitError::VideoInit)?;
+        let audio = audio(&mut output)
+            .transpose()
+            .map_err(InitError::AudioInit)?;
+        let audio_stems = audio_stems(&mut output).map_err(InitError::AudioInit)?;
+        label_audio_streams(&mut output, audio_titles);
+
+        info!("Prepared encoders for webm file");
+
+        // make sure this happens after adding all encoders!
+        output.write_header().map_err(InitError::Ffmpeg)?;
+
+        Ok(Self {
+            tag,
+            output,
+            video,
+            audio,
+            audio_stems,
+            is_finished: false,
+        })
+    }
+
+    pub fn queue_video_frame_reusable(
+        &mut self,
+        frame: &mut frame::Video,
+        converted_frame: &mut Option<frame::Video>,
+        timestamp: Duration,
+    ) -> Result<(), QueueFrameError> {
+        if self.is_finished {
+            return Ok(());
+        }
+
+        self.video
+            .queue_frame_reusable(frame, converted_frame, timestamp, &mut self.output)
+    }
+
+    pub fn queue_audio_frame(&mut self, frame: frame::Audio) {
+        if self.is_finished {
+            return;
+        }
+
+        let Some(audio) = &mut self.audio else {
+            return;
+        };
+
+        audio.send_frame(frame, &mut self.output);
+    }
+
+    pub fn queue_audio_stem_frame(&mut self, index: usize, frame: frame::Audio) {
+        if self.is_finished {
+            return;
+        }
+
+        let Some(audio) = self.audio_stems.get_mut(index) else {
+            return;
+        };
+
+        audio.send_frame(frame, &mut self.output);
+    }
+
+    pub fn finish(&mut self) -> Result<FinishResult, FinishError> {
+        if self.is_finished {
+            return Err(FinishError::AlreadyFinished);
+        }
+
+        self.is_finished = true;
+
+        tracing::info!("WebMEncoder: Finishing encoding");
+
+        let video_finish = self.video.flush(&mut self.output).inspect_err(|e| {
+            error!("Failed to finish video encoder: {e:#}");
+        });
+
+        let audio_finish = self
+            .audio
+            .as_mut()
+            .map(|enc| {
+                tracing::info!("WebMEncoder: Flushing audio encoder");
+                enc.flush(&mut self.output).inspect_err(|e| {
+                    error!("Failed to finish audio encoder: {e:#}");
+                })
+            })
+            .unwrap_or(Ok(()))
+            .and(flush_audio_stems(&mut self.audio_stems, &mut self.output));
+
+        tracing::info!("WebMEncoder: Writing trailer");
+        self.output
+            .write_trailer()
+            .map_err(FinishError::WriteTrailerFailed)?;
+
+        Ok(FinishResult {
+            video_finish,
+            audio_finish,
+        })
+    }
+
+    pub fn video(&self) -> &WebMVideoEncoder {
+        &self.video
+    }
+}
+
+impl Drop for WebMFile {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+unsafe impl Send for Vp9Encoder {}
+unsafe impl Send for Av1Encoder {}
@@ -1,16 +1,29 @@
 use std::{
     ffi::CString,
     io::Write,
+    ops::Range,
     path::{Path, PathBuf},
     ptr,
     sync::atomic::{AtomicI32, Ordering},
     time::Duration,
 };
 
-use cap_media_info::{AudioInfo, AudioInfoError};
-use ffmpeg::{ChannelLayout, codec as avcodec, format as avformat, packet::Mut as PacketMut};
+use cap_media_info::{AudioInfo, AudioInfoError, VideoInfo};
+use ffmpeg::{
+    ChannelLayout, Rational, Rescale, codec as avcodec, color::TransferCharacteristic,
+    format as avformat, format::Pixel, packet::Mut as PacketMut, rescale,
+};
 
-use crate::audio::opus::{OpusEncoder, OpusEncoderError};
+use crate::{
+    audio::opus::{OpusEncoder, OpusEncoderError},
+    h264::H264Encoder,
+    hevc::{HdrTransfer, HevcEncoder},
+    mp4::{
+        ContainerMetadata, MovTextStream, Mp4FileOptions, add_chapters, label_audio_streams,
+        set_container_metadata, set_encoder_tag, signal_hdr_video,
+    },
+    rate_control::RateControl,
+};
 
 static ORIGINAL_LOG_LEVEL: AtomicI32 = AtomicI32::new(-1);
 
@@ -49,9 +62,31 @@ pub enum RemuxError {
     AudioInfo(#[from] AudioInfoError),
     #[error("Concat demuxer not found")]
     ConcatDemuxerNotFound,
+    #[error("Input not found: {0}")]
+    InputNotFound(PathBuf),
+    #[error("No video stream found")]
+    NoVideoStream,
+    #[error("No keyframe found in video stream")]
+    NoKeyframe,
+    #[error("Invalid trim range {start:?}..{end:?}")]
+    InvalidTrimRange { start: Duration, end: Duration },
+    #[error("Expected {expected} split outputs, got {actual}")]
+    SplitOutputCount { expected: usize, actual: usize },
+    #[error("Video encoder error: {0}")]
+    VideoEncoder(String),
 }
 
 pub fn concatenate_video_fragments(fragments: &[PathBuf], output: &Path) -> Result<(), RemuxError> {
+    concatenate_video_fragments_with_options(fragments, output, Mp4FileOptions::default())
+}
+
+/// Like [`concatenate_video_fragments`], but also muxes the subtitle tracks and chapters
+/// in `options`, which span the whole output rather than any one fragment.
+pub fn concatenate_video_fragments_with_options(
+    fragments: &[PathBuf],
+    output: &Path,
+    options: Mp4FileOptions,
+) -> Result<(), RemuxError> {
     if fragments.is_empty() {
         return Err(RemuxError::NoFragments);
     }
@@ -74,7 +109,7 @@ pub fn concatenate_video_fragments(fragments: &[PathBuf], output: &Path) -> Resu
         }
     }
 
-    let result = concatenate_with_concat_demuxer(&concat_list_path, output);
+    let result = concatenate_with_concat_demuxer(&concat_list_path, output, options);
 
     let _ = std::fs::remove_file(&concat_list_path);
 
@@ -126,6 +161,7 @@ fn open_input_with_format(
 fn remux_streams(
     ictx: &mut avformat::context::Input,
     octx: &mut avformat::context::Output,
+    options: Mp4FileOptions,
 ) -> Result<(), RemuxError> {
     let mut stream_mapping: Vec<Option<usize>> = Vec::new();
     let mut output_stream_index = 0usize;
@@ -148,7 +184,18 @@ fn remux_streams(
         }
     }
 
+    let subtitles = options
+        .subtitles
+        .into_iter()
+        .map(|track| MovTextStream::add(octx, track))
+        .collect::<Result<Vec<_>, _>>()?;
+    label_audio_streams(octx, &options.audio_titles);
+    add_chapters(octx, &options.chapters)?;
+    set_container_metadata(octx, &options.metadata);
+    signal_hdr_video(octx);
+
     octx.write_header()?;
+    set_encoder_tag(octx, &options.metadata);
 
     let mut last_dts: Vec<i64> = vec![i64::MIN; output_stream_index];
     let mut dts_offset: Vec<i64> = vec![0; output_stream_index];
@@ -189,6 +236,10 @@ fn remux_streams(
         }
     }
 
+    for subtitles in &subtitles {
+        subtitles.write(octx)?;
+    }
+
     octx.write_trailer()?;
 
     Ok(())
@@ -197,14 +248,15 @@ fn remux_streams(
 fn concatenate_with_concat_demuxer(
     concat_list_path: &Path,
     output: &Path,
+    options: Mp4FileOptions,
 ) -> Result<(), RemuxError> {
-    let mut options = ffmpeg::Dictionary::new();
-    options.set("safe", "0");
+    let mut demuxer_options = ffmpeg::Dictionary::new();
+    demuxer_options.set("safe", "0");
 
-    let mut ictx = open_input_with_format(concat_list_path, "concat", options)?;
+    let mut ictx = open_input_with_format(concat_list_path, "concat", demuxer_options)?;
     let mut octx = avformat::output(output)?;
 
-    remux_streams(&mut ictx, &mut octx)
+    remux_streams(&mut ictx, &mut octx, options)
 }
 
 pub fn concatenate_audio_to_ogg(fragments: &[PathBuf], output: &Path) -> Result<(), RemuxError> {
@@ -500,5 +552,807 @@ fn remux_to_regular_mp4(input_path: &Path, output_path: &Path) -> Result<(), Rem
     let mut ictx = avformat::input(input_path)?;
     let mut octx = avformat::output(output_path)?;
 
-    remux_streams(&mut ictx, &mut octx)
+    remux_streams(&mut ictx, &mut octx, Mp4FileOptions::default())
+}
+
+/// How [`trim_video`] and [`split_video`] start a cut that doesn't land on a keyframe.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CutMode {
+    /// Start at the last keyframe at or before the cut. Lossless and fast, but keeps up to
+    /// one GOP of video, and the audio alongside it, from before the cut.
+    #[default]
+    Keyframe,
+    /// Re-encode from the cut to the next keyframe and stream-copy the rest, so the output
+    /// starts exactly at the cut. Supports 8-bit H.264 and HEVC and 10-bit HDR HEVC; other
+    /// video falls back to [`CutMode::Keyframe`].
+    Smart,
+}
+
+/// Quality used to re-encode a smart cut's first GOP when the source bitrate is unknown.
+const SMART_CUT_CRF: u8 = 18;
+
+/// Writes `range` of `input`, measured from the start of its video, to `output`, copying
+/// the video and audio packets without re-encoding them. `mode` decides how a start that
+/// isn't on a keyframe is handled. As with `ffmpeg -c copy`, every stream stops at its
+/// first packet decoded at or after `range.end`.
+pub fn trim_video(
+    input: &Path,
+    output: &Path,
+    range: Range<Duration>,
+    mode: CutMode,
+) -> Result<(), RemuxError> {
+    if range.start >= range.end {
+        return Err(RemuxError::InvalidTrimRange {
+            start: range.start,
+            end: range.end,
+        });
+    }
+
+    if !input.exists() {
+        return Err(RemuxError::InputNotFound(input.to_path_buf()));
+    }
+
+    let plan = plan_cut(input, range.start, mode)?;
+
+    let mut ictx = avformat::input(input)?;
+    let mut octx = avformat::output(output)?;
+
+    cut_streams(&mut ictx, &mut octx, plan, range.end)
+}
+
+/// Splits `input` at each time in `at` (ascending), writing the pieces to `outputs`, which
+/// must hold one more path than there are split points. With [`CutMode::Keyframe`] each
+/// piece after the first starts at the keyframe before its split point, so neighbouring
+/// pieces can overlap by up to a GOP.
+pub fn split_video(
+    input: &Path,
+    at: &[Duration],
+    outputs: &[PathBuf],
+    mode: CutMode,
+) -> Result<(), RemuxError> {
+    if outputs.len() != at.len() + 1 {
+        return Err(RemuxError::SplitOutputCount {
+            expected: at.len() + 1,
+            actual: outputs.len(),
+        });
+    }
+
+    for (piece, output) in split_ranges(at).into_iter().zip(outputs) {
+        trim_video(input, output, piece, mode)?;
+    }
+
+    Ok(())
+}
+
+/// The pieces `at` splits a file into, the last one open-ended.
+fn split_ranges(at: &[Duration]) -> Vec<Range<Duration>> {
+    std::iter::once(Duration::ZERO)
+        .chain(at.iter().copied())
+        .zip(at.iter().copied().chain(std::iter::once(Duration::MAX)))
+        .map(|(start, end)| start..end)
+        .collect()
+}
+
+struct CutPlan {
+    video_index: usize,
+    video_start: Duration,
+    start: CutStart,
+    /// Set for a smart cut, which re-encodes the frames before `start.copy_from`.
+    head: Option<SmartCutSource>,
+}
+
+/// Where a cut's output starts, in the time base of the video stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct CutStart {
+    /// Input timestamp that becomes zero in the output.
+    origin: i64,
+    /// Keyframe from which video packets are copied.
+    copy_from: i64,
+    /// Whether the frames from `origin` up to `copy_from` are re-encoded.
+    reencode: bool,
+}
+
+impl CutStart {
+    /// Starts at the keyframe at or before `start`, or the first keyframe if there's none.
+    /// A smart cut that doesn't land on a keyframe starts at `start` itself and re-encodes
+    /// up to the next keyframe, or to the end if there isn't one.
+    fn choose(previous: Option<i64>, next: Option<i64>, start: i64, smart: bool) -> Option<Self> {
+        let keyframe = previous.or(next)?;
+
+        Some(if smart && keyframe < start {
+            Self {
+                origin: start,
+                copy_from: next.unwrap_or(i64::MAX),
+                reencode: true,
+            }
+        } else {
+            Self {
+                origin: keyframe,
+                copy_from: keyframe,
+                reencode: false,
+            }
+        })
+    }
+}
+
+/// The last keyframe at or before `start` and the first one after it, from keyframe
+/// timestamps in decode order. Stops reading at the first keyframe after `start`.
+fn keyframes_around(
+    keyframes: impl IntoIterator<Item = i64>,
+    start: i64,
+) -> (Option<i64>, Option<i64>) {
+    let mut previous = None;
+
+    for pts in keyframes {
+        if pts > start {
+            return (previous, Some(pts));
+        }
+        previous = Some(pts);
+    }
+
+    (previous, None)
+}
+
+/// What the re-encoded head of a smart cut needs to match the copied video after it.
+struct SmartCutSource {
+    /// The source's parameter sets, sent in-band ahead of the first copied keyframe so it
+    /// doesn't decode with the head encoder's.
+    parameter_sets: Vec<u8>,
+    hdr: Option<HdrTransfer>,
+}
+
+fn plan_cut(input: &Path, start: Duration, mode: CutMode) -> Result<CutPlan, RemuxError> {
+    let mut ictx = avformat::input(input)?;
+
+    let (video_index, time_base, video_start, head) = {
+        let stream = ictx
+            .streams()
+            .best(ffmpeg::media::Type::Video)
+            .ok_or(RemuxError::NoVideoStream)?;
+
+        let head = match mode {
+            CutMode::Keyframe => None,
+            CutMode::Smart => smart_cut_source(&stream.parameters())
+                .inspect_err(|reason| {
+                    tracing::warn!("Can't smart cut {}: {reason}", input.display());
+                })
+                .ok(),
+        };
+
+        (
+            stream.index(),
+            stream.time_base(),
+            stream_start(stream.start_time(), stream.time_base()),
+            head,
+        )
+    };
+
+    let start_ts = timestamp_at(start, video_start, time_base);
+    let seek_to = start_ts.rescale(time_base, rescale::TIME_BASE);
+    if let Err(e) = ictx.seek(seek_to, ..seek_to) {
+        tracing::debug!("Seek before cut failed, scanning from the start: {e}");
+    }
+
+    let keyframes = ictx
+        .packets()
+        .filter(|(stream, packet)| stream.index() == video_index && packet.is_key())
+        .filter_map(|(_, packet)| packet.pts());
+    let (previous, next) = keyframes_around(keyframes, start_ts);
+
+    let start =
+        CutStart::choose(previous, next, start_ts, head.is_some()).ok_or(RemuxError::NoKeyframe)?;
+
+    Ok(CutPlan {
+        video_index,
+        video_start,
+        start,
+        head: head.filter(|_| start.reencode),
+    })
+}
+
+fn smart_cut_source(parameters: &avcodec::Parameters) -> Result<SmartCutSource, String> {
+    let codec = parameters.id();
+    let decoder = avcodec::Context::from_parameters(parameters.clone())
+        .and_then(|context| context.decoder().video())
+        .map_err(|e| format!("failed to create decoder: {e}"))?;
+
+    let hdr = match decoder.color_transfer_characteristic() {
+        TransferCharacteristic::SMPTE2084 => Some(HdrTransfer::Pq),
+        TransferCharacteristic::ARIB_STD_B67 => Some(HdrTransfer::Hlg),
+        _ => None,
+    };
+
+    let supported = match (codec, decoder.format(), hdr) {
+        (
+            avcodec::Id::H264 | avcodec::Id::HEVC,
+            Pixel::YUV420P | Pixel::YUVJ420P | Pixel::NV12,
+            None,
+        ) => true,
+        (avcodec::Id::HEVC, Pixel::YUV420P10LE | Pixel::P010LE, Some(_)) => true,
+        _ => false,
+    };
+    if !supported {
+        return Err(format!(
+            "{codec:?} {:?} video can't be re-encoded to match",
+            decoder.format()
+        ));
+    }
+
+    let extradata = unsafe {
+        let codecpar = &*parameters.as_ptr();
+        if codecpar.extradata.is_null() {
+            &[][..]
+        } else {
+            std::slice::from_raw_parts(codecpar.extradata, codecpar.extradata_size as usize)
+        }
+    };
+
+    let parameter_sets = length_prefixed_parameter_sets(codec, extradata)
+        .ok_or_else(|| "parameter sets aren't in 4-byte length-prefixed form".to_string())?;
+
+    Ok(SmartCutSource {
+        parameter_sets,
+        hdr,
+    })
+}
+
+fn cut_streams(
+    ictx: &mut avformat::context::Input,
+    octx: &mut avformat::context::Output,
+    plan: CutPlan,
+    end: Duration,
+) -> Result<(), RemuxError> {
+    let video_time_base = ictx
+        .stream(plan.video_index)
+        .ok_or(RemuxError::NoVideoStream)?
+        .time_base();
+    let end_ts = timestamp_at(end, plan.video_start, video_time_base);
+    let metadata = ContainerMetadata::from_tags(ictx.metadata().iter());
+
+    // Per input stream: output index, then the origin and end in its own time base.
+    let mut stream_mapping: Vec<Option<(usize, i64, i64)>> = Vec::new();
+    let mut video_output_index = 0;
+    let mut head = None;
+
+    for input_stream in ictx.streams() {
+        let codec_params = input_stream.parameters();
+        let medium = codec_params.medium();
+        let time_base = input_stream.time_base();
+        let output_index = octx.nb_streams() as usize;
+
+        if input_stream.index() == plan.video_index {
+            video_output_index = output_index;
+
+            if let Some(source) = &plan.head {
+                head = Some(CutHead::new(
+                    &input_stream,
+                    source,
+                    plan.start.origin,
+                    plan.start.copy_from.min(end_ts),
+                    octx,
+                )?);
+            } else {
+                let mut output_stream = octx.add_stream(None)?;
+                output_stream.set_parameters(codec_params);
+                unsafe {
+                    (*output_stream.as_mut_ptr()).time_base = (*input_stream.as_ptr()).time_base;
+                }
+            }
+
+            stream_mapping.push(Some((output_index, plan.start.origin, end_ts)));
+        } else if medium == ffmpeg::media::Type::Audio {
+            let mut output_stream = octx.add_stream(None)?;
+            output_stream.set_parameters(codec_params);
+            unsafe {
+                (*output_stream.as_mut_ptr()).time_base = (*input_stream.as_ptr()).time_base;
+            }
+
+            let (origin, end) =
+                stream_window(plan.start.origin, end_ts, video_time_base, time_base);
+            stream_mapping.push(Some((output_index, origin, end)));
+        } else {
+            stream_mapping.push(None);
+        }
+    }
+
+    signal_hdr_video(octx);
+
+    if let (Some(head), Some(mut stream)) = (&head, octx.stream_mut(video_output_index)) {
+        // The copied video carries its own parameter sets in-band from the first keyframe,
+        // which only `avc3`/`hev1` sample entries allow.
+        unsafe {
+            (*(*stream.as_mut_ptr()).codecpar).codec_tag = head.in_band_codec_tag();
+        }
+    }
+
+    set_container_metadata(octx, &metadata);
+    octx.write_header()?;
+    set_encoder_tag(octx, &metadata);
+
+    let output_stream_count = octx.nb_streams() as usize;
+    let mut last_dts: Vec<i64> = vec![i64::MIN; output_stream_count];
+    let mut dts_offset: Vec<i64> = vec![0; output_stream_count];
+    let mut copying_video = false;
+
+    let seek_to = plan
+        .start
+        .origin
+        .rescale(video_time_base, rescale::TIME_BASE);
+    if let Err(e) = ictx.seek(seek_to, ..seek_to) {
+        tracing::debug!("Seek before cut failed, reading from the start: {e}");
+    }
+
+    for (input_stream, mut packet) in ictx.packets() {
+        let Some(Some((output_index, origin, end))) =
+            stream_mapping.get(input_stream.index()).copied()
+        else {
+            continue;
+        };
+
+        if packet.dts().is_some_and(|dts| dts >= end) {
+            continue;
+        }
+
+        let pts = packet.pts().or(packet.dts()).unwrap_or(0);
+
+        if input_stream.index() == plan.video_index {
+            if !copying_video {
+                if !packet.is_key() || pts < plan.start.copy_from {
+                    if let Some(head) = &mut head {
+                        head.decode(&packet, octx)?;
+                    }
+                    continue;
+                }
+
+                if let (Some(head), Some(source)) = (head.take(), &plan.head) {
+                    head.finish(octx)?;
+
+                    // The head ends before the keyframe's presentation time, so copied
+                    // packets that decode earlier (B-frame sources) are nudged after it.
+                    let output_time_base = octx.stream(output_index).unwrap().time_base();
+                    last_dts[output_index] = (plan.start.copy_from - origin)
+                        .rescale(input_stream.time_base(), output_time_base)
+                        - 1;
+                    packet = with_parameter_sets(&packet, &source.parameter_sets);
+                }
+
+                copying_video = true;
+            }
+        } else if pts < origin {
+            continue;
+        }
+
+        let input_time_base = input_stream.time_base();
+        let output_time_base = octx.stream(output_index).unwrap().time_base();
+
+        packet.set_pts(packet.pts().map(|pts| pts - origin));
+        packet.set_dts(packet.dts().map(|dts| dts - origin));
+        packet.rescale_ts(input_time_base, output_time_base);
+
+        let current_dts = packet.dts().unwrap_or(0);
+
+        if last_dts[output_index] != i64::MIN && current_dts <= last_dts[output_index] {
+            dts_offset[output_index] = last_dts[output_index] - current_dts + 1;
+        }
+
+        let adjusted_dts = current_dts + dts_offset[output_index];
+        let adjusted_pts = packet.pts().map(|pts| pts + dts_offset[output_index]);
+
+        unsafe {
+            (*packet.as_mut_ptr()).dts = adjusted_dts;
+            if let Some(pts) = adjusted_pts {
+                (*packet.as_mut_ptr()).pts = pts;
+            }
+        }
+
+        last_dts[output_index] = adjusted_dts;
+
+        packet.set_stream(output_index);
+        packet.set_position(-1);
+
+        packet.write_interleaved(octx)?;
+    }
+
+    if let Some(head) = head {
+        head.finish(octx)?;
+    }
+
+    octx.write_trailer()?;
+
+    Ok(())
+}
+
+/// Decodes from the keyframe before a smart cut and re-encodes the frames between the cut
+/// and the next keyframe, writing them to the cut's video stream.
+struct CutHead {
+    decoder: avcodec::decoder::Video,
+    encoder: HeadEncoder,
+    origin: i64,
+    until: i64,
+    frame: ffmpeg::frame::Video,
+}
+
+enum HeadEncoder {
+    H264(H264Encoder),
+    Hevc(HevcEncoder),
+}
+
+impl CutHead {
+    fn new(
+        stream: &avformat::stream::Stream,
+        source: &SmartCutSource,
+        origin: i64,
+        until: i64,
+        octx: &mut avformat::context::Output,
+    ) -> Result<Self, RemuxError> {
+        let parameters = stream.parameters();
+        let codec = parameters.id();
+        let decoder = avcodec::Context::from_parameters(parameters)?
+            .decoder()
+            .video()?;
+
+        let mut video_info =
+            VideoInfo::from_raw_ffmpeg(decoder.format(), decoder.width(), decoder.height(), 0);
+        video_info.time_base = stream.time_base();
+        video_info.frame_rate = match stream.avg_frame_rate() {
+            rate if rate.denominator() != 0 && rate.numerator() != 0 => rate,
+            _ => stream.rate(),
+        };
+
+        // Match the source's bitrate so the re-encoded frames don't stand out, and never
+        // put B-frames in the head: its last packet must decode before the copied keyframe.
+        let rate_control = match decoder.bit_rate() {
+            0 => RateControl::Crf(SMART_CUT_CRF),
+            bitrate => RateControl::constrained_vbr(bitrate as u64),
+        };
+
+        let encoder = if codec == avcodec::Id::H264 {
+            H264Encoder::builder(video_info)
+                .with_rate_control(rate_control)
+                .with_b_frames(0)
+                .build(octx)
+                .map(HeadEncoder::H264)
+                .map_err(|e| RemuxError::VideoEncoder(e.to_string()))?
+        } else {
+            let mut builder = HevcEncoder::builder(video_info)
+                .with_rate_control(rate_control)
+                .with_b_frames(0);
+            if let Some(transfer) = source.hdr {
+                builder = builder.with_hdr(transfer);
+            }
+
+            builder
+                .build(octx)
+                .map(HeadEncoder::Hevc)
+                .map_err(|e| RemuxError::VideoEncoder(e.to_string()))?
+        };
+
+        Ok(Self {
+            decoder,
+            encoder,
+            origin,
+            until,
+            frame: ffmpeg::frame::Video::empty(),
+        })
+    }
+
+    fn in_band_codec_tag(&self) -> u32 {
+        match self.encoder {
+            HeadEncoder::H264(_) => u32::from_le_bytes(*b"avc3"),
+            HeadEncoder::Hevc(_) => u32::from_le_bytes(*b"hev1"),
+        }
+    }
+
+    fn decode(
+        &mut self,
+        packet: &ffmpeg::Packet,
+        octx: &mut avformat::context::Output,
+    ) -> Result<(), RemuxError> {
+        self.decoder.send_packet(packet)?;
+        self.encode_decoded(octx)
+    }
+
+    fn finish(mut self, octx: &mut avformat::context::Output) -> Result<(), RemuxError> {
+        self.decoder.send_eof()?;
+        self.encode_decoded(octx)?;
+
+        match &mut self.encoder {
+            HeadEncoder::H264(encoder) => encoder.flush(octx)?,
+            HeadEncoder::Hevc(encoder) => encoder.flush(octx)?,
+        }
+
+        Ok(())
+    }
+
+    fn encode_decoded(&mut self, octx: &mut avformat::context::Output) -> Result<(), RemuxError> {
+        while self.decoder.receive_frame(&mut self.frame).is_ok() {
+            let Some(pts) = self.frame.timestamp().or(self.frame.pts()) else {
+                continue;
+            };
+
+            if pts < self.origin || pts >= self.until {
+                continue;
+            }
+
+            let mut frame = self.frame.clone();
+            frame.set_pts(Some(pts - self.origin));
+
+            let queued = match &mut self.encoder {
+                HeadEncoder::H264(encoder) => encoder
+                    .queue_frame(frame, Duration::MAX, octx)
+                    .map_err(|e| e.to_string()),
+                HeadEncoder::Hevc(encoder) => encoder
+                    .queue_frame(frame, Duration::MAX, octx)
+                    .map_err(|e| e.to_string()),
+            };
+            queued.map_err(RemuxError::VideoEncoder)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn stream_start(start_time: i64, time_base: Rational) -> Duration {
+    if start_time <= 0 || start_time == ffmpeg::ffi::AV_NOPTS_VALUE {
+        return Duration::ZERO;
+    }
+
+    Duration::from_secs_f64(start_time as f64 * f64::from(time_base))
+}
+
+/// `time` after the start of the video, as a timestamp in `time_base`. Saturates rather
+/// than overflowing for open-ended ranges.
+fn timestamp_at(time: Duration, video_start: Duration, time_base: Rational) -> i64 {
+    let seconds = video_start.saturating_add(time).as_secs_f64();
+    (seconds / f64::from(time_base)).round() as i64
+}
+
+/// The video's cut `origin` and `end` in another stream's time base. An open end stays
+/// open rather than overflowing.
+fn stream_window(origin: i64, end: i64, video: Rational, stream: Rational) -> (i64, i64) {
+    let end = match end {
+        i64::MAX => i64::MAX,
+        end => end.rescale(video, stream),
+    };
+
+    (origin.rescale(video, stream), end)
+}
+
+fn with_parameter_sets(packet: &ffmpeg::Packet, parameter_sets: &[u8]) -> ffmpeg::Packet {
+    let mut data = Vec::with_capacity(parameter_sets.len() + packet.size());
+    data.extend_from_slice(parameter_sets);
+    data.extend_from_slice(packet.data().unwrap_or_default());
+
+    let mut combined = ffmpeg::Packet::copy(&data);
+    combined.set_pts(packet.pts());
+    combined.set_dts(packet.dts());
+    combined.set_duration(packet.duration());
+    combined.set_flags(packet.flags());
+    combined
+}
+
+/// The SPS and PPS (and VPS for HEVC) in an `avcC` or `hvcC` record, as 4-byte
+/// length-prefixed NAL units ready to put in front of a sample. `None` for Annex B
+/// extradata or other NAL length sizes, which couldn't share a track with the re-encoded
+/// head.
+fn length_prefixed_parameter_sets(codec: avcodec::Id, extradata: &[u8]) -> Option<Vec<u8>> {
+    let mut nal_units = Vec::new();
+
+    match codec {
+        avcodec::Id::H264 => {
+            if extradata.first() != Some(&1) || extradata.get(4)? & 0x3 != 0x3 {
+                return None;
+            }
+
+            let mut data = extradata.get(5..)?;
+            let sps_count = take(&mut data, 1)?[0] & 0x1f;
+            for _ in 0..sps_count {
+                nal_units.push(take_nal_unit(&mut data)?);
+            }
+
+            let pps_count = take(&mut data, 1)?[0];
+            for _ in 0..pps_count {
+                nal_units.push(take_nal_unit(&mut data)?);
+            }
+        }
+        avcodec::Id::HEVC => {
+            if extradata.first() != Some(&1) || extradata.get(21)? & 0x3 != 0x3 {
+                return None;
+            }
+
+            let mut data = extradata.get(22..)?;
+            let array_count = take(&mut data, 1)?[0];
+            for _ in 0..array_count {
+                take(&mut data, 1)?;
+                let count = u16::from_be_bytes(take(&mut data, 2)?.try_into().ok()?);
+                for _ in 0..count {
+                    nal_units.push(take_nal_unit(&mut data)?);
+                }
+            }
+        }
+        _ => return None,
+    }
+
+    if nal_units.is_empty() {
+        return None;
+    }
+
+    Some(
+        nal_units
+            .into_iter()
+            .flat_map(|nal_unit| {
+                (nal_unit.len() as u32)
+                    .to_be_bytes()
+                    .into_iter()
+                    .chain(nal_unit.iter().copied())
+            })
+            .collect(),
+    )
+}
+
+fn take<'a>(data: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    let (head, rest) = data.split_at_checked(len)?;
+    *data = rest;
+    Some(head)
+}
+
+fn take_nal_unit<'a>(data: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let len = u16::from_be_bytes(take(data, 2)?.try_into().ok()?);
+    take(data, len as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn avcc_parameter_sets_become_length_prefixed() {
+        let avcc = [
+            1, 0x64, 0x00, 0x1f, 0xff, // version, profile, compatibility, level, length size
+            0xe1, 0x00, 0x03, 0x67, 0x64, 0x1f, // one SPS
+            0x01, 0x00, 0x02, 0x68, 0xee, // one PPS
+        ];
+
+        assert_eq!(
+            length_prefixed_parameter_sets(avcodec::Id::H264, &avcc),
+            Some(vec![
+                0, 0, 0, 3, 0x67, 0x64, 0x1f, //
+                0, 0, 0, 2, 0x68, 0xee,
+            ])
+        );
+    }
+
+    #[test]
+    fn annex_b_and_short_length_extradata_are_rejected() {
+        let annex_b = [0, 0, 0, 1, 0x67, 0x64, 0x1f];
+        let two_byte_lengths = [1, 0x64, 0x00, 0x1f, 0xfd, 0xe0, 0x00];
+
+        assert_eq!(
+            length_prefixed_parameter_sets(avcodec::Id::H264, &annex_b),
+            None
+        );
+        assert_eq!(
+            length_prefixed_parameter_sets(avcodec::Id::H264, &two_byte_lengths),
+            None
+        );
+    }
+
+    #[test]
+    fn cut_starts_around_the_requested_timestamp() {
+        let keyframes = [0, 60, 120];
+
+        assert_eq!(keyframes_around(keyframes, 90), (Some(60), Some(120)));
+        assert_eq!(keyframes_around(keyframes, 60), (Some(60), Some(120)));
+        assert_eq!(keyframes_around(keyframes, 150), (Some(120), None));
+        assert_eq!(keyframes_around([10, 70], 5), (None, Some(10)));
+    }
+
+    #[test]
+    fn keyframe_cuts_start_at_the_previous_keyframe() {
+        let start = CutStart::choose(Some(60), Some(120), 90, false);
+
+        assert_eq!(
+            start,
+            Some(CutStart {
+                origin: 60,
+                copy_from: 60,
+                reencode: false,
+            })
+        );
+        assert_eq!(
+            CutStart::choose(None, Some(10), 5, false).map(|s| s.origin),
+            Some(10)
+        );
+        assert_eq!(CutStart::choose(None, None, 5, false), None);
+    }
+
+    #[test]
+    fn smart_cuts_reencode_up_to_the_next_keyframe() {
+        assert_eq!(
+            CutStart::choose(Some(60), Some(120), 90, true),
+            Some(CutStart {
+                origin: 90,
+                copy_from: 120,
+                reencode: true,
+            })
+        );
+        assert_eq!(
+            CutStart::choose(Some(120), None, 150, true),
+            Some(CutStart {
+                origin: 150,
+                copy_from: i64::MAX,
+                reencode: true,
+            })
+        );
+        assert_eq!(
+            CutStart::choose(Some(60), Some(120), 60, true),
+            Some(CutStart {
+                origin: 60,
+                copy_from: 60,
+                reencode: false,
+            })
+        );
+    }
+
+    #[test]
+    fn cut_timestamps_are_relative_to_the_stream_start() {
+        let time_base = Rational::new(1, 90_000);
+
+        assert_eq!(
+            timestamp_at(
+                Duration::from_millis(1500),
+                Duration::from_millis(500),
+                time_base
+            ),
+            180_000
+        );
+        assert_eq!(
+            timestamp_at(Duration::MAX, Duration::ZERO, time_base),
+            i64::MAX
+        );
+    }
+
+    #[test]
+    fn audio_window_is_rescaled_from_the_video_time_base() {
+        let video = Rational::new(1, 15_360);
+        let audio = Rational::new(1, 48_000);
+
+        assert_eq!(
+            stream_window(30_720, 61_440, video, audio),
+            (96_000, 192_000)
+        );
+        assert_eq!(
+            stream_window(30_720, i64::MAX, video, audio),
+            (96_000, i64::MAX)
+        );
+    }
+
+    #[test]
+    fn split_points_bound_consecutive_pieces() {
+        let ten = Duration::from_secs(10);
+        let twenty = Duration::from_secs(20);
+
+        assert_eq!(
+            split_ranges(&[ten, twenty]),
+            vec![Duration::ZERO..ten, ten..twenty, twenty..Duration::MAX]
+        );
+        assert_eq!(split_ranges(&[]), vec![Duration::ZERO..Duration::MAX]);
+    }
+
+    #[test]
+    fn input_tags_carry_over_without_brands() {
+        let metadata = ContainerMetadata::from_tags([
+            ("major_brand", "isom"),
+            ("title", "Demo"),
+            ("encoder", "Cap"),
+            ("cap:project", "abc"),
+        ]);
+
+        assert_eq!(metadata.title.as_deref(), Some("Demo"));
+        assert_eq!(metadata.encoder.as_deref(), Some("Cap"));
+        assert_eq!(
+            metadata.tags,
+            vec![("cap:project".to_string(), "abc".to_string())]
+        );
+    }
 }
@@ -0,0 +1,250 @@
+use std::{thread, time::Duration};
+
+use cap_media_info::{Pixel, VideoInfo};
+use ffmpeg::{
+    Dictionary,
+    codec::{context, encoder},
+    format::{self},
+    frame,
+    threading::Config,
+};
+use tracing::{debug, error};
+
+use crate::base::EncoderBase;
+
+/// Codecs for animated images, which keep full colour where GIF is limited to 256.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnimatedImageCodec {
+    WebP,
+    Apng,
+}
+
+impl AnimatedImageCodec {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::WebP => "webp",
+            Self::Apng => "png",
+        }
+    }
+
+    pub(crate) fn muxer(&self) -> &'static str {
+        match self {
+            Self::WebP => "webp",
+            Self::Apng => "apng",
+        }
+    }
+
+    /// The muxer option holding how many times the animation plays, where 0 loops forever.
+    pub(crate) fn loop_option(&self) -> &'static str {
+        match self {
+            Self::WebP => "loop",
+            Self::Apng => "plays",
+        }
+    }
+
+    fn find_encoder(&self) -> Option<ffmpeg::Codec> {
+        match self {
+            Self::WebP => {
+                encoder::find_by_name("libwebp_anim").or_else(|| encoder::find_by_name("libwebp"))
+            }
+            Self::Apng => encoder::find_by_name("apng"),
+        }
+    }
+}
+
+pub struct AnimatedImageEncoderBuilder {
+    input_config: VideoInfo,
+    codec: AnimatedImageCodec,
+    quality: u8,
+    lossless: bool,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AnimatedImageEncoderError {
+    #[error("{0:?}")]
+    FFmpeg(#[from] ffmpeg::Error),
+    #[error("Codec not found")]
+    CodecNotFound,
+    #[error("Pixel format {0:?} not supported")]
+    PixFmtNotSupported(Pixel),
+}
+
+impl AnimatedImageEncoderBuilder {
+    pub const DEFAULT_QUALITY: u8 = 80;
+
+    pub fn new(input_config: VideoInfo, codec: AnimatedImageCodec) -> Self {
+        Self {
+            input_config,
+            codec,
+            quality: Self::DEFAULT_QUALITY,
+            lossless: false,
+        }
+    }
+
+    /// WebP quality from 0-100. APNG is always lossless and ignores it.
+    pub fn with_quality(mut self, quality: u8) -> Self {
+        self.quality = quality.min(100);
+        self
+    }
+
+    /// Lossless WebP. APNG is always lossless.
+    pub fn with_lossless(mut self) -> Self {
+        self.lossless = true;
+        self
+    }
+
+    fn output_format(&self) -> format::Pixel {
+        match self.codec {
+            AnimatedImageCodec::WebP if self.lossless => format::Pixel::BGRA,
+            AnimatedImageCodec::WebP => format::Pixel::YUVA420P,
+            AnimatedImageCodec::Apng => format::Pixel::RGBA,
+        }
+    }
+
+    pub fn build(
+        self,
+        output: &mut format::context::Output,
+    ) -> Result<AnimatedImageEncoder, AnimatedImageEncoderError> {
+        let input_config = self.input_config;
+        let codec = self
+            .codec
+            .find_encoder()
+            .ok_or(AnimatedImageEncoderError::CodecNotFound)?;
+
+        let output_format = self.output_format();
+        let converter = if input_config.pixel_format != output_format {
+            match ffmpeg::software::scaling::Context::get(
+                input_config.pixel_format,
+                input_config.width,
+                input_config.height,
+                output_format,
+                input_config.width,
+                input_config.height,
+                ffmpeg::software::scaling::flag::Flags::FAST_BILINEAR,
+            ) {
+                Ok(context) => Some(context),
+                Err(e) => {
+                    error!(
+                        "Failed to create converter from {:?} to {:?}: {:?}",
+                        input_config.pixel_format, output_format, e
+                    );
+                    return Err(AnimatedImageEncoderError::PixFmtNotSupported(
+                        input_config.pixel_format,
+                    ));
+                }
+            }
+        } else {
+            None
+        };
+
+        let mut encoder_ctx = context::Context::new_with_codec(codec);
+
+        let thread_count = thread::available_parallelism()
+            .map(|v| v.get())
+            .unwrap_or(1);
+        encoder_ctx.set_threading(Config::count(thread_count));
+        let mut encoder = encoder_ctx.encoder().video()?;
+
+        encoder.set_width(input_config.width);
+        encoder.set_height(input_config.height);
+        encoder.set_format(output_format);
+        encoder.set_time_base(input_config.time_base);
+        encoder.set_frame_rate(Some(input_config.frame_rate));
+
+        let mut options = Dictionary::new();
+        match self.codec {
+            AnimatedImageCodec::WebP => {
+                options.set("lossless", if self.lossless { "1" } else { "0" });
+                options.set("quality", &self.quality.to_string());
+            }
+            AnimatedImageCodec::Apng => {
+                // Tries every PNG filter per row, which is slow but gives the smallest file.
+                options.set("pred", "mixed");
+            }
+        }
+
+        let encoder = encoder.open_with(options)?;
+
+        let mut output_stream = output.add_stream(codec)?;
+        let stream_index = output_stream.index();
+        output_stream.set_time_base(input_config.time_base);
+        output_stream.set_rate(input_config.frame_rate);
+        output_stream.set_parameters(&encoder);
+
+        debug!(
+            "Using {:?} encoder at {}x{}",
+            self.codec, input_config.width, input_config.height
+        );
+
+        Ok(AnimatedImageEncoder {
+            base: EncoderBase::new(stream_index),
+            encoder,
+            converter,
+            output_format,
+            output_width: input_config.width,
+            output_height: input_config.height,
+        })
+    }
+}
+
+pub struct AnimatedImageEncoder {
+    base: EncoderBase,
+    encoder: encoder::Video,
+    converter: Option<ffmpeg::software::scaling::Context>,
+    output_format: format::Pixel,
+    output_width: u32,
+    output_height: u32,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum QueueFrameError {
+    #[error("Converter: {0}")]
+    Converter(ffmpeg::Error),
+    #[error("Encode: {0}")]
+    Encode(ffmpeg::Error),
+}
+
+impl AnimatedImageEncoder {
+    pub fn builder(
+        input_config: VideoInfo,
+        codec: AnimatedImageCodec,
+    ) -> AnimatedImageEncoderBuilder {
+        AnimatedImageEncoderBuilder::new(input_config, codec)
+    }
+
+    pub fn queue_frame_reusable(
+        &mut self,
+        frame: &mut frame::Video,
+        converted_frame: &mut Option<frame::Video>,
+        timestamp: Duration,
+        output: &mut format::context::Output,
+    ) -> Result<(), QueueFrameError> {
+        self.base.update_pts(frame, timestamp, &mut self.encoder);
+
+        let frame_to_send = if let Some(converter) = &mut self.converter {
+            let pts = frame.pts();
+            let converted = converted_frame.get_or_insert_with(|| {
+                frame::Video::new(self.output_format, self.output_width, self.output_height)
+            });
+            converter
+                .run(frame, converted)
+                .map_err(QueueFrameError::Converter)?;
+            converted.set_pts(pts);
+            converted as &frame::Video
+        } else {
+            frame as &frame::Video
+        };
+
+        self.base
+            .send_frame(frame_to_send, output, &mut self.encoder)
+            .map_err(QueueFrameError::Encode)?;
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self, output: &mut format::context::Output) -> Result<(), ffmpeg::Error> {
+        self.base.process_eof(output, &mut self.encoder)
+    }
+}
+
+unsafe impl Send for AnimatedImageEncoder {}
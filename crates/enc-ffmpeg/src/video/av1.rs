@@ -0,0 +1,688 @@
+use std::{thread, time::Duration};
+
+use cap_media_info::{Pixel, VideoInfo, ensure_even};
+use ffmpeg::{
+    Dictionary,
+    codec::{codec::Codec, context, encoder},
+    color,
+    format::{self},
+    frame,
+    threading::Config,
+};
+use tracing::{debug, error, trace, warn};
+
+use crate::base::EncoderBase;
+
+fn is_420(format: ffmpeg::format::Pixel) -> bool {
+    format
+        .descriptor()
+        .map(|desc| desc.log2_chroma_w() == 1 && desc.log2_chroma_h() == 1)
+        .unwrap_or(false)
+}
+
+pub struct Av1EncoderBuilder {
+    bpp: f32,
+    input_config: VideoInfo,
+    preset: Av1Preset,
+    output_size: Option<(u32, u32)>,
+    external_conversion: bool,
+    crf: Option<u8>,
+    target_bitrate: Option<u64>,
+}
+
+#[derive(Clone, Copy)]
+pub enum Av1Preset {
+    Slow,
+    Medium,
+    Ultrafast,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Av1EncoderError {
+    #[error("{0:?}")]
+    FFmpeg(#[from] ffmpeg::Error),
+    #[error("Codec not found")]
+    CodecNotFound,
+    #[error("Pixel format {0:?} not supported")]
+    PixFmtNotSupported(Pixel),
+    #[error("Invalid output dimensions {width}x{height}; expected non-zero even width and height")]
+    InvalidOutputDimensions { width: u32, height: u32 },
+}
+
+impl Av1EncoderBuilder {
+    pub const QUALITY_BPP: f32 = 0.15;
+
+    pub fn new(input_config: VideoInfo) -> Self {
+        Self {
+            input_config,
+            bpp: Self::QUALITY_BPP,
+            preset: Av1Preset::Medium,
+            output_size: None,
+            external_conversion: false,
+            crf: None,
+            target_bitrate: None,
+        }
+    }
+
+    pub fn with_preset(mut self, preset: Av1Preset) -> Self {
+        self.preset = preset;
+        self
+    }
+
+    pub fn with_bpp(mut self, bpp: f32) -> Self {
+        self.bpp = bpp;
+        self
+    }
+
+    pub fn with_output_size(mut self, width: u32, height: u32) -> Result<Self, Av1EncoderError> {
+        if width == 0 || height == 0 {
+            return Err(Av1EncoderError::InvalidOutputDimensions { width, height });
+        }
+
+        self.output_size = Some((width, height));
+        Ok(self)
+    }
+
+    pub fn with_external_conversion(mut self) -> Self {
+        self.external_conversion = true;
+        self
+    }
+
+    /// Constant quality on AV1's 0-63 scale. Hardware encoders get it mapped onto their
+    /// own quantizer range.
+    pub fn with_crf(mut self, crf: u8) -> Self {
+        self.crf = Some(crf.min(63));
+        self
+    }
+
+    /// Encodes with constrained VBR around `bits_per_second` instead of deriving the bitrate
+    /// from `bpp`, so the output size is predictable. Takes precedence over `with_crf`.
+    pub fn with_target_bitrate(mut self, bits_per_second: u64) -> Self {
+        self.target_bitrate = Some(bits_per_second);
+        self
+    }
+
+    pub fn build(
+        self,
+        output: &mut format::context::Output,
+    ) -> Result<Av1Encoder, Av1EncoderError> {
+        let input_config = self.input_config;
+        let (raw_width, raw_height) = self
+            .output_size
+            .unwrap_or((input_config.width, input_config.height));
+
+        let output_width = ensure_even(raw_width);
+        let output_height = ensure_even(raw_height);
+
+        if raw_width != output_width || raw_height != output_height {
+            warn!(
+                raw_width,
+                raw_height,
+                output_width,
+                output_height,
+                "Auto-adjusted odd dimensions to even for AV1 encoding"
+            );
+        }
+
+        let crf = self.crf.filter(|_| self.target_bitrate.is_none());
+        let candidates = get_codec_and_options(&input_config, self.preset, crf);
+        if candidates.is_empty() {
+            return Err(Av1EncoderError::CodecNotFound);
+        }
+
+        let mut last_error = None;
+
+        for (codec, encoder_options) in candidates {
+            let codec_name = codec.name().to_string();
+
+            match Self::build_with_codec(
+                codec,
+                encoder_options,
+                &input_config,
+                output,
+                output_width,
+                output_height,
+                self.bpp,
+                self.target_bitrate,
+                self.external_conversion,
+                crf,
+            ) {
+                Ok(encoder) => {
+                    debug!("Using AV1 encoder {}", codec_name);
+                    return Ok(encoder);
+                }
+                Err(err) => {
+                    debug!("AV1 encoder {} init failed: {:?}", codec_name, err);
+                    last_error = Some(err);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or(Av1EncoderError::CodecNotFound))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn build_with_codec(
+        codec: Codec,
+        encoder_options: Dictionary<'static>,
+        input_config: &VideoInfo,
+        output: &mut format::context::Output,
+        output_width: u32,
+        output_height: u32,
+        bpp: f32,
+        target_bitrate: Option<u64>,
+        external_conversion: bool,
+        crf: Option<u8>,
+    ) -> Result<Av1Encoder, Av1EncoderError> {
+        let supported_formats = codec
+            .video()
+            .ok()
+            .and_then(|codec_video| codec_video.formats())
+            .map(|formats| formats.collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        let encoder_supports_input_format = supported_formats.contains(&input_config.pixel_format);
+
+        let mut needs_pixel_conversion = false;
+
+        let output_format = if encoder_supports_input_format {
+            input_config.pixel_format
+        } else {
+            needs_pixel_conversion = true;
+            // The software encoders only take planar YUV, most hardware ones only NV12.
+            let format = if supported_formats.contains(&ffmpeg::format::Pixel::YUV420P) {
+                ffmpeg::format::Pixel::YUV420P
+            } else {
+                ffmpeg::format::Pixel::NV12
+            };
+            if !external_conversion {
+                debug!(
+                    "Converting from {:?} to {:?} for AV1 encoding",
+                    input_config.pixel_format, format
+                );
+            }
+            format
+        };
+
+        if is_420(output_format)
+            && (!output_width.is_multiple_of(2) || !output_height.is_multiple_of(2))
+        {
+            return Err(Av1EncoderError::InvalidOutputDimensions {
+                width: output_width,
+                height: output_height,
+            });
+        }
+
+        let needs_scaling =
+            output_width != input_config.width || output_height != input_config.height;
+
+        if needs_scaling && !external_conversion {
+            debug!(
+                "Scaling video frames for AV1 encoding from {}x{} to {}x{}",
+                input_config.width, input_config.height, output_width, output_height
+            );
+        }
+
+        let converter = if external_conversion {
+            debug!(
+                "External conversion enabled, skipping internal converter. Expected input: {:?} {}x{}",
+                output_format, output_width, output_height
+            );
+            None
+        } else if needs_pixel_conversion || needs_scaling {
+            let flags = if needs_scaling {
+                ffmpeg::software::scaling::flag::Flags::BICUBIC
+            } else {
+                ffmpeg::software::scaling::flag::Flags::FAST_BILINEAR
+            };
+
+            match ffmpeg::software::scaling::Context::get(
+                input_config.pixel_format,
+                input_config.width,
+                input_config.height,
+                output_format,
+                output_width,
+                output_height,
+                flags,
+            ) {
+                Ok(context) => Some(context),
+                Err(e) => {
+                    if needs_pixel_conversion {
+                        error!(
+                            "Failed to create converter from {:?} to {:?}: {:?}",
+                            input_config.pixel_format, output_format, e
+                        );
+                        return Err(Av1EncoderError::PixFmtNotSupported(
+                            input_config.pixel_format,
+                        ));
+                    }
+
+                    return Err(Av1EncoderError::FFmpeg(e));
+                }
+            }
+        } else {
+            None
+        };
+
+        let mut encoder_ctx = context::Context::new_with_codec(codec);
+
+        let thread_count = thread::available_parallelism()
+            .map(|v| v.get())
+            .unwrap_or(1);
+        encoder_ctx.set_threading(Config::count(thread_count));
+        let mut encoder = encoder_ctx.encoder().video()?;
+
+        encoder.set_width(output_width);
+        encoder.set_height(output_height);
+        encoder.set_format(output_format);
+        encoder.set_time_base(input_config.time_base);
+        encoder.set_frame_rate(Some(input_config.frame_rate));
+        encoder.set_colorspace(color::Space::BT709);
+        encoder.set_color_range(color::Range::MPEG);
+        unsafe {
+            (*encoder.as_mut_ptr()).color_primaries =
+                ffmpeg::ffi::AVColorPrimaries::AVCOL_PRI_BT709;
+            (*encoder.as_mut_ptr()).color_trc =
+                ffmpeg::ffi::AVColorTransferCharacteristic::AVCOL_TRC_BT709;
+        }
+
+        if crf.is_some() {
+            encoder.set_bit_rate(0);
+        } else {
+            let bitrate = target_bitrate.map(|b| b as usize).unwrap_or_else(|| {
+                get_bitrate(
+                    output_width,
+                    output_height,
+                    input_config.frame_rate.0 as f32 / input_config.frame_rate.1.max(1) as f32,
+                    bpp,
+                )
+            });
+
+            encoder.set_bit_rate(bitrate);
+            unsafe {
+                (*encoder.as_mut_ptr()).rc_max_rate = (bitrate as f64 * 1.5) as i64;
+                (*encoder.as_mut_ptr()).rc_buffer_size = bitrate as i32;
+            }
+        }
+
+        let encoder = encoder.open_with(encoder_options)?;
+
+        let mut output_stream = output.add_stream(codec)?;
+        let stream_index = output_stream.index();
+        output_stream.set_time_base((1, Av1Encoder::TIME_BASE));
+        output_stream.set_rate(input_config.frame_rate);
+        output_stream.set_parameters(&encoder);
+
+        Ok(Av1Encoder {
+            base: EncoderBase::new(stream_index),
+            encoder,
+            converter,
+            output_format,
+            output_width,
+            output_height,
+            input_format: input_config.pixel_format,
+            input_width: input_config.width,
+            input_height: input_config.height,
+        })
+    }
+}
+
+pub struct Av1Encoder {
+    base: EncoderBase,
+    encoder: encoder::Video,
+    converter: Option<ffmpeg::software::scaling::Context>,
+    output_format: format::Pixel,
+    output_width: u32,
+    output_height: u32,
+    input_format: format::Pixel,
+    input_width: u32,
+    input_height: u32,
+}
+
+pub struct ConversionRequirements {
+    pub input_format: format::Pixel,
+    pub input_width: u32,
+    pub input_height: u32,
+    pub output_format: format::Pixel,
+    pub output_width: u32,
+    pub output_height: u32,
+    pub needs_conversion: bool,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum QueueFrameError {
+    #[error("Converter: {0}")]
+    Converter(ffmpeg::Error),
+    #[error("Encode: {0}")]
+    Encode(ffmpeg::Error),
+}
+
+impl Av1Encoder {
+    const TIME_BASE: i32 = 1000;
+
+    pub fn builder(input_config: VideoInfo) -> Av1EncoderBuilder {
+        Av1EncoderBuilder::new(input_config)
+    }
+
+    pub fn output_format(&self) -> format::Pixel {
+        self.output_format
+    }
+
+    /// Forces a keyframe at each of `timestamps`, measured from the first frame queued,
+    /// so the output can be seeked to and cut at exactly those points.
+    pub fn force_keyframes_at(&mut self, timestamps: impl IntoIterator<Item = Duration>) {
+        let time_base = self.encoder.time_base();
+        let rate = time_base.denominator() as f64 / time_base.numerator() as f64;
+        self.base.force_keyframes(
+            timestamps
+                .into_iter()
+                .map(|timestamp| (timestamp.as_secs_f64() * rate).round() as i64),
+        );
+    }
+
+    pub fn conversion_requirements(&self) -> ConversionRequirements {
+        let needs_conversion = self.input_format != self.output_format
+            || self.input_width != self.output_width
+            || self.input_height != self.output_height;
+        ConversionRequirements {
+            input_format: self.input_format,
+            input_width: self.input_width,
+            input_height: self.input_height,
+            output_format: self.output_format,
+            output_width: self.output_width,
+            output_height: self.output_height,
+            needs_conversion,
+        }
+    }
+
+    pub fn queue_frame(
+        &mut self,
+        mut frame: frame::Video,
+        timestamp: Duration,
+        output: &mut format::context::Output,
+    ) -> Result<(), QueueFrameError> {
+        self.base
+            .update_pts(&mut frame, timestamp, &mut self.encoder);
+
+        if let Some(converter) = &mut self.converter {
+            let pts = frame.pts();
+            let mut converted =
+                frame::Video::new(self.output_format, self.output_width, self.output_height);
+            converter
+                .run(&frame, &mut converted)
+                .map_err(QueueFrameError::Converter)?;
+            converted.set_pts(pts);
+            frame = converted;
+        }
+        self.base.mark_forced_keyframe(&mut frame);
+
+        self.base
+            .send_frame(&frame, output, &mut self.encoder)
+            .map_err(QueueFrameError::Encode)?;
+
+        Ok(())
+    }
+
+    pub fn queue_frame_reusable(
+        &mut self,
+        frame: &mut frame::Video,
+        converted_frame: &mut Option<frame::Video>,
+        timestamp: Duration,
+        output: &mut format::context::Output,
+    ) -> Result<(), QueueFrameError> {
+        self.base.update_pts(frame, timestamp, &mut self.encoder);
+
+        let frame_to_send = if let Some(converter) = &mut self.converter {
+            let pts = frame.pts();
+            let converted = converted_frame.get_or_insert_with(|| {
+                frame::Video::new(self.output_format, self.output_width, self.output_height)
+            });
+            converter
+                .run(frame, converted)
+                .map_err(QueueFrameError::Converter)?;
+            converted.set_pts(pts);
+            converted
+        } else {
+            frame
+        };
+        self.base.mark_forced_keyframe(frame_to_send);
+
+        self.base
+            .send_frame(frame_to_send, output, &mut self.encoder)
+            .map_err(QueueFrameError::Encode)?;
+
+        Ok(())
+    }
+
+    pub fn queue_preconverted_frame(
+        &mut self,
+        mut frame: frame::Video,
+        timestamp: Duration,
+        output: &mut format::context::Output,
+    ) -> Result<(), QueueFrameError> {
+        trace!(
+            "Encoding pre-converted frame: format={:?}, size={}x{}, expected={:?} {}x{}",
+            frame.format(),
+            frame.width(),
+            frame.height(),
+            self.output_format,
+            self.output_width,
+            self.output_height
+        );
+
+        self.base
+            .update_pts(&mut frame, timestamp, &mut self.encoder);
+        self.base.mark_forced_keyframe(&mut frame);
+
+        self.base
+            .send_frame(&frame, output, &mut self.encoder)
+            .map_err(QueueFrameError::Encode)?;
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self, output: &mut format::context::Output) -> Result<(), ffmpeg::Error> {
+        self.base.process_eof(output, &mut self.encoder)
+    }
+}
+
+/// Software encoders come first: they're available everywhere and compress better than
+/// the first generations of AV1 hardware. Hardware encoders are tried when neither is
+/// built in.
+fn get_encoder_priority() -> &'static [&'static str] {
+    // VideoToolbox can decode AV1 but has no encoder.
+    #[cfg(target_os = "macos")]
+    {
+        &["libsvtav1", "libaom-av1"]
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        use cap_frame_converter::{GpuVendor, detect_primary_gpu};
+
+        static ENCODER_PRIORITY_NVIDIA: &[&str] = &[
+            "libsvtav1",
+            "libaom-av1",
+            "av1_nvenc",
+            "av1_mf",
+            "av1_qsv",
+            "av1_amf",
+        ];
+        static ENCODER_PRIORITY_AMD: &[&str] = &[
+            "libsvtav1",
+            "libaom-av1",
+            "av1_amf",
+            "av1_mf",
+            "av1_nvenc",
+            "av1_qsv",
+        ];
+        static ENCODER_PRIORITY_INTEL: &[&str] = &[
+            "libsvtav1",
+            "libaom-av1",
+            "av1_qsv",
+            "av1_mf",
+            "av1_nvenc",
+            "av1_amf",
+        ];
+        static ENCODER_PRIORITY_DEFAULT: &[&str] = &[
+            "libsvtav1",
+            "libaom-av1",
+            "av1_nvenc",
+            "av1_qsv",
+            "av1_amf",
+            "av1_mf",
+        ];
+
+        match detect_primary_gpu().map(|info| info.vendor) {
+            Some(GpuVendor::Nvidia) => ENCODER_PRIORITY_NVIDIA,
+            Some(GpuVendor::Amd) => ENCODER_PRIORITY_AMD,
+            Some(GpuVendor::Intel) => ENCODER_PRIORITY_INTEL,
+            _ => ENCODER_PRIORITY_DEFAULT,
+        }
+    }
+
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    {
+        &["libsvtav1", "libaom-av1", "av1_nvenc", "av1_qsv"]
+    }
+}
+
+/// Maps a CRF on AV1's 0-63 scale to the 0-255 quantizer index hardware encoders take.
+fn crf_to_qindex(crf: u8) -> u32 {
+    (crf as u32 * 255 / 63).min(255)
+}
+
+fn get_codec_and_options(
+    config: &VideoInfo,
+    preset: Av1Preset,
+    crf: Option<u8>,
+) -> Vec<(Codec, Dictionary<'static>)> {
+    let keyframe_interval_secs = 2;
+    let denominator = config.frame_rate.denominator();
+    let frames_per_sec = config.frame_rate.numerator() as f64
+        / if denominator == 0 { 1 } else { denominator } as f64;
+    let keyframe_interval = (keyframe_interval_secs as f64 * frames_per_sec)
+        .round()
+        .max(1.0) as i32;
+    let keyframe_interval_str = keyframe_interval.to_string();
+    let qindex_str = crf.map(|crf| crf_to_qindex(crf).to_string());
+
+    let mut encoders = Vec::new();
+
+    for encoder_name in get_encoder_priority() {
+        let Some(codec) = encoder::find_by_name(encoder_name) else {
+            continue;
+        };
+
+        let mut options = Dictionary::new();
+
+        match *encoder_name {
+            "libsvtav1" => {
+                options.set(
+                    "preset",
+                    match preset {
+                        Av1Preset::Slow => "4",
+                        Av1Preset::Medium => "7",
+                        Av1Preset::Ultrafast => "10",
+                    },
+                );
+                options.set("g", &keyframe_interval_str);
+                if let Some(crf) = crf {
+                    options.set("crf", &crf.to_string());
+                }
+            }
+            "libaom-av1" => {
+                options.set("usage", "good");
+                options.set(
+                    "cpu-used",
+                    match preset {
+                        Av1Preset::Slow => "3",
+                        Av1Preset::Medium => "5",
+                        Av1Preset::Ultrafast => "8",
+                    },
+                );
+                options.set("row-mt", "1");
+                options.set("tiles", "2x2");
+                options.set("g", &keyframe_interval_str);
+                if let Some(crf) = crf {
+                    options.set("crf", &crf.to_string());
+                }
+            }
+            "av1_nvenc" => {
+                options.set(
+                    "preset",
+                    match preset {
+                        Av1Preset::Slow => "p7",
+                        Av1Preset::Medium => "p5",
+                        Av1Preset::Ultrafast => "p2",
+                    },
+                );
+                options.set("tune", "hq");
+                options.set("spatial-aq", "1");
+                options.set("rc-lookahead", "32");
+                options.set("g", &keyframe_interval_str);
+                match &qindex_str {
+                    Some(qindex) => {
+                        options.set("rc", "constqp");
+                        options.set("qp", qindex);
+                    }
+                    None => options.set("rc", "vbr"),
+                }
+            }
+            "av1_qsv" => {
+                options.set(
+                    "preset",
+                    match preset {
+                        Av1Preset::Slow => "slower",
+                        Av1Preset::Medium => "medium",
+                        Av1Preset::Ultrafast => "veryfast",
+                    },
+                );
+                options.set("g", &keyframe_interval_str);
+                if let Some(qindex) = &qindex_str {
+                    options.set("global_quality", qindex);
+                }
+            }
+            "av1_amf" => {
+                options.set(
+                    "quality",
+                    match preset {
+                        Av1Preset::Slow => "quality",
+                        Av1Preset::Medium => "balanced",
+                        Av1Preset::Ultrafast => "speed",
+                    },
+                );
+                options.set("g", &keyframe_interval_str);
+                match &qindex_str {
+                    Some(qindex) => {
+                        options.set("rc", "cqp");
+                        options.set("qp_i", qindex);
+                        options.set("qp_p", qindex);
+                    }
+                    None => options.set("rc", "vbr_latency"),
+                }
+            }
+            "av1_mf" => {
+                options.set("hw_encoding", "true");
+                options.set("g", &keyframe_interval_str);
+                if let Some(crf) = crf {
+                    options.set("rate_control", "quality");
+                    options.set("quality", &(100 - crf as u32 * 100 / 63).to_string());
+                }
+            }
+            _ => {}
+        }
+
+        encoders.push((codec, options));
+    }
+
+    encoders
+}
+
+fn get_bitrate(width: u32, height: u32, frame_rate: f32, bpp: f32) -> usize {
+    let frame_rate_multiplier = ((frame_rate as f64 - 30.0).max(0.0) * 0.6) + 30.0;
+    let area = (width as f64) * (height as f64);
+    let pixels_per_second = area * frame_rate_multiplier;
+
+    (pixels_per_second * bpp as f64) as usize
+}
@@ -11,7 +11,7 @@ use ffmpeg::{
 };
 use tracing::{debug, error, trace, warn};
 
-use crate::base::EncoderBase;
+use crate::{base::EncoderBase, rate_control::RateControl};
 
 fn is_420(format: ffmpeg::format::Pixel) -> bool {
     format
@@ -26,6 +26,9 @@ pub struct H264EncoderBuilder {
     preset: H264Preset,
     output_size: Option<(u32, u32)>,
     external_conversion: bool,
+    rate_control: Option<RateControl>,
+    keyframe_interval: Duration,
+    b_frames: Option<u32>,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -58,6 +61,9 @@ impl H264EncoderBuilder {
             preset: H264Preset::Ultrafast,
             output_size: None,
             external_conversion: false,
+            rate_control: None,
+            keyframe_interval: Duration::from_secs(DEFAULT_KEYFRAME_INTERVAL_SECS as u64),
+            b_frames: None,
         }
     }
 
@@ -85,6 +91,34 @@ impl H264EncoderBuilder {
         self
     }
 
+    /// Encodes with constrained VBR around `bits_per_second` instead of deriving the bitrate
+    /// from `bpp`, so the output size is predictable. Hardware encoders that default to
+    /// constant QP are switched to VBR.
+    pub fn with_target_bitrate(self, bits_per_second: u64) -> Self {
+        self.with_rate_control(RateControl::constrained_vbr(bits_per_second))
+    }
+
+    /// Overrides the default rate control (a `bpp`-derived bitrate, or constant QP on
+    /// NVENC/AMF/QSV). Replaces any earlier `with_target_bitrate`.
+    pub fn with_rate_control(mut self, rate_control: RateControl) -> Self {
+        self.rate_control = Some(rate_control);
+        self
+    }
+
+    /// Maximum time between keyframes (GOP length). Defaults to
+    /// [`DEFAULT_KEYFRAME_INTERVAL_SECS`].
+    pub fn with_keyframe_interval(mut self, interval: Duration) -> Self {
+        self.keyframe_interval = interval;
+        self
+    }
+
+    /// Maximum consecutive B-frames. Each encoder has its own default, which is none for
+    /// the low-latency hardware configurations.
+    pub fn with_b_frames(mut self, b_frames: u32) -> Self {
+        self.b_frames = Some(b_frames);
+        self
+    }
+
     pub fn build(
         self,
         output: &mut format::context::Output,
@@ -107,7 +141,12 @@ impl H264EncoderBuilder {
             );
         }
 
-        let candidates = get_codec_and_options(&input_config, self.preset);
+        let candidates = get_codec_and_options(
+            &input_config,
+            self.preset,
+            self.rate_control.is_some(),
+            self.keyframe_interval,
+        );
         if candidates.is_empty() {
             return Err(H264EncoderError::CodecNotFound);
         }
@@ -125,6 +164,8 @@ impl H264EncoderBuilder {
                 output_width,
                 output_height,
                 self.bpp,
+                self.rate_control,
+                self.b_frames,
                 self.external_conversion,
             ) {
                 Ok(encoder) => {
@@ -179,12 +220,14 @@ impl H264EncoderBuilder {
     #[allow(clippy::too_many_arguments)]
     fn build_with_codec(
         codec: Codec,
-        encoder_options: Dictionary<'static>,
+        mut encoder_options: Dictionary<'static>,
         input_config: &VideoInfo,
         output: &mut format::context::Output,
         output_width: u32,
         output_height: u32,
         bpp: f32,
+        rate_control: Option<RateControl>,
+        b_frames: Option<u32>,
         external_conversion: bool,
     ) -> Result<H264Encoder, H264EncoderError> {
         let encoder_supports_input_format = codec
@@ -310,12 +353,15 @@ impl H264EncoderBuilder {
                 ffmpeg::ffi::AVColorTransferCharacteristic::AVCOL_TRC_BT709;
         }
 
-        let is_cqp_encoder = matches!(
-            codec.name(),
-            "h264_nvenc" | "h264_amf" | "h264_qsv"
-        );
+        let is_cqp_encoder = matches!(codec.name(), "h264_nvenc" | "h264_amf" | "h264_qsv");
 
-        if is_cqp_encoder {
+        if let Some(rate_control) = rate_control {
+            rate_control.for_encoder(codec.name()).apply(
+                codec.name(),
+                &mut encoder,
+                &mut encoder_options,
+            );
+        } else if is_cqp_encoder {
             encoder.set_bit_rate(0);
             unsafe {
                 (*encoder.as_mut_ptr()).rc_max_rate = 0;
@@ -335,6 +381,14 @@ impl H264EncoderBuilder {
             }
         }
 
+        if let Some(b_frames) = b_frames {
+            encoder_options.set("bf", &b_frames.to_string());
+            // Baseline has no B-frames.
+            if b_frames > 0 && codec.name() == "h264_videotoolbox" {
+                encoder_options.set("profile", "high");
+            }
+        }
+
         let encoder = encoder.open_with(encoder_options)?;
 
         let mut output_stream = output.add_stream(codec)?;
@@ -406,6 +460,18 @@ impl H264Encoder {
         H264EncoderBuilder::new(input_config)
     }
 
+    /// Forces an IDR frame at each of `timestamps`, measured from the first frame queued,
+    /// so the output can be seeked to and cut at exactly those points.
+    pub fn force_keyframes_at(&mut self, timestamps: impl IntoIterator<Item = Duration>) {
+        let time_base = self.encoder.time_base();
+        let rate = time_base.denominator() as f64 / time_base.numerator() as f64;
+        self.base.force_keyframes(
+            timestamps
+                .into_iter()
+                .map(|timestamp| (timestamp.as_secs_f64() * rate).round() as i64),
+        );
+    }
+
     pub fn conversion_requirements(&self) -> ConversionRequirements {
         let needs_conversion = self.input_format != self.output_format
             || self.input_width != self.output_width
@@ -437,10 +503,11 @@ impl H264Encoder {
                 .run(&frame, converted)
                 .map_err(QueueFrameError::Converter)?;
             converted.set_pts(pts);
-            converted as &frame::Video
+            converted
         } else {
-            &frame
+            &mut frame
         };
+        self.base.mark_forced_keyframe(frame_to_send);
 
         self.base
             .send_frame(frame_to_send, output, &mut self.encoder)
@@ -467,10 +534,11 @@ impl H264Encoder {
                 .run(frame, converted)
                 .map_err(QueueFrameError::Converter)?;
             converted.set_pts(pts);
-            converted as &frame::Video
+            converted
         } else {
-            frame as &frame::Video
+            frame
         };
+        self.base.mark_forced_keyframe(frame_to_send);
 
         self.base
             .send_frame(frame_to_send, output, &mut self.encoder)
@@ -497,6 +565,7 @@ impl H264Encoder {
 
         self.base
             .update_pts(&mut frame, timestamp, &mut self.encoder);
+        self.base.mark_forced_keyframe(&mut frame);
 
         self.base
             .send_frame(&frame, output, &mut self.encoder)
@@ -645,14 +714,14 @@ pub const DEFAULT_KEYFRAME_INTERVAL_SECS: u32 = 3;
 fn get_codec_and_options(
     config: &VideoInfo,
     preset: H264Preset,
+    explicit_rate_control: bool,
+    keyframe_interval: Duration,
 ) -> Vec<(Codec, Dictionary<'static>)> {
-    let keyframe_interval_secs = DEFAULT_KEYFRAME_INTERVAL_SECS;
+    let keyframe_interval_secs = keyframe_interval.as_secs_f64();
     let denominator = config.frame_rate.denominator();
     let frames_per_sec = config.frame_rate.numerator() as f64
         / if denominator == 0 { 1 } else { denominator } as f64;
-    let keyframe_interval = (keyframe_interval_secs as f64 * frames_per_sec)
-        .round()
-        .max(1.0) as i32;
+    let keyframe_interval = (keyframe_interval_secs * frames_per_sec).round().max(1.0) as i32;
     let keyframe_interval_str = keyframe_interval.to_string();
 
     let encoder_priority = get_encoder_priority(config, preset);
@@ -675,23 +744,31 @@ fn get_codec_and_options(
             "h264_nvenc" => {
                 options.set("preset", "p5");
                 options.set("tune", "ll");
-                options.set("rc", "constqp");
-                options.set("qp", "20");
+                if !explicit_rate_control {
+                    options.set("rc", "constqp");
+                    options.set("qp", "20");
+                }
                 options.set("rc-lookahead", "0");
                 options.set("bf", "0");
+                options.set("forced-idr", "1");
                 options.set("g", &keyframe_interval_str);
             }
             "h264_qsv" => {
                 options.set("preset", "faster");
-                options.set("global_quality", "20");
+                if !explicit_rate_control {
+                    options.set("global_quality", "20");
+                }
                 options.set("look_ahead", "0");
+                options.set("forced_idr", "1");
                 options.set("g", &keyframe_interval_str);
             }
             "h264_amf" => {
                 options.set("quality", "quality");
-                options.set("rc", "cqp");
-                options.set("qp_i", "20");
-                options.set("qp_p", "20");
+                if !explicit_rate_control {
+                    options.set("rc", "cqp");
+                    options.set("qp_i", "20");
+                    options.set("qp_p", "20");
+                }
                 options.set("bf", "0");
                 options.set("g", &keyframe_interval_str);
             }
@@ -712,6 +789,7 @@ fn get_codec_and_options(
                     },
                 );
                 options.set("vsync", "1");
+                options.set("forced-idr", "1");
                 options.set("g", &keyframe_interval_str);
                 options.set("keyint_min", &keyframe_interval_str);
             }
@@ -11,7 +11,7 @@ use ffmpeg::{
 };
 use tracing::{debug, error, trace, warn};
 
-use crate::base::EncoderBase;
+use crate::{base::EncoderBase, rate_control::RateControl};
 
 fn is_420(format: ffmpeg::format::Pixel) -> bool {
     format
@@ -20,13 +20,41 @@ fn is_420(format: ffmpeg::format::Pixel) -> bool {
         .unwrap_or(false)
 }
 
+fn is_10_bit(format: ffmpeg::format::Pixel) -> bool {
+    matches!(
+        format,
+        ffmpeg::format::Pixel::P010LE | ffmpeg::format::Pixel::YUV420P10LE
+    )
+}
+
 pub struct HevcEncoderBuilder {
     bpp: f32,
     input_config: VideoInfo,
     preset: HevcPreset,
     output_size: Option<(u32, u32)>,
     external_conversion: bool,
-    crf: Option<u8>,
+    rate_control: Option<RateControl>,
+    hdr: Option<HdrTransfer>,
+    keyframe_interval: Duration,
+    b_frames: Option<u32>,
+}
+
+/// Transfer function for HDR output, which is always BT.2020 at 10 bits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HdrTransfer {
+    /// SMPTE ST 2084, as used by HDR10.
+    Pq,
+    /// ARIB STD-B67 hybrid log-gamma, which SDR displays can show without tone mapping.
+    Hlg,
+}
+
+impl HdrTransfer {
+    pub fn color_trc(&self) -> ffmpeg::ffi::AVColorTransferCharacteristic {
+        match self {
+            Self::Pq => ffmpeg::ffi::AVColorTransferCharacteristic::AVCOL_TRC_SMPTE2084,
+            Self::Hlg => ffmpeg::ffi::AVColorTransferCharacteristic::AVCOL_TRC_ARIB_STD_B67,
+        }
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -46,6 +74,8 @@ pub enum HevcEncoderError {
     PixFmtNotSupported(Pixel),
     #[error("Invalid output dimensions {width}x{height}; expected non-zero even width and height")]
     InvalidOutputDimensions { width: u32, height: u32 },
+    #[error("Encoder {0} has no 10-bit output")]
+    TenBitNotSupported(String),
 }
 
 impl HevcEncoderBuilder {
@@ -58,7 +88,10 @@ impl HevcEncoderBuilder {
             preset: HevcPreset::Ultrafast,
             output_size: None,
             external_conversion: false,
-            crf: None,
+            rate_control: None,
+            hdr: None,
+            keyframe_interval: Duration::from_secs(DEFAULT_KEYFRAME_INTERVAL_SECS as u64),
+            b_frames: None,
         }
     }
 
@@ -87,11 +120,46 @@ impl HevcEncoderBuilder {
     }
 
     pub fn with_crf(mut self, crf: u8) -> Self {
-        self.crf = Some(crf);
+        self.rate_control = Some(RateControl::Crf(crf));
         self.preset = HevcPreset::Medium;
         self
     }
 
+    /// Encodes with constrained VBR around `bits_per_second` instead of deriving the bitrate
+    /// from `bpp`, so the output size is predictable. Replaces an earlier `with_crf`.
+    pub fn with_target_bitrate(self, bits_per_second: u64) -> Self {
+        self.with_rate_control(RateControl::constrained_vbr(bits_per_second))
+    }
+
+    /// Overrides the default `bpp`-derived VBR. Quality-based modes use the slower,
+    /// higher quality encoder settings that `with_crf` does.
+    pub fn with_rate_control(mut self, rate_control: RateControl) -> Self {
+        self.rate_control = Some(rate_control);
+        self
+    }
+
+    /// Encodes 10-bit Main10 with BT.2020 primaries and the given transfer instead of 8-bit
+    /// BT.709. Input frames should already be in that colour space; they're only converted
+    /// to the encoder's 10-bit layout (P010 or planar). Encoders without 10-bit output are
+    /// skipped.
+    pub fn with_hdr(mut self, transfer: HdrTransfer) -> Self {
+        self.hdr = Some(transfer);
+        self
+    }
+
+    /// Maximum time between keyframes (GOP length). Defaults to
+    /// [`DEFAULT_KEYFRAME_INTERVAL_SECS`].
+    pub fn with_keyframe_interval(mut self, interval: Duration) -> Self {
+        self.keyframe_interval = interval;
+        self
+    }
+
+    /// Maximum consecutive B-frames, overriding each encoder's default.
+    pub fn with_b_frames(mut self, b_frames: u32) -> Self {
+        self.b_frames = Some(b_frames);
+        self
+    }
+
     pub fn build(
         self,
         output: &mut format::context::Output,
@@ -114,10 +182,18 @@ impl HevcEncoderBuilder {
             );
         }
 
-        let candidates = if let Some(crf_val) = self.crf {
-            get_codec_and_options_crf(&input_config, crf_val)
+        let candidates = if self
+            .rate_control
+            .is_some_and(|rate_control| rate_control.is_quality_based())
+        {
+            get_codec_and_options_quality(&input_config, self.keyframe_interval)
         } else {
-            get_codec_and_options(&input_config, self.preset)
+            get_codec_and_options(
+                &input_config,
+                self.preset,
+                self.rate_control.is_some(),
+                self.keyframe_interval,
+            )
         };
         if candidates.is_empty() {
             return Err(HevcEncoderError::CodecNotFound);
@@ -136,8 +212,10 @@ impl HevcEncoderBuilder {
                 output_width,
                 output_height,
                 self.bpp,
+                self.rate_control,
+                self.hdr,
+                self.b_frames,
                 self.external_conversion,
-                self.crf,
             ) {
                 Ok(encoder) => {
                     debug!("Using HEVC encoder {}", codec_name);
@@ -156,20 +234,26 @@ impl HevcEncoderBuilder {
     #[allow(clippy::too_many_arguments)]
     fn build_with_codec(
         codec: Codec,
-        encoder_options: Dictionary<'static>,
+        mut encoder_options: Dictionary<'static>,
         input_config: &VideoInfo,
         output: &mut format::context::Output,
         output_width: u32,
         output_height: u32,
         bpp: f32,
+        rate_control: Option<RateControl>,
+        hdr: Option<HdrTransfer>,
+        b_frames: Option<u32>,
         external_conversion: bool,
-        crf: Option<u8>,
     ) -> Result<HevcEncoder, HevcEncoderError> {
-        let encoder_supports_input_format = codec
+        let supported_formats = codec
             .video()
             .ok()
             .and_then(|codec_video| codec_video.formats())
-            .is_some_and(|mut formats| formats.any(|f| f == input_config.pixel_format));
+            .map(|formats| formats.collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        let encoder_supports_input_format = supported_formats.contains(&input_config.pixel_format)
+            && (hdr.is_none() || is_10_bit(input_config.pixel_format));
 
         let mut needs_pixel_conversion = false;
 
@@ -177,7 +261,18 @@ impl HevcEncoderBuilder {
             input_config.pixel_format
         } else {
             needs_pixel_conversion = true;
-            let format = ffmpeg::format::Pixel::NV12;
+            let format = if hdr.is_some() {
+                // Hardware encoders take P010, libx265 only planar 10-bit.
+                [
+                    ffmpeg::format::Pixel::P010LE,
+                    ffmpeg::format::Pixel::YUV420P10LE,
+                ]
+                .into_iter()
+                .find(|format| supported_formats.contains(format))
+                .ok_or_else(|| HevcEncoderError::TenBitNotSupported(codec.name().to_string()))?
+            } else {
+                ffmpeg::format::Pixel::NV12
+            };
             if !external_conversion {
                 debug!(
                     "Converting from {:?} to {:?} for HEVC encoding",
@@ -260,20 +355,33 @@ impl HevcEncoderBuilder {
         encoder.set_format(output_format);
         encoder.set_time_base(input_config.time_base);
         encoder.set_frame_rate(Some(input_config.frame_rate));
-        encoder.set_colorspace(color::Space::BT709);
         encoder.set_color_range(color::Range::MPEG);
-        unsafe {
-            (*encoder.as_mut_ptr()).color_primaries =
-                ffmpeg::ffi::AVColorPrimaries::AVCOL_PRI_BT709;
-            (*encoder.as_mut_ptr()).color_trc =
-                ffmpeg::ffi::AVColorTransferCharacteristic::AVCOL_TRC_BT709;
-        }
-
-        if let Some(crf_val) = crf {
+        if let Some(transfer) = hdr {
+            encoder.set_colorspace(color::Space::BT2020NCL);
+            unsafe {
+                (*encoder.as_mut_ptr()).color_primaries =
+                    ffmpeg::ffi::AVColorPrimaries::AVCOL_PRI_BT2020;
+                (*encoder.as_mut_ptr()).color_trc = transfer.color_trc();
+            }
+            if codec.name() != "hevc_mf" {
+                encoder_options.set("profile", "main10");
+            }
+        } else {
+            encoder.set_colorspace(color::Space::BT709);
             unsafe {
-                (*encoder.as_mut_ptr()).global_quality = crf_val as i32;
+                (*encoder.as_mut_ptr()).color_primaries =
+                    ffmpeg::ffi::AVColorPrimaries::AVCOL_PRI_BT709;
+                (*encoder.as_mut_ptr()).color_trc =
+                    ffmpeg::ffi::AVColorTransferCharacteristic::AVCOL_TRC_BT709;
             }
-            encoder.set_bit_rate(0);
+        }
+
+        if let Some(rate_control) = rate_control {
+            rate_control.for_encoder(codec.name()).apply(
+                codec.name(),
+                &mut encoder,
+                &mut encoder_options,
+            );
         } else {
             let bitrate = get_bitrate(
                 output_width,
@@ -289,6 +397,10 @@ impl HevcEncoderBuilder {
             }
         }
 
+        if let Some(b_frames) = b_frames {
+            encoder_options.set("bf", &b_frames.to_string());
+        }
+
         let encoder = encoder.open_with(encoder_options)?;
 
         let mut output_stream = output.add_stream(codec)?;
@@ -307,6 +419,7 @@ impl HevcEncoderBuilder {
             input_format: input_config.pixel_format,
             input_width: input_config.width,
             input_height: input_config.height,
+            hdr,
         })
     }
 }
@@ -321,6 +434,7 @@ pub struct HevcEncoder {
     input_format: format::Pixel,
     input_width: u32,
     input_height: u32,
+    hdr: Option<HdrTransfer>,
 }
 
 pub struct ConversionRequirements {
@@ -348,6 +462,22 @@ impl HevcEncoder {
         HevcEncoderBuilder::new(input_config)
     }
 
+    pub fn hdr(&self) -> Option<HdrTransfer> {
+        self.hdr
+    }
+
+    /// Forces an IDR frame at each of `timestamps`, measured from the first frame queued,
+    /// so the output can be seeked to and cut at exactly those points.
+    pub fn force_keyframes_at(&mut self, timestamps: impl IntoIterator<Item = Duration>) {
+        let time_base = self.encoder.time_base();
+        let rate = time_base.denominator() as f64 / time_base.numerator() as f64;
+        self.base.force_keyframes(
+            timestamps
+                .into_iter()
+                .map(|timestamp| (timestamp.as_secs_f64() * rate).round() as i64),
+        );
+    }
+
     pub fn conversion_requirements(&self) -> ConversionRequirements {
         let needs_conversion = self.input_format != self.output_format
             || self.input_width != self.output_width
@@ -382,6 +512,7 @@ impl HevcEncoder {
             converted.set_pts(pts);
             frame = converted;
         }
+        self.base.mark_forced_keyframe(&mut frame);
 
         self.base
             .send_frame(&frame, output, &mut self.encoder)
@@ -408,10 +539,11 @@ impl HevcEncoder {
                 .run(frame, converted)
                 .map_err(QueueFrameError::Converter)?;
             converted.set_pts(pts);
-            converted as &frame::Video
+            converted
         } else {
-            frame as &frame::Video
+            frame
         };
+        self.base.mark_forced_keyframe(frame_to_send);
 
         self.base
             .send_frame(frame_to_send, output, &mut self.encoder)
@@ -438,6 +570,7 @@ impl HevcEncoder {
 
         self.base
             .update_pts(&mut frame, timestamp, &mut self.encoder);
+        self.base.mark_forced_keyframe(&mut frame);
 
         self.base
             .send_frame(&frame, output, &mut self.encoder)
@@ -451,6 +584,8 @@ impl HevcEncoder {
     }
 }
 
+pub const DEFAULT_KEYFRAME_INTERVAL_SECS: u32 = 2;
+
 fn get_encoder_priority() -> &'static [&'static str] {
     #[cfg(target_os = "macos")]
     {
@@ -494,14 +629,14 @@ fn get_encoder_priority() -> &'static [&'static str] {
 fn get_codec_and_options(
     config: &VideoInfo,
     preset: HevcPreset,
+    explicit_rate_control: bool,
+    keyframe_interval: Duration,
 ) -> Vec<(Codec, Dictionary<'static>)> {
-    let keyframe_interval_secs = 2;
+    let keyframe_interval_secs = keyframe_interval.as_secs_f64();
     let denominator = config.frame_rate.denominator();
     let frames_per_sec = config.frame_rate.numerator() as f64
         / if denominator == 0 { 1 } else { denominator } as f64;
-    let keyframe_interval = (keyframe_interval_secs as f64 * frames_per_sec)
-        .round()
-        .max(1.0) as i32;
+    let keyframe_interval = (keyframe_interval_secs * frames_per_sec).round().max(1.0) as i32;
     let keyframe_interval_str = keyframe_interval.to_string();
 
     let encoder_priority = get_encoder_priority();
@@ -524,23 +659,29 @@ fn get_codec_and_options(
             "hevc_nvenc" => {
                 options.set("preset", "p6");
                 options.set("tune", "hq");
-                options.set("rc", "vbr");
+                if !explicit_rate_control {
+                    options.set("rc", "vbr");
+                }
                 options.set("spatial-aq", "1");
                 options.set("temporal-aq", "1");
                 options.set("rc-lookahead", "32");
                 options.set("bf", "3");
                 options.set("b_adapt", "1");
                 options.set("tier", "main");
+                options.set("forced-idr", "1");
                 options.set("g", &keyframe_interval_str);
             }
             "hevc_qsv" => {
                 options.set("preset", "faster");
                 options.set("look_ahead", "1");
+                options.set("forced_idr", "1");
                 options.set("g", &keyframe_interval_str);
             }
             "hevc_amf" => {
                 options.set("quality", "balanced");
-                options.set("rc", "vbr_latency");
+                if !explicit_rate_control {
+                    options.set("rc", "vbr_latency");
+                }
                 options.set("g", &keyframe_interval_str);
             }
             "hevc_mf" => {
@@ -558,6 +699,7 @@ fn get_codec_and_options(
                         HevcPreset::Ultrafast => "faster",
                     },
                 );
+                options.set("forced-idr", "1");
                 options.set("g", &keyframe_interval_str);
             }
             _ => {}
@@ -569,16 +711,18 @@ fn get_codec_and_options(
     encoders
 }
 
-fn get_codec_and_options_crf(config: &VideoInfo, crf: u8) -> Vec<(Codec, Dictionary<'static>)> {
-    let keyframe_interval_secs = 2;
+/// Slower, higher quality settings for CRF/CQP encodes. The rate control options themselves
+/// come from [`RateControl::apply`].
+fn get_codec_and_options_quality(
+    config: &VideoInfo,
+    keyframe_interval: Duration,
+) -> Vec<(Codec, Dictionary<'static>)> {
+    let keyframe_interval_secs = keyframe_interval.as_secs_f64();
     let denominator = config.frame_rate.denominator();
     let frames_per_sec = config.frame_rate.numerator() as f64
         / if denominator == 0 { 1 } else { denominator } as f64;
-    let keyframe_interval = (keyframe_interval_secs as f64 * frames_per_sec)
-        .round()
-        .max(1.0) as i32;
+    let keyframe_interval = (keyframe_interval_secs * frames_per_sec).round().max(1.0) as i32;
     let keyframe_interval_str = keyframe_interval.to_string();
-    let crf_str = crf.to_string();
 
     let encoder_priority = get_encoder_priority();
 
@@ -594,43 +738,37 @@ fn get_codec_and_options_crf(config: &VideoInfo, crf: u8) -> Vec<(Codec, Diction
         match *encoder_name {
             "hevc_videotoolbox" => {
                 options.set("allow_sw", "1");
-                options.set("q", &crf_str);
                 options.set("g", &keyframe_interval_str);
             }
             "hevc_nvenc" => {
                 options.set("preset", "p7");
                 options.set("tune", "hq");
-                options.set("rc", "constqp");
-                options.set("qp", &crf_str);
                 options.set("spatial-aq", "1");
                 options.set("temporal-aq", "1");
                 options.set("rc-lookahead", "32");
                 options.set("bf", "3");
                 options.set("b_adapt", "1");
                 options.set("tier", "main");
+                options.set("forced-idr", "1");
                 options.set("g", &keyframe_interval_str);
             }
             "hevc_qsv" => {
                 options.set("preset", "slower");
-                options.set("global_quality", &crf_str);
                 options.set("look_ahead", "1");
+                options.set("forced_idr", "1");
                 options.set("g", &keyframe_interval_str);
             }
             "hevc_amf" => {
                 options.set("quality", "quality");
-                options.set("rc", "cqp");
-                options.set("qp_i", &crf_str);
-                options.set("qp_p", &crf_str);
                 options.set("g", &keyframe_interval_str);
             }
             "hevc_mf" => {
                 options.set("hw_encoding", "true");
-                options.set("quality", "0");
                 options.set("g", &keyframe_interval_str);
             }
             "libx265" => {
                 options.set("preset", "medium");
-                options.set("crf", &crf_str);
+                options.set("forced-idr", "1");
                 options.set("g", &keyframe_interval_str);
             }
             _ => {}
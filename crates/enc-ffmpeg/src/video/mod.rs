@@ -1,2 +1,7 @@
+pub mod animated;
+pub mod av1;
 pub mod h264;
 pub mod hevc;
+pub mod prores;
+pub mod rate_control;
+pub mod vp9;
@@ -0,0 +1,222 @@
+use std::{thread, time::Duration};
+
+use cap_media_info::{Pixel, VideoInfo, ensure_even};
+use ffmpeg::{
+    Dictionary,
+    codec::{context, encoder},
+    color,
+    format::{self},
+    frame,
+    threading::Config,
+};
+use tracing::{debug, error, warn};
+
+use crate::base::EncoderBase;
+
+/// ProRes 4444 through `prores_ks`, the intermediate codec NLEs expect for footage with an
+/// alpha channel.
+pub struct ProResEncoderBuilder {
+    input_config: VideoInfo,
+    output_size: Option<(u32, u32)>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ProResEncoderError {
+    #[error("{0:?}")]
+    FFmpeg(#[from] ffmpeg::Error),
+    #[error("Codec not found")]
+    CodecNotFound,
+    #[error("Pixel format {0:?} not supported")]
+    PixFmtNotSupported(Pixel),
+    #[error("Invalid output dimensions {width}x{height}; expected non-zero even width and height")]
+    InvalidOutputDimensions { width: u32, height: u32 },
+}
+
+impl ProResEncoderBuilder {
+    const OUTPUT_FORMAT: format::Pixel = format::Pixel::YUVA444P10LE;
+
+    pub fn new(input_config: VideoInfo) -> Self {
+        Self {
+            input_config,
+            output_size: None,
+        }
+    }
+
+    pub fn with_output_size(mut self, width: u32, height: u32) -> Result<Self, ProResEncoderError> {
+        if width == 0 || height == 0 {
+            return Err(ProResEncoderError::InvalidOutputDimensions { width, height });
+        }
+
+        self.output_size = Some((width, height));
+        Ok(self)
+    }
+
+    pub fn build(
+        self,
+        output: &mut format::context::Output,
+    ) -> Result<ProResEncoder, ProResEncoderError> {
+        let input_config = self.input_config;
+        let (raw_width, raw_height) = self
+            .output_size
+            .unwrap_or((input_config.width, input_config.height));
+
+        let output_width = ensure_even(raw_width);
+        let output_height = ensure_even(raw_height);
+
+        if raw_width != output_width || raw_height != output_height {
+            warn!(
+                raw_width,
+                raw_height,
+                output_width,
+                output_height,
+                "Auto-adjusted odd dimensions to even for ProRes encoding"
+            );
+        }
+
+        let codec = encoder::find_by_name("prores_ks").ok_or(ProResEncoderError::CodecNotFound)?;
+
+        let output_format = Self::OUTPUT_FORMAT;
+        let needs_scaling =
+            output_width != input_config.width || output_height != input_config.height;
+
+        let converter = if input_config.pixel_format != output_format || needs_scaling {
+            let flags = if needs_scaling {
+                ffmpeg::software::scaling::flag::Flags::BICUBIC
+            } else {
+                ffmpeg::software::scaling::flag::Flags::FAST_BILINEAR
+            };
+
+            match ffmpeg::software::scaling::Context::get(
+                input_config.pixel_format,
+                input_config.width,
+                input_config.height,
+                output_format,
+                output_width,
+                output_height,
+                flags,
+            ) {
+                Ok(context) => Some(context),
+                Err(e) => {
+                    error!(
+                        "Failed to create converter from {:?} to {:?}: {:?}",
+                        input_config.pixel_format, output_format, e
+                    );
+                    return Err(ProResEncoderError::PixFmtNotSupported(
+                        input_config.pixel_format,
+                    ));
+                }
+            }
+        } else {
+            None
+        };
+
+        let mut encoder_ctx = context::Context::new_with_codec(codec);
+
+        let thread_count = thread::available_parallelism()
+            .map(|v| v.get())
+            .unwrap_or(1);
+        encoder_ctx.set_threading(Config::count(thread_count));
+        let mut encoder = encoder_ctx.encoder().video()?;
+
+        encoder.set_width(output_width);
+        encoder.set_height(output_height);
+        encoder.set_format(output_format);
+        encoder.set_time_base(input_config.time_base);
+        encoder.set_frame_rate(Some(input_config.frame_rate));
+        encoder.set_colorspace(color::Space::BT709);
+        encoder.set_color_range(color::Range::MPEG);
+        unsafe {
+            (*encoder.as_mut_ptr()).color_primaries =
+                ffmpeg::ffi::AVColorPrimaries::AVCOL_PRI_BT709;
+            (*encoder.as_mut_ptr()).color_trc =
+                ffmpeg::ffi::AVColorTransferCharacteristic::AVCOL_TRC_BT709;
+        }
+
+        let mut options = Dictionary::new();
+        options.set("profile", "4444");
+        options.set("alpha_bits", "16");
+        // Tags the stream the way Apple's encoder does, which some NLEs check for.
+        options.set("vendor", "apl0");
+
+        let encoder = encoder.open_with(options)?;
+
+        let mut output_stream = output.add_stream(codec)?;
+        let stream_index = output_stream.index();
+        output_stream.set_time_base(input_config.time_base);
+        output_stream.set_rate(input_config.frame_rate);
+        output_stream.set_parameters(&encoder);
+
+        debug!(
+            "Using ProRes 4444 encoder at {}x{}",
+            output_width, output_height
+        );
+
+        Ok(ProResEncoder {
+            base: EncoderBase::new(stream_index),
+            encoder,
+            converter,
+            output_format,
+            output_width,
+            output_height,
+        })
+    }
+}
+
+pub struct ProResEncoder {
+    base: EncoderBase,
+    encoder: encoder::Video,
+    converter: Option<ffmpeg::software::scaling::Context>,
+    output_format: format::Pixel,
+    output_width: u32,
+    output_height: u32,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum QueueFrameError {
+    #[error("Converter: {0}")]
+    Converter(ffmpeg::Error),
+    #[error("Encode: {0}")]
+    Encode(ffmpeg::Error),
+}
+
+impl ProResEncoder {
+    pub fn builder(input_config: VideoInfo) -> ProResEncoderBuilder {
+        ProResEncoderBuilder::new(input_config)
+    }
+
+    pub fn queue_frame_reusable(
+        &mut self,
+        frame: &mut frame::Video,
+        converted_frame: &mut Option<frame::Video>,
+        timestamp: Duration,
+        output: &mut format::context::Output,
+    ) -> Result<(), QueueFrameError> {
+        self.base.update_pts(frame, timestamp, &mut self.encoder);
+
+        let frame_to_send = if let Some(converter) = &mut self.converter {
+            let pts = frame.pts();
+            let converted = converted_frame.get_or_insert_with(|| {
+                frame::Video::new(self.output_format, self.output_width, self.output_height)
+            });
+            converter
+                .run(frame, converted)
+                .map_err(QueueFrameError::Converter)?;
+            converted.set_pts(pts);
+            converted as &frame::Video
+        } else {
+            frame as &frame::Video
+        };
+
+        self.base
+            .send_frame(frame_to_send, output, &mut self.encoder)
+            .map_err(QueueFrameError::Encode)?;
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self, output: &mut format::context::Output) -> Result<(), ffmpeg::Error> {
+        self.base.process_eof(output, &mut self.encoder)
+    }
+}
+
+unsafe impl Send for ProResEncoder {}
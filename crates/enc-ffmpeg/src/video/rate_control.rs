@@ -0,0 +1,235 @@
+use ffmpeg::{Dictionary, codec::encoder};
+use tracing::warn;
+
+/// How an H.264/HEVC encoder spends bits. Encoders without a native equivalent of the
+/// requested mode fall back to the closest one they have and log a warning.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RateControl {
+    /// Constant quality on the x264/x265 CRF scale (0-51, lower is better).
+    Crf(u8),
+    /// Constant quantiser for every frame (0-51, lower is better).
+    Cqp(u8),
+    /// Variable bitrate averaging `bitrate`, capped at `max_bitrate` over a `buffer_size`
+    /// bit VBV buffer. All values are in bits (per second for the rates).
+    ConstrainedVbr {
+        bitrate: u64,
+        max_bitrate: u64,
+        buffer_size: u64,
+    },
+    /// Constant bitrate, for streaming and other consumers that need a flat rate.
+    Cbr { bitrate: u64 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum EncoderFamily {
+    Software,
+    VideoToolbox,
+    Nvenc,
+    Qsv,
+    Amf,
+    Mf,
+    Other,
+}
+
+impl EncoderFamily {
+    fn of(encoder_name: &str) -> Self {
+        match encoder_name.rsplit('_').next() {
+            Some("libx264" | "libx265") => Self::Software,
+            Some("videotoolbox") => Self::VideoToolbox,
+            Some("nvenc") => Self::Nvenc,
+            Some("qsv") => Self::Qsv,
+            Some("amf") => Self::Amf,
+            Some("mf") => Self::Mf,
+            _ => Self::Other,
+        }
+    }
+}
+
+// libavcodec's scale between QP and the lambda stored in `global_quality`.
+const FF_QP2LAMBDA: i32 = 118;
+
+impl RateControl {
+    pub const MAX_QUALITY_VALUE: u8 = 51;
+
+    /// Constrained VBR with headroom for complex scenes: peaks may reach 1.5x `bitrate` and
+    /// the buffer holds one second at the average rate.
+    pub fn constrained_vbr(bitrate: u64) -> Self {
+        Self::ConstrainedVbr {
+            bitrate,
+            max_bitrate: bitrate + bitrate / 2,
+            buffer_size: bitrate,
+        }
+    }
+
+    /// The mode `encoder_name` will actually run, swapping in the closest supported one.
+    pub(crate) fn for_encoder(self, encoder_name: &str) -> Self {
+        let family = EncoderFamily::of(encoder_name);
+
+        let fallback = match (self, family) {
+            // VideoToolbox, QSV and Media Foundation only expose a quality scale, not a
+            // fixed quantiser.
+            (
+                Self::Cqp(qp),
+                EncoderFamily::VideoToolbox | EncoderFamily::Qsv | EncoderFamily::Mf,
+            ) => Self::Crf(qp),
+            // AMF has no constant quality mode that shares the CRF scale.
+            (Self::Crf(crf), EncoderFamily::Amf) => Self::Cqp(crf),
+            _ => return self,
+        };
+
+        warn!(
+            encoder = encoder_name,
+            requested = ?self,
+            using = ?fallback,
+            "Rate control mode not supported by encoder, falling back"
+        );
+
+        fallback
+    }
+
+    pub(crate) fn is_quality_based(&self) -> bool {
+        matches!(self, Self::Crf(_) | Self::Cqp(_))
+    }
+
+    /// Configures `encoder` and its private `options` for this mode. Call on the result of
+    /// [`Self::for_encoder`] so unsupported modes have already been swapped out.
+    pub(crate) fn apply(
+        self,
+        encoder_name: &str,
+        encoder: &mut encoder::video::Video,
+        options: &mut Dictionary,
+    ) {
+        let family = EncoderFamily::of(encoder_name);
+        let ctx = unsafe { &mut *encoder.as_mut_ptr() };
+
+        match self {
+            Self::Crf(crf) => {
+                let crf = crf.min(Self::MAX_QUALITY_VALUE);
+                ctx.bit_rate = 0;
+                ctx.rc_max_rate = 0;
+                ctx.rc_buffer_size = 0;
+
+                match family {
+                    EncoderFamily::Software => options.set("crf", &crf.to_string()),
+                    EncoderFamily::VideoToolbox => {
+                        ctx.flags |= ffmpeg::ffi::AV_CODEC_FLAG_QSCALE as i32;
+                        ctx.global_quality = crf_to_quality_percent(crf) as i32 * FF_QP2LAMBDA;
+                    }
+                    EncoderFamily::Nvenc => {
+                        options.set("rc", "vbr");
+                        options.set("cq", &crf.to_string());
+                    }
+                    // ICQ, selected by a quality with no maximum rate.
+                    EncoderFamily::Qsv => ctx.global_quality = crf as i32,
+                    EncoderFamily::Mf => {
+                        options.set("rate_control", "quality");
+                        options.set("quality", &crf_to_quality_percent(crf).to_string());
+                    }
+                    EncoderFamily::Amf | EncoderFamily::Other => {
+                        ctx.global_quality = crf as i32;
+                    }
+                }
+            }
+            Self::Cqp(qp) => {
+                let qp = qp.min(Self::MAX_QUALITY_VALUE).to_string();
+                ctx.bit_rate = 0;
+                ctx.rc_max_rate = 0;
+                ctx.rc_buffer_size = 0;
+
+                match family {
+                    EncoderFamily::Software => options.set("qp", &qp),
+                    EncoderFamily::Nvenc => {
+                        options.set("rc", "constqp");
+                        options.set("qp", &qp);
+                    }
+                    EncoderFamily::Amf => {
+                        options.set("rc", "cqp");
+                        options.set("qp_i", &qp);
+                        options.set("qp_p", &qp);
+                    }
+                    _ => options.set("qp", &qp),
+                }
+            }
+            Self::ConstrainedVbr {
+                bitrate,
+                max_bitrate,
+                buffer_size,
+            } => {
+                ctx.bit_rate = bitrate as i64;
+                ctx.rc_max_rate = max_bitrate.max(bitrate) as i64;
+                ctx.rc_buffer_size = buffer_size.min(i32::MAX as u64) as i32;
+
+                match family {
+                    EncoderFamily::Nvenc => options.set("rc", "vbr"),
+                    EncoderFamily::Amf => options.set("rc", "vbr_peak"),
+                    EncoderFamily::Mf => options.set("rate_control", "pc_vbr"),
+                    // x264/x265 and VideoToolbox cap the rate from the VBV settings alone,
+                    // and QSV picks VBR when the maximum exceeds the average.
+                    _ => {}
+                }
+            }
+            Self::Cbr { bitrate } => {
+                ctx.bit_rate = bitrate as i64;
+                ctx.rc_max_rate = bitrate as i64;
+                ctx.rc_min_rate = bitrate as i64;
+                ctx.rc_buffer_size = bitrate.min(i32::MAX as u64) as i32;
+
+                match family {
+                    EncoderFamily::Software if encoder_name == "libx265" => {
+                        options.set("x265-params", "strict-cbr=1");
+                    }
+                    EncoderFamily::Software => options.set("nal-hrd", "cbr"),
+                    EncoderFamily::VideoToolbox => options.set("constant_bit_rate", "true"),
+                    EncoderFamily::Nvenc => options.set("rc", "cbr"),
+                    EncoderFamily::Amf => options.set("rc", "cbr"),
+                    EncoderFamily::Mf => options.set("rate_control", "cbr"),
+                    // QSV picks CBR when the minimum, average and maximum rates match.
+                    EncoderFamily::Qsv | EncoderFamily::Other => {}
+                }
+            }
+        }
+    }
+}
+
+/// Maps the CRF scale onto the 0-100 (higher is better) quality used by VideoToolbox and
+/// Media Foundation.
+fn crf_to_quality_percent(crf: u8) -> u8 {
+    let crf = crf.min(RateControl::MAX_QUALITY_VALUE) as u32;
+    (100 - crf * 100 / RateControl::MAX_QUALITY_VALUE as u32).max(1) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsupported_modes_fall_back() {
+        assert_eq!(
+            RateControl::Cqp(23).for_encoder("h264_videotoolbox"),
+            RateControl::Crf(23)
+        );
+        assert_eq!(
+            RateControl::Cqp(23).for_encoder("hevc_qsv"),
+            RateControl::Crf(23)
+        );
+        assert_eq!(
+            RateControl::Crf(23).for_encoder("h264_amf"),
+            RateControl::Cqp(23)
+        );
+        assert_eq!(
+            RateControl::Crf(23).for_encoder("libx264"),
+            RateControl::Crf(23)
+        );
+        assert_eq!(
+            RateControl::Cbr { bitrate: 1_000 }.for_encoder("hevc_mf"),
+            RateControl::Cbr { bitrate: 1_000 }
+        );
+    }
+
+    #[test]
+    fn crf_maps_onto_quality_percent() {
+        assert_eq!(crf_to_quality_percent(0), 100);
+        assert_eq!(crf_to_quality_percent(51), 1);
+        assert!(crf_to_quality_percent(18) > crf_to_quality_percent(28));
+    }
+}
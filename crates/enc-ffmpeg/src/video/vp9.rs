@@ -0,0 +1,470 @@
+use std::{thread, time::Duration};
+
+use cap_media_info::{Pixel, VideoInfo, ensure_even};
+use ffmpeg::{
+    Dictionary,
+    codec::{codec::Codec, context, encoder},
+    color,
+    format::{self},
+    frame,
+    threading::Config,
+};
+use tracing::{debug, error, trace, warn};
+
+use crate::base::EncoderBase;
+
+pub struct Vp9EncoderBuilder {
+    bpp: f32,
+    input_config: VideoInfo,
+    preset: Vp9Preset,
+    output_size: Option<(u32, u32)>,
+    external_conversion: bool,
+    crf: Option<u8>,
+    alpha: bool,
+}
+
+#[derive(Clone, Copy)]
+pub enum Vp9Preset {
+    Slow,
+    Medium,
+    Ultrafast,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Vp9EncoderError {
+    #[error("{0:?}")]
+    FFmpeg(#[from] ffmpeg::Error),
+    #[error("Codec not found")]
+    CodecNotFound,
+    #[error("Pixel format {0:?} not supported")]
+    PixFmtNotSupported(Pixel),
+    #[error("Invalid output dimensions {width}x{height}; expected non-zero even width and height")]
+    InvalidOutputDimensions { width: u32, height: u32 },
+}
+
+impl Vp9EncoderBuilder {
+    pub const QUALITY_BPP: f32 = 0.2;
+
+    pub fn new(input_config: VideoInfo) -> Self {
+        Self {
+            input_config,
+            bpp: Self::QUALITY_BPP,
+            preset: Vp9Preset::Medium,
+            output_size: None,
+            external_conversion: false,
+            crf: None,
+            alpha: false,
+        }
+    }
+
+    pub fn with_preset(mut self, preset: Vp9Preset) -> Self {
+        self.preset = preset;
+        self
+    }
+
+    pub fn with_bpp(mut self, bpp: f32) -> Self {
+        self.bpp = bpp;
+        self
+    }
+
+    pub fn with_output_size(mut self, width: u32, height: u32) -> Result<Self, Vp9EncoderError> {
+        if width == 0 || height == 0 {
+            return Err(Vp9EncoderError::InvalidOutputDimensions { width, height });
+        }
+
+        self.output_size = Some((width, height));
+        Ok(self)
+    }
+
+    pub fn with_external_conversion(mut self) -> Self {
+        self.external_conversion = true;
+        self
+    }
+
+    pub fn with_crf(mut self, crf: u8) -> Self {
+        self.crf = Some(crf);
+        self
+    }
+
+    /// Encodes the input's alpha channel as well, as `yuva420p`. The WebM muxer stores it
+    /// alongside the colour planes, which browsers and NLEs composite as transparency.
+    pub fn with_alpha(mut self) -> Self {
+        self.alpha = true;
+        self
+    }
+
+    pub fn build(
+        self,
+        output: &mut format::context::Output,
+    ) -> Result<Vp9Encoder, Vp9EncoderError> {
+        let input_config = self.input_config;
+        let (raw_width, raw_height) = self
+            .output_size
+            .unwrap_or((input_config.width, input_config.height));
+
+        let output_width = ensure_even(raw_width);
+        let output_height = ensure_even(raw_height);
+
+        if raw_width != output_width || raw_height != output_height {
+            warn!(
+                raw_width,
+                raw_height,
+                output_width,
+                output_height,
+                "Auto-adjusted odd dimensions to even for VP9 encoding"
+            );
+        }
+
+        let candidates = get_codec_and_options(&input_config, self.preset, self.crf);
+        if candidates.is_empty() {
+            return Err(Vp9EncoderError::CodecNotFound);
+        }
+
+        let mut last_error = None;
+
+        for (codec, encoder_options) in candidates {
+            let codec_name = codec.name().to_string();
+
+            match Self::build_with_codec(
+                codec,
+                encoder_options,
+                &input_config,
+                output,
+                output_width,
+                output_height,
+                self.bpp,
+                self.external_conversion,
+                self.crf,
+                self.alpha,
+            ) {
+                Ok(encoder) => {
+                    debug!("Using VP9 encoder {}", codec_name);
+                    return Ok(encoder);
+                }
+                Err(err) => {
+                    debug!("VP9 encoder {} init failed: {:?}", codec_name, err);
+                    last_error = Some(err);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or(Vp9EncoderError::CodecNotFound))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn build_with_codec(
+        codec: Codec,
+        encoder_options: Dictionary<'static>,
+        input_config: &VideoInfo,
+        output: &mut format::context::Output,
+        output_width: u32,
+        output_height: u32,
+        bpp: f32,
+        external_conversion: bool,
+        crf: Option<u8>,
+        alpha: bool,
+    ) -> Result<Vp9Encoder, Vp9EncoderError> {
+        let encoder_supports_input_format = codec
+            .video()
+            .ok()
+            .and_then(|codec_video| codec_video.formats())
+            .is_some_and(|mut formats| formats.any(|f| f == input_config.pixel_format));
+
+        let mut needs_pixel_conversion = false;
+
+        let output_format = if alpha && input_config.pixel_format != format::Pixel::YUVA420P {
+            needs_pixel_conversion = true;
+            format::Pixel::YUVA420P
+        } else if encoder_supports_input_format {
+            input_config.pixel_format
+        } else {
+            needs_pixel_conversion = true;
+            let format = ffmpeg::format::Pixel::YUV420P;
+            if !external_conversion {
+                debug!(
+                    "Converting from {:?} to {:?} for VP9 encoding",
+                    input_config.pixel_format, format
+                );
+            }
+            format
+        };
+
+        let needs_scaling =
+            output_width != input_config.width || output_height != input_config.height;
+
+        let converter = if external_conversion {
+            debug!(
+                "External conversion enabled, skipping internal converter. Expected input: {:?} {}x{}",
+                output_format, output_width, output_height
+            );
+            None
+        } else if needs_pixel_conversion || needs_scaling {
+            let flags = if needs_scaling {
+                ffmpeg::software::scaling::flag::Flags::BICUBIC
+            } else {
+                ffmpeg::software::scaling::flag::Flags::FAST_BILINEAR
+            };
+
+            match ffmpeg::software::scaling::Context::get(
+                input_config.pixel_format,
+                input_config.width,
+                input_config.height,
+                output_format,
+                output_width,
+                output_height,
+                flags,
+            ) {
+                Ok(context) => Some(context),
+                Err(e) => {
+                    if needs_pixel_conversion {
+                        error!(
+                            "Failed to create converter from {:?} to {:?}: {:?}",
+                            input_config.pixel_format, output_format, e
+                        );
+                        return Err(Vp9EncoderError::PixFmtNotSupported(
+                            input_config.pixel_format,
+                        ));
+                    }
+
+                    return Err(Vp9EncoderError::FFmpeg(e));
+                }
+            }
+        } else {
+            None
+        };
+
+        let mut encoder_ctx = context::Context::new_with_codec(codec);
+
+        let thread_count = thread::available_parallelism()
+            .map(|v| v.get())
+            .unwrap_or(1);
+        encoder_ctx.set_threading(Config::count(thread_count));
+        let mut encoder = encoder_ctx.encoder().video()?;
+
+        encoder.set_width(output_width);
+        encoder.set_height(output_height);
+        encoder.set_format(output_format);
+        encoder.set_time_base(input_config.time_base);
+        encoder.set_frame_rate(Some(input_config.frame_rate));
+        encoder.set_colorspace(color::Space::BT709);
+        encoder.set_color_range(color::Range::MPEG);
+        unsafe {
+            (*encoder.as_mut_ptr()).color_primaries =
+                ffmpeg::ffi::AVColorPrimaries::AVCOL_PRI_BT709;
+            (*encoder.as_mut_ptr()).color_trc =
+                ffmpeg::ffi::AVColorTransferCharacteristic::AVCOL_TRC_BT709;
+        }
+
+        if crf.is_some() {
+            // libvpx only treats `crf` as constant quality when the target bitrate is zero
+            encoder.set_bit_rate(0);
+        } else {
+            let bitrate = get_bitrate(
+                output_width,
+                output_height,
+                input_config.frame_rate.0 as f32 / input_config.frame_rate.1.max(1) as f32,
+                bpp,
+            );
+
+            encoder.set_bit_rate(bitrate);
+            unsafe {
+                (*encoder.as_mut_ptr()).rc_max_rate = (bitrate as f64 * 1.5) as i64;
+                (*encoder.as_mut_ptr()).rc_buffer_size = bitrate as i32;
+            }
+        }
+
+        let encoder = encoder.open_with(encoder_options)?;
+
+        let mut output_stream = output.add_stream(codec)?;
+        let stream_index = output_stream.index();
+        output_stream.set_time_base((1, Vp9Encoder::TIME_BASE));
+        output_stream.set_rate(input_config.frame_rate);
+        output_stream.set_parameters(&encoder);
+        if alpha {
+            let mut metadata = Dictionary::new();
+            metadata.set("alpha_mode", "1");
+            output_stream.set_metadata(metadata);
+        }
+
+        Ok(Vp9Encoder {
+            base: EncoderBase::new(stream_index),
+            encoder,
+            converter,
+            output_format,
+            output_width,
+            output_height,
+        })
+    }
+}
+
+pub struct Vp9Encoder {
+    base: EncoderBase,
+    encoder: encoder::Video,
+    converter: Option<ffmpeg::software::scaling::Context>,
+    output_format: format::Pixel,
+    output_width: u32,
+    output_height: u32,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum QueueFrameError {
+    #[error("Converter: {0}")]
+    Converter(ffmpeg::Error),
+    #[error("Encode: {0}")]
+    Encode(ffmpeg::Error),
+}
+
+impl Vp9Encoder {
+    const TIME_BASE: i32 = 1000;
+
+    pub fn builder(input_config: VideoInfo) -> Vp9EncoderBuilder {
+        Vp9EncoderBuilder::new(input_config)
+    }
+
+    pub fn output_format(&self) -> format::Pixel {
+        self.output_format
+    }
+
+    pub fn queue_frame(
+        &mut self,
+        mut frame: frame::Video,
+        timestamp: Duration,
+        output: &mut format::context::Output,
+    ) -> Result<(), QueueFrameError> {
+        self.base
+            .update_pts(&mut frame, timestamp, &mut self.encoder);
+
+        if let Some(converter) = &mut self.converter {
+            let pts = frame.pts();
+            let mut converted =
+                frame::Video::new(self.output_format, self.output_width, self.output_height);
+            converter
+                .run(&frame, &mut converted)
+                .map_err(QueueFrameError::Converter)?;
+            converted.set_pts(pts);
+            frame = converted;
+        }
+
+        self.base
+            .send_frame(&frame, output, &mut self.encoder)
+            .map_err(QueueFrameError::Encode)?;
+
+        Ok(())
+    }
+
+    pub fn queue_frame_reusable(
+        &mut self,
+        frame: &mut frame::Video,
+        converted_frame: &mut Option<frame::Video>,
+        timestamp: Duration,
+        output: &mut format::context::Output,
+    ) -> Result<(), QueueFrameError> {
+        self.base.update_pts(frame, timestamp, &mut self.encoder);
+
+        let frame_to_send = if let Some(converter) = &mut self.converter {
+            let pts = frame.pts();
+            let converted = converted_frame.get_or_insert_with(|| {
+                frame::Video::new(self.output_format, self.output_width, self.output_height)
+            });
+            converter
+                .run(frame, converted)
+                .map_err(QueueFrameError::Converter)?;
+            converted.set_pts(pts);
+            converted as &frame::Video
+        } else {
+            frame as &frame::Video
+        };
+
+        self.base
+            .send_frame(frame_to_send, output, &mut self.encoder)
+            .map_err(QueueFrameError::Encode)?;
+
+        Ok(())
+    }
+
+    pub fn queue_preconverted_frame(
+        &mut self,
+        mut frame: frame::Video,
+        timestamp: Duration,
+        output: &mut format::context::Output,
+    ) -> Result<(), QueueFrameError> {
+        trace!(
+            "Encoding pre-converted frame: format={:?}, size={}x{}, expected={:?} {}x{}",
+            frame.format(),
+            frame.width(),
+            frame.height(),
+            self.output_format,
+            self.output_width,
+            self.output_height
+        );
+
+        self.base
+            .update_pts(&mut frame, timestamp, &mut self.encoder);
+
+        self.base
+            .send_frame(&frame, output, &mut self.encoder)
+            .map_err(QueueFrameError::Encode)?;
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self, output: &mut format::context::Output) -> Result<(), ffmpeg::Error> {
+        self.base.process_eof(output, &mut self.encoder)
+    }
+}
+
+fn get_codec_and_options(
+    config: &VideoInfo,
+    preset: Vp9Preset,
+    crf: Option<u8>,
+) -> Vec<(Codec, Dictionary<'static>)> {
+    let keyframe_interval_secs = 2;
+    let denominator = config.frame_rate.denominator();
+    let frames_per_sec = config.frame_rate.numerator() as f64
+        / if denominator == 0 { 1 } else { denominator } as f64;
+    let keyframe_interval = (keyframe_interval_secs as f64 * frames_per_sec)
+        .round()
+        .max(1.0) as i32;
+    let keyframe_interval_str = keyframe_interval.to_string();
+
+    let mut encoders = Vec::new();
+
+    for encoder_name in ["libvpx-vp9"] {
+        let Some(codec) = encoder::find_by_name(encoder_name) else {
+            continue;
+        };
+
+        let mut options = Dictionary::new();
+
+        options.set("deadline", "good");
+        options.set(
+            "cpu-used",
+            match preset {
+                Vp9Preset::Slow => "1",
+                Vp9Preset::Medium => "3",
+                Vp9Preset::Ultrafast => "5",
+            },
+        );
+        options.set("row-mt", "1");
+        options.set("tile-columns", "2");
+        options.set("frame-parallel", "0");
+        options.set("auto-alt-ref", "1");
+        options.set("lag-in-frames", "25");
+        options.set("g", &keyframe_interval_str);
+        if let Some(crf) = crf {
+            options.set("crf", &crf.to_string());
+        }
+
+        encoders.push((codec, options));
+    }
+
+    encoders
+}
+
+fn get_bitrate(width: u32, height: u32, frame_rate: f32, bpp: f32) -> usize {
+    let frame_rate_multiplier = ((frame_rate as f64 - 30.0).max(0.0) * 0.6) + 30.0;
+    let area = (width as f64) * (height as f64);
+    let pixels_per_second = area * frame_rate_multiplier;
+
+    (pixels_per_second * bpp as f64) as usize
+}
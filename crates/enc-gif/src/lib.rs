@@ -14,6 +14,9 @@ pub enum GifEncodingError {
     /// Error from the gifski encoder
     #[error("Gifski error: {0}")]
     Gifski(String),
+    /// Error from reducing a frame's colours before encoding
+    #[error("Quantization error: {0}")]
+    Quantization(String),
     /// Invalid frame data provided
     #[error("Invalid frame data")]
     InvalidFrameData,
@@ -29,6 +32,21 @@ pub struct GifQuality {
     pub quality: u8,
     /// Whether to prioritize speed over quality (default: false)
     pub fast: bool,
+    /// How many times the animation plays, `None` loops forever (default: None)
+    pub loop_count: Option<u16>,
+    /// Most colours in each frame's palette, 2-256 (default: None, gifski decides)
+    pub max_colors: Option<u32>,
+    /// Quantize every frame against the first frame's palette, so colours don't
+    /// shift between frames (default: false)
+    pub shared_palette: bool,
+    /// Dithering strength from 0.0 to 1.0 when colours are reduced (default: 1.0)
+    pub dithering: Option<f32>,
+    /// Lossy LZW compression quality from 1-100, lower is smaller and noisier
+    /// (default: None, lossless)
+    pub lossy_quality: Option<u8>,
+    /// Drop frames identical to the previous one and extend its delay instead
+    /// (default: false)
+    pub merge_duplicate_frames: bool,
 }
 
 impl Default for GifQuality {
@@ -36,17 +54,125 @@ impl Default for GifQuality {
         Self {
             quality: 90,
             fast: false,
+            loop_count: None,
+            max_colors: None,
+            shared_palette: false,
+            dithering: None,
+            lossy_quality: None,
+            merge_duplicate_frames: false,
         }
     }
 }
 
+impl GifQuality {
+    fn repeat(&self) -> Repeat {
+        match self.loop_count {
+            None => Repeat::Infinite,
+            // The loop count in the file is the number of repeats after the first play.
+            Some(plays) => Repeat::Finite(plays.saturating_sub(1)),
+        }
+    }
+
+    /// gifski quantizes each frame on its own with fixed dithering, so colour limits and
+    /// dithering are applied beforehand. Frames that already fit in the palette go through
+    /// gifski unchanged.
+    fn needs_quantizer(&self) -> bool {
+        self.max_colors.is_some() || self.shared_palette || self.dithering.is_some()
+    }
+}
+
+/// Reduces frames to a limited palette ahead of gifski.
+struct FrameQuantizer {
+    attributes: imagequant::Attributes,
+    dithering: f32,
+    shared_palette: bool,
+    shared: Option<imagequant::QuantizationResult>,
+}
+
+impl FrameQuantizer {
+    fn new(quality: &GifQuality) -> Result<Self, GifEncodingError> {
+        let mut attributes = imagequant::new();
+        attributes
+            .set_max_colors(quality.max_colors.unwrap_or(256).clamp(2, 256))
+            .map_err(quantization_error)?;
+        if quality.fast {
+            attributes.set_speed(10).map_err(quantization_error)?;
+        }
+
+        Ok(Self {
+            attributes,
+            dithering: quality.dithering.unwrap_or(1.0).clamp(0.0, 1.0),
+            shared_palette: quality.shared_palette,
+            shared: None,
+        })
+    }
+
+    fn quantize(
+        &mut self,
+        pixels: Vec<RGBA8>,
+        width: usize,
+        height: usize,
+    ) -> Result<Vec<RGBA8>, GifEncodingError> {
+        let Self {
+            attributes,
+            dithering,
+            shared_palette,
+            shared,
+        } = self;
+
+        let mut image = attributes
+            .new_image(pixels, width, height, 0.0)
+            .map_err(quantization_error)?;
+
+        let mut per_frame;
+        let result = if *shared_palette {
+            match shared {
+                Some(result) => result,
+                None => shared.insert(Self::palette(attributes, &mut image, *dithering)?),
+            }
+        } else {
+            per_frame = Self::palette(attributes, &mut image, *dithering)?;
+            &mut per_frame
+        };
+
+        let (palette, indices) = result.remapped(&mut image).map_err(quantization_error)?;
+
+        Ok(indices.into_iter().map(|i| palette[i as usize]).collect())
+    }
+
+    fn palette(
+        attributes: &mut imagequant::Attributes,
+        image: &mut imagequant::Image<'static>,
+        dithering: f32,
+    ) -> Result<imagequant::QuantizationResult, GifEncodingError> {
+        let mut result = attributes.quantize(image).map_err(quantization_error)?;
+        result
+            .set_dithering_level(dithering)
+            .map_err(quantization_error)?;
+
+        Ok(result)
+    }
+}
+
+fn quantization_error(e: imagequant::Error) -> GifEncodingError {
+    GifEncodingError::Quantization(e.to_string())
+}
+
 /// Wrapper around gifski for encoding GIF animations
 pub struct GifEncoderWrapper {
     collector: Option<Collector>,
     writer_thread: Option<JoinHandle<Result<(), GifEncodingError>>>,
+    quantizer: Option<FrameQuantizer>,
+    merge_duplicate_frames: bool,
+    /// The last frame handed to gifski, kept to spot duplicates.
+    last_frame: Option<imgref::ImgVec<RGBA8>>,
+    /// Whether frames after `last_frame` were dropped as duplicates.
+    holding_duplicates: bool,
     width: u32,
     height: u32,
     frame_index: u32,
+    /// Frames handed to gifski, which expects consecutive indices.
+    collected_frames: usize,
     fps: u32,
     finished: bool,
 }
@@ -76,10 +202,18 @@ impl GifEncoderWrapper {
             height: Some(height),
             quality: quality.quality,
             fast: quality.fast,
-            repeat: Repeat::Infinite,
+            repeat: quality.repeat(),
         };
-        let (collector, writer) =
+        let (collector, mut writer) =
             gifski::new(settings).map_err(|e| GifEncodingError::Gifski(e.to_string()))?;
+        if let Some(lossy_quality) = quality.lossy_quality {
+            writer.set_lossy_quality(lossy_quality.clamp(1, 100));
+        }
+
+        let quantizer = quality
+            .needs_quantizer()
+            .then(|| FrameQuantizer::new(&quality))
+            .transpose()?;
 
         let output_path = path.as_ref().to_path_buf();
         let writer_thread = thread::spawn(move || {
@@ -92,9 +226,14 @@ impl GifEncoderWrapper {
         Ok(Self {
             collector: Some(collector),
             writer_thread: Some(writer_thread),
+            quantizer,
+            merge_duplicate_frames: quality.merge_duplicate_frames,
+            last_frame: None,
+            holding_duplicates: false,
             width,
             height,
             frame_index: 0,
+            collected_frames: 0,
             fps,
             finished: false,
         })
@@ -109,10 +248,9 @@ impl GifEncoderWrapper {
             return Err(GifEncodingError::EncoderFinished);
         }
 
-        let collector = self
-            .collector
-            .as_mut()
-            .ok_or(GifEncodingError::EncoderFinished)?;
+        if self.collector.is_none() {
+            return Err(GifEncodingError::EncoderFinished);
+        }
 
         let w = self.width as usize;
         let h = self.height as usize;
@@ -124,13 +262,13 @@ impl GifEncoderWrapper {
             return Err(GifEncodingError::InvalidFrameData);
         }
 
-        let img = if bytes_per_row == expected_bytes_per_row {
+        let pixels = if bytes_per_row == expected_bytes_per_row {
             let pixel_count = w * h;
             let byte_slice = &frame_data[..pixel_count * 4];
             let pixels: &[RGBA8] = unsafe {
                 std::slice::from_raw_parts(byte_slice.as_ptr().cast::<RGBA8>(), pixel_count)
             };
-            imgref::Img::new(pixels.to_vec(), w, h)
+            pixels.to_vec()
         } else {
             let mut rgba_pixels = Vec::with_capacity(w * h);
             for y in 0..h {
@@ -140,16 +278,53 @@ impl GifEncoderWrapper {
                     unsafe { std::slice::from_raw_parts(row_bytes.as_ptr().cast::<RGBA8>(), w) };
                 rgba_pixels.extend_from_slice(row_pixels);
             }
-            imgref::Img::new(rgba_pixels, w, h)
+            rgba_pixels
+        };
+
+        let pixels = match self.quantizer.as_mut() {
+            Some(quantizer) => quantizer.quantize(pixels, w, h)?,
+            None => pixels,
         };
+        let img = imgref::Img::new(pixels, w, h);
 
-        let pts = (self.frame_index as f64) / (self.fps as f64);
+        let pts = self.pts(self.frame_index);
+        self.frame_index += 1;
+
+        // gifski derives each frame's delay from the next frame's timestamp, so skipping
+        // a duplicate lengthens the frame before it.
+        if self.merge_duplicate_frames
+            && self
+                .last_frame
+                .as_ref()
+                .is_some_and(|last| last.buf() == img.buf())
+        {
+            self.holding_duplicates = true;
+            return Ok(());
+        }
+
+        self.collect(img, pts)
+    }
+
+    fn pts(&self, frame_index: u32) -> f64 {
+        (frame_index as f64) / (self.fps as f64)
+    }
+
+    fn collect(&mut self, img: imgref::ImgVec<RGBA8>, pts: f64) -> Result<(), GifEncodingError> {
+        let collector = self
+            .collector
+            .as_mut()
+            .ok_or(GifEncodingError::EncoderFinished)?;
+
+        if self.merge_duplicate_frames {
+            self.last_frame = Some(img.clone());
+            self.holding_duplicates = false;
+        }
 
         collector
-            .add_frame_rgba(self.frame_index as usize, img, pts)
+            .add_frame_rgba(self.collected_frames, img, pts)
             .map_err(|e| GifEncodingError::Gifski(e.to_string()))?;
 
-        self.frame_index += 1;
+        self.collected_frames += 1;
         Ok(())
     }
 
@@ -162,6 +337,14 @@ impl GifEncoderWrapper {
             return Ok(());
         }
 
+        // A trailing run of duplicates needs one more frame to mark where it ends
+        if self.holding_duplicates
+            && let Some(last_frame) = self.last_frame.take()
+        {
+            let pts = self.pts(self.frame_index - 1);
+            self.collect(last_frame, pts)?;
+        }
+
         // Drop the collector to signal that we're done adding frames
         drop(self.collector.take());
 
@@ -212,3 +395,160 @@ impl Drop for GifEncoderWrapper {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: RGBA8 = RGBA8::new(255, 0, 0, 255);
+    const GREEN: RGBA8 = RGBA8::new(0, 255, 0, 255);
+    const BLUE: RGBA8 = RGBA8::new(0, 0, 255, 255);
+
+    const SIZE: u32 = 8;
+
+    fn solid_frame(colour: RGBA8) -> Vec<u8> {
+        [colour.r, colour.g, colour.b, colour.a].repeat((SIZE * SIZE) as usize)
+    }
+
+    fn merging_encoder(path: &Path, loop_count: Option<u16>) -> GifEncoderWrapper {
+        let quality = GifQuality {
+            loop_count,
+            merge_duplicate_frames: true,
+            ..Default::default()
+        };
+        GifEncoderWrapper::new_with_quality(path, SIZE, SIZE, 10, quality).unwrap()
+    }
+
+    fn add_frames(encoder: &mut GifEncoderWrapper, colours: &[RGBA8]) {
+        for &colour in colours {
+            encoder
+                .add_frame(&solid_frame(colour), SIZE as usize * 4)
+                .unwrap();
+        }
+    }
+
+    fn decode(path: &Path) -> (gif::Repeat, Vec<u16>) {
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::RGBA);
+        let mut decoder = options.read_info(File::open(path).unwrap()).unwrap();
+
+        let mut delays = vec![];
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            delays.push(frame.delay);
+        }
+
+        (decoder.repeat(), delays)
+    }
+
+    #[test]
+    fn loop_count_is_plays_not_repeats() {
+        let repeat = |loop_count| {
+            GifQuality {
+                loop_count,
+                ..Default::default()
+            }
+            .repeat()
+        };
+
+        assert!(matches!(repeat(None), Repeat::Infinite));
+        assert!(matches!(repeat(Some(1)), Repeat::Finite(0)));
+        assert!(matches!(repeat(Some(3)), Repeat::Finite(2)));
+        assert!(matches!(repeat(Some(0)), Repeat::Finite(0)));
+    }
+
+    #[test]
+    fn duplicates_are_held_back_until_the_frame_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut encoder = merging_encoder(&dir.path().join("out.gif"), None);
+
+        add_frames(&mut encoder, &[RED, RED, RED]);
+        assert_eq!(encoder.collected_frames, 1);
+        assert!(encoder.holding_duplicates);
+
+        add_frames(&mut encoder, &[BLUE]);
+        assert_eq!(encoder.collected_frames, 2);
+        assert!(!encoder.holding_duplicates);
+        assert_eq!(encoder.frame_count(), 4);
+
+        encoder.finish().unwrap();
+    }
+
+    #[test]
+    fn merged_duplicates_become_one_longer_frame() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.gif");
+        let mut encoder = merging_encoder(&path, Some(3));
+
+        add_frames(&mut encoder, &[RED, RED, RED, BLUE, GREEN]);
+        encoder.finish().unwrap();
+
+        let (repeat, delays) = decode(&path);
+        assert_eq!(repeat, gif::Repeat::Finite(2));
+        assert_eq!(delays.len(), 3);
+        assert_eq!(&delays[..2], &[30, 10]);
+    }
+
+    #[test]
+    fn trailing_duplicates_keep_their_duration() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.gif");
+        let mut encoder = merging_encoder(&path, None);
+
+        add_frames(&mut encoder, &[RED, BLUE, BLUE, BLUE]);
+        encoder.finish().unwrap();
+
+        let (repeat, delays) = decode(&path);
+        assert_eq!(repeat, gif::Repeat::Infinite);
+        assert_eq!(delays[0], 10);
+        assert!(delays[1..].iter().sum::<u16>() >= 30);
+    }
+
+    #[test]
+    fn shared_palette_comes_from_the_first_frame() {
+        let quality = GifQuality {
+            shared_palette: true,
+            ..Default::default()
+        };
+        let mut quantizer = FrameQuantizer::new(&quality).unwrap();
+        let pixels = (SIZE * SIZE) as usize;
+
+        let first = [vec![RED; pixels / 2], vec![BLUE; pixels / 2]].concat();
+        quantizer
+            .quantize(first, SIZE as usize, SIZE as usize)
+            .unwrap();
+
+        let second = quantizer
+            .quantize(vec![GREEN; pixels], SIZE as usize, SIZE as usize)
+            .unwrap();
+        assert!(second.iter().all(|&p| p == RED || p == BLUE));
+    }
+
+    #[test]
+    fn per_frame_palettes_are_limited_to_max_colors() {
+        let quality = GifQuality {
+            max_colors: Some(4),
+            dithering: Some(0.0),
+            ..Default::default()
+        };
+        let mut quantizer = FrameQuantizer::new(&quality).unwrap();
+
+        let gradient = (0..SIZE * SIZE)
+            .map(|i| RGBA8::new((i * 4) as u8, 0, 255 - (i * 4) as u8, 255))
+            .collect::<Vec<_>>();
+        let mut colours = quantizer
+            .quantize(gradient, SIZE as usize, SIZE as usize)
+            .unwrap();
+        colours.sort_by_key(|p| (p.r, p.g, p.b, p.a));
+        colours.dedup();
+        assert!(colours.len() <= 4);
+
+        let green = quantizer
+            .quantize(
+                vec![GREEN; (SIZE * SIZE) as usize],
+                SIZE as usize,
+                SIZE as usize,
+            )
+            .unwrap();
+        assert!(green.iter().all(|&p| p == GREEN));
+    }
+}
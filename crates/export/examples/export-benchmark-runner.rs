@@ -1,7 +1,7 @@
 use cap_export::{
     ExporterBase,
     gif::GifExportSettings,
-    mp4::{ExportCompression, Mp4ExportSettings},
+    mp4::{ExportCompression, Mp4ExportSettings, Mp4VideoCodec},
 };
 use cap_project::XY;
 use chrono::{Local, Utc};
@@ -427,6 +427,16 @@ async fn run_mp4_export(
         compression,
         custom_bpp: None,
         force_ffmpeg_decoder: false,
+        crf: None,
+        target_size_mb: None,
+        metadata: None,
+        storyboard: None,
+        loudness: None,
+        audio_stems: false,
+        video_codec: Mp4VideoCodec::Auto,
+        hdr: None,
+        resumable: false,
+        time_range: None,
     };
 
     let total_frames = exporter_base.total_frames(fps);
@@ -480,6 +490,7 @@ async fn run_gif_export(
         fps,
         resolution_base: XY::new(width, height),
         quality: None,
+        time_range: None,
     };
 
     let total_frames = exporter_base.total_frames(fps);
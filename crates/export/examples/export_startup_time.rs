@@ -1,6 +1,6 @@
 use cap_export::{
     ExporterBase,
-    mp4::{ExportCompression, Mp4ExportSettings},
+    mp4::{ExportCompression, Mp4ExportSettings, Mp4VideoCodec},
 };
 use cap_project::XY;
 use std::{env, path::PathBuf, time::Instant};
@@ -18,6 +18,16 @@ async fn main() -> Result<(), String> {
         compression: ExportCompression::Maximum,
         custom_bpp: None,
         force_ffmpeg_decoder: false,
+        crf: None,
+        target_size_mb: None,
+        metadata: None,
+        storyboard: None,
+        loudness: None,
+        audio_stems: false,
+        video_codec: Mp4VideoCodec::Auto,
+        hdr: None,
+        resumable: false,
+        time_range: None,
     };
 
     let temp_out = tempfile::Builder::new()
@@ -0,0 +1,347 @@
+use crate::{
+    ExportError, ExportTimeRange, ExporterBase,
+    mp4::{ExportAudioRenderer, ExportCompression},
+    suffixed_output_path,
+    webm::WebmVideoCodec,
+};
+use cap_editor::{AudioRenderer, get_audio_segments};
+use cap_enc_ffmpeg::{
+    AudioEncoder,
+    mov::MovFile,
+    opus::OpusEncoder,
+    pcm::PcmEncoder,
+    prores::ProResEncoder,
+    vp9::Vp9Encoder,
+    webm::{WebMFile, WebMVideoEncoder},
+};
+use cap_media_info::{RawVideoFormat, VideoInfo};
+use cap_project::{BackgroundSource, XY};
+use cap_rendering::{ProjectUniforms, RenderSegment, RenderedFrame};
+use futures::FutureExt;
+use serde::Deserialize;
+use specta::Type;
+use std::{path::PathBuf, time::Duration};
+use tracing::info;
+
+#[derive(Deserialize, Type, Clone, Copy, Debug, Default, PartialEq)]
+pub enum AlphaCodec {
+    /// ProRes 4444 in a QuickTime file with PCM audio, for editing.
+    #[default]
+    ProRes4444,
+    /// VP9 with an alpha plane in WebM with Opus audio, for the web.
+    Vp9,
+}
+
+impl AlphaCodec {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::ProRes4444 => "mov",
+            Self::Vp9 => "webm",
+        }
+    }
+
+    /// Added to the file name so a VP9 export doesn't replace a regular WebM export.
+    pub fn file_suffix(&self) -> &'static str {
+        match self {
+            Self::ProRes4444 => "",
+            Self::Vp9 => "-alpha",
+        }
+    }
+}
+
+#[derive(Deserialize, Type, Clone, Copy, Debug)]
+pub struct AlphaExportSettings {
+    pub fps: u32,
+    pub resolution_base: XY<u32>,
+    #[serde(default)]
+    pub codec: AlphaCodec,
+    /// Only used for VP9, ProRes picks its own bitrate.
+    pub compression: ExportCompression,
+    #[serde(default)]
+    pub time_range: Option<ExportTimeRange>,
+}
+
+enum AlphaMuxer {
+    Mov(MovFile),
+    WebM(WebMFile),
+}
+
+impl AlphaExportSettings {
+    /// Renders the timeline over a transparent background, keeping the screen's rounded
+    /// corners and shadow in the alpha channel. A colour background keeps its own opacity,
+    /// any other background is dropped.
+    pub async fn export(
+        self,
+        mut base: ExporterBase,
+        mut on_progress: impl FnMut(u32) -> bool + Send + 'static,
+    ) -> Result<PathBuf, String> {
+        info!("Exporting with alpha using settings: {:?}", &self);
+
+        base.set_time_range(self.time_range);
+        let frame_range = base.frame_range(self.fps);
+
+        base.project_config.background.source =
+            transparent_background(&base.project_config.background.source);
+
+        let output_path = suffixed_output_path(
+            &base.output_path,
+            self.codec.file_suffix(),
+            self.codec.extension(),
+        );
+        let fps = self.fps;
+        let first_frame = frame_range.start;
+        let codec = self.codec;
+
+        let output_size = ProjectUniforms::get_output_size(
+            &base.render_constants.options,
+            &base.project_config,
+            self.resolution_base,
+        );
+
+        let mut video_info =
+            VideoInfo::from_raw(RawVideoFormat::Rgba, output_size.0, output_size.1, fps);
+        video_info.time_base = ffmpeg::Rational::new(1, fps as i32);
+
+        let vp9_bpp = self.compression.bits_per_pixel() * WebmVideoCodec::Vp9.bpp_scale();
+
+        let audio_segments = get_audio_segments(&base.segments);
+        let has_audio = audio_segments
+            .first()
+            .filter(|_| !base.project_config.audio.mute)
+            .is_some();
+
+        let (tx_image_data, mut video_rx) = tokio::sync::mpsc::channel::<(RenderedFrame, u32)>(4);
+
+        let project_for_audio = base.project_config.clone();
+        let muxer_path = output_path.clone();
+        let encoder_thread = tokio::task::spawn_blocking(move || {
+            let mut muxer = match codec {
+                AlphaCodec::ProRes4444 => MovFile::init(
+                    muxer_path,
+                    |o| ProResEncoder::builder(video_info).build(o),
+                    |o| {
+                        has_audio.then(|| {
+                            PcmEncoder::init(AudioRenderer::info(), o)
+                                .map(|v| v.boxed())
+                                .map_err(Into::into)
+                        })
+                    },
+                )
+                .map(AlphaMuxer::Mov)
+                .map_err(|e| ExportError::Other(e.to_string()))?,
+                AlphaCodec::Vp9 => WebMFile::init(
+                    "output",
+                    muxer_path,
+                    |o| {
+                        Vp9Encoder::builder(video_info)
+                            .with_bpp(vp9_bpp)
+                            .with_alpha()
+                            .build(o)
+                            .map(WebMVideoEncoder::from)
+                            .map_err(Into::into)
+                    },
+                    |o| {
+                        has_audio.then(|| {
+                            OpusEncoder::init(AudioRenderer::info(), o)
+                                .map(|v| v.boxed())
+                                .map_err(Into::into)
+                        })
+                    },
+                )
+                .map(AlphaMuxer::WebM)
+                .map_err(|e| ExportError::Other(e.to_string()))?,
+            };
+
+            let mut audio_renderer = has_audio.then(|| {
+                ExportAudioRenderer::new(AudioRenderer::new(audio_segments), fps, first_frame)
+            });
+
+            let mut reusable_frame = ffmpeg::frame::Video::new(
+                ffmpeg::format::Pixel::RGBA,
+                output_size.0,
+                output_size.1,
+            );
+            let mut converted_frame: Option<ffmpeg::frame::Video> = None;
+            let mut frame_count = 0;
+
+            while let Some((frame, _frame_number)) = video_rx.blocking_recv() {
+                if !(on_progress)(frame_count) {
+                    return Err(ExportError::Other("Export cancelled".to_string()));
+                }
+
+                let audio_frame = audio_renderer
+                    .as_mut()
+                    .and_then(|audio| audio.render_for_frame(frame_count, &project_for_audio));
+
+                fill_straight_rgba_frame(&mut reusable_frame, &frame);
+                reusable_frame.set_pts(Some(frame_count as i64));
+
+                match &mut muxer {
+                    AlphaMuxer::Mov(file) => file
+                        .queue_video_frame_reusable(
+                            &mut reusable_frame,
+                            &mut converted_frame,
+                            Duration::MAX,
+                        )
+                        .map_err(|e| ExportError::Other(e.to_string()))?,
+                    AlphaMuxer::WebM(file) => file
+                        .queue_video_frame_reusable(
+                            &mut reusable_frame,
+                            &mut converted_frame,
+                            Duration::MAX,
+                        )
+                        .map_err(|e| ExportError::Other(e.to_string()))?,
+                }
+
+                if let Some(audio) = audio_frame {
+                    match &mut muxer {
+                        AlphaMuxer::Mov(file) => file.queue_audio_frame(audio),
+                        AlphaMuxer::WebM(file) => file.queue_audio_frame(audio),
+                    }
+                }
+
+                frame_count += 1;
+            }
+
+            let (video_finish, audio_finish) = match &mut muxer {
+                AlphaMuxer::Mov(file) => file
+                    .finish()
+                    .map(|res| (res.video_finish, res.audio_finish))
+                    .map_err(|e| e.to_string()),
+                AlphaMuxer::WebM(file) => file
+                    .finish()
+                    .map(|res| (res.video_finish, res.audio_finish))
+                    .map_err(|e| e.to_string()),
+            }
+            .map_err(|e| ExportError::Other(format!("Failed to finish encoding: {e}")))?;
+
+            if let Err(e) = video_finish {
+                return Err(ExportError::Other(format!("Video encoding failed: {e}")));
+            }
+            if let Err(e) = audio_finish {
+                return Err(ExportError::Other(format!("Audio encoding failed: {e}")));
+            }
+
+            Ok(())
+        })
+        .then(|f| async {
+            f.map_err(|e| e.to_string())
+                .and_then(|v| v.map_err(|v| v.to_string()))
+        });
+
+        let render_video_task = cap_rendering::render_video_to_channel(
+            &base.render_constants,
+            &base.project_config,
+            tx_image_data,
+            &base.recording_meta,
+            &base.studio_meta,
+            base.segments
+                .iter()
+                .map(|s| RenderSegment {
+                    cursor: s.cursor.clone(),
+                    keyboard: s.keyboard.clone(),
+                    decoders: s.decoders.clone(),
+                })
+                .collect(),
+            fps,
+            self.resolution_base,
+            &base.recordings,
+            Some(frame_range),
+        )
+        .then(|f| async { f.map_err(|v| v.to_string()) });
+
+        tokio::try_join!(encoder_thread, render_video_task)?;
+
+        Ok(output_path)
+    }
+}
+
+/// The renderer blends each layer with straight alpha, so the output is premultiplied
+/// as long as the background is. Premultiplying the colour here keeps the shadow black
+/// instead of picking up the background's hidden colour.
+fn transparent_background(source: &BackgroundSource) -> BackgroundSource {
+    match source {
+        BackgroundSource::Color { value, alpha } => BackgroundSource::Color {
+            value: value.map(|c| (c as u32 * *alpha as u32 / 255) as u16),
+            alpha: *alpha,
+        },
+        _ => BackgroundSource::Color {
+            value: [0, 0, 0],
+            alpha: 0,
+        },
+    }
+}
+
+/// Copies the rendered frame into `frame` with straight alpha, which is what ProRes, VP9,
+/// WebP and APNG store.
+pub(crate) fn fill_straight_rgba_frame(frame: &mut ffmpeg::frame::Video, rendered: &RenderedFrame) {
+    let row_bytes = rendered.width as usize * 4;
+    let src_stride = rendered.padded_bytes_per_row as usize;
+    let dst_stride = frame.stride(0);
+    let data = frame.data_mut(0);
+
+    for y in 0..rendered.height as usize {
+        let src = &rendered.data[y * src_stride..y * src_stride + row_bytes];
+        let dst = &mut data[y * dst_stride..y * dst_stride + row_bytes];
+
+        for (src, dst) in src.chunks_exact(4).zip(dst.chunks_exact_mut(4)) {
+            dst.copy_from_slice(&unpremultiply([src[0], src[1], src[2], src[3]]));
+        }
+    }
+}
+
+fn unpremultiply([r, g, b, a]: [u8; 4]) -> [u8; 4] {
+    match a {
+        0 => [0, 0, 0, 0],
+        255 => [r, g, b, a],
+        _ => {
+            let scale = |c: u8| ((c as u32 * 255 + a as u32 / 2) / a as u32).min(255) as u8;
+            [scale(r), scale(g), scale(b), a]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unpremultiply_restores_straight_colour() {
+        assert_eq!(unpremultiply([64, 0, 32, 128]), [128, 0, 64, 128]);
+        assert_eq!(unpremultiply([10, 20, 30, 255]), [10, 20, 30, 255]);
+        assert_eq!(unpremultiply([10, 20, 30, 0]), [0, 0, 0, 0]);
+        assert_eq!(unpremultiply([200, 0, 0, 100]), [255, 0, 0, 100]);
+    }
+
+    #[test]
+    fn background_is_made_transparent() {
+        assert!(matches!(
+            transparent_background(&BackgroundSource::Wallpaper { path: None }),
+            BackgroundSource::Color { alpha: 0, .. }
+        ));
+        assert!(matches!(
+            transparent_background(&BackgroundSource::Color {
+                value: [255, 128, 0],
+                alpha: 51,
+            }),
+            BackgroundSource::Color {
+                value: [51, 25, 0],
+                alpha: 51,
+            }
+        ));
+    }
+
+    #[test]
+    fn vp9_alpha_export_does_not_replace_the_webm_export() {
+        let base = std::path::Path::new("/exports/recording.mp4");
+
+        assert_eq!(
+            suffixed_output_path(base, AlphaCodec::Vp9.file_suffix(), "webm"),
+            PathBuf::from("/exports/recording-alpha.webm")
+        );
+        assert_eq!(
+            suffixed_output_path(base, AlphaCodec::ProRes4444.file_suffix(), "mov"),
+            PathBuf::from("/exports/recording.mov")
+        );
+    }
+}
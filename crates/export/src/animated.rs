@@ -0,0 +1,215 @@
+use cap_enc_ffmpeg::{
+    animated::{AnimatedImageCodec, AnimatedImageEncoder},
+    animated_image::AnimatedImageFile,
+};
+use cap_media_info::{RawVideoFormat, VideoInfo};
+use cap_project::XY;
+use cap_rendering::{ProjectUniforms, RenderSegment, RenderedFrame};
+use futures::FutureExt;
+use serde::Deserialize;
+use specta::Type;
+use std::{ops::Range, path::PathBuf, time::Duration};
+use tracing::info;
+
+use crate::{ExportTimeRange, ExporterBase, alpha::fill_straight_rgba_frame};
+
+#[derive(Deserialize, Type, Clone, Copy, Debug, Default, PartialEq)]
+pub enum AnimatedImageFormat {
+    #[default]
+    WebP,
+    Apng,
+}
+
+impl AnimatedImageFormat {
+    pub fn extension(&self) -> &'static str {
+        self.codec().extension()
+    }
+
+    fn codec(&self) -> AnimatedImageCodec {
+        match self {
+            Self::WebP => AnimatedImageCodec::WebP,
+            Self::Apng => AnimatedImageCodec::Apng,
+        }
+    }
+}
+
+/// Animated WebP or APNG, for when GIF's 256 colours band gradients.
+#[derive(Deserialize, Type, Clone, Copy, Debug)]
+pub struct AnimatedImageExportSettings {
+    pub fps: u32,
+    pub resolution_base: XY<u32>,
+    #[serde(default)]
+    pub image_format: AnimatedImageFormat,
+    /// WebP quality from 0-100 (default: 80). APNG is always lossless.
+    #[serde(default)]
+    pub quality: Option<u8>,
+    /// Encode WebP losslessly, ignoring `quality`.
+    #[serde(default)]
+    pub lossless: bool,
+    /// How many times the animation plays (default: loops forever)
+    #[serde(default)]
+    pub loop_count: Option<u16>,
+    #[serde(default)]
+    pub time_range: Option<ExportTimeRange>,
+}
+
+impl AnimatedImageExportSettings {
+    pub async fn export(
+        self,
+        mut base: ExporterBase,
+        on_progress: impl FnMut(u32) -> bool + Send + 'static,
+    ) -> Result<PathBuf, String> {
+        info!("Exporting animated image with settings: {:?}", &self);
+
+        base.set_time_range(self.time_range);
+        let frame_range = base.frame_range(self.fps);
+
+        let codec = self.image_format.codec();
+        let output_path = base.output_path.with_extension(codec.extension());
+
+        let output_size = ProjectUniforms::get_output_size(
+            &base.render_constants.options,
+            &base.project_config,
+            self.resolution_base,
+        );
+
+        let mut video_info =
+            VideoInfo::from_raw(RawVideoFormat::Rgba, output_size.0, output_size.1, self.fps);
+        video_info.time_base = ffmpeg::Rational::new(1, self.fps as i32);
+
+        let muxer_path = output_path.clone();
+        let loop_count = self.loop_count;
+        let quality = self.quality;
+        let lossless = self.lossless;
+
+        render_rgba_frames(
+            &base,
+            self.fps,
+            self.resolution_base,
+            frame_range,
+            move || {
+                let file = AnimatedImageFile::init(muxer_path, codec, loop_count, |o| {
+                    let mut builder = AnimatedImageEncoder::builder(video_info, codec);
+                    if let Some(quality) = quality {
+                        builder = builder.with_quality(quality);
+                    }
+                    if lossless {
+                        builder = builder.with_lossless();
+                    }
+                    builder.build(o)
+                })
+                .map_err(|e| e.to_string())?;
+
+                Ok(AnimatedImageSink {
+                    file,
+                    frame: ffmpeg::frame::Video::new(
+                        ffmpeg::format::Pixel::RGBA,
+                        output_size.0,
+                        output_size.1,
+                    ),
+                    converted_frame: None,
+                    frame_count: 0,
+                })
+            },
+            on_progress,
+        )
+        .await?;
+
+        Ok(output_path)
+    }
+}
+
+/// Where [`render_rgba_frames`] sends each rendered frame.
+pub(crate) trait RgbaFrameSink {
+    fn add_frame(&mut self, frame: &RenderedFrame) -> Result<(), String>;
+
+    fn finish(self) -> Result<(), String>;
+}
+
+/// The render loop shared by exporters that only need RGBA frames and no audio: renders
+/// `frame_range` of the timeline and feeds it to the sink on a blocking thread, calling
+/// `on_progress` before each frame and stopping if it returns false.
+pub(crate) async fn render_rgba_frames<S: RgbaFrameSink>(
+    base: &ExporterBase,
+    fps: u32,
+    resolution_base: XY<u32>,
+    frame_range: Range<u32>,
+    make_sink: impl FnOnce() -> Result<S, String> + Send + 'static,
+    mut on_progress: impl FnMut(u32) -> bool + Send + 'static,
+) -> Result<(), String> {
+    let (tx_image_data, mut video_rx) = tokio::sync::mpsc::channel::<(RenderedFrame, u32)>(4);
+
+    let encoder_thread = tokio::task::spawn_blocking(move || {
+        let mut sink = make_sink()?;
+        let mut frame_count = 0;
+
+        while let Some((frame, _frame_number)) = video_rx.blocking_recv() {
+            if !(on_progress)(frame_count) {
+                return Err("Export cancelled".to_string());
+            }
+
+            sink.add_frame(&frame)?;
+
+            frame_count += 1;
+        }
+
+        sink.finish()
+    })
+    .then(|f| async { f.map_err(|e| e.to_string()).and_then(|v| v) });
+
+    let render_video_task = cap_rendering::render_video_to_channel(
+        &base.render_constants,
+        &base.project_config,
+        tx_image_data,
+        &base.recording_meta,
+        &base.studio_meta,
+        base.segments
+            .iter()
+            .map(|s| RenderSegment {
+                cursor: s.cursor.clone(),
+                keyboard: s.keyboard.clone(),
+                decoders: s.decoders.clone(),
+            })
+            .collect(),
+        fps,
+        resolution_base,
+        &base.recordings,
+        Some(frame_range),
+    )
+    .then(|f| async { f.map_err(|v| v.to_string()) });
+
+    tokio::try_join!(encoder_thread, render_video_task)?;
+
+    Ok(())
+}
+
+struct AnimatedImageSink {
+    file: AnimatedImageFile,
+    frame: ffmpeg::frame::Video,
+    converted_frame: Option<ffmpeg::frame::Video>,
+    frame_count: i64,
+}
+
+impl RgbaFrameSink for AnimatedImageSink {
+    fn add_frame(&mut self, frame: &RenderedFrame) -> Result<(), String> {
+        fill_straight_rgba_frame(&mut self.frame, frame);
+        self.frame.set_pts(Some(self.frame_count));
+
+        self.file
+            .queue_video_frame_reusable(&mut self.frame, &mut self.converted_frame, Duration::MAX)
+            .map_err(|e| e.to_string())?;
+
+        self.frame_count += 1;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<(), String> {
+        let res = self
+            .file
+            .finish()
+            .map_err(|e| format!("Failed to finish encoding: {e}"))?;
+
+        res.video_finish
+            .map_err(|e| format!("Video encoding failed: {e}"))
+    }
+}
@@ -0,0 +1,104 @@
+use crate::{AudioExporterBase, ExportTimeRange, mp4::ExportAudioRenderer};
+use cap_editor::AudioRenderer;
+use cap_enc_ffmpeg::{
+    AudioEncoder, aac::AACEncoder, audio_file::AudioFile, flac::FlacEncoder, opus::OpusEncoder,
+    pcm::PcmEncoder,
+};
+use serde::Deserialize;
+use specta::Type;
+use std::path::PathBuf;
+use tracing::info;
+
+#[derive(Deserialize, Type, Clone, Copy, Debug, PartialEq)]
+pub enum AudioExportFormat {
+    Wav,
+    Flac,
+    M4a,
+    Opus,
+}
+
+impl AudioExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Wav => "wav",
+            Self::Flac => "flac",
+            Self::M4a => "m4a",
+            Self::Opus => "opus",
+        }
+    }
+}
+
+#[derive(Deserialize, Type, Clone, Copy, Debug)]
+pub struct AudioExportSettings {
+    pub audio_format: AudioExportFormat,
+    #[serde(default)]
+    pub time_range: Option<ExportTimeRange>,
+}
+
+impl AudioExportSettings {
+    /// Audio is rendered in 20ms blocks, and progress is reported once per block.
+    pub const BLOCK_RATE: u32 = 50;
+
+    pub async fn export(
+        self,
+        mut base: AudioExporterBase,
+        mut on_progress: impl FnMut(u32) -> bool + Send + 'static,
+    ) -> Result<PathBuf, String> {
+        info!("Exporting audio with settings: {:?}", &self);
+
+        if base.audio_segments.iter().all(|s| s.tracks.is_empty()) {
+            return Err("Recording has no audio to export".to_string());
+        }
+
+        if base.project_config.audio.mute {
+            return Err("Project audio is muted".to_string());
+        }
+
+        base.set_time_range(self.time_range);
+        let block_range = base.frame_range(Self::BLOCK_RATE);
+        let output_path = base
+            .output_path
+            .with_extension(self.audio_format.extension());
+        let audio_format = self.audio_format;
+
+        tokio::task::spawn_blocking(move || {
+            let mut file = AudioFile::init(
+                output_path.clone(),
+                |o| -> Result<Box<dyn AudioEncoder + Send>, Box<dyn std::error::Error>> {
+                    let info = AudioRenderer::info();
+                    Ok(match audio_format {
+                        AudioExportFormat::Wav => PcmEncoder::init(info, o)?.boxed(),
+                        AudioExportFormat::Flac => FlacEncoder::init(info, o)?.boxed(),
+                        AudioExportFormat::M4a => AACEncoder::init(info, o)?.boxed(),
+                        AudioExportFormat::Opus => OpusEncoder::init(info, o)?.boxed(),
+                    })
+                },
+            )
+            .map_err(|e| e.to_string())?;
+
+            let mut renderer = ExportAudioRenderer::new(
+                AudioRenderer::new(base.audio_segments),
+                Self::BLOCK_RATE,
+                block_range.start,
+            );
+
+            for block in 0..(block_range.end - block_range.start) {
+                if let Some(frame) = renderer.render_for_frame(block, &base.project_config) {
+                    file.queue_frame(frame);
+                }
+
+                if !on_progress(block) {
+                    return Err("Export cancelled".to_string());
+                }
+            }
+
+            file.finish()
+                .map_err(|e| format!("Failed to finish encoding: {e}"))?
+                .map_err(|e| format!("Audio encoding failed: {e}"))?;
+
+            Ok(output_path)
+        })
+        .await
+        .map_err(|e| e.to_string())?
+    }
+}
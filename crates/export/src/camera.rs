@@ -0,0 +1,291 @@
+use crate::{
+    ExportTimeRange, ExporterBase,
+    mp4::{ExportAudioRenderer, ExportCompression},
+    suffixed_output_path,
+};
+use cap_editor::{AudioRenderer, AudioStem, get_audio_stem_segments};
+use cap_enc_ffmpeg::{AudioEncoder, aac::AACEncoder, h264::H264Encoder, mp4::MP4File};
+use cap_media_info::{RawVideoFormat, VideoInfo};
+use cap_rendering::{DecodedFrame, PixelFormat};
+use ffmpeg::{format::Pixel, frame, software::scaling};
+use futures::FutureExt;
+use serde::Deserialize;
+use specta::Type;
+use std::{path::PathBuf, time::Duration};
+use tracing::{info, warn};
+
+/// Exports the camera recording on its own, cut the same way as the timeline, so the
+/// talking-head footage can be edited separately from the screen.
+#[derive(Deserialize, Type, Clone, Copy, Debug)]
+pub struct CameraExportSettings {
+    pub fps: u32,
+    pub compression: ExportCompression,
+    #[serde(default)]
+    pub time_range: Option<ExportTimeRange>,
+}
+
+impl CameraExportSettings {
+    /// Writes the camera at its native resolution with the microphone as the only audio.
+    /// Timeline segments, their timescale and the camera's clip offsets are applied; zoom,
+    /// layout and the camera's on-screen styling aren't. The file is `<name>-camera.mp4`
+    /// so it doesn't replace the composited MP4 export.
+    pub async fn export(
+        self,
+        mut base: ExporterBase,
+        mut on_progress: impl FnMut(u32) -> bool + Send + 'static,
+    ) -> Result<PathBuf, String> {
+        info!("Exporting camera with settings: {:?}", &self);
+
+        base.set_time_range(self.time_range);
+        let frame_range = base.frame_range(self.fps);
+        let fps = self.fps;
+
+        let output_size = base
+            .segments
+            .iter()
+            .find_map(|s| s.decoders.camera_video_dimensions())
+            .ok_or_else(|| "Recording has no camera track".to_string())?;
+        let output_path = suffixed_output_path(&base.output_path, "-camera", "mp4");
+
+        let mut video_info =
+            VideoInfo::from_raw(RawVideoFormat::Nv12, output_size.0, output_size.1, fps);
+        video_info.time_base = ffmpeg::Rational::new(1, fps as i32);
+        let bpp = self.compression.bits_per_pixel();
+
+        let has_audio =
+            !base.project_config.audio.mute && base.segments.iter().any(|s| s.audio.is_some());
+        let audio_segments = get_audio_stem_segments(&base.segments, AudioStem::Mic);
+
+        let (frame_tx, mut frame_rx) = tokio::sync::mpsc::channel::<Option<DecodedFrame>>(4);
+
+        let project_for_audio = base.project_config.clone();
+        let muxer_path = output_path.clone();
+        let first_frame = frame_range.start;
+        let encoder_thread = tokio::task::spawn_blocking(move || {
+            let mut muxer = MP4File::init(
+                "camera",
+                muxer_path,
+                |o| {
+                    H264Encoder::builder(video_info)
+                        .with_bpp(bpp)
+                        .with_external_conversion()
+                        .build(o)
+                },
+                |o| {
+                    has_audio.then(|| {
+                        AACEncoder::init(AudioRenderer::info(), o)
+                            .map(|v| v.boxed())
+                            .map_err(Into::into)
+                    })
+                },
+            )
+            .map_err(|e| e.to_string())?;
+
+            let mut audio_renderer = has_audio.then(|| {
+                ExportAudioRenderer::new(AudioRenderer::new(audio_segments), fps, first_frame)
+            });
+
+            let mut converter = CameraFrameConverter::default();
+            let mut reusable_frame = frame::Video::new(Pixel::NV12, output_size.0, output_size.1);
+            fill_black_nv12(&mut reusable_frame);
+            let mut converted_frame: Option<frame::Video> = None;
+            let mut frame_count = 0;
+
+            while let Some(camera_frame) = frame_rx.blocking_recv() {
+                if !(on_progress)(frame_count) {
+                    return Err("Export cancelled".to_string());
+                }
+
+                // Gaps in the camera recording hold the last frame.
+                if let Some(camera_frame) = camera_frame
+                    && let Err(e) = converter.convert(&camera_frame, &mut reusable_frame)
+                {
+                    warn!(frame_count, "Failed to convert camera frame: {e}");
+                }
+                reusable_frame.set_pts(Some(frame_count as i64));
+
+                muxer
+                    .queue_video_frame_reusable(
+                        &mut reusable_frame,
+                        &mut converted_frame,
+                        Duration::MAX,
+                    )
+                    .map_err(|e| e.to_string())?;
+
+                if let Some(audio) = audio_renderer
+                    .as_mut()
+                    .and_then(|audio| audio.render_for_frame(frame_count, &project_for_audio))
+                {
+                    muxer.queue_audio_frame(audio);
+                }
+
+                frame_count += 1;
+            }
+
+            let res = muxer
+                .finish()
+                .map_err(|e| format!("Failed to finish encoding: {e}"))?;
+            if let Err(e) = res.video_finish {
+                return Err(format!("Video encoding failed: {e}"));
+            }
+            if let Err(e) = res.audio_finish {
+                return Err(format!("Audio encoding failed: {e}"));
+            }
+
+            Ok(())
+        })
+        .then(|r| async { r.map_err(|e| e.to_string()).and_then(|v| v) });
+
+        let project = &base.project_config;
+        let segments = &base.segments;
+        let decode_task = async move {
+            for frame_number in frame_range.clone() {
+                let Some((segment_time, segment)) =
+                    project.get_segment_time(frame_number as f64 / fps as f64)
+                else {
+                    break;
+                };
+
+                let offsets = project
+                    .clips
+                    .iter()
+                    .find(|c| c.index == segment.recording_clip)
+                    .map(|c| c.offsets)
+                    .unwrap_or_default();
+                let decoders = &segments[segment.recording_clip as usize].decoders;
+
+                let camera_frame = if frame_number == frame_range.start {
+                    decoders
+                        .get_camera_frame_initial(segment_time as f32, offsets)
+                        .await
+                } else {
+                    decoders
+                        .get_camera_frame(segment_time as f32, offsets)
+                        .await
+                };
+
+                if frame_tx.send(camera_frame).await.is_err() {
+                    break;
+                }
+            }
+
+            Ok::<_, String>(())
+        };
+
+        tokio::try_join!(encoder_thread, decode_task)?;
+
+        Ok(output_path)
+    }
+}
+
+/// Converts decoded camera frames, in whatever format and size the decoder hands back,
+/// to the NV12 frame the encoder takes.
+#[derive(Default)]
+struct CameraFrameConverter {
+    source: Option<frame::Video>,
+    scaler: Option<scaling::Context>,
+}
+
+impl CameraFrameConverter {
+    fn convert(&mut self, decoded: &DecodedFrame, output: &mut frame::Video) -> Result<(), String> {
+        if decoded.data().is_empty() {
+            return Err("frame isn't in CPU memory".to_string());
+        }
+
+        let format = match decoded.format() {
+            PixelFormat::Rgba => Pixel::RGBA,
+            PixelFormat::Nv12 => Pixel::NV12,
+            PixelFormat::Yuv420p => Pixel::YUV420P,
+        };
+        let (width, height) = (decoded.width(), decoded.height());
+
+        if !self
+            .source
+            .as_ref()
+            .is_some_and(|f| f.format() == format && f.width() == width && f.height() == height)
+        {
+            self.source = None;
+            self.scaler = None;
+        }
+        let source = self
+            .source
+            .get_or_insert_with(|| frame::Video::new(format, width, height));
+
+        fill_source_frame(source, decoded);
+
+        let mut scaler = match self.scaler.take() {
+            Some(scaler) => scaler,
+            None => scaling::Context::get(
+                format,
+                width,
+                height,
+                Pixel::NV12,
+                output.width(),
+                output.height(),
+                scaling::flag::Flags::BICUBIC,
+            )
+            .map_err(|e| e.to_string())?,
+        };
+        let result = scaler.run(source, output).map_err(|e| e.to_string());
+        self.scaler = Some(scaler);
+
+        result
+    }
+}
+
+fn fill_source_frame(frame: &mut frame::Video, decoded: &DecodedFrame) {
+    let width = decoded.width() as usize;
+    let height = decoded.height() as usize;
+    let chroma_width = width.div_ceil(2);
+    let chroma_height = height.div_ceil(2);
+    let y_stride = decoded.y_stride() as usize;
+    let uv_stride = decoded.uv_stride() as usize;
+
+    match decoded.format() {
+        PixelFormat::Rgba => copy_plane(frame, 0, decoded.data(), y_stride, width * 4, height),
+        PixelFormat::Nv12 => {
+            if let (Some(y), Some(uv)) = (decoded.y_plane(), decoded.uv_plane()) {
+                copy_plane(frame, 0, y, y_stride, width, height);
+                copy_plane(frame, 1, uv, uv_stride, chroma_width * 2, chroma_height);
+            }
+        }
+        PixelFormat::Yuv420p => {
+            if let (Some(y), Some(u), Some(v)) =
+                (decoded.y_plane(), decoded.u_plane(), decoded.v_plane())
+            {
+                copy_plane(frame, 0, y, y_stride, width, height);
+                copy_plane(frame, 1, u, uv_stride, chroma_width, chroma_height);
+                copy_plane(frame, 2, v, uv_stride, chroma_width, chroma_height);
+            }
+        }
+    }
+}
+
+fn copy_plane(
+    frame: &mut frame::Video,
+    plane: usize,
+    src: &[u8],
+    src_stride: usize,
+    row_bytes: usize,
+    rows: usize,
+) {
+    let dst_stride = frame.stride(plane);
+    let dst = frame.data_mut(plane);
+    let row_bytes = row_bytes.min(src_stride).min(dst_stride);
+
+    for row in 0..rows {
+        let (Some(src), Some(dst)) = (
+            src.get(row * src_stride..row * src_stride + row_bytes),
+            dst.get_mut(row * dst_stride..row * dst_stride + row_bytes),
+        ) else {
+            break;
+        };
+        dst.copy_from_slice(src);
+    }
+}
+
+/// Shown until the camera's first frame arrives.
+fn fill_black_nv12(frame: &mut frame::Video) {
+    frame.data_mut(0).fill(16);
+    frame.data_mut(1).fill(128);
+}
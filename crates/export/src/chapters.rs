@@ -0,0 +1,107 @@
+use std::{ops::Range, time::Duration};
+
+use cap_enc_ffmpeg::mp4::Chapter;
+use cap_project::{ProjectConfiguration, TimelineMarker};
+
+/// Builds MP4 chapters from the timeline's markers, rebased onto the exported `frame_range`.
+pub(crate) fn timeline_chapters(
+    project: &ProjectConfiguration,
+    frame_range: &Range<u32>,
+    fps: u32,
+) -> Vec<Chapter> {
+    let Some(timeline) = project.timeline.as_ref() else {
+        return vec![];
+    };
+
+    let range_start = frame_range.start as f64 / fps as f64;
+    let range_end = frame_range.end as f64 / fps as f64;
+    marker_chapters(&timeline.markers, range_start, range_end)
+}
+
+/// Each marker starts a chapter that runs until the next marker or the end of the export.
+/// A marker placed before the exported range still names the chapter the range opens in.
+fn marker_chapters(markers: &[TimelineMarker], range_start: f64, range_end: f64) -> Vec<Chapter> {
+    let mut markers = markers
+        .iter()
+        .filter(|marker| marker.time.is_finite() && marker.time < range_end)
+        .collect::<Vec<_>>();
+    markers.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+    let first_in_range = markers
+        .iter()
+        .rposition(|marker| marker.time <= range_start)
+        .unwrap_or(0);
+    let markers = &markers[first_in_range..];
+
+    markers
+        .iter()
+        .enumerate()
+        .filter_map(|(i, marker)| {
+            let start = marker.time.max(range_start);
+            let end = markers
+                .get(i + 1)
+                .map_or(range_end, |next| next.time.min(range_end));
+            if end <= start {
+                return None;
+            }
+
+            Some(Chapter {
+                start: Duration::from_secs_f64(start - range_start),
+                end: Duration::from_secs_f64(end - range_start),
+                title: marker.title.trim().to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn marker(time: f64, title: &str) -> TimelineMarker {
+        TimelineMarker {
+            id: format!("{time}"),
+            time,
+            title: title.to_string(),
+        }
+    }
+
+    #[test]
+    fn markers_become_contiguous_chapters() {
+        let markers = vec![
+            marker(30.0, "Setup"),
+            marker(0.0, "Intro"),
+            marker(90.0, "Wrap up"),
+        ];
+
+        let chapters = marker_chapters(&markers, 0.0, 120.0);
+
+        assert_eq!(chapters.len(), 3);
+        assert_eq!(chapters[0].title, "Intro");
+        assert_eq!(chapters[0].end, Duration::from_secs(30));
+        assert_eq!(chapters[1].title, "Setup");
+        assert_eq!(chapters[1].end, Duration::from_secs(90));
+        assert_eq!(chapters[2].start, Duration::from_secs(90));
+        assert_eq!(chapters[2].end, Duration::from_secs(120));
+    }
+
+    #[test]
+    fn chapters_are_rebased_to_range() {
+        let markers = vec![
+            marker(0.0, "Intro"),
+            marker(10.0, "Setup"),
+            marker(30.0, "Build"),
+            marker(50.0, "Deploy"),
+        ];
+
+        let chapters = marker_chapters(&markers, 20.0, 40.0);
+
+        assert_eq!(chapters.len(), 2);
+        assert_eq!(chapters[0].title, "Setup");
+        assert_eq!(chapters[0].start, Duration::ZERO);
+        assert_eq!(chapters[0].end, Duration::from_secs(10));
+        assert_eq!(chapters[1].title, "Build");
+        assert_eq!(chapters[1].start, Duration::from_secs(10));
+        assert_eq!(chapters[1].end, Duration::from_secs(20));
+    }
+}
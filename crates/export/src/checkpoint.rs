@@ -0,0 +1,279 @@
+use cap_utils::move_file;
+use serde::{Deserialize, Serialize};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+use tracing::{info, warn};
+
+const CURRENT_CHECKPOINT_VERSION: u32 = 1;
+const MANIFEST_NAME: &str = "manifest.json";
+
+/// Progress of an export that's written as independently playable fragments, so a failed
+/// or cancelled export can pick up from the last completed fragment.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CheckpointManifest {
+    version: u32,
+    /// Fingerprint of everything that affects the encoded output. A checkpoint written with
+    /// a different key is discarded rather than resumed.
+    key: String,
+    fragments: Vec<CheckpointFragment>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct CheckpointFragment {
+    index: u32,
+    file_name: String,
+    /// Timeline frames covered by the fragment, end exclusive.
+    start_frame: u32,
+    end_frame: u32,
+    file_size: u64,
+}
+
+pub(crate) struct ExportCheckpoint {
+    dir: PathBuf,
+    manifest: CheckpointManifest,
+}
+
+impl ExportCheckpoint {
+    /// Fragments are cut on a fresh encoder, which always starts on a keyframe.
+    pub(crate) const FRAGMENT_SECS: u32 = 30;
+
+    /// Opens the checkpoint in `dir`, keeping the completed fragments of a previous run
+    /// with the same `key` and clearing out anything else.
+    pub(crate) fn open(dir: PathBuf, key: String) -> std::io::Result<Self> {
+        let previous = CheckpointManifest::load(&dir.join(MANIFEST_NAME))
+            .ok()
+            .filter(|m| m.version == CURRENT_CHECKPOINT_VERSION && m.key == key);
+
+        let fragments = match previous {
+            Some(previous) => {
+                let fragments = valid_prefix(&dir, previous.fragments);
+                info!(
+                    dir = %dir.display(),
+                    fragments = fragments.len(),
+                    "Resuming export from checkpoint"
+                );
+                fragments
+            }
+            None => {
+                if dir.exists() {
+                    std::fs::remove_dir_all(&dir)?;
+                }
+                Vec::new()
+            }
+        };
+
+        std::fs::create_dir_all(&dir)?;
+
+        Ok(Self {
+            dir,
+            manifest: CheckpointManifest {
+                version: CURRENT_CHECKPOINT_VERSION,
+                key,
+                fragments,
+            },
+        })
+    }
+
+    /// The timeline frame to continue rendering from.
+    pub(crate) fn resume_frame(&self, range_start: u32) -> u32 {
+        self.manifest
+            .fragments
+            .last()
+            .map(|f| f.end_frame)
+            .unwrap_or(range_start)
+    }
+
+    pub(crate) fn next_fragment_path(&self) -> PathBuf {
+        self.dir.join(fragment_file_name(self.next_index()))
+    }
+
+    /// Records the fragment at `next_fragment_path` as complete.
+    pub(crate) fn commit(&mut self, start_frame: u32, end_frame: u32) -> std::io::Result<()> {
+        let index = self.next_index();
+        let file_name = fragment_file_name(index);
+        let path = self.dir.join(&file_name);
+
+        let file = std::fs::File::open(&path)?;
+        file.sync_all()?;
+        let file_size = file.metadata()?.len();
+
+        self.manifest.fragments.push(CheckpointFragment {
+            index,
+            file_name,
+            start_frame,
+            end_frame,
+            file_size,
+        });
+
+        self.write_manifest()
+    }
+
+    pub(crate) fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub(crate) fn fragment_paths(&self) -> Vec<PathBuf> {
+        self.manifest
+            .fragments
+            .iter()
+            .map(|f| self.dir.join(&f.file_name))
+            .collect()
+    }
+
+    /// Deletes the checkpoint once the fragments have been joined into the final output.
+    pub(crate) fn remove(self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.dir) {
+            warn!(
+                "Failed to remove export checkpoint {}: {e}",
+                self.dir.display()
+            );
+        }
+    }
+
+    fn next_index(&self) -> u32 {
+        self.manifest
+            .fragments
+            .last()
+            .map(|f| f.index + 1)
+            .unwrap_or(0)
+    }
+
+    fn write_manifest(&self) -> std::io::Result<()> {
+        let path = self.dir.join(MANIFEST_NAME);
+        let temp_path = path.with_extension("json.tmp");
+        let json = serde_json::to_string_pretty(&self.manifest)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+
+        let mut file = std::fs::File::create(&temp_path)?;
+        file.write_all(json.as_bytes())?;
+        file.sync_all()?;
+
+        move_file(&temp_path, &path)
+    }
+}
+
+impl CheckpointManifest {
+    fn load(path: &Path) -> std::io::Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        serde_json::from_str(&content)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Keeps fragments up to the first one that's missing, truncated or out of sequence.
+fn valid_prefix(dir: &Path, fragments: Vec<CheckpointFragment>) -> Vec<CheckpointFragment> {
+    let mut valid = Vec::with_capacity(fragments.len());
+
+    for fragment in fragments {
+        let contiguous = match valid.last() {
+            Some(CheckpointFragment {
+                index, end_frame, ..
+            }) => fragment.index == index + 1 && fragment.start_frame == *end_frame,
+            None => fragment.index == 0,
+        };
+        let intact = std::fs::metadata(dir.join(&fragment.file_name))
+            .is_ok_and(|m| m.len() == fragment.file_size);
+
+        if !contiguous || !intact {
+            break;
+        }
+
+        valid.push(fragment);
+    }
+
+    valid
+}
+
+fn fragment_file_name(index: u32) -> String {
+    format!("fragment_{index:03}.mp4")
+}
+
+/// FNV-1a, which unlike `DefaultHasher` is stable across Rust releases.
+pub(crate) fn checkpoint_key(parts: &[&str]) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for part in parts {
+        for byte in part.bytes().chain([0]) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    format!("{hash:016x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_fragment(dir: &Path, checkpoint: &mut ExportCheckpoint, start: u32, end: u32) {
+        std::fs::write(
+            checkpoint.next_fragment_path(),
+            vec![0u8; (end - start) as usize],
+        )
+        .unwrap();
+        checkpoint.commit(start, end).unwrap();
+        assert!(dir.join(MANIFEST_NAME).exists());
+    }
+
+    #[test]
+    fn resumes_from_last_completed_fragment() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("parts");
+
+        let mut checkpoint = ExportCheckpoint::open(path.clone(), "a".to_string()).unwrap();
+        assert_eq!(checkpoint.resume_frame(10), 10);
+        write_fragment(&path, &mut checkpoint, 10, 100);
+        write_fragment(&path, &mut checkpoint, 100, 190);
+        // An interrupted fragment that never got committed.
+        std::fs::write(checkpoint.next_fragment_path(), [1, 2, 3]).unwrap();
+        drop(checkpoint);
+
+        let checkpoint = ExportCheckpoint::open(path.clone(), "a".to_string()).unwrap();
+        assert_eq!(checkpoint.resume_frame(10), 190);
+        assert_eq!(
+            checkpoint.fragment_paths(),
+            vec![path.join("fragment_000.mp4"), path.join("fragment_001.mp4")]
+        );
+        assert_eq!(
+            checkpoint.next_fragment_path(),
+            path.join("fragment_002.mp4")
+        );
+    }
+
+    #[test]
+    fn truncated_fragments_are_redone() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("parts");
+
+        let mut checkpoint = ExportCheckpoint::open(path.clone(), "a".to_string()).unwrap();
+        write_fragment(&path, &mut checkpoint, 0, 90);
+        write_fragment(&path, &mut checkpoint, 90, 180);
+        std::fs::write(path.join("fragment_001.mp4"), [0u8; 10]).unwrap();
+        drop(checkpoint);
+
+        let checkpoint = ExportCheckpoint::open(path.clone(), "a".to_string()).unwrap();
+        assert_eq!(checkpoint.resume_frame(0), 90);
+    }
+
+    #[test]
+    fn checkpoint_with_different_key_is_discarded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("parts");
+
+        let mut checkpoint = ExportCheckpoint::open(path.clone(), "a".to_string()).unwrap();
+        write_fragment(&path, &mut checkpoint, 0, 90);
+        drop(checkpoint);
+
+        let checkpoint = ExportCheckpoint::open(path.clone(), "b".to_string()).unwrap();
+        assert_eq!(checkpoint.resume_frame(0), 0);
+        assert!(!path.join("fragment_000.mp4").exists());
+    }
+
+    #[test]
+    fn checkpoint_keys_separate_parts() {
+        assert_eq!(checkpoint_key(&["a", "b"]), checkpoint_key(&["a", "b"]));
+        assert_ne!(checkpoint_key(&["ab", ""]), checkpoint_key(&["a", "b"]));
+    }
+}
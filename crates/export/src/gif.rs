@@ -1,12 +1,14 @@
 use cap_project::XY;
-use cap_rendering::{ProjectUniforms, RenderSegment, RenderedFrame};
-use futures::FutureExt;
+use cap_rendering::{ProjectUniforms, RenderedFrame};
 use serde::Deserialize;
 use specta::Type;
 use std::path::PathBuf;
 use tracing::trace;
 
-use crate::{ExportError, ExporterBase};
+use crate::{
+    ExportTimeRange, ExporterBase,
+    animated::{RgbaFrameSink, render_rgba_frames},
+};
 
 #[derive(Deserialize, Clone, Copy, Debug, Type)]
 pub struct GifQuality {
@@ -14,6 +16,24 @@ pub struct GifQuality {
     pub quality: Option<u8>,
     /// Whether to prioritize speed over quality (default: false)
     pub fast: Option<bool>,
+    /// How many times the animation plays (default: loops forever)
+    #[serde(default)]
+    pub loop_count: Option<u16>,
+    /// Most colours in each frame's palette, 2-256 (default: 256)
+    #[serde(default)]
+    pub max_colors: Option<u32>,
+    /// Reuse the first frame's palette for every frame (default: false)
+    #[serde(default)]
+    pub shared_palette: Option<bool>,
+    /// Dithering strength from 0.0 to 1.0 (default: 1.0)
+    #[serde(default)]
+    pub dithering: Option<f32>,
+    /// Lossy compression quality from 1-100, lower is smaller (default: lossless)
+    #[serde(default)]
+    pub lossy_quality: Option<u8>,
+    /// Hold the previous frame instead of repeating identical ones (default: false)
+    #[serde(default)]
+    pub merge_duplicate_frames: Option<bool>,
 }
 
 #[derive(Deserialize, Clone, Copy, Debug, Type)]
@@ -21,6 +41,8 @@ pub struct GifExportSettings {
     pub fps: u32,
     pub resolution_base: XY<u32>,
     pub quality: Option<GifQuality>,
+    #[serde(default)]
+    pub time_range: Option<ExportTimeRange>,
 }
 
 impl Default for GifExportSettings {
@@ -29,6 +51,7 @@ impl Default for GifExportSettings {
             fps: 30,
             resolution_base: XY { x: 1920, y: 1080 },
             quality: None,
+            time_range: None,
         }
     }
 }
@@ -36,12 +59,11 @@ impl Default for GifExportSettings {
 impl GifExportSettings {
     pub async fn export(
         self,
-        base: ExporterBase,
-        mut on_progress: impl FnMut(u32) -> bool + Send + 'static,
+        mut base: ExporterBase,
+        on_progress: impl FnMut(u32) -> bool + Send + 'static,
     ) -> Result<PathBuf, String> {
-        let meta = &base.studio_meta;
-
-        let (tx_image_data, mut video_rx) = tokio::sync::mpsc::channel::<(RenderedFrame, u32)>(4);
+        base.set_time_range(self.time_range);
+        let frame_range = base.frame_range(self.fps);
 
         let fps = self.fps;
 
@@ -70,71 +92,51 @@ impl GifExportSettings {
             .map(|q| cap_enc_gif::GifQuality {
                 quality: q.quality.unwrap_or(90),
                 fast: q.fast.unwrap_or(false),
+                loop_count: q.loop_count,
+                max_colors: q.max_colors,
+                shared_palette: q.shared_palette.unwrap_or(false),
+                dithering: q.dithering,
+                lossy_quality: q.lossy_quality,
+                merge_duplicate_frames: q.merge_duplicate_frames.unwrap_or(false),
             })
             .unwrap_or_default();
 
-        let mut gif_encoder = cap_enc_gif::GifEncoderWrapper::new_with_quality(
-            &gif_output_path,
-            output_size.0,
-            output_size.1,
+        let output_path = gif_output_path.clone();
+        render_rgba_frames(
+            &base,
             fps,
-            quality,
+            self.resolution_base,
+            frame_range,
+            move || {
+                cap_enc_gif::GifEncoderWrapper::new_with_quality(
+                    &gif_output_path,
+                    output_size.0,
+                    output_size.1,
+                    fps,
+                    quality,
+                )
+                .map_err(|e| format!("Failed to create GIF encoder: {e}"))
+            },
+            on_progress,
         )
-        .map_err(|e| format!("Failed to create GIF encoder: {e}"))?;
-
-        let encoder_thread = tokio::task::spawn_blocking(move || {
-            let mut frame_count = 0;
-
-            while let Some((frame, _frame_number)) = video_rx.blocking_recv() {
-                if !(on_progress)(frame_count) {
-                    return Err(ExportError::Other("Export cancelled".to_string()));
-                }
+        .await?;
 
-                if let Err(e) =
-                    gif_encoder.add_frame(&frame.data, frame.padded_bytes_per_row as usize)
-                {
-                    return Err(ExportError::Other(format!(
-                        "Failed to add frame to GIF: {e}"
-                    )));
-                }
-
-                frame_count += 1;
-            }
-
-            if let Err(e) = gif_encoder.finish() {
-                return Err(ExportError::Other(format!("Failed to finish GIF: {e}")));
-            }
-
-            Ok(gif_output_path)
-        })
-        .then(|f| async {
-            f.map_err(|e| e.to_string())
-                .and_then(|v| v.map_err(|v| v.to_string()))
-        });
+        Ok(output_path)
+    }
+}
 
-        let render_video_task = cap_rendering::render_video_to_channel(
-            &base.render_constants,
-            &base.project_config,
-            tx_image_data,
-            &base.recording_meta,
-            meta,
-            base.segments
-                .iter()
-                .map(|s| RenderSegment {
-                    cursor: s.cursor.clone(),
-                    keyboard: s.keyboard.clone(),
-                    decoders: s.decoders.clone(),
-                })
-                .collect(),
-            fps,
-            self.resolution_base,
-            &base.recordings,
+impl RgbaFrameSink for cap_enc_gif::GifEncoderWrapper {
+    fn add_frame(&mut self, frame: &RenderedFrame) -> Result<(), String> {
+        cap_enc_gif::GifEncoderWrapper::add_frame(
+            self,
+            &frame.data,
+            frame.padded_bytes_per_row as usize,
         )
-        .then(|f| async { f.map_err(|v| v.to_string()) });
-
-        let (output_path, _) =
-            tokio::try_join!(encoder_thread, render_video_task).map_err(|e| e.to_string())?;
+        .map_err(|e| format!("Failed to add frame to GIF: {e}"))
+    }
 
-        Ok(output_path)
+    fn finish(self) -> Result<(), String> {
+        cap_enc_gif::GifEncoderWrapper::finish(self)
+            .map_err(|e| format!("Failed to finish GIF: {e}"))
     }
 }
@@ -0,0 +1,247 @@
+use cap_enc_ffmpeg::hevc::HdrTransfer;
+use serde::Deserialize;
+use specta::Type;
+
+#[derive(Deserialize, Type, Clone, Copy, Debug, PartialEq)]
+pub enum HdrFormat {
+    /// PQ transfer, for HDR10 players.
+    Hdr10,
+    /// Hybrid log-gamma, which also looks right on SDR displays.
+    Hlg,
+}
+
+impl HdrFormat {
+    pub fn transfer(&self) -> HdrTransfer {
+        match self {
+            Self::Hdr10 => HdrTransfer::Pq,
+            Self::Hlg => HdrTransfer::Hlg,
+        }
+    }
+}
+
+/// Luminance SDR white is placed at inside an HDR signal (ITU-R BT.2408).
+const REFERENCE_WHITE_NITS: f32 = 203.0;
+/// Scene light that the HLG OETF maps to 75%, BT.2408's reference white.
+const HLG_REFERENCE_WHITE: f32 = 0.2647;
+
+const LINEARIZE_LUT_SIZE: usize = 1024;
+/// Indexed by the square root of linear light, so dark tones get most of the entries.
+const ENCODE_LUT_SIZE: usize = 4096;
+
+// Linear BT.709 to BT.2020 primaries (ITU-R BT.2087).
+const BT709_TO_BT2020: [[f32; 3]; 3] = [
+    [0.6274, 0.3293, 0.0433],
+    [0.0691, 0.9195, 0.0114],
+    [0.0164, 0.0880, 0.8956],
+];
+
+const BT2020_KR: f32 = 0.2627;
+const BT2020_KB: f32 = 0.0593;
+
+// Y'CbCr to R'G'B' for the BT.601 matrix the renderer's `rgba_to_nv12` shader encodes with.
+const BT601_CR_TO_R: f32 = 1.402;
+const BT601_CB_TO_G: f32 = 0.344_136;
+const BT601_CR_TO_G: f32 = 0.714_136;
+const BT601_CB_TO_B: f32 = 1.772;
+
+/// Converts the renderer's 8-bit NV12 frames (sRGB primaries, BT.601 matrix, limited range)
+/// into P010 BT.2020 frames for the HDR HEVC encoder, with SDR white at the reference
+/// level so the picture looks the same as the SDR export on an HDR display.
+pub(crate) struct SdrToHdrConverter {
+    linearize: Vec<f32>,
+    encode: Vec<f32>,
+}
+
+impl SdrToHdrConverter {
+    pub fn new(transfer: HdrTransfer) -> Self {
+        let linearize = (0..LINEARIZE_LUT_SIZE)
+            .map(|i| (i as f32 / (LINEARIZE_LUT_SIZE - 1) as f32).powf(2.4))
+            .collect();
+
+        let encode = (0..ENCODE_LUT_SIZE)
+            .map(|i| {
+                let linear = (i as f32 / (ENCODE_LUT_SIZE - 1) as f32).powi(2);
+                match transfer {
+                    HdrTransfer::Pq => pq_oetf(linear * REFERENCE_WHITE_NITS / 10_000.0),
+                    HdrTransfer::Hlg => hlg_oetf(linear * HLG_REFERENCE_WHITE),
+                }
+            })
+            .collect();
+
+        Self { linearize, encode }
+    }
+
+    /// Fills `dst` (P010, same size as `src`) from `src` (NV12, limited range).
+    pub fn convert(&self, src: &ffmpeg::frame::Video, dst: &mut ffmpeg::frame::Video) {
+        let width = src.width().min(dst.width()) as usize;
+        let height = src.height().min(dst.height()) as usize;
+
+        let (src_y_stride, src_uv_stride) = (src.stride(0), src.stride(1));
+        let (dst_y_stride, dst_uv_stride) = (dst.stride(0) / 2, dst.stride(1) / 2);
+        let (src_y, src_uv) = (src.data(0), src.data(1));
+
+        for block_y in 0..height.div_ceil(2) {
+            for block_x in 0..width.div_ceil(2) {
+                let uv_index = block_y * src_uv_stride + block_x * 2;
+                let cb = (src_uv[uv_index] as f32 - 128.0) / 224.0;
+                let cr = (src_uv[uv_index + 1] as f32 - 128.0) / 224.0;
+
+                let mut rgb_sum = [0.0; 3];
+                let mut samples = 0.0;
+
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let (x, y) = (block_x * 2 + dx, block_y * 2 + dy);
+                    if x >= width || y >= height {
+                        continue;
+                    }
+
+                    let luma = (src_y[y * src_y_stride + x] as f32 - 16.0) / 219.0;
+                    let rgb = self.pixel(luma, cb, cr);
+                    let luma = BT2020_KR * rgb[0]
+                        + (1.0 - BT2020_KR - BT2020_KB) * rgb[1]
+                        + BT2020_KB * rgb[2];
+
+                    plane_mut(dst, 0)[y * dst_y_stride + x] = p010_sample(64.0 + 876.0 * luma);
+
+                    for (sum, value) in rgb_sum.iter_mut().zip(rgb) {
+                        *sum += value;
+                    }
+                    samples += 1.0;
+                }
+
+                let [r, g, b] = rgb_sum.map(|sum| sum / samples);
+                let luma = BT2020_KR * r + (1.0 - BT2020_KR - BT2020_KB) * g + BT2020_KB * b;
+                let cb = (b - luma) / (2.0 * (1.0 - BT2020_KB));
+                let cr = (r - luma) / (2.0 * (1.0 - BT2020_KR));
+
+                let uv = plane_mut(dst, 1);
+                let index = block_y * dst_uv_stride + block_x * 2;
+                uv[index] = p010_sample(512.0 + 896.0 * cb);
+                uv[index + 1] = p010_sample(512.0 + 896.0 * cr);
+            }
+        }
+    }
+
+    /// BT.601 Y'CbCr (normalised) to HDR-encoded BT.2020 R'G'B'.
+    fn pixel(&self, luma: f32, cb: f32, cr: f32) -> [f32; 3] {
+        let rgb = [
+            luma + BT601_CR_TO_R * cr,
+            luma - BT601_CB_TO_G * cb - BT601_CR_TO_G * cr,
+            luma + BT601_CB_TO_B * cb,
+        ]
+        .map(|v| self.lookup(&self.linearize, v));
+
+        BT709_TO_BT2020.map(|row| {
+            let linear = row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2];
+            self.lookup(&self.encode, linear.max(0.0).sqrt())
+        })
+    }
+
+    fn lookup(&self, lut: &[f32], value: f32) -> f32 {
+        let index = (value.clamp(0.0, 1.0) * (lut.len() - 1) as f32).round() as usize;
+        lut[index]
+    }
+}
+
+fn plane_mut(frame: &mut ffmpeg::frame::Video, index: usize) -> &mut [u16] {
+    // ffmpeg aligns frame planes well beyond 2 bytes.
+    let (prefix, samples, _) = unsafe { frame.data_mut(index).align_to_mut::<u16>() };
+    debug_assert!(prefix.is_empty());
+    samples
+}
+
+/// P010 keeps 10-bit samples in the high bits of each little-endian 16-bit word.
+fn p010_sample(code: f32) -> u16 {
+    ((code.round().clamp(0.0, 1023.0) as u16) << 6).to_le()
+}
+
+/// SMPTE ST 2084 inverse EOTF, from luminance normalised to 10,000 nits.
+fn pq_oetf(luminance: f32) -> f32 {
+    const M1: f32 = 0.159_301_76;
+    const M2: f32 = 78.843_75;
+    const C1: f32 = 0.835_937_5;
+    const C2: f32 = 18.851_563;
+    const C3: f32 = 18.6875;
+
+    let l = luminance.clamp(0.0, 1.0).powf(M1);
+    ((C1 + C2 * l) / (1.0 + C3 * l)).powf(M2)
+}
+
+/// ARIB STD-B67 OETF, from normalised scene light.
+fn hlg_oetf(scene: f32) -> f32 {
+    const A: f32 = 0.178_832_77;
+    const B: f32 = 0.284_668_92;
+    const C: f32 = 0.559_910_7;
+
+    let scene = scene.clamp(0.0, 1.0);
+    if scene <= 1.0 / 12.0 {
+        (3.0 * scene).sqrt()
+    } else {
+        A * (12.0 * scene - B).ln() + C
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert_flat(transfer: HdrTransfer, y: u8, cb: u8, cr: u8) -> (u16, u16, u16) {
+        let mut src = ffmpeg::frame::Video::new(ffmpeg::format::Pixel::NV12, 2, 2);
+        let mut dst = ffmpeg::frame::Video::new(ffmpeg::format::Pixel::P010LE, 2, 2);
+        src.data_mut(0).fill(y);
+        for pair in src.data_mut(1).chunks_exact_mut(2) {
+            pair.copy_from_slice(&[cb, cr]);
+        }
+
+        SdrToHdrConverter::new(transfer).convert(&src, &mut dst);
+
+        let y = plane_mut(&mut dst, 0)[0];
+        let uv = plane_mut(&mut dst, 1);
+        (
+            u16::from_le(y) >> 6,
+            u16::from_le(uv[0]) >> 6,
+            u16::from_le(uv[1]) >> 6,
+        )
+    }
+
+    #[test]
+    fn black_stays_at_limited_range_black() {
+        assert_eq!(convert_flat(HdrTransfer::Pq, 16, 128, 128), (64, 512, 512));
+        assert_eq!(convert_flat(HdrTransfer::Hlg, 16, 128, 128), (64, 512, 512));
+    }
+
+    #[test]
+    fn white_maps_to_reference_white() {
+        // 203 nits is 58% PQ and reference white is 75% HLG.
+        let (y, cb, cr) = convert_flat(HdrTransfer::Pq, 235, 128, 128);
+        assert!((y as i32 - (64.0 + 876.0 * 0.58) as i32).abs() <= 2, "{y}");
+        assert!((cb as i32 - 512).abs() <= 1 && (cr as i32 - 512).abs() <= 1);
+
+        let (y, _, _) = convert_flat(HdrTransfer::Hlg, 235, 128, 128);
+        assert!((y as i32 - (64.0 + 876.0 * 0.75) as i32).abs() <= 2, "{y}");
+    }
+
+    #[test]
+    fn saturated_colour_keeps_its_hue() {
+        // sRGB (0, 1, 0) as the renderer's shader encodes it.
+        let (y, cb, cr) = convert_flat(HdrTransfer::Pq, 144, 53, 34);
+
+        // The same green in linear BT.2020 at reference white, PQ-encoded.
+        let [r, g, b] =
+            BT709_TO_BT2020.map(|row| pq_oetf(row[1] * REFERENCE_WHITE_NITS / 10_000.0));
+        let luma = BT2020_KR * r + (1.0 - BT2020_KR - BT2020_KB) * g + BT2020_KB * b;
+        let expected = (
+            64.0 + 876.0 * luma,
+            512.0 + 896.0 * (b - luma) / (2.0 * (1.0 - BT2020_KB)),
+            512.0 + 896.0 * (r - luma) / (2.0 * (1.0 - BT2020_KR)),
+        );
+
+        for (actual, expected) in [(y, expected.0), (cb, expected.1), (cr, expected.2)] {
+            assert!(
+                (actual as f32 - expected).abs() <= 4.0,
+                "{actual} vs {expected}, got {:?}",
+                (y, cb, cr)
+            );
+        }
+    }
+}
@@ -0,0 +1,386 @@
+use cap_editor::{AudioRenderer, get_audio_segments};
+use cap_enc_ffmpeg::{AudioEncoder, audio_file::AudioFile, pcm::PcmEncoder};
+use cap_project::XY;
+use cap_rendering::{ProjectUniforms, RenderSegment, RenderedFrame};
+use futures::FutureExt;
+use image::ImageFormat;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use std::path::{Path, PathBuf};
+use tracing::{info, trace};
+
+use crate::{ExportError, ExportTimeRange, ExporterBase, mp4::ExportAudioRenderer};
+
+#[derive(Deserialize, Type, Clone, Copy, Debug, Default, PartialEq)]
+pub enum ImageSequenceFormat {
+    #[default]
+    Png,
+    /// 32-bit float OpenEXR in linear light.
+    Exr,
+}
+
+impl ImageSequenceFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Exr => "exr",
+        }
+    }
+
+    fn image_format(&self) -> ImageFormat {
+        match self {
+            Self::Png => ImageFormat::Png,
+            Self::Exr => ImageFormat::OpenExr,
+        }
+    }
+}
+
+#[derive(Deserialize, Type, Clone, Copy, Debug)]
+pub struct ImageSequenceExportSettings {
+    pub fps: u32,
+    pub resolution_base: XY<u32>,
+    #[serde(default)]
+    pub image_format: ImageSequenceFormat,
+    /// Keep the alpha channel, for projects with a transparent background.
+    #[serde(default)]
+    pub alpha: bool,
+    #[serde(default)]
+    pub time_range: Option<ExportTimeRange>,
+}
+
+/// Written next to the frames as `sequence.json` so the sequence can be imported with
+/// the right frame rate and lined up against the audio.
+#[derive(Serialize, Debug)]
+struct SequenceSidecar {
+    fps: u32,
+    width: u32,
+    height: u32,
+    frame_count: u32,
+    frame_pattern: String,
+    /// Timeline time of the first frame, in seconds.
+    start_time: f64,
+    duration: f64,
+    alpha: bool,
+    audio: Option<String>,
+}
+
+impl ImageSequenceExportSettings {
+    const FRAME_DIGITS: usize = 6;
+
+    /// Renders every frame into a directory named after the output path, alongside an
+    /// `audio.wav` and a `sequence.json` sidecar. Returns the directory. Files left there by
+    /// an earlier export are removed first, so a shorter re-export isn't mixed with them.
+    pub async fn export(
+        self,
+        mut base: ExporterBase,
+        mut on_progress: impl FnMut(u32) -> bool + Send + 'static,
+    ) -> Result<PathBuf, String> {
+        info!("Exporting image sequence with settings: {:?}", &self);
+
+        base.set_time_range(self.time_range);
+        let frame_range = base.frame_range(self.fps);
+
+        let output_dir = base.output_path.with_extension("");
+        std::fs::create_dir_all(&output_dir).map_err(|e| e.to_string())?;
+        clear_previous_export(&output_dir).map_err(|e| e.to_string())?;
+
+        let fps = self.fps;
+        let first_frame = frame_range.start;
+        let format = self.image_format;
+        let alpha = self.alpha;
+
+        let output_size = ProjectUniforms::get_output_size(
+            &base.render_constants.options,
+            &base.project_config,
+            self.resolution_base,
+        );
+
+        let audio_segments = get_audio_segments(&base.segments);
+        let has_audio = audio_segments
+            .first()
+            .filter(|_| !base.project_config.audio.mute)
+            .is_some();
+
+        let (tx_image_data, mut video_rx) = tokio::sync::mpsc::channel::<(RenderedFrame, u32)>(4);
+
+        let project_for_audio = base.project_config.clone();
+        let frames_dir = output_dir.clone();
+        let encoder_thread = tokio::task::spawn_blocking(move || {
+            let mut audio = if has_audio {
+                let file = AudioFile::init(frames_dir.join("audio.wav"), |o| {
+                    PcmEncoder::init(AudioRenderer::info(), o)
+                        .map(|v| v.boxed())
+                        .map_err(Into::into)
+                })
+                .map_err(|e| ExportError::Other(e.to_string()))?;
+                let renderer =
+                    ExportAudioRenderer::new(AudioRenderer::new(audio_segments), fps, first_frame);
+
+                Some((file, renderer))
+            } else {
+                None
+            };
+
+            let mut frame_count = 0;
+
+            while let Some((frame, _frame_number)) = video_rx.blocking_recv() {
+                if !(on_progress)(frame_count) {
+                    return Err(ExportError::Other("Export cancelled".to_string()));
+                }
+
+                let path = frames_dir.join(frame_file_name(frame_count, format));
+                trace!("Writing frame {}", path.display());
+                write_frame(&frame, &path, format, alpha)?;
+
+                if let Some((file, renderer)) = &mut audio
+                    && let Some(audio_frame) =
+                        renderer.render_for_frame(frame_count, &project_for_audio)
+                {
+                    file.queue_frame(audio_frame);
+                }
+
+                frame_count += 1;
+            }
+
+            if let Some((mut file, _)) = audio.take() {
+                file.finish()
+                    .map_err(|e| ExportError::Other(e.to_string()))?
+                    .map_err(|e| ExportError::Other(format!("Audio encoding failed: {e}")))?;
+            }
+
+            let sidecar = SequenceSidecar {
+                fps,
+                width: output_size.0,
+                height: output_size.1,
+                frame_count,
+                frame_pattern: format!("frame_%0{}d.{}", Self::FRAME_DIGITS, format.extension()),
+                start_time: first_frame as f64 / fps as f64,
+                duration: frame_count as f64 / fps as f64,
+                alpha,
+                audio: has_audio.then(|| "audio.wav".to_string()),
+            };
+
+            let sidecar_file = std::fs::File::create(frames_dir.join("sequence.json"))?;
+            serde_json::to_writer_pretty(sidecar_file, &sidecar)
+                .map_err(|e| ExportError::Other(e.to_string()))?;
+
+            Ok(frames_dir)
+        })
+        .then(|f| async {
+            f.map_err(|e| e.to_string())
+                .and_then(|v| v.map_err(|v| v.to_string()))
+        });
+
+        let render_video_task = cap_rendering::render_video_to_channel(
+            &base.render_constants,
+            &base.project_config,
+            tx_image_data,
+            &base.recording_meta,
+            &base.studio_meta,
+            base.segments
+                .iter()
+                .map(|s| RenderSegment {
+                    cursor: s.cursor.clone(),
+                    keyboard: s.keyboard.clone(),
+                    decoders: s.decoders.clone(),
+                })
+                .collect(),
+            fps,
+            self.resolution_base,
+            &base.recordings,
+            Some(frame_range),
+        )
+        .then(|f| async { f.map_err(|v| v.to_string()) });
+
+        let (output_dir, _) = tokio::try_join!(encoder_thread, render_video_task)?;
+
+        Ok(output_dir)
+    }
+}
+
+fn frame_file_name(index: u32, format: ImageSequenceFormat) -> String {
+    format!(
+        "frame_{index:0width$}.{}",
+        format.extension(),
+        width = ImageSequenceExportSettings::FRAME_DIGITS
+    )
+}
+
+/// Removes the frames, audio and sidecar an earlier export wrote to `dir`. Anything else
+/// in it is left alone.
+fn clear_previous_export(dir: &Path) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+
+        if is_export_file_name(name) && entry.file_type()?.is_file() {
+            std::fs::remove_file(entry.path())?;
+        }
+    }
+
+    Ok(())
+}
+
+fn is_export_file_name(name: &str) -> bool {
+    if matches!(name, "audio.wav" | "sequence.json") {
+        return true;
+    }
+
+    let Some((index, extension)) = name
+        .strip_prefix("frame_")
+        .and_then(|rest| rest.split_once('.'))
+    else {
+        return false;
+    };
+
+    !index.is_empty()
+        && index.bytes().all(|b| b.is_ascii_digit())
+        && [ImageSequenceFormat::Png, ImageSequenceFormat::Exr]
+            .iter()
+            .any(|format| format.extension() == extension)
+}
+
+fn write_frame(
+    frame: &RenderedFrame,
+    path: &Path,
+    format: ImageSequenceFormat,
+    alpha: bool,
+) -> Result<(), ExportError> {
+    let rgba = unpad_rows(frame);
+    let (width, height) = (frame.width, frame.height);
+
+    let image = match (format, alpha) {
+        (ImageSequenceFormat::Png, true) => {
+            image::RgbaImage::from_raw(width, height, rgba).map(image::DynamicImage::ImageRgba8)
+        }
+        (ImageSequenceFormat::Png, false) => {
+            image::RgbaImage::from_raw(width, height, rgba).map(|img| {
+                image::DynamicImage::ImageRgb8(image::DynamicImage::ImageRgba8(img).into_rgb8())
+            })
+        }
+        (ImageSequenceFormat::Exr, _) => {
+            let channels = if alpha { 4 } else { 3 };
+            let linear = rgba
+                .chunks_exact(4)
+                .flat_map(|px| {
+                    [
+                        srgb_to_linear(px[0]),
+                        srgb_to_linear(px[1]),
+                        srgb_to_linear(px[2]),
+                        px[3] as f32 / 255.0,
+                    ]
+                    .into_iter()
+                    .take(channels)
+                })
+                .collect::<Vec<_>>();
+
+            if alpha {
+                image::Rgba32FImage::from_raw(width, height, linear)
+                    .map(image::DynamicImage::ImageRgba32F)
+            } else {
+                image::Rgb32FImage::from_raw(width, height, linear)
+                    .map(image::DynamicImage::ImageRgb32F)
+            }
+        }
+    }
+    .ok_or_else(|| ExportError::Other("Rendered frame has an unexpected size".to_string()))?;
+
+    image
+        .save_with_format(path, format.image_format())
+        .map_err(|e| ExportError::Other(format!("Failed to write {}: {e}", path.display())))
+}
+
+/// Rendered frames have rows padded to the GPU's copy alignment.
+fn unpad_rows(frame: &RenderedFrame) -> Vec<u8> {
+    let row_bytes = frame.width as usize * 4;
+    let stride = frame.padded_bytes_per_row as usize;
+
+    if stride == row_bytes {
+        return frame.data[..row_bytes * frame.height as usize].to_vec();
+    }
+
+    frame
+        .data
+        .chunks(stride)
+        .take(frame.height as usize)
+        .flat_map(|row| &row[..row_bytes])
+        .copied()
+        .collect()
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let c = value as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_names_are_zero_padded() {
+        assert_eq!(
+            frame_file_name(42, ImageSequenceFormat::Png),
+            "frame_000042.png"
+        );
+        assert_eq!(
+            frame_file_name(1_234_567, ImageSequenceFormat::Exr),
+            "frame_1234567.exr"
+        );
+    }
+
+    #[test]
+    fn padded_rows_are_stripped() {
+        let frame = RenderedFrame {
+            data: std::sync::Arc::new(vec![
+                1, 2, 3, 4, 5, 6, 7, 8, 0, 0, 0, 0, //
+                9, 10, 11, 12, 13, 14, 15, 16, 0, 0, 0, 0,
+            ]),
+            width: 2,
+            height: 2,
+            padded_bytes_per_row: 12,
+            frame_number: 0,
+            target_time_ns: 0,
+        };
+
+        assert_eq!(unpad_rows(&frame), (1..=16).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn srgb_endpoints_map_to_linear_endpoints() {
+        assert_eq!(srgb_to_linear(0), 0.0);
+        assert!((srgb_to_linear(255) - 1.0).abs() < f32::EPSILON);
+        assert!((srgb_to_linear(188) - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn previous_export_files_are_cleared() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in [
+            "frame_000000.png",
+            "frame_000001.exr",
+            "audio.wav",
+            "sequence.json",
+            "notes.txt",
+            "frame_final.png",
+        ] {
+            std::fs::write(dir.path().join(name), b"").unwrap();
+        }
+
+        clear_previous_export(dir.path()).unwrap();
+
+        let mut remaining = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        remaining.sort();
+        assert_eq!(remaining, ["frame_final.png", "notes.txt"]);
+    }
+}
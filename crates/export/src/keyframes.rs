@@ -0,0 +1,86 @@
+use std::ops::Range;
+
+use cap_project::{ProjectConfiguration, TimelineSegment};
+
+use crate::chapters::timeline_chapters;
+
+/// Frames, relative to the start of the exported `frame_range`, that should begin a new GOP:
+/// every timeline segment boundary and chapter start inside the range. Keyframes there let
+/// players seek to cuts and chapters exactly and let stream-copy trims land cleanly.
+pub(crate) fn timeline_keyframes(
+    project: &ProjectConfiguration,
+    frame_range: &Range<u32>,
+    fps: u32,
+) -> Vec<u32> {
+    let range_start = frame_range.start as f64 / fps as f64;
+
+    let segment_boundaries = project
+        .timeline
+        .as_ref()
+        .map(|timeline| segment_boundaries(&timeline.segments))
+        .unwrap_or_default()
+        .into_iter()
+        .map(|time| time - range_start);
+    let chapter_starts = timeline_chapters(project, frame_range, fps)
+        .into_iter()
+        .map(|chapter| chapter.start.as_secs_f64());
+
+    keyframe_frames(
+        segment_boundaries.chain(chapter_starts),
+        fps,
+        frame_range.end - frame_range.start,
+    )
+}
+
+/// Where each segment ends on the output timeline, in seconds.
+fn segment_boundaries(segments: &[TimelineSegment]) -> Vec<f64> {
+    segments
+        .iter()
+        .scan(0.0, |end, segment| {
+            *end += segment.duration();
+            Some(*end)
+        })
+        .collect()
+}
+
+fn keyframe_frames(times: impl Iterator<Item = f64>, fps: u32, frame_count: u32) -> Vec<u32> {
+    let mut frames = times
+        .filter(|time| time.is_finite() && *time > 0.0)
+        .map(|time| (time * fps as f64).round() as u32)
+        .filter(|&frame| frame > 0 && frame < frame_count)
+        .collect::<Vec<_>>();
+    frames.sort_unstable();
+    frames.dedup();
+    frames
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(start: f64, end: f64, timescale: f64) -> TimelineSegment {
+        TimelineSegment {
+            recording_clip: 0,
+            timescale,
+            start,
+            end,
+        }
+    }
+
+    #[test]
+    fn boundaries_account_for_timescale() {
+        let segments = vec![segment(0.0, 10.0, 1.0), segment(20.0, 24.0, 2.0)];
+
+        assert_eq!(segment_boundaries(&segments), vec![10.0, 12.0]);
+    }
+
+    #[test]
+    fn keyframes_inside_the_range_are_sorted_and_unique() {
+        let times = [10.0, 12.0, 5.0, 10.0, 0.0, -3.0, 40.0];
+
+        assert_eq!(
+            keyframe_frames(times.into_iter(), 30, 600),
+            vec![150, 300, 360]
+        );
+    }
+}
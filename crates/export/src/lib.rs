@@ -1,10 +1,33 @@
+pub mod alpha;
+pub mod animated;
+pub mod audio;
+pub mod camera;
+mod chapters;
+mod checkpoint;
 pub mod gif;
+pub mod hdr;
+pub mod image_sequence;
+mod keyframes;
+pub mod loudness;
+pub mod metadata;
 pub mod mp4;
+pub mod overrides;
+pub mod renditions;
+pub mod storyboard;
+pub mod streaming;
+mod subtitles;
+pub mod webm;
 
-use cap_editor::SegmentMedia;
+use cap_editor::{AudioSegment, SegmentMedia};
 use cap_project::{ProjectConfiguration, RecordingMeta, StudioRecordingMeta};
 use cap_rendering::{ProjectRecordingsMeta, RenderVideoConstants};
-use std::{path::PathBuf, sync::Arc};
+use serde::Deserialize;
+use specta::Type;
+use std::{
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 #[derive(thiserror::Error, Debug)]
 pub enum ExportError {
@@ -34,6 +57,10 @@ pub enum ExportError {
 pub enum ExporterBuildError {
     #[error("Failed to load config: {0}")]
     ConfigLoad(#[source] Box<dyn std::error::Error>),
+    #[error("Invalid project config: {0}")]
+    ConfigInvalid(#[source] Box<dyn std::error::Error>),
+    #[error("Failed to apply config override: {0}")]
+    ConfigOverride(#[source] Box<dyn std::error::Error>),
     #[error("Failed to load meta: {0}")]
     MetaLoad(#[source] Box<dyn std::error::Error>),
     #[error("Recording is not a studio recording")]
@@ -48,19 +75,45 @@ pub enum ExporterBuildError {
     IO(PathBuf, std::io::Error),
 }
 
+/// A window of the timeline to export, in seconds of timeline time.
+#[derive(Deserialize, Type, Clone, Copy, Debug, PartialEq)]
+pub struct ExportTimeRange {
+    pub start: f64,
+    pub end: f64,
+}
+
+impl ExportTimeRange {
+    /// Timeline frames covered by the range, clamped to `total_frames`.
+    pub fn frame_range(&self, fps: u32, total_frames: u32) -> Range<u32> {
+        let start = ((self.start.max(0.0) * fps as f64).round() as u32).min(total_frames);
+        let end = ((self.end.max(0.0) * fps as f64).ceil() as u32).clamp(start, total_frames);
+
+        start..end
+    }
+}
+
 pub struct ExporterBuilder {
     project_path: PathBuf,
     config: Option<ProjectConfiguration>,
+    config_overrides: Vec<serde_json::Value>,
     output_path: Option<PathBuf>,
     force_ffmpeg_decoder: bool,
 }
 
 impl ExporterBuilder {
+    /// Exports `config` instead of the project's saved `project-config.json`.
     pub fn with_config(mut self, config: ProjectConfiguration) -> Self {
         self.config = Some(config);
         self
     }
 
+    /// Layers a JSON merge patch over the project configuration for this export only.
+    /// Overrides are applied in the order they're added. See [`overrides`] for presets.
+    pub fn with_config_override(mut self, patch: serde_json::Value) -> Self {
+        self.config_overrides.push(patch);
+        self
+    }
+
     pub fn with_output_path(mut self, output_path: PathBuf) -> Self {
         self.output_path = Some(output_path);
         self
@@ -74,22 +127,13 @@ impl ExporterBuilder {
     pub async fn build(self) -> Result<ExporterBase, ExporterBuildError> {
         type Error = ExporterBuildError;
 
-        let project_config = serde_json::from_reader(
-            std::fs::File::open(self.project_path.join("project-config.json"))
-                .map_err(|v| Error::ConfigLoad(v.into()))?,
-        )
-        .map_err(|v| Error::ConfigLoad(v.into()))?;
-
-        let recording_meta =
-            RecordingMeta::load_for_project(&self.project_path).map_err(Error::MetaLoad)?;
-        let studio_meta = recording_meta
-            .studio_meta()
-            .ok_or(Error::NotStudioRecording)?;
-
-        let recordings = Arc::new(
-            ProjectRecordingsMeta::new(&recording_meta.project_path, studio_meta)
-                .map_err(Error::RecordingsMeta)?,
-        );
+        let LoadedProject {
+            project_config,
+            recording_meta,
+            studio_meta,
+            recordings,
+            output_path,
+        } = self.load()?;
 
         let render_constants = Arc::new(
             RenderVideoConstants::new(
@@ -102,12 +146,87 @@ impl ExporterBuilder {
         );
 
         let segments =
-            cap_editor::create_segments(&recording_meta, studio_meta, self.force_ffmpeg_decoder)
+            cap_editor::create_segments(&recording_meta, &studio_meta, self.force_ffmpeg_decoder)
                 .await
                 .map_err(Error::MediaLoad)?;
 
+        Ok(ExporterBase {
+            output_path,
+            studio_meta,
+            recordings,
+            render_constants,
+            segments,
+            recording_meta,
+            project_config,
+            project_path: self.project_path,
+            time_range: None,
+        })
+    }
+
+    /// Loads the project for an audio-only export. Only the audio tracks are opened; no
+    /// renderer or video decoders are created.
+    pub fn build_audio(self) -> Result<AudioExporterBase, ExporterBuildError> {
+        let LoadedProject {
+            project_config,
+            recording_meta,
+            studio_meta,
+            recordings,
+            output_path,
+        } = self.load()?;
+
+        let audio_segments = cap_editor::load_audio_segments(&recording_meta, &studio_meta)
+            .map_err(ExporterBuildError::MediaLoad)?;
+
+        let duration = cap_rendering::get_duration(
+            &recordings,
+            &recording_meta,
+            &studio_meta,
+            &project_config,
+        );
+
+        Ok(AudioExporterBase {
+            project_config,
+            audio_segments,
+            duration,
+            output_path,
+            time_range: None,
+        })
+    }
+
+    fn load(&self) -> Result<LoadedProject, ExporterBuildError> {
+        type Error = ExporterBuildError;
+
+        let project_config = match &self.config {
+            Some(config) => config.clone(),
+            None => ProjectConfiguration::load(&self.project_path)
+                .map_err(|v| Error::ConfigLoad(v.into()))?,
+        };
+
+        let project_config = overrides::apply_overrides(&project_config, &self.config_overrides)
+            .map_err(|v| Error::ConfigOverride(v.into()))?;
+        project_config.validate().map_err(|v| {
+            if self.config_overrides.is_empty() {
+                Error::ConfigInvalid(v.into())
+            } else {
+                Error::ConfigOverride(v.into())
+            }
+        })?;
+
+        let recording_meta =
+            RecordingMeta::load_for_project(&self.project_path).map_err(Error::MetaLoad)?;
+        let studio_meta = recording_meta
+            .studio_meta()
+            .ok_or(Error::NotStudioRecording)?
+            .clone();
+
+        let recordings = Arc::new(
+            ProjectRecordingsMeta::new(&recording_meta.project_path, &studio_meta)
+                .map_err(Error::RecordingsMeta)?,
+        );
+
         let output_path = self
             .output_path
+            .clone()
             .unwrap_or_else(|| recording_meta.output_path());
 
         if let Some(parent) = output_path.parent() {
@@ -115,19 +234,24 @@ impl ExporterBuilder {
                 .map_err(|e| ExporterBuildError::IO(parent.to_path_buf(), e))?;
         }
 
-        Ok(ExporterBase {
-            output_path,
-            studio_meta: studio_meta.clone(),
-            recordings,
-            render_constants,
-            segments,
-            recording_meta,
+        Ok(LoadedProject {
             project_config,
-            project_path: self.project_path,
+            recording_meta,
+            studio_meta,
+            recordings,
+            output_path,
         })
     }
 }
 
+struct LoadedProject {
+    project_config: ProjectConfiguration,
+    recording_meta: RecordingMeta,
+    studio_meta: StudioRecordingMeta,
+    recordings: Arc<ProjectRecordingsMeta>,
+    output_path: PathBuf,
+}
+
 pub struct ExporterBase {
     project_path: PathBuf,
     recording_meta: RecordingMeta,
@@ -137,10 +261,21 @@ pub struct ExporterBase {
     render_constants: Arc<RenderVideoConstants>,
     segments: Vec<SegmentMedia>,
     output_path: PathBuf,
+    time_range: Option<ExportTimeRange>,
 }
 
 impl ExporterBase {
+    /// Restricts the export to part of the timeline. `None` exports everything.
+    pub fn set_time_range(&mut self, time_range: Option<ExportTimeRange>) {
+        self.time_range = time_range;
+    }
+
     pub fn total_frames(&self, fps: u32) -> u32 {
+        let range = self.frame_range(fps);
+        range.end - range.start
+    }
+
+    pub(crate) fn frame_range(&self, fps: u32) -> Range<u32> {
         let duration = cap_rendering::get_duration(
             &self.recordings,
             &self.recording_meta,
@@ -148,15 +283,119 @@ impl ExporterBase {
             &self.project_config,
         );
 
-        (fps as f64 * duration).ceil() as u32
+        timeline_frame_range(duration, fps, self.time_range)
     }
 
     pub fn builder(project_path: PathBuf) -> ExporterBuilder {
         ExporterBuilder {
             project_path,
             config: None,
+            config_overrides: Vec::new(),
             output_path: None,
             force_ffmpeg_decoder: false,
         }
     }
 }
+
+/// The parts of a project an audio-only export needs.
+pub struct AudioExporterBase {
+    project_config: ProjectConfiguration,
+    audio_segments: Vec<AudioSegment>,
+    duration: f64,
+    output_path: PathBuf,
+    time_range: Option<ExportTimeRange>,
+}
+
+impl AudioExporterBase {
+    /// Restricts the export to part of the timeline. `None` exports everything.
+    pub fn set_time_range(&mut self, time_range: Option<ExportTimeRange>) {
+        self.time_range = time_range;
+    }
+
+    pub fn total_frames(&self, fps: u32) -> u32 {
+        let range = self.frame_range(fps);
+        range.end - range.start
+    }
+
+    pub(crate) fn frame_range(&self, fps: u32) -> Range<u32> {
+        timeline_frame_range(self.duration, fps, self.time_range)
+    }
+}
+
+/// `video.mp4` becomes `video<suffix>.<extension>`, for exports that sit next to the main
+/// one instead of replacing it. A path that already carries the suffix is only given the
+/// extension.
+pub(crate) fn suffixed_output_path(output_path: &Path, suffix: &str, extension: &str) -> PathBuf {
+    let stem = output_path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "output".to_string());
+    let stem = stem.strip_suffix(suffix).unwrap_or(&stem);
+
+    output_path.with_file_name(format!("{stem}{suffix}.{extension}"))
+}
+
+fn timeline_frame_range(
+    duration: f64,
+    fps: u32,
+    time_range: Option<ExportTimeRange>,
+) -> Range<u32> {
+    let total_frames = (fps as f64 * duration).ceil() as u32;
+
+    match time_range {
+        Some(time_range) => time_range.frame_range(fps, total_frames),
+        None => 0..total_frames,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_range_maps_to_frames() {
+        let range = ExportTimeRange {
+            start: 2.0,
+            end: 4.5,
+        };
+
+        assert_eq!(range.frame_range(30, 300), 60..135);
+    }
+
+    #[test]
+    fn time_range_is_clamped_to_timeline() {
+        let past_end = ExportTimeRange {
+            start: 8.0,
+            end: 20.0,
+        };
+        assert_eq!(past_end.frame_range(30, 300), 240..300);
+
+        let inverted = ExportTimeRange {
+            start: 5.0,
+            end: 1.0,
+        };
+        assert_eq!(inverted.frame_range(30, 300), 150..150);
+
+        let negative = ExportTimeRange {
+            start: -1.0,
+            end: 1.0,
+        };
+        assert_eq!(negative.frame_range(60, 300), 0..60);
+    }
+
+    #[test]
+    fn suffixed_output_path_is_applied_once() {
+        assert_eq!(
+            suffixed_output_path(Path::new("out/My Recording.mp4"), "-camera", "mp4"),
+            PathBuf::from("out/My Recording-camera.mp4")
+        );
+        assert_eq!(
+            suffixed_output_path(Path::new("out/My Recording-camera.mp4"), "-camera", "mp4"),
+            PathBuf::from("out/My Recording-camera.mp4")
+        );
+        assert_eq!(
+            suffixed_output_path(Path::new("out/v1.2 demo.cap"), "-camera", "mp4"),
+            PathBuf::from("out/v1.2 demo-camera.mp4")
+        );
+    }
+}
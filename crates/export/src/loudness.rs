@@ -0,0 +1,339 @@
+use cap_editor::AudioRenderer;
+use cap_project::ProjectConfiguration;
+use serde::Deserialize;
+use specta::Type;
+use std::f64::consts::PI;
+
+use crate::mp4::ExportAudioRenderer;
+
+/// Two-pass EBU R128 normalization: the timeline's audio is measured first, then a single
+/// gain is applied to the whole export so it lands on `target_lufs`.
+///
+/// The gain is held back if reaching the target would push the true peak above
+/// `max_true_peak_db`, so quiet recordings with loud transients can end up under the
+/// target rather than clipped.
+#[derive(Deserialize, Type, Clone, Copy, Debug)]
+pub struct LoudnessNormalization {
+    /// Integrated loudness to aim for, in LUFS.
+    #[serde(default = "LoudnessNormalization::default_target_lufs")]
+    pub target_lufs: f32,
+    /// Highest true peak allowed after normalization, in dBTP.
+    #[serde(default = "LoudnessNormalization::default_max_true_peak_db")]
+    pub max_true_peak_db: f32,
+}
+
+impl Default for LoudnessNormalization {
+    fn default() -> Self {
+        Self {
+            target_lufs: Self::default_target_lufs(),
+            max_true_peak_db: Self::default_max_true_peak_db(),
+        }
+    }
+}
+
+/// What the first pass measured and the gain the second pass applied.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoudnessMeasurement {
+    /// `None` when everything was below the absolute gate, i.e. the audio is silent.
+    pub integrated_lufs: Option<f64>,
+    pub true_peak_db: f64,
+    pub gain_db: f64,
+}
+
+impl LoudnessNormalization {
+    fn default_target_lufs() -> f32 {
+        -23.0
+    }
+
+    fn default_max_true_peak_db() -> f32 {
+        -1.0
+    }
+
+    /// Renders `frame_count` frames of audio the same way the export will and measures
+    /// them.
+    pub(crate) fn measure(
+        &self,
+        mut renderer: ExportAudioRenderer,
+        frame_count: u32,
+        project: &ProjectConfiguration,
+    ) -> LoudnessMeasurement {
+        let mut meter =
+            LoudnessMeter::new(AudioRenderer::SAMPLE_RATE, AudioRenderer::CHANNELS as usize);
+
+        for frame_number in 0..frame_count {
+            if let Some(frame) = renderer.render_for_frame(frame_number, project) {
+                meter.push_frame(&frame);
+            }
+        }
+
+        let integrated_lufs = meter.integrated_lufs();
+        let true_peak_db = meter.true_peak_db();
+
+        LoudnessMeasurement {
+            integrated_lufs,
+            true_peak_db,
+            gain_db: self.gain_db(integrated_lufs, true_peak_db),
+        }
+    }
+
+    fn gain_db(&self, integrated_lufs: Option<f64>, true_peak_db: f64) -> f64 {
+        let Some(integrated_lufs) = integrated_lufs else {
+            return 0.0;
+        };
+
+        let gain_db = f64::from(self.target_lufs) - integrated_lufs;
+        let headroom_db = f64::from(self.max_true_peak_db) - true_peak_db;
+
+        gain_db.min(headroom_db)
+    }
+}
+
+/// ITU-R BS.1770-4 integrated loudness and true peak meter for interleaved f32 audio.
+pub(crate) struct LoudnessMeter {
+    channels: usize,
+    filters: Vec<[Biquad; 2]>,
+    peaks: Vec<TruePeak>,
+    /// Samples per channel in one 100ms step of the 400ms gating block.
+    step_len: usize,
+    step_pos: usize,
+    step_energy: f64,
+    /// Mean square of each completed 100ms step, summed over channels.
+    steps: Vec<f64>,
+}
+
+impl LoudnessMeter {
+    const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+    const RELATIVE_GATE_LU: f64 = -10.0;
+    const STEPS_PER_BLOCK: usize = 4;
+
+    pub(crate) fn new(sample_rate: u32, channels: usize) -> Self {
+        let sample_rate = f64::from(sample_rate);
+
+        Self {
+            channels,
+            filters: (0..channels)
+                .map(|_| {
+                    [
+                        Biquad::high_shelf(sample_rate),
+                        Biquad::high_pass(sample_rate),
+                    ]
+                })
+                .collect(),
+            peaks: (0..channels).map(|_| TruePeak::default()).collect(),
+            step_len: (sample_rate / 10.0).round() as usize,
+            step_pos: 0,
+            step_energy: 0.0,
+            steps: Vec::new(),
+        }
+    }
+
+    pub(crate) fn push_frame(&mut self, frame: &ffmpeg::frame::Audio) {
+        let len = frame.samples() * self.channels * size_of::<f32>();
+        let samples = frame.data(0)[..len]
+            .chunks_exact(size_of::<f32>())
+            .map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+            .collect::<Vec<_>>();
+
+        self.push_interleaved(&samples);
+    }
+
+    pub(crate) fn push_interleaved(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for (channel, &sample) in frame.iter().enumerate() {
+                let [shelf, pass] = &mut self.filters[channel];
+                let weighted = pass.process(shelf.process(f64::from(sample)));
+
+                self.step_energy += weighted * weighted;
+                self.peaks[channel].push(sample);
+            }
+
+            self.step_pos += 1;
+            if self.step_pos == self.step_len {
+                self.steps.push(self.step_energy / self.step_len as f64);
+                self.step_pos = 0;
+                self.step_energy = 0.0;
+            }
+        }
+    }
+
+    /// Gated integrated loudness, or `None` if no block made it through the absolute gate.
+    pub(crate) fn integrated_lufs(&self) -> Option<f64> {
+        let blocks = self
+            .steps
+            .windows(Self::STEPS_PER_BLOCK)
+            .map(|steps| steps.iter().sum::<f64>() / Self::STEPS_PER_BLOCK as f64)
+            .filter(|&energy| loudness(energy) > Self::ABSOLUTE_GATE_LUFS)
+            .collect::<Vec<_>>();
+
+        if blocks.is_empty() {
+            return None;
+        }
+
+        let relative_gate = loudness(mean(&blocks)) + Self::RELATIVE_GATE_LU;
+        let gated = blocks
+            .into_iter()
+            .filter(|&energy| loudness(energy) > relative_gate)
+            .collect::<Vec<_>>();
+
+        Some(loudness(mean(&gated)))
+    }
+
+    pub(crate) fn true_peak_db(&self) -> f64 {
+        let peak = self.peaks.iter().map(|p| p.peak).fold(0.0, f64::max);
+
+        20.0 * peak.log10()
+    }
+}
+
+fn loudness(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// One stage of the K-weighting filter, with coefficients derived for the actual sample
+/// rate rather than the 48kHz table in the spec.
+#[derive(Clone, Copy)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    fn high_shelf(sample_rate: f64) -> Self {
+        const F0: f64 = 1681.974450955533;
+        const GAIN_DB: f64 = 3.999843853973347;
+        const Q: f64 = 0.7071752369554196;
+
+        let k = (PI * F0 / sample_rate).tan();
+        let vh = 10f64.powf(GAIN_DB / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / Q + k * k;
+
+        Self {
+            b: [
+                (vh + vb * k / Q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / Q + k * k) / a0,
+            ],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / Q + k * k) / a0],
+            z: [0.0; 2],
+        }
+    }
+
+    fn high_pass(sample_rate: f64) -> Self {
+        const F0: f64 = 38.13547087602444;
+        const Q: f64 = 0.5003270373238773;
+
+        let k = (PI * F0 / sample_rate).tan();
+        let a0 = 1.0 + k / Q + k * k;
+
+        Self {
+            b: [1.0, -2.0, 1.0],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / Q + k * k) / a0],
+            z: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+/// Sample peak of the signal upsampled 4x with a windowed-sinc interpolator, which catches
+/// the inter-sample peaks a DAC would reconstruct.
+#[derive(Default)]
+struct TruePeak {
+    history: [f32; TruePeak::TAPS],
+    peak: f64,
+}
+
+impl TruePeak {
+    const TAPS: usize = 12;
+    const OVERSAMPLING: usize = 4;
+
+    fn push(&mut self, sample: f32) {
+        self.history.copy_within(1.., 0);
+        self.history[Self::TAPS - 1] = sample;
+        self.peak = self.peak.max(f64::from(sample.abs()));
+
+        // Interpolate between the two samples in the middle of the window.
+        let center = (Self::TAPS / 2 - 1) as f64;
+        for phase in 1..Self::OVERSAMPLING {
+            let t = center + phase as f64 / Self::OVERSAMPLING as f64;
+            let value = self
+                .history
+                .iter()
+                .enumerate()
+                .map(|(i, &s)| f64::from(s) * windowed_sinc(i as f64 - t))
+                .sum::<f64>();
+
+            self.peak = self.peak.max(value.abs());
+        }
+    }
+}
+
+fn windowed_sinc(x: f64) -> f64 {
+    let half_width = (TruePeak::TAPS / 2) as f64;
+    if x == 0.0 {
+        return 1.0;
+    }
+    if x.abs() >= half_width {
+        return 0.0;
+    }
+
+    let sinc = (PI * x).sin() / (PI * x);
+    let window = 0.5 * (1.0 + (PI * x / half_width).cos());
+
+    sinc * window
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stereo_sine(amplitude: f32, freq: f64, secs: f64) -> Vec<f32> {
+        let rate = f64::from(AudioRenderer::SAMPLE_RATE);
+        (0..(secs * rate) as usize)
+            .flat_map(|i| {
+                let s = amplitude * (2.0 * PI * freq * i as f64 / rate).sin() as f32;
+                [s, s]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn measures_reference_sine() {
+        let mut meter = LoudnessMeter::new(AudioRenderer::SAMPLE_RATE, 2);
+        meter.push_interleaved(&stereo_sine(0.1, 997.0, 5.0));
+
+        let integrated = meter.integrated_lufs().unwrap();
+        assert!((integrated + 20.0).abs() < 0.1, "{integrated}");
+
+        let true_peak = meter.true_peak_db();
+        assert!((true_peak + 20.0).abs() < 0.1, "{true_peak}");
+    }
+
+    #[test]
+    fn silence_is_gated_out() {
+        let mut meter = LoudnessMeter::new(AudioRenderer::SAMPLE_RATE, 2);
+        meter.push_interleaved(&vec![0.0; 48_000 * 2]);
+
+        assert_eq!(meter.integrated_lufs(), None);
+    }
+
+    #[test]
+    fn gain_is_limited_by_true_peak() {
+        let settings = LoudnessNormalization::default();
+
+        assert_eq!(settings.gain_db(Some(-30.0), -20.0), 7.0);
+        assert_eq!(settings.gain_db(Some(-30.0), -5.0), 4.0);
+        assert_eq!(settings.gain_db(None, -5.0), 0.0);
+    }
+}
@@ -0,0 +1,121 @@
+use cap_enc_ffmpeg::mp4::ContainerMetadata;
+use cap_project::StudioRecordingMeta;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Deserialize;
+use specta::Type;
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use crate::ExporterBase;
+
+/// Tags embedded in the exported file. Fields left empty fall back to what's known
+/// about the recording.
+#[derive(Deserialize, Type, Clone, Debug, Default)]
+pub struct ExportMetadata {
+    /// Defaults to the recording's name.
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub author: Option<String>,
+    #[serde(default)]
+    pub comment: Option<String>,
+    /// Defaults to `Cap`.
+    #[serde(default)]
+    pub encoder: Option<String>,
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+}
+
+impl ExportMetadata {
+    const DEFAULT_ENCODER: &str = "Cap";
+
+    pub(crate) fn container_metadata(&self, base: &ExporterBase) -> ContainerMetadata {
+        ContainerMetadata {
+            title: Some(
+                self.title
+                    .clone()
+                    .unwrap_or_else(|| base.recording_meta.pretty_name.clone()),
+            ),
+            artist: self.author.clone(),
+            comment: self.comment.clone(),
+            creation_time: recording_started_at(&base.studio_meta, &base.project_path)
+                .map(format_creation_time),
+            encoder: Some(
+                self.encoder
+                    .clone()
+                    .unwrap_or_else(|| Self::DEFAULT_ENCODER.to_string()),
+            ),
+            tags: self
+                .tags
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+        }
+    }
+}
+
+/// When the recording started. Segment start times are only offsets from when the capture
+/// pipeline started, so they're added to when the project directory was created, which
+/// happens as the recording begins.
+fn recording_started_at(meta: &StudioRecordingMeta, project_path: &Path) -> Option<DateTime<Utc>> {
+    let created = std::fs::metadata(project_path)
+        .and_then(|m| m.created())
+        .ok()?;
+
+    Some(started_at(meta, created))
+}
+
+fn started_at(meta: &StudioRecordingMeta, project_created: SystemTime) -> DateTime<Utc> {
+    let start_offset = match meta {
+        StudioRecordingMeta::SingleSegment { segment } => segment.display.start_time,
+        StudioRecordingMeta::MultipleSegments { inner } => inner
+            .segments
+            .first()
+            .and_then(|segment| segment.display.start_time),
+    }
+    .filter(|secs| secs.is_finite() && *secs >= 0.0)
+    .map(Duration::from_secs_f64)
+    .unwrap_or_default();
+
+    DateTime::from(project_created + start_offset)
+}
+
+fn format_creation_time(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn creation_time_is_utc_iso_8601() {
+        let time = DateTime::from_timestamp_millis(1_731_688_536_250).unwrap();
+
+        assert_eq!(format_creation_time(time), "2024-11-15T16:35:36.250000Z");
+    }
+
+    #[test]
+    fn segment_start_time_offsets_the_project_creation_time() {
+        // As written by studio recordings: seconds since the capture pipeline started.
+        let meta: StudioRecordingMeta = serde_json::from_value(serde_json::json!({
+            "segments": [{
+                "display": {
+                    "path": "content/segments/segment-0/display.mp4",
+                    "fps": 60,
+                    "start_time": 1.5,
+                },
+            }],
+        }))
+        .unwrap();
+        let created = SystemTime::UNIX_EPOCH + Duration::from_secs(1_731_688_536);
+
+        assert_eq!(
+            format_creation_time(started_at(&meta, created)),
+            "2024-11-15T16:35:37.500000Z"
+        );
+    }
+}
@@ -1,6 +1,21 @@
-use crate::ExporterBase;
-use cap_editor::{AudioRenderer, get_audio_segments};
-use cap_enc_ffmpeg::{AudioEncoder, aac::AACEncoder, h264::H264Encoder, hevc::HevcEncoder, mp4::*};
+use crate::{
+    ExportTimeRange, ExporterBase,
+    chapters::timeline_chapters,
+    checkpoint::{ExportCheckpoint, checkpoint_key},
+    hdr::{HdrFormat, SdrToHdrConverter},
+    keyframes::timeline_keyframes,
+    loudness::{LoudnessMeasurement, LoudnessNormalization},
+    metadata::ExportMetadata,
+    storyboard::{StoryboardCapture, StoryboardFiles, StoryboardSettings},
+    subtitles::caption_subtitle_track,
+};
+use cap_editor::{
+    AudioRenderer, AudioSegment, AudioStem, SegmentMedia, get_audio_segments,
+    get_audio_stem_segments,
+};
+use cap_enc_ffmpeg::{
+    AudioEncoder, aac::AACEncoder, av1::Av1Encoder, h264::H264Encoder, hevc::HevcEncoder, mp4::*,
+};
 use cap_media_info::{RawVideoFormat, VideoInfo};
 use cap_project::XY;
 use cap_rendering::{
@@ -11,6 +26,7 @@ use image::ImageBuffer;
 use serde::Deserialize;
 use specta::Type;
 use std::{
+    ops::Range,
     path::PathBuf,
     sync::{
         Arc, Mutex,
@@ -39,8 +55,20 @@ impl ExportCompression {
     }
 }
 
+#[derive(Deserialize, Type, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Mp4VideoCodec {
+    /// H.264, or HEVC when `crf` is set.
+    #[default]
+    Auto,
+    /// AV1 through SVT-AV1, libaom or a hardware encoder, whichever is available. `crf`
+    /// is on AV1's 0-63 scale.
+    Av1,
+}
+
 #[derive(Clone, Default)]
 struct ExportNv12Mode {
+    /// Write fragments with a checkpoint manifest so a re-run can resume.
+    checkpoint: bool,
     stop_after_frames_sent: Option<u32>,
     record_first_queued_ms_since_pipeline: Option<Arc<AtomicU64>>,
     nv12_render_startup_breakdown_ms:
@@ -53,7 +81,7 @@ pub struct FirstFrameQueuedBenchmark {
     pub nv12_render_startup_breakdown_ms: Option<cap_rendering::Nv12RenderStartupBreakdownMs>,
 }
 
-#[derive(Deserialize, Type, Clone, Copy, Debug)]
+#[derive(Deserialize, Type, Clone, Debug)]
 pub struct Mp4ExportSettings {
     pub fps: u32,
     pub resolution_base: XY<u32>,
@@ -63,6 +91,47 @@ pub struct Mp4ExportSettings {
     pub force_ffmpeg_decoder: bool,
     #[serde(default)]
     pub crf: Option<u8>,
+    /// Fit the export under this many megabytes (10^6 bytes) by encoding at the bitrate the
+    /// budget allows instead of using `compression`. Takes precedence over `crf`.
+    #[serde(default)]
+    pub target_size_mb: Option<f32>,
+    /// Title, creation time and other tags to write into the file. `None` writes none.
+    #[serde(default)]
+    pub metadata: Option<ExportMetadata>,
+    /// Poster frame, thumbnail sprite sheet and WebVTT storyboard to write next to the file.
+    #[serde(default)]
+    pub storyboard: Option<StoryboardSettings>,
+    /// Measure the audio first and normalize it to a loudness target. `None` keeps the
+    /// project's volumes as they are.
+    #[serde(default)]
+    pub loudness: Option<LoudnessNormalization>,
+    /// Also write the microphone and system audio as their own labelled tracks after the
+    /// mix, for editing them separately.
+    #[serde(default)]
+    pub audio_stems: bool,
+    #[serde(default)]
+    pub video_codec: Mp4VideoCodec,
+    /// Encode 10-bit HEVC signalled as HDR10 or HLG, with the SDR render placed at HDR
+    /// reference white. Ignored for AV1.
+    #[serde(default)]
+    pub hdr: Option<HdrFormat>,
+    /// Write the export in fragments next to a checkpoint, so an interrupted export picks
+    /// up where it stopped. Costs a final pass to join the fragments, and each fragment's
+    /// audio starts with its own encoder delay.
+    #[serde(default)]
+    pub resumable: bool,
+    #[serde(default)]
+    pub time_range: Option<ExportTimeRange>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Mp4ExportResult {
+    pub path: PathBuf,
+    pub size_bytes: u64,
+    pub target_size_bytes: Option<u64>,
+    pub storyboard: Option<StoryboardFiles>,
+    /// Set when loudness normalization was requested and there was audio to measure.
+    pub loudness: Option<LoudnessMeasurement>,
 }
 
 impl Mp4ExportSettings {
@@ -70,6 +139,11 @@ impl Mp4ExportSettings {
         self.custom_bpp
             .unwrap_or_else(|| self.compression.bits_per_pixel())
     }
+
+    pub fn target_size_bytes(&self) -> Option<u64> {
+        self.target_size_mb
+            .map(|mb| (f64::from(mb.max(0.0)) * 1_000_000.0) as u64)
+    }
 }
 
 impl Mp4ExportSettings {
@@ -78,8 +152,20 @@ impl Mp4ExportSettings {
         base: ExporterBase,
         on_progress: impl FnMut(u32) -> bool + Send + 'static,
     ) -> Result<PathBuf, String> {
+        self.export_with_result(base, on_progress)
+            .await
+            .map(|result| result.path)
+    }
+
+    /// Like [`Self::export`], also reporting the size of the file that was written.
+    pub async fn export_with_result(
+        self,
+        base: ExporterBase,
+        on_progress: impl FnMut(u32) -> bool + Send + 'static,
+    ) -> Result<Mp4ExportResult, String> {
         info!("Exporting mp4 with settings: {:?}", &self);
-        info!("Expected to render {} frames", base.total_frames(self.fps));
+
+        let target_size_bytes = self.target_size_bytes();
 
         let fps = self.fps;
 
@@ -94,14 +180,43 @@ impl Mp4ExportSettings {
             height = output_size.1,
             "Exporting with NV12 pipeline (GPU when possible, CPU fallback otherwise)"
         );
-        self.export_nv12(
-            base,
-            output_size,
-            fps,
-            on_progress,
-            ExportNv12Mode::default(),
-        )
-        .await
+        let Nv12ExportOutput {
+            path,
+            storyboard,
+            loudness,
+        } = self
+            .export_nv12(
+                base,
+                output_size,
+                fps,
+                on_progress,
+                ExportNv12Mode {
+                    checkpoint: self.resumable,
+                    ..Default::default()
+                },
+            )
+            .await?;
+
+        let size_bytes = std::fs::metadata(&path)
+            .map(|m| m.len())
+            .map_err(|e| format!("Failed to read exported file: {e}"))?;
+
+        match target_size_bytes {
+            Some(target) if size_bytes > target => warn!(
+                size_bytes,
+                target_size_bytes = target,
+                "Export is larger than its target size"
+            ),
+            _ => info!(size_bytes, ?target_size_bytes, "Exported mp4"),
+        }
+
+        Ok(Mp4ExportResult {
+            path,
+            size_bytes,
+            target_size_bytes,
+            storyboard,
+            loudness,
+        })
     }
 
     pub async fn benchmark_first_frame_with_breakdown(
@@ -124,6 +239,7 @@ impl Mp4ExportSettings {
             fps,
             |_| true,
             ExportNv12Mode {
+                checkpoint: false,
                 stop_after_frames_sent: Some(1),
                 record_first_queued_ms_since_pipeline: Some(first_ms_enc),
                 nv12_render_startup_breakdown_ms: Some(breakdown_enc),
@@ -153,16 +269,57 @@ impl Mp4ExportSettings {
 
     async fn export_nv12(
         self,
-        base: ExporterBase,
+        mut base: ExporterBase,
         output_size: (u32, u32),
         fps: u32,
-        on_progress: impl FnMut(u32) -> bool + Send + 'static,
+        mut on_progress: impl FnMut(u32) -> bool + Send + 'static,
         mode: ExportNv12Mode,
-    ) -> Result<PathBuf, String> {
+    ) -> Result<Nv12ExportOutput, String> {
         let pipeline_start = std::time::Instant::now();
+        base.set_time_range(self.time_range);
+        let frame_range = base.frame_range(fps);
         let output_path = base.output_path.clone();
         let meta = &base.studio_meta;
 
+        let checkpoint = if mode.checkpoint {
+            let project_config =
+                serde_json::to_string(&base.project_config).map_err(|e| e.to_string())?;
+            let key = checkpoint_key(&[
+                &format!("{self:?}"),
+                &format!("{output_size:?}"),
+                &base.project_path.to_string_lossy(),
+                &project_config,
+            ]);
+
+            Some(
+                ExportCheckpoint::open(output_path.with_extension("parts"), key)
+                    .map_err(|e| format!("Failed to open export checkpoint: {e}"))?,
+            )
+        } else {
+            None
+        };
+
+        let storyboard = self
+            .storyboard
+            .map(|settings| {
+                StoryboardCapture::new(
+                    settings,
+                    output_size,
+                    fps,
+                    &frame_range,
+                    checkpoint.as_ref().map(|c| c.dir().to_path_buf()),
+                )
+            })
+            .transpose()
+            .map_err(|e| format!("Failed to prepare storyboard: {e}"))?;
+
+        let resume_frame = checkpoint
+            .as_ref()
+            .map(|c| c.resume_frame(frame_range.start).min(frame_range.end))
+            .unwrap_or(frame_range.start);
+        let render_range = resume_frame..frame_range.end;
+        let frames_already_exported = resume_frame - frame_range.start;
+
         let (frame_tx, frame_rx) = std::sync::mpsc::sync_channel::<ExportFrame>(4);
 
         let mut video_info =
@@ -176,102 +333,327 @@ impl Mp4ExportSettings {
             .filter(|_| !base.project_config.audio.mute)
             .is_some();
 
+        let (stem_segments, audio_titles) =
+            audio_stem_tracks(&base.segments, self.audio_stems && has_audio);
+
         let record_first_queued_ms = mode.record_first_queued_ms_since_pipeline;
         let nv12_render_startup_breakdown_ms = mode.nv12_render_startup_breakdown_ms;
 
+        // Absolute timeline frames, since fragments each start their own timestamps at zero.
+        let keyframes = timeline_keyframes(&base.project_config, &frame_range, fps)
+            .into_iter()
+            .map(|frame| frame_range.start + frame)
+            .collect::<Vec<_>>();
+
+        let file_options = Mp4FileOptions {
+            subtitles: caption_subtitle_track(&base.project_config, &frame_range, fps)
+                .into_iter()
+                .collect(),
+            chapters: timeline_chapters(&base.project_config, &frame_range, fps),
+            metadata: self
+                .metadata
+                .as_ref()
+                .map(|metadata| metadata.container_metadata(&base))
+                .unwrap_or_default(),
+            audio_titles,
+        };
+
+        let target_bitrate = self
+            .target_size_bytes()
+            .map(|target| {
+                let duration_secs = (frame_range.end - frame_range.start) as f64 / fps as f64;
+                let audio_tracks = if has_audio {
+                    1 + stem_segments.len() as u64
+                } else {
+                    0
+                };
+                let audio_bitrate = audio_tracks * AACEncoder::OUTPUT_BITRATE as u64;
+                target_video_bitrate(target, duration_secs, audio_bitrate)
+            })
+            .transpose()?;
+        if let Some(bitrate) = target_bitrate {
+            info!(bitrate, "Encoding to target size with constrained VBR");
+        }
+
+        let loudness = match self.loudness.filter(|_| has_audio) {
+            Some(settings) => {
+                let renderer = ExportAudioRenderer::new(
+                    AudioRenderer::new(audio_segments.clone()),
+                    fps,
+                    frame_range.start,
+                );
+                let frame_count = frame_range.end - frame_range.start;
+                let project = base.project_config.clone();
+
+                let measurement = tokio::task::spawn_blocking(move || {
+                    settings.measure(renderer, frame_count, &project)
+                })
+                .await
+                .map_err(|e| format!("Failed to measure loudness: {e}"))?;
+
+                info!(?measurement, "Measured export loudness");
+                Some(measurement)
+            }
+            None => None,
+        };
+        let audio_gain_db = loudness.map(|m| m.gain_db).unwrap_or(0.0);
+
         let project_for_audio = base.project_config.clone();
         let pipeline_start_for_encoder = pipeline_start;
         let crf_mode = self.crf;
+        let video_codec = self.video_codec;
+        let hdr = self.hdr.filter(|_| {
+            let supported = video_codec != Mp4VideoCodec::Av1;
+            if !supported {
+                warn!("HDR export needs HEVC, exporting SDR AV1 instead");
+            }
+            supported
+        });
+        let mut hdr_video_info = video_info;
+        hdr_video_info.pixel_format = ffmpeg::format::Pixel::P010LE;
         let effective_bpp = self.effective_bpp();
+        let first_render_frame = render_range.start;
+        let stem_count = stem_segments.len();
+        let fragment_frames = match checkpoint {
+            Some(_) => ExportCheckpoint::FRAGMENT_SECS * fps,
+            None => u32::MAX,
+        };
         let encoder_thread = tokio::task::spawn_blocking(move || {
-            trace!("Creating MP4File encoder (NV12 path)");
-
-            let mut muxer = if let Some(crf) = crf_mode {
-                info!(crf = crf, "Using HEVC CRF export mode");
-                let encoder = HevcMP4File::init(
-                    "output",
-                    base.output_path.clone(),
-                    |o| {
-                        HevcEncoder::builder(video_info)
-                            .with_crf(crf)
-                            .with_external_conversion()
-                            .build(o)
-                    },
-                    |o| {
-                        has_audio.then(|| {
+            let mut checkpoint = checkpoint;
+            // Fragments carry only audio and video; subtitles and chapters are added when
+            // they're joined.
+            let mut single_file_options = checkpoint.is_none().then(|| file_options.clone());
+
+            let open_muxer = |path: PathBuf,
+                              file_options: Mp4FileOptions,
+                              first_frame: u32|
+             -> Result<ExportMuxer, String> {
+                trace!("Creating MP4File encoder (NV12 path)");
+
+                let audio_stems = |o: &mut ffmpeg::format::context::Output| -> Result<
+                    Vec<Box<dyn AudioEncoder + Send>>,
+                    Box<dyn std::error::Error>,
+                > {
+                    (0..stem_count)
+                        .map(|_| {
                             AACEncoder::init(AudioRenderer::info(), o)
                                 .map(|v| v.boxed())
                                 .map_err(Into::into)
                         })
-                    },
-                )
-                .map_err(|v| v.to_string())?;
-                ExportMuxer::Hevc(encoder)
-            } else {
-                let encoder = MP4File::init(
-                    "output",
-                    base.output_path.clone(),
-                    |o| {
-                        H264Encoder::builder(video_info)
-                            .with_bpp(effective_bpp)
-                            .with_external_conversion()
+                        .collect()
+                };
+
+                let mut muxer = if video_codec == Mp4VideoCodec::Av1 {
+                    info!(crf = ?crf_mode, "Using AV1 export mode");
+                    let encoder = Av1MP4File::init_with_audio_stems(
+                        "output",
+                        path,
+                        file_options,
+                        |o| {
+                            // The AV1 encoders don't take NV12, so the builder converts.
+                            let builder = Av1Encoder::builder(video_info).with_bpp(effective_bpp);
+                            let builder = match crf_mode {
+                                Some(crf) => builder.with_crf(crf),
+                                None => builder,
+                            };
+                            match target_bitrate {
+                                Some(bitrate) => builder.with_target_bitrate(bitrate),
+                                None => builder,
+                            }
                             .build(o)
-                    },
-                    |o| {
-                        has_audio.then(|| {
-                            AACEncoder::init(AudioRenderer::info(), o)
-                                .map(|v| v.boxed())
-                                .map_err(Into::into)
-                        })
-                    },
-                )
-                .map_err(|v| v.to_string())?;
-                ExportMuxer::H264(encoder)
-            };
+                        },
+                        |o| {
+                            has_audio.then(|| {
+                                AACEncoder::init(AudioRenderer::info(), o)
+                                    .map(|v| v.boxed())
+                                    .map_err(Into::into)
+                            })
+                        },
+                        audio_stems,
+                    )
+                    .map_err(|v| v.to_string())?;
+                    ExportMuxer::Av1(encoder)
+                } else if let Some(transfer) = hdr.map(|hdr| hdr.transfer()) {
+                    info!(crf = ?crf_mode, ?transfer, "Using HEVC HDR export mode");
+                    let encoder = HevcMP4File::init_with_audio_stems(
+                        "output",
+                        path,
+                        file_options,
+                        |o| {
+                            // libx265 takes planar 10-bit rather than P010, so the builder
+                            // converts when it has to.
+                            let builder = HevcEncoder::builder(hdr_video_info)
+                                .with_bpp(effective_bpp)
+                                .with_hdr(transfer);
+                            let builder = match crf_mode {
+                                Some(crf) => builder.with_crf(crf),
+                                None => builder,
+                            };
+                            match target_bitrate {
+                                Some(bitrate) => builder.with_target_bitrate(bitrate),
+                                None => builder,
+                            }
+                            .build(o)
+                        },
+                        |o| {
+                            has_audio.then(|| {
+                                AACEncoder::init(AudioRenderer::info(), o)
+                                    .map(|v| v.boxed())
+                                    .map_err(Into::into)
+                            })
+                        },
+                        audio_stems,
+                    )
+                    .map_err(|v| v.to_string())?;
+                    ExportMuxer::Hevc(encoder)
+                } else if let Some(crf) = crf_mode {
+                    info!(crf = crf, "Using HEVC CRF export mode");
+                    let encoder = HevcMP4File::init_with_audio_stems(
+                        "output",
+                        path,
+                        file_options,
+                        |o| {
+                            let builder = HevcEncoder::builder(video_info)
+                                .with_crf(crf)
+                                .with_external_conversion();
+                            match target_bitrate {
+                                Some(bitrate) => builder.with_target_bitrate(bitrate),
+                                None => builder,
+                            }
+                            .build(o)
+                        },
+                        |o| {
+                            has_audio.then(|| {
+                                AACEncoder::init(AudioRenderer::info(), o)
+                                    .map(|v| v.boxed())
+                                    .map_err(Into::into)
+                            })
+                        },
+                        audio_stems,
+                    )
+                    .map_err(|v| v.to_string())?;
+                    ExportMuxer::Hevc(encoder)
+                } else {
+                    let encoder = MP4File::init_with_audio_stems(
+                        "output",
+                        path,
+                        file_options,
+                        |o| {
+                            let builder = H264Encoder::builder(video_info)
+                                .with_bpp(effective_bpp)
+                                .with_external_conversion();
+                            match target_bitrate {
+                                Some(bitrate) => builder.with_target_bitrate(bitrate),
+                                None => builder,
+                            }
+                            .build(o)
+                        },
+                        |o| {
+                            has_audio.then(|| {
+                                AACEncoder::init(AudioRenderer::info(), o)
+                                    .map(|v| v.boxed())
+                                    .map_err(Into::into)
+                            })
+                        },
+                        audio_stems,
+                    )
+                    .map_err(|v| v.to_string())?;
+                    ExportMuxer::H264(encoder)
+                };
 
-            info!("Created encoder (NV12, external conversion, export settings)");
+                info!("Created encoder (NV12, external conversion, export settings)");
 
-            let mut audio_renderer = if has_audio {
-                Some(AudioRenderer::new(audio_segments))
-            } else {
-                None
+                muxer.force_keyframes_at(
+                    keyframes
+                        .iter()
+                        .filter(|&&frame| frame > first_frame)
+                        .map(|&frame| {
+                            Duration::from_secs_f64((frame - first_frame) as f64 / fps as f64)
+                        }),
+                );
+
+                Ok(muxer)
             };
 
+            let mut audio_renderer = has_audio.then(|| {
+                ExportAudioRenderer::new(
+                    AudioRenderer::new(audio_segments),
+                    fps,
+                    first_render_frame,
+                )
+                .with_gain_db(audio_gain_db)
+            });
+            let mut stem_renderers = stem_segments
+                .into_iter()
+                .map(|segments| {
+                    ExportAudioRenderer::new(AudioRenderer::new(segments), fps, first_render_frame)
+                        .with_gain_db(audio_gain_db)
+                })
+                .collect::<Vec<_>>();
+
             let mut reusable_frame = ffmpeg::frame::Video::new(
                 ffmpeg::format::Pixel::NV12,
                 output_size.0,
                 output_size.1,
             );
             let mut converted_frame: Option<ffmpeg::frame::Video> = None;
+            let hdr_converter = hdr.map(|hdr| SdrToHdrConverter::new(hdr.transfer()));
+            let mut hdr_frame = hdr.map(|_| {
+                ffmpeg::frame::Video::new(
+                    ffmpeg::format::Pixel::P010LE,
+                    output_size.0,
+                    output_size.1,
+                )
+            });
             let mut encoded_frames = 0u32;
             let encode_start = std::time::Instant::now();
-            let sample_rate = u64::from(AudioRenderer::SAMPLE_RATE);
-            let fps_u64 = u64::from(fps);
-            let mut audio_sample_cursor = 0u64;
+
+            // The open muxer and the timeline frame it starts at.
+            let mut fragment: Option<(ExportMuxer, u32)> = None;
+            let mut next_frame = first_render_frame;
 
             while let Ok(input) = frame_rx.recv() {
-                if encoded_frames == 0
-                    && let Some(audio) = &mut audio_renderer
-                {
-                    audio.set_playhead(0.0, &project_for_audio);
-                }
+                let timeline_frame = first_render_frame + input.frame_number;
+
+                let (mut muxer, fragment_start) = match fragment.take() {
+                    Some(fragment) => fragment,
+                    None => match &checkpoint {
+                        Some(checkpoint) => (
+                            open_muxer(
+                                checkpoint.next_fragment_path(),
+                                Mp4FileOptions::default(),
+                                timeline_frame,
+                            )?,
+                            timeline_frame,
+                        ),
+                        None => (
+                            open_muxer(
+                                base.output_path.clone(),
+                                single_file_options.take().unwrap_or_default(),
+                                timeline_frame,
+                            )?,
+                            timeline_frame,
+                        ),
+                    },
+                };
 
                 let audio_frame = audio_renderer.as_mut().and_then(|audio| {
-                    let n = u64::from(input.frame_number);
-                    let end = ((n + 1) * sample_rate) / fps_u64;
-                    if end <= audio_sample_cursor {
-                        return None;
-                    }
-                    let pts = audio_sample_cursor as i64;
-                    let samples = (end - audio_sample_cursor) as usize;
-                    audio_sample_cursor = end;
-                    audio
-                        .render_frame(samples, &project_for_audio)
-                        .map(|mut frame| {
-                            frame.set_pts(Some(pts));
-                            frame
-                        })
+                    audio.render_for_frame(input.frame_number, &project_for_audio)
                 });
+                let stem_frames = stem_renderers
+                    .iter_mut()
+                    .map(|stem| stem.render_for_frame(input.frame_number, &project_for_audio))
+                    .collect::<Vec<_>>();
+
+                if let Some(storyboard) = &storyboard {
+                    storyboard.capture(
+                        timeline_frame,
+                        &input.nv12_data,
+                        input.width,
+                        input.height,
+                        input.y_stride,
+                    )?;
+                }
 
                 fill_nv12_frame_direct(
                     &mut reusable_frame,
@@ -281,16 +663,25 @@ impl Mp4ExportSettings {
                     input.y_stride,
                     input.frame_number as i64,
                 );
+                let video_frame = match (&hdr_converter, hdr_frame.as_mut()) {
+                    (Some(converter), Some(hdr_frame)) => {
+                        converter.convert(&reusable_frame, hdr_frame);
+                        hdr_frame.set_pts(reusable_frame.pts());
+                        hdr_frame
+                    }
+                    _ => &mut reusable_frame,
+                };
                 muxer
-                    .queue_video_frame_reusable(
-                        &mut reusable_frame,
-                        &mut converted_frame,
-                        Duration::MAX,
-                    )
+                    .queue_video_frame_reusable(video_frame, &mut converted_frame, Duration::MAX)
                     .map_err(|err| err.to_string())?;
                 if let Some(audio) = audio_frame {
                     muxer.queue_audio_frame(audio);
                 }
+                for (index, audio) in stem_frames.into_iter().enumerate() {
+                    if let Some(audio) = audio {
+                        muxer.queue_audio_stem_frame(index, audio);
+                    }
+                }
                 encoded_frames += 1;
                 if encoded_frames == 1
                     && let Some(atom) = record_first_queued_ms.as_ref()
@@ -299,6 +690,19 @@ impl Mp4ExportSettings {
                     let _ =
                         atom.compare_exchange(u64::MAX, ms, Ordering::Relaxed, Ordering::Relaxed);
                 }
+
+                next_frame = timeline_frame + 1;
+                if next_frame - fragment_start < fragment_frames {
+                    fragment = Some((muxer, fragment_start));
+                    continue;
+                }
+
+                finish_muxer(&mut muxer)?;
+                if let Some(checkpoint) = &mut checkpoint {
+                    checkpoint
+                        .commit(fragment_start, next_frame)
+                        .map_err(|e| format!("Failed to write export checkpoint: {e}"))?;
+                }
             }
 
             let encode_elapsed = encode_start.elapsed();
@@ -312,21 +716,50 @@ impl Mp4ExportSettings {
                 );
             }
 
-            let res = muxer
-                .finish()
-                .map_err(|e| format!("Failed to finish encoding: {e}"))?;
-
-            if let Err(e) = res.video_finish {
-                return Err(format!("Video encoding failed: {e}"));
+            if let Some((mut muxer, fragment_start)) = fragment.take() {
+                finish_muxer(&mut muxer)?;
+                if let Some(checkpoint) = &mut checkpoint {
+                    checkpoint
+                        .commit(fragment_start, next_frame)
+                        .map_err(|e| format!("Failed to write export checkpoint: {e}"))?;
+                }
             }
-            if let Err(e) = res.audio_finish {
-                return Err(format!("Audio encoding failed: {e}"));
+
+            // Captured frames live in the checkpoint directory, so this has to happen
+            // before it's removed.
+            let storyboard = storyboard
+                .map(|storyboard| storyboard.finish(&base.output_path))
+                .transpose()?;
+
+            if let Some(checkpoint) = checkpoint {
+                info!(
+                    fragments = checkpoint.fragment_paths().len(),
+                    "Joining export fragments"
+                );
+                cap_enc_ffmpeg::remux::concatenate_video_fragments_with_options(
+                    &checkpoint.fragment_paths(),
+                    &base.output_path,
+                    file_options,
+                )
+                .map_err(|e| format!("Failed to join export fragments: {e}"))?;
+                checkpoint.remove();
             }
 
-            Ok::<_, String>(base.output_path)
+            Ok::<_, String>((base.output_path, storyboard))
         })
         .then(|r| async { r.map_err(|e| e.to_string()).and_then(|v| v) });
 
+        info!(
+            "Expected to render {} frames",
+            render_range.end - render_range.start
+        );
+        if frames_already_exported > 0 {
+            info!(
+                frames_already_exported,
+                "Resuming export from frame {resume_frame}"
+            );
+        }
+
         let stop_after_frames_sent = mode.stop_after_frames_sent;
         let render_video_task = export_render_to_channel(
             &base.render_constants,
@@ -345,26 +778,121 @@ impl Mp4ExportSettings {
             fps,
             self.resolution_base,
             &base.recordings,
+            render_range,
             stop_after_frames_sent,
             nv12_render_startup_breakdown_ms,
-            on_progress,
+            move |frame| on_progress(frames_already_exported + frame),
             base.project_path.clone(),
         )
         .then(|v| async { v.map_err(|e| e.to_string()) });
 
-        tokio::try_join!(encoder_thread, render_video_task)?;
+        let ((_, storyboard), _) = tokio::try_join!(encoder_thread, render_video_task)?;
+
+        Ok(Nv12ExportOutput {
+            path: output_path,
+            storyboard,
+            loudness,
+        })
+    }
+}
+
+struct Nv12ExportOutput {
+    path: PathBuf,
+    storyboard: Option<StoryboardFiles>,
+    loudness: Option<LoudnessMeasurement>,
+}
+
+/// Video bitrate that keeps an export of `duration_secs` under `target_bytes` once the audio
+/// track is accounted for, leaving headroom for container overhead and rate control overshoot.
+fn target_video_bitrate(
+    target_bytes: u64,
+    duration_secs: f64,
+    audio_bitrate: u64,
+) -> Result<u64, String> {
+    const SIZE_MARGIN: f64 = 0.05;
+    const MIN_VIDEO_BITRATE: f64 = 100_000.0;
+
+    if duration_secs <= 0.0 {
+        return Err("Nothing to export".to_string());
+    }
+
+    let budget_bits = target_bytes as f64 * 8.0 * (1.0 - SIZE_MARGIN);
+    let video_bitrate = budget_bits / duration_secs - audio_bitrate as f64;
+
+    if video_bitrate < MIN_VIDEO_BITRATE {
+        return Err(format!(
+            "{:.1} MB is too small for {duration_secs:.0}s of video",
+            target_bytes as f64 / 1_000_000.0
+        ));
+    }
+
+    Ok(video_bitrate as u64)
+}
+
+/// The segments for each stem to write after the mix, and titles for all the audio tracks.
+/// Both are empty when stems are off or the recording has none.
+pub(crate) fn audio_stem_tracks(
+    segments: &[SegmentMedia],
+    enabled: bool,
+) -> (Vec<Vec<AudioSegment>>, Vec<String>) {
+    let stems = [AudioStem::Mic, AudioStem::SystemAudio]
+        .into_iter()
+        .filter(|&stem| enabled && has_audio_stem(segments, stem))
+        .collect::<Vec<_>>();
+
+    if stems.is_empty() {
+        return (vec![], vec![]);
+    }
+
+    let titles = std::iter::once("Mix")
+        .chain(stems.iter().map(|&stem| audio_stem_title(stem)))
+        .map(str::to_string)
+        .collect();
+    let stem_segments = stems
+        .into_iter()
+        .map(|stem| get_audio_stem_segments(segments, stem))
+        .collect();
+
+    (stem_segments, titles)
+}
+
+fn has_audio_stem(segments: &[SegmentMedia], stem: AudioStem) -> bool {
+    segments.iter().any(|s| match stem {
+        AudioStem::Mic => s.audio.is_some(),
+        AudioStem::SystemAudio => s.system_audio.is_some(),
+    })
+}
+
+fn audio_stem_title(stem: AudioStem) -> &'static str {
+    match stem {
+        AudioStem::Mic => "Microphone",
+        AudioStem::SystemAudio => "System Audio",
+    }
+}
+
+fn finish_muxer(muxer: &mut ExportMuxer) -> Result<(), String> {
+    let res = muxer
+        .finish()
+        .map_err(|e| format!("Failed to finish encoding: {e}"))?;
 
-        Ok(output_path)
+    if let Err(e) = res.video_finish {
+        return Err(format!("Video encoding failed: {e}"));
+    }
+    if let Err(e) = res.audio_finish {
+        return Err(format!("Audio encoding failed: {e}"));
     }
+
+    Ok(())
 }
 
-enum ExportMuxer {
+pub(crate) enum ExportMuxer {
     H264(MP4File),
     Hevc(HevcMP4File),
+    Av1(Av1MP4File),
 }
 
 impl ExportMuxer {
-    fn queue_video_frame_reusable(
+    pub(crate) fn queue_video_frame_reusable(
         &mut self,
         frame: &mut ffmpeg::frame::Video,
         converted_frame: &mut Option<ffmpeg::frame::Video>,
@@ -377,30 +905,125 @@ impl ExportMuxer {
             Self::Hevc(m) => m
                 .queue_video_frame_reusable(frame, converted_frame, timestamp)
                 .map_err(|e| e.to_string()),
+            Self::Av1(m) => m
+                .queue_video_frame_reusable(frame, converted_frame, timestamp)
+                .map_err(|e| e.to_string()),
         }
     }
 
-    fn queue_audio_frame(&mut self, frame: ffmpeg::frame::Audio) {
+    /// Forces keyframes at these times from the start of this muxer's video.
+    pub(crate) fn force_keyframes_at(&mut self, timestamps: impl IntoIterator<Item = Duration>) {
+        match self {
+            Self::H264(m) => m.video_mut().force_keyframes_at(timestamps),
+            Self::Hevc(m) => m.video_mut().force_keyframes_at(timestamps),
+            Self::Av1(m) => m.video_mut().force_keyframes_at(timestamps),
+        }
+    }
+
+    pub(crate) fn queue_audio_frame(&mut self, frame: ffmpeg::frame::Audio) {
         match self {
             Self::H264(m) => m.queue_audio_frame(frame),
             Self::Hevc(m) => m.queue_audio_frame(frame),
+            Self::Av1(m) => m.queue_audio_frame(frame),
+        }
+    }
+
+    pub(crate) fn queue_audio_stem_frame(&mut self, index: usize, frame: ffmpeg::frame::Audio) {
+        match self {
+            Self::H264(m) => m.queue_audio_stem_frame(index, frame),
+            Self::Hevc(m) => m.queue_audio_stem_frame(index, frame),
+            Self::Av1(m) => m.queue_audio_stem_frame(index, frame),
         }
     }
 
-    fn finish(&mut self) -> Result<FinishResult, FinishError> {
+    pub(crate) fn finish(&mut self) -> Result<FinishResult, FinishError> {
         match self {
             Self::H264(m) => m.finish(),
             Self::Hevc(m) => m.finish(),
+            Self::Av1(m) => m.finish(),
         }
     }
 }
 
-struct ExportFrame {
-    nv12_data: SharedNv12Buffer,
-    width: u32,
-    height: u32,
-    y_stride: u32,
-    frame_number: u32,
+#[derive(Clone)]
+pub(crate) struct ExportFrame {
+    pub(crate) nv12_data: SharedNv12Buffer,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) y_stride: u32,
+    pub(crate) frame_number: u32,
+}
+
+pub(crate) struct ExportAudioRenderer {
+    renderer: AudioRenderer,
+    fps: u64,
+    first_frame: u32,
+    sample_cursor: u64,
+    started: bool,
+    gain: f32,
+}
+
+impl ExportAudioRenderer {
+    /// `first_frame` is the timeline frame the exported video starts at; frame numbers
+    /// passed to `render_for_frame` are relative to it.
+    pub(crate) fn new(renderer: AudioRenderer, fps: u32, first_frame: u32) -> Self {
+        Self {
+            renderer,
+            fps: u64::from(fps),
+            first_frame,
+            sample_cursor: 0,
+            started: false,
+            gain: 1.0,
+        }
+    }
+
+    /// Scales every rendered sample by `gain_db`, on top of the project's track volumes.
+    pub(crate) fn with_gain_db(mut self, gain_db: f64) -> Self {
+        self.gain = 10f64.powf(gain_db / 20.0) as f32;
+        self
+    }
+
+    pub(crate) fn render_for_frame(
+        &mut self,
+        frame_number: u32,
+        project: &ProjectConfiguration,
+    ) -> Option<ffmpeg::frame::Audio> {
+        if !self.started {
+            self.renderer
+                .set_playhead(self.first_frame as f64 / self.fps as f64, project);
+            self.started = true;
+        }
+
+        let sample_rate = u64::from(AudioRenderer::SAMPLE_RATE);
+        let end = ((u64::from(frame_number) + 1) * sample_rate) / self.fps;
+        if end <= self.sample_cursor {
+            return None;
+        }
+
+        let pts = self.sample_cursor as i64;
+        let samples = (end - self.sample_cursor) as usize;
+        self.sample_cursor = end;
+
+        self.renderer
+            .render_frame(samples, project)
+            .map(|mut frame| {
+                frame.set_pts(Some(pts));
+                if self.gain != 1.0 {
+                    apply_gain(&mut frame, self.gain);
+                }
+                frame
+            })
+    }
+}
+
+/// Scales the interleaved f32 samples the audio renderer produces.
+fn apply_gain(frame: &mut ffmpeg::frame::Audio, gain: f32) {
+    let len = frame.samples() * usize::from(AudioRenderer::CHANNELS) * size_of::<f32>();
+
+    for bytes in frame.data_mut(0)[..len].chunks_exact_mut(size_of::<f32>()) {
+        let sample = f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) * gain;
+        bytes.copy_from_slice(&sample.to_ne_bytes());
+    }
 }
 
 struct FirstFrameNv12 {
@@ -502,7 +1125,7 @@ fn nv12_from_rendered_frame(frame: Nv12RenderedFrame) -> ExportFrame {
     }
 }
 
-fn fill_nv12_frame_direct(
+pub(crate) fn fill_nv12_frame_direct(
     frame: &mut ffmpeg::frame::Video,
     nv12_data: &[u8],
     width: u32,
@@ -632,7 +1255,7 @@ const FRAME_RECEIVE_STEADY_TIMEOUT_SECS: u64 = 90;
 const MAX_CONSECUTIVE_FRAME_TIMEOUTS: u32 = 3;
 
 #[allow(clippy::too_many_arguments)]
-async fn export_render_to_channel(
+pub(crate) async fn export_render_to_channel(
     constants: &RenderVideoConstants,
     project: &ProjectConfiguration,
     sender: std::sync::mpsc::SyncSender<ExportFrame>,
@@ -642,6 +1265,7 @@ async fn export_render_to_channel(
     fps: u32,
     resolution_base: XY<u32>,
     recordings: &ProjectRecordingsMeta,
+    frame_range: Range<u32>,
     stop_after_frames_sent: Option<u32>,
     startup_breakdown_ms: Option<Arc<Mutex<Option<cap_rendering::Nv12RenderStartupBreakdownMs>>>>,
     mut on_progress: impl FnMut(u32) -> bool + Send + 'static,
@@ -650,6 +1274,7 @@ async fn export_render_to_channel(
     let (tx_image_data, mut video_rx) = tokio::sync::mpsc::channel::<(Nv12RenderedFrame, u32)>(8);
 
     let screenshot_project_path = project_path;
+    let is_partial_export = frame_range.start > 0;
 
     let render_result = {
         let render_future = cap_rendering::render_video_to_channel_nv12(
@@ -662,6 +1287,7 @@ async fn export_render_to_channel(
             fps,
             resolution_base,
             recordings,
+            Some(frame_range),
             stop_after_frames_sent,
             startup_breakdown_ms,
         );
@@ -717,7 +1343,7 @@ async fn export_render_to_channel(
 
                 let export_frame = nv12_from_rendered_frame(frame);
 
-                if first_frame_data.is_none() {
+                if first_frame_data.is_none() && !is_partial_export {
                     first_frame_data = Some(FirstFrameNv12 {
                         data: export_frame.nv12_data.clone(),
                         width: export_frame.width,
@@ -774,6 +1400,21 @@ mod tests {
             .sum()
     }
 
+    #[test]
+    fn target_bitrate_fits_video_and_audio_in_budget() {
+        let bitrate = target_video_bitrate(25_000_000, 100.0, 128_000).unwrap();
+        let total_bytes = (bitrate + 128_000) as f64 * 100.0 / 8.0;
+
+        assert!(total_bytes < 25_000_000.0);
+        assert!(total_bytes > 23_000_000.0);
+    }
+
+    #[test]
+    fn target_bitrate_rejects_budgets_too_small_for_duration() {
+        assert!(target_video_bitrate(1_000_000, 600.0, 128_000).is_err());
+        assert!(target_video_bitrate(25_000_000, 0.0, 128_000).is_err());
+    }
+
     #[test]
     fn audio_samples_match_duration_across_fps() {
         let sample_rate = u64::from(AudioRenderer::SAMPLE_RATE);
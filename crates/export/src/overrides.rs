@@ -0,0 +1,134 @@
+use cap_project::ProjectConfiguration;
+use serde_json::{Value, json};
+
+/// Hides the camera overlay.
+pub fn no_camera() -> Value {
+    json!({ "camera": { "hide": true } })
+}
+
+/// Crops the output to 9:16.
+pub fn vertical_aspect() -> Value {
+    json!({ "aspectRatio": "vertical" })
+}
+
+/// Crops the output to 9:16 with a window that follows the cursor.
+pub fn vertical_reframe() -> Value {
+    json!({ "aspectRatio": "vertical", "autoReframe": true })
+}
+
+/// Hides the rendered cursor.
+pub fn hide_cursor() -> Value {
+    json!({ "cursor": { "hide": true } })
+}
+
+/// Layers JSON merge patches (RFC 7386) over `config` in order, so variants of a project
+/// can be exported without touching its saved configuration.
+pub fn apply_overrides(
+    config: &ProjectConfiguration,
+    overrides: &[Value],
+) -> Result<ProjectConfiguration, serde_json::Error> {
+    if overrides.is_empty() {
+        return Ok(config.clone());
+    }
+
+    let mut value = serde_json::to_value(config)?;
+    for patch in overrides {
+        merge_patch(&mut value, patch);
+    }
+
+    let mut patched: ProjectConfiguration = serde_json::from_value(value)?;
+    // Not serialized, so it can't be patched and would otherwise be lost.
+    patched.hidden_text_segments = config.hidden_text_segments.clone();
+
+    Ok(patched)
+}
+
+/// Objects are merged recursively, `null` removes a key and anything else replaces it.
+fn merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+
+    if !target.is_object() {
+        *target = Value::Object(Default::default());
+    }
+
+    let Value::Object(target) = target else {
+        return;
+    };
+
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(key);
+        } else {
+            merge_patch(target.entry(key.clone()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_patch_follows_rfc_7386() {
+        let mut target = json!({
+            "title": "Goodbye!",
+            "author": { "givenName": "John", "familyName": "Doe" },
+            "tags": ["example", "sample"],
+            "content": "This will be unchanged"
+        });
+
+        merge_patch(
+            &mut target,
+            &json!({
+                "title": "Hello!",
+                "phoneNumber": "+01-123-456-7890",
+                "author": { "familyName": null },
+                "tags": ["example"]
+            }),
+        );
+
+        assert_eq!(
+            target,
+            json!({
+                "title": "Hello!",
+                "author": { "givenName": "John" },
+                "tags": ["example"],
+                "content": "This will be unchanged",
+                "phoneNumber": "+01-123-456-7890"
+            })
+        );
+    }
+
+    #[test]
+    fn overrides_are_layered_in_order() {
+        let config = ProjectConfiguration::default();
+
+        let patched = apply_overrides(
+            &config,
+            &[
+                no_camera(),
+                vertical_aspect(),
+                json!({ "camera": { "hide": false } }),
+            ],
+        )
+        .unwrap();
+
+        assert!(!patched.camera.hide);
+        assert!(matches!(
+            patched.aspect_ratio,
+            Some(cap_project::AspectRatio::Vertical)
+        ));
+        assert!(!config.camera.hide);
+        assert!(config.aspect_ratio.is_none());
+    }
+
+    #[test]
+    fn invalid_overrides_are_rejected() {
+        let config = ProjectConfiguration::default();
+
+        assert!(apply_overrides(&config, &[json!({ "aspectRatio": "diagonal" })]).is_err());
+    }
+}
@@ -0,0 +1,434 @@
+use crate::{
+    ExportTimeRange, ExporterBase,
+    chapters::timeline_chapters,
+    mp4::{
+        ExportAudioRenderer, ExportCompression, ExportFrame, ExportMuxer, export_render_to_channel,
+        fill_nv12_frame_direct,
+    },
+    subtitles::caption_subtitle_track,
+};
+use cap_editor::{AudioRenderer, get_audio_segments};
+use cap_enc_ffmpeg::{
+    AudioEncoder,
+    aac::AACEncoder,
+    av1::Av1Encoder,
+    h264::H264Encoder,
+    hevc::HevcEncoder,
+    mp4::{Av1MP4File, HevcMP4File, MP4File, Mp4FileOptions},
+};
+use cap_media_info::{RawVideoFormat, VideoInfo};
+use cap_project::XY;
+use cap_rendering::{ProjectUniforms, RenderSegment};
+use serde::Deserialize;
+use specta::Type;
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, mpsc},
+    time::Duration,
+};
+use tracing::info;
+
+#[derive(Deserialize, Type, Clone, Copy, Debug, Default, PartialEq)]
+pub enum RenditionCodec {
+    #[default]
+    H264,
+    Hevc,
+    Av1,
+}
+
+#[derive(Deserialize, Type, Clone, Copy, Debug)]
+pub struct Rendition {
+    /// Output height in pixels. The width follows the aspect ratio of the rendered frame,
+    /// and renditions taller than the rendered frame are encoded at its size.
+    pub height: u32,
+    pub compression: ExportCompression,
+    #[serde(default)]
+    pub custom_bpp: Option<f32>,
+    #[serde(default)]
+    pub codec: RenditionCodec,
+}
+
+impl Rendition {
+    pub fn effective_bpp(&self) -> f32 {
+        self.custom_bpp
+            .unwrap_or_else(|| self.compression.bits_per_pixel())
+    }
+
+    fn output_size(&self, source: (u32, u32)) -> (u32, u32) {
+        scaled_size(source, self.height)
+    }
+}
+
+/// Scales `source` down to `height` keeping its aspect ratio, with even dimensions as the
+/// encoders require. Never upscales.
+pub(crate) fn scaled_size(source: (u32, u32), height: u32) -> (u32, u32) {
+    if height >= source.1 {
+        return source;
+    }
+
+    let width = (u64::from(source.0) * u64::from(height) / u64::from(source.1)) as u32;
+
+    ((width & !1).max(2), (height & !1).max(2))
+}
+
+#[derive(Deserialize, Type, Clone, Debug)]
+pub struct MultiRenditionExportSettings {
+    pub fps: u32,
+    /// Resolution the frame is composited at once, before being scaled for each rendition.
+    pub resolution_base: XY<u32>,
+    pub renditions: Vec<Rendition>,
+    #[serde(default)]
+    pub time_range: Option<ExportTimeRange>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RenditionProgress {
+    /// Index into `MultiRenditionExportSettings::renditions`.
+    pub rendition: usize,
+    pub frames_encoded: u32,
+}
+
+type ProgressCallback = Arc<Mutex<dyn FnMut(RenditionProgress) -> bool + Send>>;
+
+struct RenditionFrame {
+    video: ExportFrame,
+    audio: Option<ffmpeg::frame::Audio>,
+}
+
+impl MultiRenditionExportSettings {
+    /// Renders the timeline once and encodes every rendition from the same frames.
+    /// Returns the output paths in the order of `renditions`.
+    pub async fn export(
+        self,
+        mut base: ExporterBase,
+        on_progress: impl FnMut(RenditionProgress) -> bool + Send + 'static,
+    ) -> Result<Vec<PathBuf>, String> {
+        info!("Exporting renditions with settings: {:?}", &self);
+
+        if self.renditions.is_empty() {
+            return Err("No renditions to export".to_string());
+        }
+
+        base.set_time_range(self.time_range);
+        let frame_range = base.frame_range(self.fps);
+        let fps = self.fps;
+
+        let output_size = ProjectUniforms::get_output_size(
+            &base.render_constants.options,
+            &base.project_config,
+            self.resolution_base,
+        );
+
+        let mut video_info =
+            VideoInfo::from_raw(RawVideoFormat::Nv12, output_size.0, output_size.1, fps);
+        video_info.time_base = ffmpeg::Rational::new(1, fps as i32);
+
+        let audio_segments = get_audio_segments(&base.segments);
+        let has_audio = audio_segments
+            .first()
+            .filter(|_| !base.project_config.audio.mute)
+            .is_some();
+
+        let file_options = Mp4FileOptions {
+            subtitles: caption_subtitle_track(&base.project_config, &frame_range, fps)
+                .into_iter()
+                .collect(),
+            chapters: timeline_chapters(&base.project_config, &frame_range, fps),
+            ..Default::default()
+        };
+
+        let on_progress: ProgressCallback = Arc::new(Mutex::new(on_progress));
+
+        let mut rendition_txs = Vec::with_capacity(self.renditions.len());
+        let mut encoder_threads = Vec::with_capacity(self.renditions.len());
+        for (index, rendition) in self.renditions.iter().copied().enumerate() {
+            let size = rendition.output_size(output_size);
+            let encoder = RenditionEncoder {
+                index,
+                rendition,
+                video_info,
+                output_size: size,
+                output_path: rendition_output_path(&base.output_path, size.1, rendition.codec),
+                file_options: file_options.clone(),
+                has_audio,
+            };
+
+            info!(
+                index,
+                width = size.0,
+                height = size.1,
+                codec = ?rendition.codec,
+                "Adding rendition"
+            );
+
+            let (tx, rx) = mpsc::sync_channel::<RenditionFrame>(4);
+            rendition_txs.push(tx);
+
+            let on_progress = on_progress.clone();
+            encoder_threads.push(tokio::task::spawn_blocking(move || {
+                encoder.run(rx, on_progress)
+            }));
+        }
+
+        let (frame_tx, frame_rx) = mpsc::sync_channel::<ExportFrame>(4);
+
+        let project_for_audio = base.project_config.clone();
+        let first_frame = frame_range.start;
+        let fan_out_thread = tokio::task::spawn_blocking(move || {
+            let mut audio_renderer = has_audio.then(|| {
+                ExportAudioRenderer::new(AudioRenderer::new(audio_segments), fps, first_frame)
+            });
+
+            while let Ok(video) = frame_rx.recv() {
+                let audio = audio_renderer.as_mut().and_then(|audio| {
+                    audio.render_for_frame(video.frame_number, &project_for_audio)
+                });
+
+                for tx in &rendition_txs {
+                    let frame = RenditionFrame {
+                        video: video.clone(),
+                        audio: audio.clone(),
+                    };
+
+                    // A rendition only hangs up when it failed or was cancelled; its
+                    // encoder thread reports why.
+                    if tx.send(frame).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        let render_result = export_render_to_channel(
+            &base.render_constants,
+            &base.project_config,
+            frame_tx,
+            &base.recording_meta,
+            &base.studio_meta,
+            base.segments
+                .iter()
+                .map(|s| RenderSegment {
+                    cursor: s.cursor.clone(),
+                    keyboard: s.keyboard.clone(),
+                    decoders: s.decoders.clone(),
+                })
+                .collect(),
+            fps,
+            self.resolution_base,
+            &base.recordings,
+            frame_range,
+            None,
+            None,
+            |_| true,
+            base.project_path.clone(),
+        )
+        .await;
+
+        fan_out_thread.await.map_err(|e| e.to_string())?;
+
+        let mut output_paths = Vec::with_capacity(encoder_threads.len());
+        for thread in encoder_threads {
+            output_paths.push(thread.await.map_err(|e| e.to_string())??);
+        }
+
+        render_result.map_err(|e| e.to_string())?;
+
+        Ok(output_paths)
+    }
+}
+
+struct RenditionEncoder {
+    index: usize,
+    rendition: Rendition,
+    video_info: VideoInfo,
+    output_size: (u32, u32),
+    output_path: PathBuf,
+    file_options: Mp4FileOptions,
+    has_audio: bool,
+}
+
+impl RenditionEncoder {
+    fn run(
+        self,
+        frames: mpsc::Receiver<RenditionFrame>,
+        on_progress: ProgressCallback,
+    ) -> Result<PathBuf, String> {
+        let video_info = self.video_info;
+        let (width, height) = self.output_size;
+        let bpp = self.rendition.effective_bpp();
+        let has_audio = self.has_audio;
+
+        let mut muxer = match self.rendition.codec {
+            RenditionCodec::H264 => ExportMuxer::H264(
+                MP4File::init_with_options(
+                    "output",
+                    self.output_path.clone(),
+                    self.file_options,
+                    |o| {
+                        H264Encoder::builder(video_info)
+                            .with_bpp(bpp)
+                            .with_output_size(width, height)
+                            .and_then(|builder| builder.build(o))
+                    },
+                    |o| aac_audio(has_audio, o),
+                )
+                .map_err(|e| e.to_string())?,
+            ),
+            RenditionCodec::Hevc => ExportMuxer::Hevc(
+                HevcMP4File::init_with_options(
+                    "output",
+                    self.output_path.clone(),
+                    self.file_options,
+                    |o| {
+                        HevcEncoder::builder(video_info)
+                            .with_bpp(bpp)
+                            .with_output_size(width, height)
+                            .and_then(|builder| builder.build(o))
+                    },
+                    |o| aac_audio(has_audio, o),
+                )
+                .map_err(|e| e.to_string())?,
+            ),
+            RenditionCodec::Av1 => ExportMuxer::Av1(
+                Av1MP4File::init_with_options(
+                    "output",
+                    self.output_path.clone(),
+                    self.file_options,
+                    |o| {
+                        Av1Encoder::builder(video_info)
+                            .with_bpp(bpp)
+                            .with_output_size(width, height)
+                            .and_then(|builder| builder.build(o))
+                    },
+                    |o| aac_audio(has_audio, o),
+                )
+                .map_err(|e| e.to_string())?,
+            ),
+        };
+
+        let mut reusable_frame = ffmpeg::frame::Video::new(
+            ffmpeg::format::Pixel::NV12,
+            video_info.width,
+            video_info.height,
+        );
+        let mut converted_frame: Option<ffmpeg::frame::Video> = None;
+        let mut frames_encoded = 0u32;
+
+        while let Ok(RenditionFrame { video, audio }) = frames.recv() {
+            fill_nv12_frame_direct(
+                &mut reusable_frame,
+                &video.nv12_data,
+                video.width,
+                video.height,
+                video.y_stride,
+                video.frame_number as i64,
+            );
+            muxer.queue_video_frame_reusable(
+                &mut reusable_frame,
+                &mut converted_frame,
+                Duration::MAX,
+            )?;
+            if let Some(audio) = audio {
+                muxer.queue_audio_frame(audio);
+            }
+
+            frames_encoded += 1;
+
+            let progress = RenditionProgress {
+                rendition: self.index,
+                frames_encoded,
+            };
+            let keep_going = match on_progress.lock() {
+                Ok(mut on_progress) => (*on_progress)(progress),
+                Err(_) => true,
+            };
+            if !keep_going {
+                return Err("Export cancelled".to_string());
+            }
+        }
+
+        let res = muxer
+            .finish()
+            .map_err(|e| format!("Failed to finish encoding: {e}"))?;
+
+        if let Err(e) = res.video_finish {
+            return Err(format!("Video encoding failed: {e}"));
+        }
+        if let Err(e) = res.audio_finish {
+            return Err(format!("Audio encoding failed: {e}"));
+        }
+
+        Ok(self.output_path.with_extension("mp4"))
+    }
+}
+
+fn aac_audio(
+    has_audio: bool,
+    output: &mut ffmpeg::format::context::Output,
+) -> Option<Result<Box<dyn AudioEncoder + Send>, Box<dyn std::error::Error>>> {
+    has_audio.then(|| {
+        AACEncoder::init(AudioRenderer::info(), output)
+            .map(|v| v.boxed())
+            .map_err(Into::into)
+    })
+}
+
+/// `video.mp4` becomes `video_720p.mp4`, or `video_720p_hevc.mp4` for HEVC renditions.
+fn rendition_output_path(base: &Path, height: u32, codec: RenditionCodec) -> PathBuf {
+    let stem = base
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "output".to_string());
+    let suffix = match codec {
+        RenditionCodec::H264 => "",
+        RenditionCodec::Hevc => "_hevc",
+        RenditionCodec::Av1 => "_av1",
+    };
+
+    base.with_file_name(format!("{stem}_{height}p{suffix}.mp4"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rendition(height: u32) -> Rendition {
+        Rendition {
+            height,
+            compression: ExportCompression::Web,
+            custom_bpp: None,
+            codec: RenditionCodec::H264,
+        }
+    }
+
+    #[test]
+    fn renditions_keep_aspect_ratio_and_even_sizes() {
+        assert_eq!(rendition(720).output_size((1920, 1080)), (1280, 720));
+        assert_eq!(rendition(480).output_size((1920, 1080)), (852, 480));
+        assert_eq!(rendition(481).output_size((1920, 1080)), (854, 480));
+    }
+
+    #[test]
+    fn renditions_are_never_upscaled() {
+        assert_eq!(rendition(1080).output_size((1920, 1080)), (1920, 1080));
+        assert_eq!(rendition(2160).output_size((1280, 720)), (1280, 720));
+    }
+
+    #[test]
+    fn rendition_paths_are_suffixed() {
+        let base = Path::new("/exports/video.mp4");
+
+        assert_eq!(
+            rendition_output_path(base, 720, RenditionCodec::H264),
+            Path::new("/exports/video_720p.mp4")
+        );
+        assert_eq!(
+            rendition_output_path(base, 1080, RenditionCodec::Hevc),
+            Path::new("/exports/video_1080p_hevc.mp4")
+        );
+        assert_eq!(
+            rendition_output_path(base, 2160, RenditionCodec::Av1),
+            Path::new("/exports/video_2160p_av1.mp4")
+        );
+    }
+}
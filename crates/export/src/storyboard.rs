@@ -0,0 +1,358 @@
+use image::{RgbImage, codecs::jpeg::JpegEncoder, imageops};
+use serde::Deserialize;
+use specta::Type;
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+use tempfile::TempDir;
+use tracing::{info, warn};
+
+#[derive(Deserialize, Type, Clone, Copy, Debug)]
+pub struct StoryboardSettings {
+    /// Seconds into the export to take the poster frame from. No poster is written when
+    /// unset.
+    #[serde(default)]
+    pub poster_time_secs: Option<f64>,
+    /// Seconds between sprite sheet thumbnails. No sprite sheet or WebVTT track is written
+    /// when unset.
+    #[serde(default)]
+    pub thumbnail_interval_secs: Option<f64>,
+    #[serde(default)]
+    pub thumbnail_width: Option<u32>,
+    /// Thumbnails per row of the sprite sheet.
+    #[serde(default)]
+    pub columns: Option<u32>,
+}
+
+impl StoryboardSettings {
+    pub const DEFAULT_THUMBNAIL_WIDTH: u32 = 160;
+    pub const DEFAULT_COLUMNS: u32 = 10;
+    const JPEG_QUALITY: u8 = 85;
+}
+
+/// Files written next to the export.
+#[derive(Debug, Clone, Default)]
+pub struct StoryboardFiles {
+    pub poster: Option<PathBuf>,
+    pub sprite: Option<PathBuf>,
+    /// WebVTT track whose cues point at regions of the sprite sheet with `#xywh=`.
+    pub vtt: Option<PathBuf>,
+}
+
+/// Grabs the poster and thumbnail frames out of the NV12 frames on their way to the
+/// encoder, so no second decode of the finished file is needed.
+///
+/// Captured frames are kept as files in `work_dir` rather than in memory, so a resumed
+/// export still has the ones from fragments that were already encoded.
+pub(crate) struct StoryboardCapture {
+    fps: u32,
+    frame_count: u32,
+    poster_frame: Option<u32>,
+    interval_secs: f64,
+    /// Timeline frame to thumbnail index.
+    thumbnail_frames: HashMap<u32, u32>,
+    thumbnail_size: (u32, u32),
+    columns: u32,
+    work_dir: PathBuf,
+    _temp_dir: Option<TempDir>,
+}
+
+impl StoryboardCapture {
+    const POSTER_NAME: &str = "poster.jpg";
+
+    pub(crate) fn new(
+        settings: StoryboardSettings,
+        output_size: (u32, u32),
+        fps: u32,
+        frame_range: &Range<u32>,
+        work_dir: Option<PathBuf>,
+    ) -> std::io::Result<Self> {
+        let frame_count = frame_range.end.saturating_sub(frame_range.start);
+        let last_frame = frame_count.saturating_sub(1);
+
+        let poster_frame = settings
+            .poster_time_secs
+            .map(|secs| frame_range.start + ((secs.max(0.0) * fps as f64) as u32).min(last_frame));
+
+        let interval_secs = settings
+            .thumbnail_interval_secs
+            .filter(|secs| *secs > 0.0)
+            .unwrap_or(0.0);
+        let thumbnail_frames = thumbnail_offsets(interval_secs, fps, frame_count)
+            .into_iter()
+            .enumerate()
+            .map(|(index, offset)| (frame_range.start + offset, index as u32))
+            .collect();
+
+        let thumbnail_width = settings
+            .thumbnail_width
+            .unwrap_or(StoryboardSettings::DEFAULT_THUMBNAIL_WIDTH)
+            .clamp(1, output_size.0.max(1));
+        let thumbnail_height = ((thumbnail_width as f64 * output_size.1 as f64
+            / output_size.0.max(1) as f64)
+            .round() as u32)
+            .max(1);
+
+        let (work_dir, temp_dir) = match work_dir {
+            Some(dir) => (dir, None),
+            None => {
+                let temp_dir = tempfile::tempdir()?;
+                (temp_dir.path().to_path_buf(), Some(temp_dir))
+            }
+        };
+        std::fs::create_dir_all(&work_dir)?;
+
+        Ok(Self {
+            fps,
+            frame_count,
+            poster_frame,
+            interval_secs,
+            thumbnail_frames,
+            thumbnail_size: (thumbnail_width, thumbnail_height),
+            columns: settings
+                .columns
+                .unwrap_or(StoryboardSettings::DEFAULT_COLUMNS)
+                .max(1),
+            work_dir,
+            _temp_dir: temp_dir,
+        })
+    }
+
+    /// Captures `timeline_frame` if it's the poster or one of the thumbnails.
+    pub(crate) fn capture(
+        &self,
+        timeline_frame: u32,
+        nv12_data: &[u8],
+        width: u32,
+        height: u32,
+        y_stride: u32,
+    ) -> Result<(), String> {
+        let is_poster = self.poster_frame == Some(timeline_frame);
+        let thumbnail = self.thumbnail_frames.get(&timeline_frame);
+
+        if !is_poster && thumbnail.is_none() {
+            return Ok(());
+        }
+
+        let Some(image) = rgb_from_nv12(nv12_data, width, height, y_stride) else {
+            warn!(
+                timeline_frame,
+                "Skipping storyboard frame with short NV12 data"
+            );
+            return Ok(());
+        };
+
+        if is_poster {
+            write_jpeg(&image, &self.work_dir.join(Self::POSTER_NAME))?;
+        }
+
+        if let Some(index) = thumbnail {
+            let (thumb_width, thumb_height) = self.thumbnail_size;
+            let thumbnail = imageops::thumbnail(&image, thumb_width, thumb_height);
+            write_jpeg(&thumbnail, &self.work_dir.join(thumbnail_file_name(*index)))?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the poster, sprite sheet and WebVTT track next to `output_path`.
+    pub(crate) fn finish(self, output_path: &Path) -> Result<StoryboardFiles, String> {
+        let mut files = StoryboardFiles::default();
+
+        if self.poster_frame.is_some() {
+            let poster = sibling_path(output_path, "poster.jpg");
+            std::fs::copy(self.work_dir.join(Self::POSTER_NAME), &poster)
+                .map_err(|e| format!("Failed to write poster frame: {e}"))?;
+            files.poster = Some(poster);
+        }
+
+        let thumbnail_count = self.thumbnail_frames.len() as u32;
+        if thumbnail_count > 0 {
+            let (thumb_width, thumb_height) = self.thumbnail_size;
+            let columns = self.columns.min(thumbnail_count);
+            let rows = thumbnail_count.div_ceil(columns);
+            let mut sprite = RgbImage::new(columns * thumb_width, rows * thumb_height);
+
+            for index in 0..thumbnail_count {
+                let path = self.work_dir.join(thumbnail_file_name(index));
+                match image::open(&path) {
+                    Ok(thumbnail) => {
+                        let (x, y) = sprite_position(index, columns, self.thumbnail_size);
+                        imageops::replace(&mut sprite, &thumbnail.to_rgb8(), x.into(), y.into());
+                    }
+                    Err(e) => warn!("Missing storyboard thumbnail {}: {e}", path.display()),
+                }
+            }
+
+            let sprite_path = sibling_path(output_path, "sprite.jpg");
+            write_jpeg(&sprite, &sprite_path)?;
+
+            let sprite_name = sprite_path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let vtt = storyboard_vtt(
+                &sprite_name,
+                thumbnail_count,
+                self.interval_secs,
+                self.frame_count as f64 / self.fps as f64,
+                columns,
+                self.thumbnail_size,
+            );
+            let vtt_path = sibling_path(output_path, "vtt");
+            std::fs::write(&vtt_path, vtt)
+                .map_err(|e| format!("Failed to write storyboard track: {e}"))?;
+
+            files.sprite = Some(sprite_path);
+            files.vtt = Some(vtt_path);
+        }
+
+        info!(thumbnails = thumbnail_count, "Wrote storyboard");
+
+        Ok(files)
+    }
+}
+
+/// Export-relative frame of each thumbnail.
+fn thumbnail_offsets(interval_secs: f64, fps: u32, frame_count: u32) -> Vec<u32> {
+    if interval_secs <= 0.0 || frame_count == 0 {
+        return Vec::new();
+    }
+
+    let duration_secs = frame_count as f64 / fps as f64;
+    let count = (duration_secs / interval_secs).ceil() as u32;
+
+    (0..count)
+        .map(|index| {
+            ((index as f64 * interval_secs * fps as f64).round() as u32).min(frame_count - 1)
+        })
+        .collect()
+}
+
+fn sprite_position(index: u32, columns: u32, (width, height): (u32, u32)) -> (u32, u32) {
+    ((index % columns) * width, (index / columns) * height)
+}
+
+fn storyboard_vtt(
+    sprite_name: &str,
+    thumbnail_count: u32,
+    interval_secs: f64,
+    duration_secs: f64,
+    columns: u32,
+    thumbnail_size: (u32, u32),
+) -> String {
+    let mut vtt = String::from("WEBVTT\n");
+
+    for index in 0..thumbnail_count {
+        let start = index as f64 * interval_secs;
+        let end = ((index + 1) as f64 * interval_secs).min(duration_secs);
+        let (x, y) = sprite_position(index, columns, thumbnail_size);
+
+        let _ = write!(
+            vtt,
+            "\n{} --> {}\n{sprite_name}#xywh={x},{y},{},{}\n",
+            vtt_timestamp(start),
+            vtt_timestamp(end),
+            thumbnail_size.0,
+            thumbnail_size.1,
+        );
+    }
+
+    vtt
+}
+
+fn vtt_timestamp(secs: f64) -> String {
+    let millis = (secs * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        (millis / 60_000) % 60,
+        (millis / 1000) % 60,
+        millis % 1000
+    )
+}
+
+/// `recording.mp4` becomes `recording.<suffix>`.
+fn sibling_path(output_path: &Path, suffix: &str) -> PathBuf {
+    let mut file_name = output_path
+        .file_stem()
+        .map(|stem| stem.to_os_string())
+        .unwrap_or_default();
+    file_name.push(".");
+    file_name.push(suffix);
+    output_path.with_file_name(file_name)
+}
+
+fn thumbnail_file_name(index: u32) -> String {
+    format!("storyboard_{index:05}.jpg")
+}
+
+fn rgb_from_nv12(nv12_data: &[u8], width: u32, height: u32, y_stride: u32) -> Option<RgbImage> {
+    let y_plane_size = y_stride as usize * height as usize;
+    if nv12_data.len() < y_plane_size + width as usize * (height as usize / 2) {
+        return None;
+    }
+    let (y_data, uv_data) = nv12_data.split_at(y_plane_size);
+
+    let mut rgba = vec![0u8; width as usize * height as usize * 4];
+    cap_rendering::cpu_yuv::nv12_to_rgba_simd(
+        y_data, uv_data, width, height, y_stride, width, &mut rgba,
+    );
+
+    RgbImage::from_raw(
+        width,
+        height,
+        rgba.chunks_exact(4)
+            .flat_map(|px| [px[0], px[1], px[2]])
+            .collect(),
+    )
+}
+
+fn write_jpeg(image: &RgbImage, path: &Path) -> Result<(), String> {
+    let file = std::fs::File::create(path)
+        .map_err(|e| format!("Failed to create {}: {e}", path.display()))?;
+    JpegEncoder::new_with_quality(
+        std::io::BufWriter::new(file),
+        StoryboardSettings::JPEG_QUALITY,
+    )
+    .encode_image(image)
+    .map_err(|e| format!("Failed to write {}: {e}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thumbnails_cover_the_whole_export() {
+        assert_eq!(thumbnail_offsets(2.0, 30, 150), vec![0, 60, 120]);
+        assert_eq!(thumbnail_offsets(2.0, 30, 121), vec![0, 60, 120]);
+        assert_eq!(thumbnail_offsets(2.0, 30, 120), vec![0, 60]);
+        assert!(thumbnail_offsets(0.0, 30, 120).is_empty());
+    }
+
+    #[test]
+    fn vtt_cues_point_at_sprite_regions() {
+        let vtt = storyboard_vtt("clip.sprite.jpg", 3, 2.0, 5.0, 2, (160, 90));
+
+        assert_eq!(
+            vtt,
+            "WEBVTT\n\
+             \n00:00:00.000 --> 00:00:02.000\nclip.sprite.jpg#xywh=0,0,160,90\n\
+             \n00:00:02.000 --> 00:00:04.000\nclip.sprite.jpg#xywh=160,0,160,90\n\
+             \n00:00:04.000 --> 00:00:05.000\nclip.sprite.jpg#xywh=0,90,160,90\n"
+        );
+    }
+
+    #[test]
+    fn storyboard_files_sit_next_to_the_export() {
+        assert_eq!(
+            sibling_path(Path::new("/exports/clip.mp4"), "sprite.jpg"),
+            PathBuf::from("/exports/clip.sprite.jpg")
+        );
+        assert_eq!(vtt_timestamp(3723.5), "01:02:03.500");
+    }
+}
@@ -0,0 +1,306 @@
+use crate::{
+    ExportTimeRange, ExporterBase,
+    mp4::{
+        ExportAudioRenderer, ExportCompression, ExportFrame, export_render_to_channel,
+        fill_nv12_frame_direct,
+    },
+    renditions::scaled_size,
+};
+use cap_editor::{AudioRenderer, get_audio_segments};
+use cap_enc_ffmpeg::{
+    AudioEncoder,
+    aac::AACEncoder,
+    packaged_stream::{PackagedStream, PackagedStreamConfig, PackagedStreamRendition},
+};
+use cap_media_info::{RawVideoFormat, VideoInfo};
+use cap_project::XY;
+use cap_rendering::{ProjectUniforms, RenderSegment};
+use futures::FutureExt;
+use serde::Deserialize;
+use specta::Type;
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tracing::info;
+
+#[derive(Deserialize, Type, Clone, Copy, Debug, Default, PartialEq)]
+pub enum StreamLadder {
+    /// One rendition at the export resolution.
+    #[default]
+    Single,
+    /// The export resolution plus 720p and 480p renditions below it, for adaptive bitrate
+    /// playback.
+    Standard,
+}
+
+impl StreamLadder {
+    const STANDARD_HEIGHTS: [u32; 2] = [720, 480];
+
+    fn sizes(&self, source: (u32, u32)) -> Vec<(u32, u32)> {
+        let mut sizes = vec![source];
+
+        if *self == Self::Standard {
+            sizes.extend(
+                Self::STANDARD_HEIGHTS
+                    .into_iter()
+                    .filter(|height| *height < source.1)
+                    .map(|height| scaled_size(source, height)),
+            );
+        }
+
+        sizes
+    }
+}
+
+#[derive(Deserialize, Type, Clone, Copy, Debug)]
+pub struct StreamingExportSettings {
+    pub fps: u32,
+    pub resolution_base: XY<u32>,
+    pub compression: ExportCompression,
+    #[serde(default)]
+    pub custom_bpp: Option<f32>,
+    #[serde(default)]
+    pub ladder: StreamLadder,
+    /// Segment length in seconds. Kept to a multiple of the encoder's 3 second keyframe
+    /// interval so every segment starts on a keyframe.
+    #[serde(default)]
+    pub segment_duration_secs: Option<u32>,
+    #[serde(default)]
+    pub time_range: Option<ExportTimeRange>,
+}
+
+impl StreamingExportSettings {
+    pub const DEFAULT_SEGMENT_DURATION_SECS: u32 = 6;
+    const KEYFRAME_INTERVAL_SECS: u32 = 3;
+
+    pub fn effective_bpp(&self) -> f32 {
+        self.custom_bpp
+            .unwrap_or_else(|| self.compression.bits_per_pixel())
+    }
+
+    fn segment_duration(&self) -> Duration {
+        let secs = self
+            .segment_duration_secs
+            .unwrap_or(Self::DEFAULT_SEGMENT_DURATION_SECS)
+            .div_ceil(Self::KEYFRAME_INTERVAL_SECS)
+            .max(1)
+            * Self::KEYFRAME_INTERVAL_SECS;
+
+        Duration::from_secs(secs.into())
+    }
+
+    /// Packages the timeline as CMAF segments with an HLS master playlist and a DASH
+    /// manifest, in a `<name>-stream` directory next to the output path. Returns the
+    /// directory.
+    pub async fn export(
+        self,
+        mut base: ExporterBase,
+        on_progress: impl FnMut(u32) -> bool + Send + 'static,
+    ) -> Result<PathBuf, String> {
+        info!("Exporting HLS/DASH stream with settings: {:?}", &self);
+
+        base.set_time_range(self.time_range);
+        let frame_range = base.frame_range(self.fps);
+        let fps = self.fps;
+
+        let output_dir = package_dir(&base.output_path);
+
+        let output_size = ProjectUniforms::get_output_size(
+            &base.render_constants.options,
+            &base.project_config,
+            self.resolution_base,
+        );
+
+        let mut video_info =
+            VideoInfo::from_raw(RawVideoFormat::Nv12, output_size.0, output_size.1, fps);
+        video_info.time_base = ffmpeg::Rational::new(1, fps as i32);
+
+        let bpp = self.effective_bpp();
+        let config = PackagedStreamConfig {
+            segment_duration: self.segment_duration(),
+            renditions: self
+                .ladder
+                .sizes(output_size)
+                .into_iter()
+                .map(|(width, height)| PackagedStreamRendition { width, height, bpp })
+                .collect(),
+        };
+
+        let audio_segments = get_audio_segments(&base.segments);
+        let has_audio = audio_segments
+            .first()
+            .filter(|_| !base.project_config.audio.mute)
+            .is_some();
+
+        let (frame_tx, frame_rx) = std::sync::mpsc::sync_channel::<ExportFrame>(4);
+
+        let project_for_audio = base.project_config.clone();
+        let first_frame = frame_range.start;
+        let encoder_thread = tokio::task::spawn_blocking(move || {
+            let mut stream = PackagedStream::init(output_dir, video_info, config, |o| {
+                has_audio.then(|| {
+                    AACEncoder::init(AudioRenderer::info(), o)
+                        .map(|v| v.boxed())
+                        .map_err(Into::into)
+                })
+            })
+            .map_err(|e| e.to_string())?;
+
+            let mut audio_renderer = has_audio.then(|| {
+                ExportAudioRenderer::new(AudioRenderer::new(audio_segments), fps, first_frame)
+            });
+
+            let mut reusable_frame = ffmpeg::frame::Video::new(
+                ffmpeg::format::Pixel::NV12,
+                output_size.0,
+                output_size.1,
+            );
+
+            while let Ok(input) = frame_rx.recv() {
+                let audio_frame = audio_renderer.as_mut().and_then(|audio| {
+                    audio.render_for_frame(input.frame_number, &project_for_audio)
+                });
+
+                fill_nv12_frame_direct(
+                    &mut reusable_frame,
+                    &input.nv12_data,
+                    input.width,
+                    input.height,
+                    input.y_stride,
+                    input.frame_number as i64,
+                );
+                stream
+                    .queue_video_frame(&mut reusable_frame)
+                    .map_err(|err| err.to_string())?;
+                if let Some(audio) = audio_frame {
+                    stream.queue_audio_frame(audio);
+                }
+            }
+
+            let res = stream
+                .finish()
+                .map_err(|e| format!("Failed to finish encoding: {e}"))?;
+
+            if let Err(e) = res.video_finish {
+                return Err(format!("Video encoding failed: {e}"));
+            }
+            if let Err(e) = res.audio_finish {
+                return Err(format!("Audio encoding failed: {e}"));
+            }
+
+            Ok::<_, String>(stream.base_path().to_path_buf())
+        })
+        .then(|r| async { r.map_err(|e| e.to_string()).and_then(|v| v) });
+
+        let render_video_task = export_render_to_channel(
+            &base.render_constants,
+            &base.project_config,
+            frame_tx,
+            &base.recording_meta,
+            &base.studio_meta,
+            base.segments
+                .iter()
+                .map(|s| RenderSegment {
+                    cursor: s.cursor.clone(),
+                    keyboard: s.keyboard.clone(),
+                    decoders: s.decoders.clone(),
+                })
+                .collect(),
+            fps,
+            self.resolution_base,
+            &base.recordings,
+            frame_range,
+            None,
+            None,
+            on_progress,
+            base.project_path.clone(),
+        )
+        .then(|v| async { v.map_err(|e| e.to_string()) });
+
+        let (output_dir, _) = tokio::try_join!(encoder_thread, render_video_task)?;
+
+        Ok(output_dir)
+    }
+}
+
+/// The directory a package is written to: `output_path` with a `-stream` suffix, so it
+/// doesn't share a directory with an image sequence export of the same project. A
+/// playlist, manifest or MP4 extension is dropped first, in case the caller named a file
+/// instead. Other extensions are kept, since recording names often contain dots.
+fn package_dir(output_path: &Path) -> PathBuf {
+    const SUFFIX: &str = "-stream";
+
+    let path = match output_path.extension().and_then(|ext| ext.to_str()) {
+        Some("m3u8" | "mpd" | "mp4") => output_path.with_extension(""),
+        _ => output_path.to_path_buf(),
+    };
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "output".to_string());
+
+    if name.ends_with(SUFFIX) {
+        path
+    } else {
+        path.with_file_name(format!("{name}{SUFFIX}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(segment_duration_secs: Option<u32>) -> StreamingExportSettings {
+        StreamingExportSettings {
+            fps: 30,
+            resolution_base: XY::new(1920, 1080),
+            compression: ExportCompression::Web,
+            custom_bpp: None,
+            ladder: StreamLadder::Single,
+            segment_duration_secs,
+            time_range: None,
+        }
+    }
+
+    #[test]
+    fn package_dir_keeps_dots_in_the_name() {
+        assert_eq!(
+            package_dir(Path::new("/exports/Cap 2024-01-01 at 10.30.00")),
+            PathBuf::from("/exports/Cap 2024-01-01 at 10.30.00-stream")
+        );
+        assert_eq!(
+            package_dir(Path::new("/exports/recording.m3u8")),
+            PathBuf::from("/exports/recording-stream")
+        );
+        assert_eq!(
+            package_dir(Path::new("/project/output/result.mp4")),
+            PathBuf::from("/project/output/result-stream")
+        );
+        assert_eq!(
+            package_dir(Path::new("/exports/recording-stream")),
+            PathBuf::from("/exports/recording-stream")
+        );
+    }
+
+    #[test]
+    fn standard_ladder_adds_smaller_renditions() {
+        assert_eq!(
+            StreamLadder::Standard.sizes((1920, 1080)),
+            vec![(1920, 1080), (1280, 720), (852, 480)]
+        );
+        assert_eq!(
+            StreamLadder::Standard.sizes((1280, 720)),
+            vec![(1280, 720), (852, 480)]
+        );
+        assert_eq!(StreamLadder::Single.sizes((1920, 1080)), vec![(1920, 1080)]);
+    }
+
+    #[test]
+    fn segment_duration_is_a_multiple_of_the_keyframe_interval() {
+        assert_eq!(settings(None).segment_duration(), Duration::from_secs(6));
+        assert_eq!(settings(Some(4)).segment_duration(), Duration::from_secs(6));
+        assert_eq!(settings(Some(1)).segment_duration(), Duration::from_secs(3));
+        assert_eq!(settings(Some(0)).segment_duration(), Duration::from_secs(3));
+    }
+}
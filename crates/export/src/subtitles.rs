@@ -0,0 +1,140 @@
+use std::{ops::Range, time::Duration};
+
+use cap_enc_ffmpeg::mp4::{SubtitleCue, SubtitleTrack};
+use cap_project::{CaptionTrackSegment, ProjectConfiguration};
+
+/// Builds a soft subtitle track from the timeline's caption segments when the project
+/// asks for subtitles on export. Cues are rebased onto the exported `frame_range`.
+pub(crate) fn caption_subtitle_track(
+    project: &ProjectConfiguration,
+    frame_range: &Range<u32>,
+    fps: u32,
+) -> Option<SubtitleTrack> {
+    let captions = project.captions.as_ref()?;
+    if !captions.settings.export_with_subtitles {
+        return None;
+    }
+
+    let segments = &project.timeline.as_ref()?.caption_segments;
+
+    let range_start = frame_range.start as f64 / fps as f64;
+    let range_end = frame_range.end as f64 / fps as f64;
+    let cues = caption_cues(segments, range_start, range_end);
+    if cues.is_empty() {
+        return None;
+    }
+
+    Some(SubtitleTrack {
+        language: Some(
+            iso639_2_language(captions.settings.language.as_deref().unwrap_or("und")).to_string(),
+        ),
+        title: Some("Captions".to_string()),
+        cues,
+    })
+}
+
+fn caption_cues(
+    segments: &[CaptionTrackSegment],
+    range_start: f64,
+    range_end: f64,
+) -> Vec<SubtitleCue> {
+    segments
+        .iter()
+        .filter_map(|segment| {
+            let text = segment.text.trim();
+            if text.is_empty() {
+                return None;
+            }
+
+            let start = segment.start.max(range_start);
+            let end = segment.end.min(range_end);
+            if end <= start {
+                return None;
+            }
+
+            Some(SubtitleCue {
+                start: Duration::from_secs_f64(start - range_start),
+                end: Duration::from_secs_f64(end - range_start),
+                text: text.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// MP4 language tags are ISO 639-2/T; captions are tagged with the two-letter codes the
+/// transcription model uses.
+fn iso639_2_language(code: &str) -> &str {
+    match code {
+        "en" => "eng",
+        "es" => "spa",
+        "fr" => "fra",
+        "de" => "deu",
+        "it" => "ita",
+        "pt" => "por",
+        "nl" => "nld",
+        "pl" => "pol",
+        "ru" => "rus",
+        "tr" => "tur",
+        "ja" => "jpn",
+        "ko" => "kor",
+        "zh" => "zho",
+        "ar" => "ara",
+        "hi" => "hin",
+        "sv" => "swe",
+        "uk" => "ukr",
+        code if code.len() == 3 && code.bytes().all(|b| b.is_ascii_lowercase()) => code,
+        _ => "und",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(start: f64, end: f64, text: &str) -> CaptionTrackSegment {
+        CaptionTrackSegment {
+            id: format!("{start}"),
+            start,
+            end,
+            text: text.to_string(),
+            words: vec![],
+            fade_duration_override: None,
+            linger_duration_override: None,
+            position_override: None,
+            color_override: None,
+            background_color_override: None,
+            font_size_override: None,
+        }
+    }
+
+    #[test]
+    fn cues_are_clipped_and_rebased_to_range() {
+        let segments = vec![
+            segment(0.0, 1.0, "before"),
+            segment(1.5, 3.0, "straddles start"),
+            segment(4.0, 5.0, " inside "),
+            segment(9.0, 12.0, "straddles end"),
+            segment(12.0, 13.0, "after"),
+            segment(5.0, 6.0, "   "),
+        ];
+
+        let cues = caption_cues(&segments, 2.0, 10.0);
+
+        assert_eq!(cues.len(), 3);
+        assert_eq!(cues[0].start, Duration::ZERO);
+        assert_eq!(cues[0].end, Duration::from_secs(1));
+        assert_eq!(cues[1].text, "inside");
+        assert_eq!(cues[1].start, Duration::from_secs(2));
+        assert_eq!(cues[2].start, Duration::from_secs(7));
+        assert_eq!(cues[2].end, Duration::from_secs(8));
+    }
+
+    #[test]
+    fn language_codes_map_to_iso639_2() {
+        assert_eq!(iso639_2_language("en"), "eng");
+        assert_eq!(iso639_2_language("zh"), "zho");
+        assert_eq!(iso639_2_language("fin"), "fin");
+        assert_eq!(iso639_2_language("auto"), "und");
+        assert_eq!(iso639_2_language("EN-us"), "und");
+    }
+}
@@ -0,0 +1,256 @@
+use crate::{
+    ExportTimeRange, ExporterBase,
+    mp4::{
+        ExportAudioRenderer, ExportCompression, ExportFrame, audio_stem_tracks,
+        export_render_to_channel, fill_nv12_frame_direct,
+    },
+};
+use cap_editor::{AudioRenderer, get_audio_segments};
+use cap_enc_ffmpeg::{
+    AudioEncoder,
+    av1::Av1Encoder,
+    opus::OpusEncoder,
+    vp9::Vp9Encoder,
+    webm::{WebMFile, WebMVideoEncoder},
+};
+use cap_media_info::{RawVideoFormat, VideoInfo};
+use cap_project::XY;
+use cap_rendering::{ProjectUniforms, RenderSegment};
+use futures::FutureExt;
+use serde::Deserialize;
+use specta::Type;
+use std::{path::PathBuf, time::Duration};
+use tracing::{info, trace};
+
+#[derive(Deserialize, Type, Clone, Copy, Debug, Default, PartialEq)]
+pub enum WebmVideoCodec {
+    #[default]
+    Vp9,
+    Av1,
+}
+
+impl WebmVideoCodec {
+    /// Bits-per-pixel relative to H.264 at the same perceived quality.
+    pub fn bpp_scale(&self) -> f32 {
+        match self {
+            Self::Vp9 => 0.7,
+            Self::Av1 => 0.5,
+        }
+    }
+}
+
+#[derive(Deserialize, Type, Clone, Copy, Debug)]
+pub struct WebmExportSettings {
+    pub fps: u32,
+    pub resolution_base: XY<u32>,
+    pub compression: ExportCompression,
+    pub custom_bpp: Option<f32>,
+    #[serde(default)]
+    pub codec: WebmVideoCodec,
+    #[serde(default)]
+    pub crf: Option<u8>,
+    /// Also write the microphone and system audio as their own labelled tracks after the
+    /// mix, for editing them separately.
+    #[serde(default)]
+    pub audio_stems: bool,
+    #[serde(default)]
+    pub time_range: Option<ExportTimeRange>,
+}
+
+impl WebmExportSettings {
+    pub fn effective_bpp(&self) -> f32 {
+        self.custom_bpp
+            .unwrap_or_else(|| self.compression.bits_per_pixel())
+            * self.codec.bpp_scale()
+    }
+
+    pub async fn export(
+        self,
+        mut base: ExporterBase,
+        on_progress: impl FnMut(u32) -> bool + Send + 'static,
+    ) -> Result<PathBuf, String> {
+        info!("Exporting webm with settings: {:?}", &self);
+
+        base.set_time_range(self.time_range);
+        let frame_range = base.frame_range(self.fps);
+        info!(
+            "Expected to render {} frames",
+            frame_range.end - frame_range.start
+        );
+
+        let output_path = base.output_path.clone();
+        let fps = self.fps;
+
+        let output_size = ProjectUniforms::get_output_size(
+            &base.render_constants.options,
+            &base.project_config,
+            self.resolution_base,
+        );
+
+        let (frame_tx, frame_rx) = std::sync::mpsc::sync_channel::<ExportFrame>(4);
+
+        let mut video_info =
+            VideoInfo::from_raw(RawVideoFormat::Nv12, output_size.0, output_size.1, fps);
+        video_info.time_base = ffmpeg::Rational::new(1, fps as i32);
+
+        let audio_segments = get_audio_segments(&base.segments);
+
+        let has_audio = audio_segments
+            .first()
+            .filter(|_| !base.project_config.audio.mute)
+            .is_some();
+
+        let (stem_segments, audio_titles) =
+            audio_stem_tracks(&base.segments, self.audio_stems && has_audio);
+        let stem_count = stem_segments.len();
+
+        let project_for_audio = base.project_config.clone();
+        let codec = self.codec;
+        let crf = self.crf;
+        let effective_bpp = self.effective_bpp();
+        let encoder_thread = tokio::task::spawn_blocking(move || {
+            trace!("Creating WebMFile encoder");
+
+            let mut muxer = WebMFile::init_with_audio_stems(
+                "output",
+                base.output_path.clone(),
+                &audio_titles,
+                |o| match codec {
+                    WebmVideoCodec::Vp9 => {
+                        let mut builder = Vp9Encoder::builder(video_info).with_bpp(effective_bpp);
+                        if let Some(crf) = crf {
+                            builder = builder.with_crf(crf);
+                        }
+                        builder
+                            .build(o)
+                            .map(WebMVideoEncoder::from)
+                            .map_err(Into::into)
+                    }
+                    WebmVideoCodec::Av1 => {
+                        let mut builder = Av1Encoder::builder(video_info).with_bpp(effective_bpp);
+                        if let Some(crf) = crf {
+                            builder = builder.with_crf(crf);
+                        }
+                        builder
+                            .build(o)
+                            .map(WebMVideoEncoder::from)
+                            .map_err(Into::into)
+                    }
+                },
+                |o| {
+                    has_audio.then(|| {
+                        OpusEncoder::init(AudioRenderer::info(), o)
+                            .map(|v| v.boxed())
+                            .map_err(Into::into)
+                    })
+                },
+                |o| {
+                    (0..stem_count)
+                        .map(|_| {
+                            OpusEncoder::init(AudioRenderer::info(), o)
+                                .map(|v| v.boxed())
+                                .map_err(Into::into)
+                        })
+                        .collect()
+                },
+            )
+            .map_err(|v| v.to_string())?;
+
+            info!(codec = ?codec, "Created webm encoder");
+
+            let mut audio_renderer = has_audio.then(|| {
+                ExportAudioRenderer::new(AudioRenderer::new(audio_segments), fps, frame_range.start)
+            });
+            let mut stem_renderers = stem_segments
+                .into_iter()
+                .map(|segments| {
+                    ExportAudioRenderer::new(AudioRenderer::new(segments), fps, frame_range.start)
+                })
+                .collect::<Vec<_>>();
+
+            let mut reusable_frame = ffmpeg::frame::Video::new(
+                ffmpeg::format::Pixel::NV12,
+                output_size.0,
+                output_size.1,
+            );
+            let mut converted_frame: Option<ffmpeg::frame::Video> = None;
+
+            while let Ok(input) = frame_rx.recv() {
+                let audio_frame = audio_renderer.as_mut().and_then(|audio| {
+                    audio.render_for_frame(input.frame_number, &project_for_audio)
+                });
+                let stem_frames = stem_renderers
+                    .iter_mut()
+                    .map(|stem| stem.render_for_frame(input.frame_number, &project_for_audio))
+                    .collect::<Vec<_>>();
+
+                fill_nv12_frame_direct(
+                    &mut reusable_frame,
+                    &input.nv12_data,
+                    input.width,
+                    input.height,
+                    input.y_stride,
+                    input.frame_number as i64,
+                );
+                muxer
+                    .queue_video_frame_reusable(
+                        &mut reusable_frame,
+                        &mut converted_frame,
+                        Duration::MAX,
+                    )
+                    .map_err(|err| err.to_string())?;
+                if let Some(audio) = audio_frame {
+                    muxer.queue_audio_frame(audio);
+                }
+                for (index, audio) in stem_frames.into_iter().enumerate() {
+                    if let Some(audio) = audio {
+                        muxer.queue_audio_stem_frame(index, audio);
+                    }
+                }
+            }
+
+            let res = muxer
+                .finish()
+                .map_err(|e| format!("Failed to finish encoding: {e}"))?;
+
+            if let Err(e) = res.video_finish {
+                return Err(format!("Video encoding failed: {e}"));
+            }
+            if let Err(e) = res.audio_finish {
+                return Err(format!("Audio encoding failed: {e}"));
+            }
+
+            Ok::<_, String>(base.output_path)
+        })
+        .then(|r| async { r.map_err(|e| e.to_string()).and_then(|v| v) });
+
+        let render_video_task = export_render_to_channel(
+            &base.render_constants,
+            &base.project_config,
+            frame_tx,
+            &base.recording_meta,
+            &base.studio_meta,
+            base.segments
+                .iter()
+                .map(|s| RenderSegment {
+                    cursor: s.cursor.clone(),
+                    keyboard: s.keyboard.clone(),
+                    decoders: s.decoders.clone(),
+                })
+                .collect(),
+            fps,
+            self.resolution_base,
+            &base.recordings,
+            frame_range.clone(),
+            None,
+            None,
+            on_progress,
+            base.project_path.clone(),
+        )
+        .then(|v| async { v.map_err(|e| e.to_string()) });
+
+        tokio::try_join!(encoder_thread, render_video_task)?;
+
+        Ok(output_path)
+    }
+}
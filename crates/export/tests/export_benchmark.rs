@@ -11,7 +11,7 @@ use std::{
 
 use cap_export::{
     ExporterBase,
-    mp4::{ExportCompression, Mp4ExportSettings},
+    mp4::{ExportCompression, Mp4ExportSettings, Mp4VideoCodec},
 };
 use cap_project::XY;
 
@@ -27,6 +27,16 @@ async fn run_export(project_path: PathBuf) -> Result<(PathBuf, Duration, u32), S
         compression: ExportCompression::Maximum,
         custom_bpp: None,
         force_ffmpeg_decoder: false,
+        crf: None,
+        target_size_mb: None,
+        metadata: None,
+        storyboard: None,
+        loudness: None,
+        audio_stems: false,
+        video_codec: Mp4VideoCodec::Auto,
+        hdr: None,
+        resumable: false,
+        time_range: None,
     };
 
     let start = Instant::now();
@@ -1,6 +1,6 @@
 use cap_export::{
     ExporterBase,
-    mp4::{ExportCompression, Mp4ExportSettings},
+    mp4::{ExportCompression, Mp4ExportSettings, Mp4VideoCodec},
 };
 use cap_project::XY;
 use std::{
@@ -132,6 +132,16 @@ async fn run_export(project_path: PathBuf, fps: u32) -> Result<(PathBuf, Duratio
         compression: ExportCompression::Potato,
         custom_bpp: None,
         force_ffmpeg_decoder: false,
+        crf: None,
+        target_size_mb: None,
+        metadata: None,
+        storyboard: None,
+        loudness: None,
+        audio_stems: false,
+        video_codec: Mp4VideoCodec::Auto,
+        hdr: None,
+        resumable: false,
+        time_range: None,
     };
 
     let total_frames = exporter_base.total_frames(fps);
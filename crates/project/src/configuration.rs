@@ -213,6 +213,19 @@ impl Crop {
     }
 }
 
+impl AspectRatio {
+    /// Width over height.
+    pub fn value(&self) -> f32 {
+        match self {
+            Self::Wide => 16.0 / 9.0,
+            Self::Vertical => 9.0 / 16.0,
+            Self::Square => 1.0,
+            Self::Classic => 4.0 / 3.0,
+            Self::Tall => 3.0 / 4.0,
+        }
+    }
+}
+
 #[derive(Type, Serialize, Deserialize, Clone, Debug)]
 #[serde(default)]
 pub struct ShadowConfiguration {
@@ -817,6 +830,17 @@ pub struct TimelineConfiguration {
     pub caption_segments: Vec<CaptionTrackSegment>,
     #[serde(default)]
     pub keyboard_segments: Vec<crate::KeyboardTrackSegment>,
+    #[serde(default)]
+    pub markers: Vec<TimelineMarker>,
+}
+
+/// A named point on the timeline, exported as the start of a chapter.
+#[derive(Type, Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TimelineMarker {
+    pub id: String,
+    pub time: f64,
+    pub title: String,
 }
 
 #[derive(Type, Serialize, Deserialize, Clone, Debug)]
@@ -927,6 +951,8 @@ pub struct CaptionSettings {
     pub word_transition_duration: f32,
     #[serde(alias = "activeWordHighlight")]
     pub active_word_highlight: bool,
+    /// Language of the caption text (e.g. `en`), used to tag exported subtitle tracks.
+    pub language: Option<String>,
 }
 
 impl CaptionSettings {
@@ -975,6 +1001,7 @@ impl Default for CaptionSettings {
             linger_duration: Self::default_linger_duration(),
             word_transition_duration: Self::default_word_transition_duration(),
             active_word_highlight: Self::default_active_word_highlight(),
+            language: None,
         }
     }
 }
@@ -1188,6 +1215,11 @@ pub struct ProjectConfiguration {
     pub screen_motion_blur: f32,
     #[serde(default)]
     pub screen_movement_spring: ScreenMovementSpring,
+    /// Instead of fitting the whole crop into a narrower aspect ratio, show a full-height
+    /// window of it that pans to follow the cursor. Turns landscape recordings into
+    /// vertical clips without keyframing.
+    #[serde(default)]
+    pub auto_reframe: bool,
 }
 
 fn camera_config_needs_migration(value: &Value) -> bool {
@@ -50,6 +50,7 @@ pub enum VideoCodec {
     #[default]
     H264,
     H265,
+    Av1,
 }
 
 pub struct InstantModeConfig {
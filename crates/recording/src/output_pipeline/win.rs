@@ -184,6 +184,7 @@ impl Muxer for WindowsMuxer {
                 enum SwEncoder {
                     H264(cap_enc_ffmpeg::h264::H264Encoder),
                     Hevc(cap_enc_ffmpeg::hevc::HevcEncoder),
+                    Av1(cap_enc_ffmpeg::av1::Av1Encoder),
                 }
 
                 let codec = config.codec;
@@ -200,14 +201,7 @@ impl Muxer for WindowsMuxer {
                         video_config.height
                     };
 
-                    let sw_fallback = |reason: Option<String>| -> anyhow::Result<either::Either<HwEncoder, SwEncoder>> {
-                        encoder_preferences.force_software_only();
-                        if let Some(reason) = reason.as_ref() {
-                            error!("Falling back to software encoder: {reason}");
-                        } else {
-                            info!("Falling back to software encoder");
-                        }
-
+                    let sw_encoder = || -> anyhow::Result<either::Either<HwEncoder, SwEncoder>> {
                         let mut output_guard = output.lock().map_err(|e| {
                             anyhow!("ScreenSoftwareEncoder: failed to lock output mutex: {e}")
                         })?;
@@ -225,7 +219,24 @@ impl Muxer for WindowsMuxer {
                                 .and_then(|b| b.build(&mut output_guard))
                                 .map(|e| either::Right(SwEncoder::Hevc(e)))
                                 .map_err(|e| anyhow!("ScreenSoftwareEncoder/{e}")),
+                            VideoCodec::Av1 => cap_enc_ffmpeg::av1::Av1Encoder::builder(video_config)
+                                .with_bpp(config.bitrate_multiplier)
+                                .with_output_size(fallback_width, fallback_height)
+                                .and_then(|b| b.build(&mut output_guard))
+                                .map(|e| either::Right(SwEncoder::Av1(e)))
+                                .map_err(|e| anyhow!("ScreenSoftwareEncoder/{e}")),
+                        }
+                    };
+
+                    let sw_fallback = |reason: Option<String>| -> anyhow::Result<either::Either<HwEncoder, SwEncoder>> {
+                        encoder_preferences.force_software_only();
+                        if let Some(reason) = reason.as_ref() {
+                            error!("Falling back to software encoder: {reason}");
+                        } else {
+                            info!("Falling back to software encoder");
                         }
+
+                        sw_encoder()
                     };
 
                     if encoder_preferences.should_force_software() {
@@ -298,6 +309,10 @@ impl Muxer for WindowsMuxer {
                                 Err(err) => sw_fallback(Some(err.to_string())),
                             }
                         }
+                        // Media Foundation has no AV1 encoder here. The FFmpeg AV1 builder tries
+                        // the GPU encoders itself before the software ones, so it doesn't count as
+                        // a fallback.
+                        VideoCodec::Av1 => sw_encoder(),
                     }
                 })();
 
@@ -575,6 +590,11 @@ impl Muxer for WindowsMuxer {
                                         .queue_frame_reusable(&mut reusable_frame, &mut converted_frame, normalized_ts, &mut output)
                                         .context("queue_frame")?;
                                 }
+                                SwEncoder::Av1(encoder) => {
+                                    encoder
+                                        .queue_frame_reusable(&mut reusable_frame, &mut converted_frame, normalized_ts, &mut output)
+                                        .context("queue_frame")?;
+                                }
                             }
 
                             drop(output);
@@ -997,6 +997,7 @@ impl RecoveryManager {
             text_segments: Vec::new(),
             caption_segments: Vec::new(),
             keyboard_segments: Vec::new(),
+            markers: Vec::new(),
         });
 
         config
@@ -22,6 +22,7 @@ use specta::Type;
 use spring_mass_damper::SpringMassDamperSimulationConfig;
 use std::{
     collections::HashMap,
+    ops::Range,
     sync::{Arc, Mutex},
 };
 use std::{path::PathBuf, time::Instant};
@@ -389,6 +390,29 @@ impl RecordingSegmentDecoders {
         })
     }
 
+    /// Just the camera frame for `segment_time`, for exporting the camera on its own.
+    pub async fn get_camera_frame(
+        &self,
+        segment_time: f32,
+        offsets: ClipOffsets,
+    ) -> Option<DecodedFrame> {
+        self.camera
+            .as_ref()?
+            .get_frame(segment_time + offsets.camera)
+            .await
+    }
+
+    pub async fn get_camera_frame_initial(
+        &self,
+        segment_time: f32,
+        offsets: ClipOffsets,
+    ) -> Option<DecodedFrame> {
+        self.camera
+            .as_ref()?
+            .get_frame_initial(segment_time + offsets.camera)
+            .await
+    }
+
     pub fn screen_video_dimensions(&self) -> (u32, u32) {
         self.screen.video_dimensions()
     }
@@ -444,6 +468,7 @@ pub async fn render_video_to_channel(
     fps: u32,
     resolution_base: XY<u32>,
     recordings: &ProjectRecordingsMeta,
+    frame_range: Option<Range<u32>>,
 ) -> Result<(), RenderingError> {
     ffmpeg::init().unwrap();
 
@@ -452,6 +477,10 @@ pub async fn render_video_to_channel(
     let duration = get_duration(recordings, recording_meta, meta, project);
 
     let total_frames = (fps as f64 * duration).ceil() as u32;
+    let frame_range = frame_range
+        .map(|range| range.start.min(total_frames)..range.end.min(total_frames))
+        .unwrap_or(0..total_frames);
+    let first_frame = frame_range.start;
 
     let cursor_smoothing =
         (!project.cursor.raw).then_some(spring_mass_damper::SpringMassDamperSimulationConfig {
@@ -493,7 +522,7 @@ pub async fn render_video_to_channel(
         })
         .collect();
 
-    let mut frame_number = 0;
+    let mut frame_number = first_frame;
 
     let mut frame_renderer = FrameRenderer::new(constants);
 
@@ -523,7 +552,7 @@ pub async fn render_video_to_channel(
     let mut prefetched_decode: Option<(u32, f64, usize, Option<DecodedSegmentFrames>)> = None;
 
     loop {
-        if frame_number >= total_frames {
+        if frame_number >= frame_range.end {
             break;
         }
 
@@ -542,9 +571,11 @@ pub async fn render_video_to_channel(
             let prev = frame_number;
             std::mem::replace(&mut frame_number, prev + 1)
         };
+        let output_frame_number = current_frame_number - first_frame;
 
         let render_segment = &render_segments[segment.recording_clip as usize];
-        let is_initial_frame = current_frame_number == 0 || last_successful_frame.is_none();
+        let is_initial_frame =
+            current_frame_number == first_frame || last_successful_frame.is_none();
         let segment_clip_index = segment.recording_clip as usize;
 
         let zoom_until = (current_frame_number as f32 + 1.0) / fps as f32;
@@ -598,7 +629,7 @@ pub async fn render_video_to_channel(
 
             let next_frame_number = frame_number;
             let mut next_prefetch_meta: Option<(f64, usize)> = None;
-            let prefetch_future = if next_frame_number < total_frames {
+            let prefetch_future = if next_frame_number < frame_range.end {
                 if let Some((next_seg_time, next_segment)) =
                     project.get_segment_time(next_frame_number as f64 / fps as f64)
                 {
@@ -655,9 +686,15 @@ pub async fn render_video_to_channel(
             };
 
             match render_result {
-                Ok(Some(frame)) if frame.width > 0 && frame.height > 0 => {
+                Ok(Some(mut frame)) if frame.width > 0 && frame.height > 0 => {
+                    rebase_frame_number(
+                        &mut frame.frame_number,
+                        &mut frame.target_time_ns,
+                        first_frame,
+                        fps,
+                    );
                     last_successful_frame = Some(frame.clone());
-                    sender.send((frame, current_frame_number)).await?;
+                    sender.send((frame, output_frame_number)).await?;
                 }
                 Ok(Some(_)) => {
                     tracing::warn!(
@@ -666,10 +703,10 @@ pub async fn render_video_to_channel(
                     );
                     if let Some(ref last_frame) = last_successful_frame {
                         let mut fallback = last_frame.clone();
-                        fallback.frame_number = current_frame_number;
+                        fallback.frame_number = output_frame_number;
                         fallback.target_time_ns =
-                            (current_frame_number as u64 * 1_000_000_000) / fps as u64;
-                        sender.send((fallback, current_frame_number)).await?;
+                            (output_frame_number as u64 * 1_000_000_000) / fps as u64;
+                        sender.send((fallback, output_frame_number)).await?;
                     }
                 }
                 Ok(None) => {}
@@ -681,10 +718,10 @@ pub async fn render_video_to_channel(
                     );
                     if let Some(ref last_frame) = last_successful_frame {
                         let mut fallback = last_frame.clone();
-                        fallback.frame_number = current_frame_number;
+                        fallback.frame_number = output_frame_number;
                         fallback.target_time_ns =
-                            (current_frame_number as u64 * 1_000_000_000) / fps as u64;
-                        sender.send((fallback, current_frame_number)).await?;
+                            (output_frame_number as u64 * 1_000_000_000) / fps as u64;
+                        sender.send((fallback, output_frame_number)).await?;
                     } else {
                         return Err(e);
                     }
@@ -718,10 +755,9 @@ pub async fn render_video_to_channel(
                     "Frame decode failed after retries - using previous frame"
                 );
                 let mut fallback = last_frame.clone();
-                fallback.frame_number = current_frame_number;
-                fallback.target_time_ns =
-                    (current_frame_number as u64 * 1_000_000_000) / fps as u64;
-                sender.send((fallback, current_frame_number)).await?;
+                fallback.frame_number = output_frame_number;
+                fallback.target_time_ns = (output_frame_number as u64 * 1_000_000_000) / fps as u64;
+                sender.send((fallback, output_frame_number)).await?;
             } else {
                 tracing::error!(
                     frame_number = current_frame_number,
@@ -738,12 +774,21 @@ pub async fn render_video_to_channel(
         }
     }
 
-    if let Some(Ok(final_frame)) = frame_renderer.flush_pipeline().await
+    if let Some(Ok(mut final_frame)) = frame_renderer.flush_pipeline().await
         && final_frame.width > 0
         && final_frame.height > 0
     {
+        rebase_frame_number(
+            &mut final_frame.frame_number,
+            &mut final_frame.target_time_ns,
+            first_frame,
+            fps,
+        );
         sender
-            .send((final_frame, frame_number.saturating_sub(1)))
+            .send((
+                final_frame,
+                frame_number.saturating_sub(1).saturating_sub(first_frame),
+            ))
             .await?;
     }
 
@@ -768,6 +813,7 @@ pub async fn render_video_to_channel_nv12(
     fps: u32,
     resolution_base: XY<u32>,
     recordings: &ProjectRecordingsMeta,
+    frame_range: Option<Range<u32>>,
     stop_after_frames_sent: Option<u32>,
     startup_breakdown_ms: Option<Arc<Mutex<Option<Nv12RenderStartupBreakdownMs>>>>,
 ) -> Result<(), RenderingError> {
@@ -780,6 +826,10 @@ pub async fn render_video_to_channel_nv12(
     let duration = get_duration(recordings, recording_meta, meta, project);
 
     let total_frames = (fps as f64 * duration).ceil() as u32;
+    let frame_range = frame_range
+        .map(|range| range.start.min(total_frames)..range.end.min(total_frames))
+        .unwrap_or(0..total_frames);
+    let first_frame = frame_range.start;
 
     let cursor_smoothing =
         (!project.cursor.raw).then_some(spring_mass_damper::SpringMassDamperSimulationConfig {
@@ -826,7 +876,7 @@ pub async fn render_video_to_channel_nv12(
     }
     let zoom_focus_interpolators_construct_ms = zoom_build_start.elapsed().as_millis() as u64;
 
-    let mut frame_number = 0;
+    let mut frame_number = first_frame;
 
     let renderer_setup_start = Instant::now();
     let mut frame_renderer = FrameRenderer::new(constants);
@@ -864,7 +914,7 @@ pub async fn render_video_to_channel_nv12(
     let mut record_first_frame_nv12_phases = startup_breakdown_ms.is_some();
 
     loop {
-        if frame_number >= total_frames {
+        if frame_number >= frame_range.end {
             break;
         }
 
@@ -883,9 +933,11 @@ pub async fn render_video_to_channel_nv12(
             let prev = frame_number;
             std::mem::replace(&mut frame_number, prev + 1)
         };
+        let output_frame_number = current_frame_number - first_frame;
 
         let render_segment = &render_segments[segment.recording_clip as usize];
-        let is_initial_frame = current_frame_number == 0 || last_successful_frame.is_none();
+        let is_initial_frame =
+            current_frame_number == first_frame || last_successful_frame.is_none();
         let segment_clip_index = segment.recording_clip as usize;
 
         let zoom_pre_start = Instant::now();
@@ -943,7 +995,7 @@ pub async fn render_video_to_channel_nv12(
 
             let next_frame_number = frame_number;
             let mut next_prefetch_meta: Option<(f64, usize)> = None;
-            let prefetch_future = if next_frame_number < total_frames {
+            let prefetch_future = if next_frame_number < frame_range.end {
                 if let Some((next_seg_time, next_segment)) =
                     project.get_segment_time(next_frame_number as f64 / fps as f64)
                 {
@@ -1054,7 +1106,7 @@ pub async fn render_video_to_channel_nv12(
                 (render, None, None, None)
             };
 
-            if current_frame_number == 0
+            if current_frame_number == first_frame
                 && let Some(ref slot) = startup_breakdown_ms
                 && let Ok(mut guard) = slot.lock()
             {
@@ -1071,7 +1123,13 @@ pub async fn render_video_to_channel_nv12(
             }
 
             match render_result {
-                Ok(Some(frame)) if frame.width > 0 && frame.height > 0 => {
+                Ok(Some(mut frame)) if frame.width > 0 && frame.height > 0 => {
+                    rebase_frame_number(
+                        &mut frame.frame_number,
+                        &mut frame.target_time_ns,
+                        first_frame,
+                        fps,
+                    );
                     if record_first_frame_nv12_phases {
                         if let Some(ref slot) = startup_breakdown_ms
                             && let Ok(mut guard) = slot.lock()
@@ -1090,7 +1148,7 @@ pub async fn render_video_to_channel_nv12(
                         record_first_frame_nv12_phases = false;
                     }
                     last_successful_frame = Some(frame.clone_metadata_with_data());
-                    sender.send((frame, current_frame_number)).await?;
+                    sender.send((frame, output_frame_number)).await?;
                     channel_frames_sent += 1;
                     if stop_after_frames_sent.is_some_and(|m| channel_frames_sent >= m) {
                         stopped_after_frame_limit = true;
@@ -1104,10 +1162,10 @@ pub async fn render_video_to_channel_nv12(
                     );
                     if let Some(ref last_frame) = last_successful_frame {
                         let mut fallback = last_frame.clone_metadata_with_data();
-                        fallback.frame_number = current_frame_number;
+                        fallback.frame_number = output_frame_number;
                         fallback.target_time_ns =
-                            (current_frame_number as u64 * 1_000_000_000) / fps as u64;
-                        sender.send((fallback, current_frame_number)).await?;
+                            (output_frame_number as u64 * 1_000_000_000) / fps as u64;
+                        sender.send((fallback, output_frame_number)).await?;
                         channel_frames_sent += 1;
                         if stop_after_frames_sent.is_some_and(|m| channel_frames_sent >= m) {
                             stopped_after_frame_limit = true;
@@ -1124,10 +1182,10 @@ pub async fn render_video_to_channel_nv12(
                     );
                     if let Some(ref last_frame) = last_successful_frame {
                         let mut fallback = last_frame.clone_metadata_with_data();
-                        fallback.frame_number = current_frame_number;
+                        fallback.frame_number = output_frame_number;
                         fallback.target_time_ns =
-                            (current_frame_number as u64 * 1_000_000_000) / fps as u64;
-                        sender.send((fallback, current_frame_number)).await?;
+                            (output_frame_number as u64 * 1_000_000_000) / fps as u64;
+                        sender.send((fallback, output_frame_number)).await?;
                         channel_frames_sent += 1;
                         if stop_after_frames_sent.is_some_and(|m| channel_frames_sent >= m) {
                             stopped_after_frame_limit = true;
@@ -1166,10 +1224,9 @@ pub async fn render_video_to_channel_nv12(
                     "Frame decode failed after retries - using previous NV12 frame"
                 );
                 let mut fallback = last_frame.clone_metadata_with_data();
-                fallback.frame_number = current_frame_number;
-                fallback.target_time_ns =
-                    (current_frame_number as u64 * 1_000_000_000) / fps as u64;
-                sender.send((fallback, current_frame_number)).await?;
+                fallback.frame_number = output_frame_number;
+                fallback.target_time_ns = (output_frame_number as u64 * 1_000_000_000) / fps as u64;
+                sender.send((fallback, output_frame_number)).await?;
                 channel_frames_sent += 1;
                 if stop_after_frames_sent.is_some_and(|m| channel_frames_sent >= m) {
                     stopped_after_frame_limit = true;
@@ -1192,12 +1249,21 @@ pub async fn render_video_to_channel_nv12(
     }
 
     if !stopped_after_frame_limit
-        && let Some(Ok(final_frame)) = frame_renderer.flush_pipeline_nv12().await
+        && let Some(Ok(mut final_frame)) = frame_renderer.flush_pipeline_nv12().await
         && final_frame.width > 0
         && final_frame.height > 0
     {
+        rebase_frame_number(
+            &mut final_frame.frame_number,
+            &mut final_frame.target_time_ns,
+            first_frame,
+            fps,
+        );
         sender
-            .send((final_frame, frame_number.saturating_sub(1)))
+            .send((
+                final_frame,
+                frame_number.saturating_sub(1).saturating_sub(first_frame),
+            ))
             .await?;
     }
 
@@ -1211,6 +1277,22 @@ pub async fn render_video_to_channel_nv12(
     Ok(())
 }
 
+/// Renumbers a frame rendered at timeline frame `frame_number` so output starts at zero
+/// when only part of the timeline is being rendered.
+fn rebase_frame_number(
+    frame_number: &mut u32,
+    target_time_ns: &mut u64,
+    first_frame: u32,
+    fps: u32,
+) {
+    if first_frame == 0 {
+        return;
+    }
+
+    *frame_number = frame_number.saturating_sub(first_frame);
+    *target_time_ns = (*frame_number as u64 * 1_000_000_000) / fps.max(1) as u64;
+}
+
 const DECODE_MAX_RETRIES_INITIAL: u32 = 5;
 const DECODE_MAX_RETRIES_STEADY: u32 = 2;
 
@@ -1791,6 +1873,36 @@ impl ProjectUniforms {
         })
     }
 
+    /// For auto-reframe: a full-height window of the crop at the project's aspect ratio,
+    /// centred horizontally on `focus` and kept inside the crop. The window has the same
+    /// height as the crop, so the output size is the same as fitting the whole crop.
+    pub fn get_reframed_crop(
+        options: &RenderOptions,
+        project: &ProjectConfiguration,
+        focus: Coord<RawDisplayUVSpace>,
+    ) -> Crop {
+        let crop = Self::get_crop(options, project);
+
+        let Some(aspect) = project.aspect_ratio.as_ref().map(AspectRatio::value) else {
+            return crop;
+        };
+        if aspect >= crop.aspect_ratio() {
+            return crop;
+        }
+
+        let width = ((crop.size.y as f32 * aspect) as u32).min(crop.size.x);
+        let focus_x = focus.x * options.screen_size.x as f64;
+        let max_x = crop.position.x + crop.size.x - width;
+        let x = (focus_x - width as f64 / 2.0)
+            .round()
+            .clamp(crop.position.x as f64, max_x as f64) as u32;
+
+        Crop {
+            position: XY::new(x, crop.position.y),
+            size: XY::new(width, crop.size.y),
+        }
+    }
+
     #[allow(unused)]
     fn get_padding(options: &RenderOptions, project: &ProjectConfiguration) -> f64 {
         let crop = Self::get_crop(options, project);
@@ -1802,7 +1914,11 @@ impl ProjectUniforms {
     }
 
     pub fn get_base_size(options: &RenderOptions, project: &ProjectConfiguration) -> (u32, u32) {
-        let crop = Self::get_crop(options, project);
+        Self::base_size_for_crop(&Self::get_crop(options, project), project)
+    }
+
+    /// [`Self::get_base_size`] for a crop other than the project's, such as auto-reframe's.
+    fn base_size_for_crop(crop: &Crop, project: &ProjectConfiguration) -> (u32, u32) {
         let crop_aspect = crop.aspect_ratio();
 
         match &project.aspect_ratio {
@@ -1858,7 +1974,15 @@ impl ProjectUniforms {
         project: &ProjectConfiguration,
         resolution_base: XY<u32>,
     ) -> (u32, u32) {
-        let (base_width, base_height) = Self::get_base_size(options, project);
+        Self::output_size_for_crop(&Self::get_crop(options, project), project, resolution_base)
+    }
+
+    fn output_size_for_crop(
+        crop: &Crop,
+        project: &ProjectConfiguration,
+        resolution_base: XY<u32>,
+    ) -> (u32, u32) {
+        let (base_width, base_height) = Self::base_size_for_crop(crop, project);
 
         let width_scale = resolution_base.x as f32 / base_width as f32;
         let height_scale = resolution_base.y as f32 / base_height as f32;
@@ -1874,13 +1998,19 @@ impl ProjectUniforms {
         project: &ProjectConfiguration,
         resolution_base: XY<u32>,
     ) -> Coord<FrameSpace> {
-        let output_size = Self::get_output_size(options, project, resolution_base);
+        Self::display_offset_for_crop(&Self::get_crop(options, project), project, resolution_base)
+    }
+
+    fn display_offset_for_crop(
+        crop: &Crop,
+        project: &ProjectConfiguration,
+        resolution_base: XY<u32>,
+    ) -> Coord<FrameSpace> {
+        let output_size = Self::output_size_for_crop(crop, project, resolution_base);
         let output_size = XY::new(output_size.0 as f64, output_size.1 as f64);
 
         let output_aspect = output_size.x / output_size.y;
 
-        let crop = Self::get_crop(options, project);
-
         let crop_start =
             Coord::<RawDisplaySpace>::new(XY::new(crop.position.x as f64, crop.position.y as f64));
         let crop_end = Coord::<RawDisplaySpace>::new(XY::new(
@@ -1929,10 +2059,18 @@ impl ProjectUniforms {
         project: &ProjectConfiguration,
         resolution_base: XY<u32>,
     ) -> Coord<FrameSpace> {
-        let output_size = Self::get_output_size(options, project, resolution_base);
+        Self::display_size_for_crop(&Self::get_crop(options, project), project, resolution_base)
+    }
+
+    fn display_size_for_crop(
+        crop: &Crop,
+        project: &ProjectConfiguration,
+        resolution_base: XY<u32>,
+    ) -> Coord<FrameSpace> {
+        let output_size = Self::output_size_for_crop(crop, project, resolution_base);
         let output_size = XY::new(output_size.0 as f64, output_size.1 as f64);
 
-        let display_offset = Self::display_offset(options, project, resolution_base);
+        let display_offset = Self::display_offset_for_crop(crop, project, resolution_base);
 
         let end = Coord::new(output_size) - display_offset;
 
@@ -2202,7 +2340,17 @@ impl ProjectUniforms {
         cursor_interp_fn: &dyn Fn(f32) -> Option<InterpolatedCursorPosition>,
     ) -> Self {
         let options = &constants.options;
-        let output_size = Self::get_output_size(options, project, resolution_base);
+
+        // Auto-reframe pans the crop with the smoothed zoom focus, so everything below
+        // (crop bounds, cursor and zoom coordinates) works against the moving window.
+        let crop = if project.auto_reframe {
+            let focus = zoom_focus_interpolator.interpolate(segment_frames.recording_time);
+            Self::get_reframed_crop(options, project, focus)
+        } else {
+            Self::get_crop(options, project)
+        };
+
+        let output_size = Self::output_size_for_crop(&crop, project, resolution_base);
         let fps_f32 = fps as f32;
         let frame_time = frame_number as f32 / fps_f32;
         let prev_frame_time = if frame_number == 0 {
@@ -2235,8 +2383,6 @@ impl ProjectUniforms {
         let has_previous = frame_number > 0;
         let normalized_screen_motion = normalized_motion_amount(screen_motion_blur, fps_f32);
 
-        let crop = Self::get_crop(options, project);
-
         let interpolated_cursor = cursor_interp_fn(cursor_time_for_interp);
         let prev_interpolated_cursor = cursor_interp_fn(prev_cursor_time_for_interp);
         let lookback_t = (cursor_time_for_interp - 0.4).max(0.0);
@@ -2370,8 +2516,8 @@ impl ProjectUniforms {
                 (crop.position.y + crop.size.y) as f64,
             ));
 
-            let display_offset = Self::display_offset(options, project, resolution_base);
-            let display_size = Self::display_size(options, project, resolution_base);
+            let display_offset = Self::display_offset_for_crop(&crop, project, resolution_base);
+            let display_size = Self::display_size_for_crop(&crop, project, resolution_base);
 
             let (start, end) =
                 Self::display_bounds(&zoom, display_offset, display_size, output_size);
@@ -3642,4 +3788,26 @@ mod project_uniforms_tests {
         assert!(delta < 0.2, "focus moved too far ahead: {delta}");
         assert!(delta > -0.25, "focus lagged too far behind: {delta}");
     }
+
+    #[test]
+    fn reframed_crop_follows_focus_inside_crop() {
+        let options = RenderOptions {
+            camera_size: None,
+            screen_size: XY::new(1920, 1080),
+        };
+        let mut project = ProjectConfiguration::default();
+        project.aspect_ratio = Some(AspectRatio::Vertical);
+
+        let crop_at = |x: f64| {
+            ProjectUniforms::get_reframed_crop(&options, &project, Coord::new(XY::new(x, 0.5)))
+        };
+
+        assert_eq!(crop_at(0.5).size, XY::new(607, 1080));
+        assert_eq!(crop_at(0.5).position, XY::new(657, 0));
+        assert_eq!(crop_at(0.0).position.x, 0);
+        assert_eq!(crop_at(1.0).position.x, 1920 - 607);
+
+        let (base_width, base_height) = ProjectUniforms::get_base_size(&options, &project);
+        assert_eq!((base_width, base_height), (607, 1080));
+    }
 }
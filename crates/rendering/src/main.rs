@@ -186,6 +186,7 @@ async fn main() -> Result<()> {
             1, // Only render 1 frame
             XY::new(output_size.0, output_size.1),
             &recordings,
+            None,
         )
         .await
     });